@@ -1,38 +1,469 @@
 use crate::messages::MessageComponent::*;
 use crate::player_data::PLAYER_META;
-use crate::util::access;
-use crate::types::towns;
+use crate::traits::Entity;
+use crate::util::access::{self, EntityAccessor};
+use crate::types::items::item_settings;
+use crate::types::towns::{self, Town};
+use crate::text;
+use crate::util::{self, timed_events};
 use crate::*;
 
 use self::ParseResult::*;
 
+#[cfg(feature = "remote_clients")]
+use crate::util::server_host;
+
+use atomic::Atomic;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use std::cmp;
+
+lazy_static! {
+    /// Commands handled directly by the game loop, before
+    /// any player dialogue. These run even while paused.
+    static ref GLOBAL_COMMANDS: RwLock<Vec<GlobalCommand>> = RwLock::new(Vec::new());
+
+    /// The real time at which `quit` was last entered
+    /// locally, or 0 if it hasn't been.
+    static ref QUIT_REQUESTED: Atomic<u64> = Atomic::new(0);
+}
+
+/// The slowest text speed that players can choose, in ms.
+const MAX_TEXT_SPEED: u64 = 5_000;
+
+/// The widest line length that players can choose.
+const MAX_LINE_LENGTH: usize = 150;
+
+/// The longest announcement that can be made, in bytes.
+/// Leaves room for the prefix within the 256 byte buffer
+/// used by remote clients.
+const MAX_ANNOUNCEMENT_LENGTH: usize = 200;
+
+/// The price of changing your name after creation.
+const RENAME_PRICE: u32 = 100;
+
+/// How long the local user has to repeat `quit` before
+/// the game closes, in ms.
+const QUIT_CONFIRM_MS: u64 = 5_000;
+
+/// A command which is handled by the game loop itself
+/// instead of through a player's dialogue. `run` returns
+/// whether the message was handled; messages that weren't
+/// get forwarded to the player's dialogue as usual.
+#[derive(Copy, Clone)]
+pub struct GlobalCommand {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+    pub run: fn(&GameMessage, &Vec<&str>, &mut bool) -> bool,
+}
+
+impl GlobalCommand {
+    pub fn matches(&self, input: &str) -> bool {
+        self.name == input || self.aliases.contains(&input)
+    }
+}
+
+pub fn register_global_command(command: GlobalCommand) {
+    GLOBAL_COMMANDS.write().push(command);
+}
+
+/// Runs the global command matching `name`, if any.
+/// Returns whether the message was handled.
+pub fn dispatch(name: &str, message: &GameMessage, args: &Vec<&str>, is_running: &mut bool) -> bool {
+    // Copy the command out so that the registry isn't
+    // locked while it runs.
+    let command = GLOBAL_COMMANDS.read()
+        .iter()
+        .find(|c| c.matches(name))
+        .cloned();
+
+    match command {
+        Some(c) => (c.run)(message, args, is_running),
+        None => false,
+    }
+}
+
+/// Iterates over a snapshot of the registered global
+/// commands, in the order they were registered.
+pub fn list_commands() -> impl Iterator<Item = GlobalCommand> {
+    GLOBAL_COMMANDS.read().clone().into_iter()
+}
+
+/// Lists every global command and its aliases.
+pub fn get_help_message() -> String {
+    let mut message = String::from("Global commands:");
+
+    for command in list_commands() {
+        let mut names = String::from(command.name);
+        for alias in command.aliases {
+            names += ", ";
+            names += alias;
+        }
+        message += &format!("\n * {}: {}", names, command.description);
+    }
+    message
+}
+
 pub fn register_global_commands() {
+    register_global_command(GlobalCommand {
+        name: "pause",
+        aliases: &["p"],
+        description: "Pause or unpause the game.",
+        run: |_, _, is_running| {
+            toggle_pause(is_running);
+            true
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "quit",
+        aliases: &["end"],
+        description: "Close the game, or leave it if playing remotely.",
+        run: |message, _, _| {
+            if message.channel_info == Local {
+                confirm_quit();
+                true
+            } else {
+                with_sender(message, leave_game)
+            }
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "announce",
+        aliases: &[],
+        description: "Send a message to everyone. Admins only.",
+        run: |message, args, _| announce_command(message, args),
+    });
+    register_global_command(GlobalCommand {
+        name: "rename",
+        aliases: &[],
+        description: "Change your name for a fee.",
+        run: |message, args, _| {
+            with_sender(message, |player| rename_command(player, args))
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "rate",
+        aliases: &[],
+        description: "Show or set the number of updates per second. Admins only.",
+        run: |message, args, _| rate_command(message, args),
+    });
+    // The rest are read-only, so they're allowed while paused.
+    register_global_command(GlobalCommand {
+        name: "map",
+        aliases: &[],
+        description: "Display the map of a visited town.",
+        run: |message, args, _| {
+            with_sender(message, |player| map_command(player, args))
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "legend",
+        aliases: &[],
+        description: "Explain the icons on your current town's map.",
+        run: |message, _, _| with_sender(message, legend_command),
+    });
+    register_global_command(GlobalCommand {
+        name: "players",
+        aliases: &["who"],
+        description: "Display all connected players.",
+        run: |message, _, _| with_sender(message, players_command),
+    });
+    register_global_command(GlobalCommand {
+        name: "speed",
+        aliases: &[],
+        description: "Show or set your text speed in ms. 0 is instant. See also tspeed.",
+        run: |message, args, _| {
+            with_sender(message, |player| speed_command(player, args))
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "width",
+        aliases: &[],
+        description: "Show or set how many characters fit on each line.",
+        run: |message, args, _| {
+            with_sender(message, |player| width_command(player, args))
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "time",
+        aliases: &["clock"],
+        description: "Display the current time of day.",
+        run: |message, _, _| {
+            with_sender(message, |player| {
+                player.send_short_message(&format!("It's {}.", format_time_of_day()));
+            })
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "log",
+        aliases: &["combat"],
+        description: "Display your most recent hits in combat.",
+        run: |message, _, _| with_sender(message, log_command),
+    });
+    register_global_command(GlobalCommand {
+        name: "stats",
+        aliases: &[],
+        description: "Display how quickly the game is updating.",
+        run: |message, _, _| {
+            reply(message, &get_stats_message());
+            true
+        },
+    });
+    register_global_command(GlobalCommand {
+        name: "help",
+        aliases: &[],
+        description: "Display this list of commands and your current options.",
+        run: |message, _, _| {
+            with_sender(message, |player| {
+                let options = get_options_text(player.get_player_id());
+                let help = format!("{}\n\nYour options:{}", get_help_message(), options);
+                player.send_message(General, &help);
+            })
+        },
+    });
+
     let mut commands = Vec::new();
     commands.push(settings_command());
-    commands.push(players_command());
     commands.push(message_command());
     if CHEATS_ENABLED {
         commands.push(tp_command());
         commands.push(money_command());
+        commands.push(give_command());
+        commands.push(heal_command());
         commands.push(god_command());
+        commands.push(inspect_command());
     }
     register_options(Dialogue::commands("Commands", commands, GLOBAL_USER));
 }
 
+/// Runs `callback` for the player who sent the message.
+/// Returns `false` if they don't have a player yet.
+fn with_sender<T, F: FnOnce(&PlayerMeta) -> T>(message: &GameMessage, callback: F) -> bool {
+    match access::player_meta_sender(&message.channel_info) {
+        Some(player) => {
+            callback(&player);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Pauses or unpauses the game and reports the updated
+/// status to the local output stream.
+fn toggle_pause(is_running: &mut bool) {
+    *is_running = !*is_running;
+    info!("Game is now {}.",
+        if *is_running { "unpaused" } else { "paused" }
+    );
+}
+
+/// Closes the game if `quit` was already entered within
+/// the last `QUIT_CONFIRM_MS`. Otherwise, asks the user
+/// to enter it again.
+fn confirm_quit() {
+    let now = current_time();
+    let requested = QUIT_REQUESTED.swap(now, SeqCst);
+
+    if requested > 0 && now - requested <= QUIT_CONFIRM_MS {
+        request_shutdown();
+        return;
+    }
+    info!("Enter `quit` again within {} seconds to close the game.", QUIT_CONFIRM_MS / 1000);
+}
+
+/// Whether the sender of `message` may use admin-only
+/// commands. Whoever runs the server always can. Remote
+/// users can if they logged in as `ADMIN_USERNAME`.
+/// Enabling cheats doesn't make anyone else an admin.
+fn is_admin(message: &GameMessage) -> bool {
+    match message.channel_info {
+        Local => true,
+        #[cfg(feature = "remote_clients")]
+        Remote(ref username) => ADMIN_USERNAME == Some(username.as_str()),
+        #[cfg(feature = "discord")]
+        _ => false,
+    }
+}
+
+/// Sends a message to every player and client. Formatting
+/// characters are removed and the text is shortened to
+/// `MAX_ANNOUNCEMENT_LENGTH`.
+/// Usage: `announce <text>`
+fn announce_command(message: &GameMessage, args: &Vec<&str>) -> bool {
+    if !is_admin(message) {
+        reply(message, "Only admins can make announcements.");
+        return true;
+    }
+    let announcement = text::sanitize(&args.join(" "), MAX_ANNOUNCEMENT_LENGTH);
+    if announcement.trim().is_empty() {
+        reply(message, "Announce what?");
+        return true;
+    }
+    info!("Announcement: {}", announcement);
+    send_global_message(&format!("Announcement: {}", announcement));
+    true
+}
+
+/// Displays or changes how many times the game updates
+/// each second. Values are clamped by `set_update_rate()`.
+/// Usage: `rate [<updates/s> | reset]`
+/// Examples: `rate`, `rate 20`, `rate reset`
+fn rate_command(message: &GameMessage, args: &Vec<&str>) -> bool {
+    let rate: u16 = match args.get(0) {
+        None => {
+            reply(message, &format!("The game updates {} times per second.", update_rate()));
+            return true;
+        }
+        Some(_) if !is_admin(message) => {
+            reply(message, "Only admins can change the update rate.");
+            return true;
+        }
+        Some(&"reset") => UPDATES_PER_SECOND,
+        Some(_) => match util::parse_arg(args, 0) {
+            Ok(num) => num,
+            Err(e) => {
+                reply(message, e);
+                return true;
+            }
+        },
+    };
+    let rate = set_update_rate(rate);
+    reply(message, &format!("Setting the update rate to {} per second.", rate));
+    true
+}
+
+fn get_stats_message() -> String {
+    let stats = tick_stats();
+    format!(
+        "Updates per second: {:.1} / {}\n\
+         Average update interval: {:.1}ms\n\
+         Pending events: {}",
+        stats.ticks_per_second,
+        update_rate(),
+        stats.avg_interval_ms,
+        timed_events::pending_count()
+    )
+}
+
+/// Sends `msg` to whoever sent `message`, or logs it if
+/// they don't have a player, e.g. the server console.
+fn reply(message: &GameMessage, msg: &str) {
+    if !with_sender(message, |player| player.send_short_message(msg)) {
+        info!("{}", msg);
+    }
+}
+
+/// Disconnects a remote player without affecting anyone
+/// else in the game.
+fn leave_game(player: &PlayerMeta) {
+    player_data::disconnect_player(player);
+    send_global_message(&format!("{} has left the game.", player.get_name()));
+
+    #[cfg(feature = "remote_clients")]
+    {
+        if let Remote(ref username) = player.get_channel() {
+            server_host::disconnect_client(username);
+        }
+    }
+}
+
+/// Displays the map of any town the player has visited.
+/// Usage: `map [<town #>]`
+/// Examples: `map`, `map 2`
+pub fn map_command(player: &PlayerMeta, args: &Vec<&str>) {
+    let current_town = player.get_coordinates().0;
+    let town_num: usize = match util::parse_arg_or(args, 0, current_town) {
+        Ok(num) => num,
+        Err(e) => {
+            player.send_short_message(e);
+            return;
+        }
+    };
+    if !player.has_visited_town(town_num) {
+        player.send_short_message("You have never been to that town.");
+        return;
+    }
+    match Town::find_map(town_num, player) {
+        Some(map) => player.send_short_message(&format!("Town #{}:\n{}", town_num, map)),
+        None => player.send_short_message("That town does not exist.")
+    };
+}
+
+/// Lists the meaning of each icon on the map of the
+/// player's current town.
+/// Usage: `legend`
+fn legend_command(player: &PlayerMeta) {
+    let town_num = player.get_coordinates().0;
+    match Town::find_legend(town_num, player) {
+        Some(legend) => player.send_short_message(&format!("Town #{} legend:\n{}", town_num, legend)),
+        None => player.send_short_message("You aren't in a town."),
+    };
+}
+
+/// Displays or changes the delay between sections of the
+/// player's messages. Finer grained than `tspeed`, which
+/// sets the same value on a 1-5 scale. Values above
+/// `MAX_TEXT_SPEED` are clamped.
+/// Usage: `speed [<ms> | reset]`
+/// Examples: `speed`, `speed 0`, `speed 1500`
+fn speed_command(player: &PlayerMeta, args: &Vec<&str>) {
+    match args.get(0) {
+        None => player.send_short_message(&format!("Your text speed is {}ms.", player.get_text_speed())),
+        Some(&"reset") => set_text_speed_ms(player, TEXT_SPEED),
+        Some(_) => match util::parse_arg(args, 0) {
+            Ok(num) => set_text_speed_ms(player, num),
+            Err(e) => player.send_short_message(e),
+        },
+    }
+}
+
+/// Used by both `speed` and `tspeed`.
+fn set_text_speed_ms(player: &PlayerMeta, ms: u64) {
+    let speed = cmp::min(ms, MAX_TEXT_SPEED);
+    player.set_text_speed(speed);
+    player.send_short_message(&format!("Setting your text speed to {}ms.", speed));
+}
+
+/// Displays or changes the length at which the player's
+/// text is wrapped. Shares `set_text_length()` with
+/// `tlength`, so values outside of `MIN_LINE_LENGTH` and
+/// `MAX_LINE_LENGTH` are refused.
+/// Usage: `width [<chars> | reset]`
+/// Examples: `width`, `width 30`, `width reset`
+fn width_command(player: &PlayerMeta, args: &Vec<&str>) {
+    match args.get(0) {
+        None => player.send_short_message(&format!("Your lines are {} characters wide.", player.get_text_length())),
+        Some(&"reset") => set_text_length(player, LINE_LENGTH as i32),
+        Some(_) => match util::parse_arg(args, 0) {
+            Ok(num) => set_text_length(player, num),
+            Err(e) => player.send_short_message(e),
+        },
+    }
+}
+
 /// Teleports the player. Cannot display entrance message.
-/// Usage: `tp [<town #> | <area_type>]`
-/// Examples: `tp 2`, `tp station`
+/// Usage: `tp [<town #> | <town #> <x> <z> | <area_type>]`
+/// Examples: `tp 2`, `tp 2 3 5`, `tp station`
 fn tp_command() -> Command {
     Command::action_only(
-        "tp #", "Teleport to town #.",
+        "tp # [# #]", "§Teleport to town #, or to area # # #.",
         |args, player| {
         if args.len() < 1 {
             player.add_short_message("Error: Missing town #.");
             return;
         }
-        let tp_result = match args[0].parse() {
-            Ok(town_num) => tp_player_to_town(player, town_num),
-            Err(_) => tp_player_to_area(player, args[0]),
+        let tp_result = if args.len() >= 3 {
+            match (util::parse_arg(args, 0), util::parse_arg(args, 1), util::parse_arg(args, 2)) {
+                (Ok(town), Ok(x), Ok(z)) => tp_player_to_coords(player, (town, x, z)),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(e),
+            }
+        } else {
+            match args[0].parse() {
+                Ok(town_num) => tp_player_to_town(player, town_num),
+                Err(_) => tp_player_to_area(player, args[0]),
+            }
         };
         if let Err(e) = tp_result {
             player.send_short_message(e);
@@ -49,6 +480,17 @@ fn tp_player_to_town(player: &PlayerMeta, town_num: usize) -> Result<(), &'stati
     tp_player(player, (town_num, x, z))
 }
 
+/// Handles transporting the player when the input
+/// refers to exact coordinates.
+fn tp_player_to_coords(player: &PlayerMeta, coords: (usize, usize, usize)) -> Result<(), &'static str> {
+    // Make sure the town is generated before checking.
+    access::town(coords.0);
+    if !access::area_exists(coords) {
+        return Err("That area does not exist.");
+    }
+    tp_player(player, coords)
+}
+
 /// Handles transporting the player when the input
 /// refers to a specific area type.
 fn tp_player_to_area(player: &PlayerMeta, location: &str) -> Result<(), &'static str> {
@@ -64,16 +506,17 @@ fn tp_player(player: &PlayerMeta, coords: (usize, usize, usize)) -> Result<(), &
     if coords == player.get_coordinates() {
         return Err("There is nowhere to go.");
     }
+    if !access::area(coords, |a| a.has_room()).unwrap_or(false) {
+        return Err("That area is too crowded right now.");
+    }
     // We have to manually update their dialogue.
     if let Err(_) = try_delete_options(player.get_player_id()) {
         return Err("Currently unable to handle player dialogue.");
     }
     player.area(|old| {
-        access::area(coords, |new| {
-            old.transfer_to_area(player.get_player_id(), new);
-        });
-    });
-    Ok(())
+        access::area(coords, |new| old.transfer_to_area(player.get_player_id(), new))
+    })
+    .unwrap_or(Err("That area does not exist."))
 }
 
 /// Gives or takes money from the player.
@@ -108,6 +551,45 @@ fn money_command() -> Command {
     })
 }
 
+/// Gives the player a new item by its type id or, for
+/// consumables, its name. The level defaults to 1.
+/// Usage: `give <item_type> [<level>]`
+/// Examples: `give sword 3`, `give healing potion 2`
+fn give_command() -> Command {
+    Command::action_only("give x [#]", "Get an item of type x.", |args, player| {
+        let (level, name_args) = match args.split_last() {
+            Some((last, rest)) if last.parse::<u32>().is_ok() => (last.parse().unwrap(), rest),
+            _ => (1, &args[..]),
+        };
+        let input = name_args.join(" ");
+        if input.is_empty() {
+            player.send_short_message("Error: You need to specify an item type.");
+            return;
+        }
+        let item = item_settings::item_from_type(&input, "", level).or_else(|| {
+            item_settings::CONSUMABLE_NAMES.iter()
+                .find(|n| n.eq_ignore_ascii_case(&input))
+                .and_then(|n| item_settings::item_from_type("consumable", n, level))
+        });
+        match item {
+            Some(item) => {
+                player.send_short_message(&format!("Giving you a {}.", item.get_name()));
+                player.entity(|e| e.give_item(item));
+            }
+            None => player.send_short_message("Unknown item type."),
+        }
+    })
+}
+
+/// Restores the player to full health.
+/// Usage: `heal`
+fn heal_command() -> Command {
+    Command::action_only("heal", "Restore your health.", |_, player| {
+        player.entity(|e| e.set_health(e.get_max_health()));
+        player.send_current_options();
+    })
+}
+
 /// Changes the player's god. Case sensitive.
 /// Usage: `god <god_name>`
 /// Examples: `god Danu`
@@ -124,6 +606,92 @@ fn god_command() -> Command {
     })
 }
 
+/// Displays the state of any entity in the world. Omit
+/// the id to list every entity in the area instead.
+/// Usage: `inspect <town> <x> <z> [<entity_id>]`
+/// Examples: `inspect 1 0 5`, `inspect 1 3 5 12345`
+fn inspect_command() -> Command {
+    Command::action_only(
+        "inspect # # # [#]", "§Inspect entity # in area # # #, or list them.",
+        |args, player| {
+            let coords = match (util::parse_arg(args, 0), util::parse_arg(args, 1), util::parse_arg(args, 2)) {
+                (Ok(town), Ok(x), Ok(z)) => (town, x, z),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    player.send_short_message(e);
+                    return;
+                }
+            };
+            if !access::area_exists(coords) {
+                player.send_short_message("That area does not exist.");
+                return;
+            }
+            let report = match util::parse_arg(args, 3) {
+                Ok(entity_id) => {
+                    let accessor = EntityAccessor { coordinates: coords, entity_id, is_player: false };
+                    access::entity(accessor, get_entity_report)
+                        .unwrap_or_else(|| String::from("There is no entity with that id in this area."))
+                }
+                Err(util::MISSING_ARG) => list_entities(coords),
+                Err(e) => e.to_string(),
+            };
+            player.send_message(General, &report);
+        })
+}
+
+/// Lists the name, type, and id of each entity in the
+/// area at `coords`.
+fn list_entities(coords: (usize, usize, usize)) -> String {
+    access::area(coords, |area| {
+        let mut list = format!("Entities in {:?}:", coords);
+        for entity in area.borrow_entity_lock().iter() {
+            list += &format!("\n * {} ({}): {}", entity.get_name(), entity.get_type(), entity.get_id());
+        }
+        list
+    })
+    .expect("Area no longer exists.")
+}
+
+/// Summarizes everything about an entity that might be
+/// useful for debugging.
+fn get_entity_report(entity: &Entity) -> String {
+    let effects: Vec<String> = entity.get_active_effects()
+        .iter()
+        .map(|e| format!("{} {}", e.name, e.level))
+        .collect();
+    let effects = if effects.len() > 0 { effects.join(", ") } else { String::from("none") };
+
+    let inventory = match entity.get_inventory() {
+        Some(i) => format!("{} / {} slots used", i.current_size(), i.get_max_size()),
+        None => String::from("none"),
+    };
+
+    format!(
+        "{} ({}): {}\n\
+         Coordinates: {:?}\n\
+         Health: {} / {}\n\
+         Damage: {}\n\
+         Defense: {}\n\
+         Attack speed: {}\n\
+         Item speed: {}\n\
+         Money: {}g\n\
+         Effects: {}\n\
+         Inventory: {}",
+        entity.get_name(),
+        entity.get_type(),
+        entity.get_id(),
+        entity.get_coordinates(),
+        entity.get_health(),
+        entity.get_max_health(),
+        entity.get_base_damage(),
+        entity.get_defense(),
+        entity.get_attack_speed(),
+        entity.get_item_speed(),
+        entity.get_money(),
+        effects,
+        inventory
+    )
+}
+
 /// Opens the player's settings dialogue. Allowing them
 /// clearer access to certain in-game settings.
 /// Usage: `settings [open]`
@@ -144,11 +712,31 @@ fn settings_command() -> Command {
 
 /// Generates the actual settings dialogue.
 fn settings_dialogue(player: &PlayerMeta) -> Dialogue {
+    let settings = player.get_settings();
+    let info = format!(
+        "Text speed: {}ms\n\
+         Line length: {}\n\
+         Short messages: {}\n\
+         Show map: {}\n\
+         Show clock: {}\n\
+         Use `<cmd> reset` to reset this setting.",
+        settings.text_speed,
+        settings.text_length,
+        settings.max_short_messages,
+        settings.show_map,
+        settings.show_clock
+    );
     Dialogue {
         title: String::from("Player Settings"),
-        info: Some(String::from("Use `<cmd> reset` to reset this setting.")),
+        info: Some(info),
         responses: vec![close_settings()],
-        commands: vec![text_speed_command(), text_length_command()],
+        commands: vec![
+            text_speed_command(),
+            text_length_command(),
+            short_messages_command(),
+            show_map_command(),
+            show_clock_command(),
+        ],
         player_id: player.get_player_id(),
         ..Dialogue::default()
     }
@@ -165,7 +753,7 @@ fn close_settings() -> Response {
 /// Examples: `tspeed 3`, `tspeed reset`
 fn text_speed_command() -> Command {
     Command::action_only(
-        "tspeed #", "§Sets your text speed to #, 1-5.",
+        "tspeed #", "§Sets your text speed to #, 1-5. See also speed.",
         |args, player| {
             match parse_first_argument(args) {
                 Number(num) => set_text_speed(player, num),
@@ -179,21 +767,17 @@ fn text_speed_command() -> Command {
 
 fn set_text_speed(player: &PlayerMeta, input: i32) {
     match input {
-        1 ... 5 => {
-            let msg = format!("Setting your text speed to {}", input);
-            player.send_short_message(&msg);
-            player.set_text_speed((1000 * input as u64) - 500);
-        },
+        1 ... 5 => set_text_speed_ms(player, (1000 * input as u64) - 500),
         _ => player.send_short_message("tspeed expects a value between 1 and 5.")
     };
 }
 
 /// Changes the player's line length.
-/// Usage: `tlength [<val 40-150> | reset]`
+/// Usage: `tlength [<val 20-150> | reset]`
 /// Examples: `tlength 60`, `tlength reset`
 fn text_length_command() -> Command {
     Command::action_only(
-        "tlength #", "§Sets your line length to #, 40-150.",
+        "tlength #", "§Sets your line length to #, 20-150.",
         |args, player|{
             match parse_first_argument(args) {
                 Number(num) => set_text_length(player, num),
@@ -204,17 +788,93 @@ fn text_length_command() -> Command {
         })
 }
 
+/// Used by both `tlength` and `width`.
 fn set_text_length(player: &PlayerMeta, input: i32) {
+    if (MIN_LINE_LENGTH as i32..=MAX_LINE_LENGTH as i32).contains(&input) {
+        let msg = format!("Setting your text length to {}", input);
+        player.send_short_message(&msg);
+        player.set_text_length(input as usize);
+    } else {
+        let msg = format!("Text length must be between {} and {}.", MIN_LINE_LENGTH, MAX_LINE_LENGTH);
+        player.send_short_message(&msg);
+    }
+}
+
+/// Changes how many short messages are shown at once.
+/// Usage: `tmessages [<val 1-5> | reset]`
+/// Examples: `tmessages 5`, `tmessages reset`
+fn short_messages_command() -> Command {
+    Command::action_only(
+        "tmessages #", "§Shows up to # short messages, 1-5.",
+        |args, player| {
+            match parse_first_argument(args) {
+                Number(num) => set_max_short_messages(player, num),
+                Reset => set_max_short_messages(player, MAX_SHORT_MESSAGES as i32),
+                TooShort => player.send_short_message("You need to specify the number of messages."),
+                _ => player.send_short_message("Unable to parse arguments.")
+            };
+        })
+}
+
+fn set_max_short_messages(player: &PlayerMeta, input: i32) {
     match input {
-        40 ... 150 => {
-            let msg = format!("Setting your text length to {}", input);
+        1 ... 5 => {
+            player.set_max_short_messages(input as usize);
+            let msg = format!("Showing up to {} short messages", input);
             player.send_short_message(&msg);
-            player.set_text_length(input as usize);
         },
-        _ => player.send_short_message("tlength expects a value between 40 and 150.")
+        _ => player.send_short_message("tmessages expects a value between 1 and 5.")
     };
 }
 
+/// Shows or hides the town map in area dialogue.
+/// Usage: `tmap [<true | false> | reset]`
+/// Examples: `tmap f`, `tmap reset`
+fn show_map_command() -> Command {
+    Command::action_only(
+        "tmap <t|f>", "Show or hide the town map.",
+        |args, player| {
+            let show = match parse_first_argument(args) {
+                Boolean(b) => b,
+                Reset => true,
+                TooShort => {
+                    player.send_short_message("You need to specify true or false.");
+                    return;
+                }
+                _ => {
+                    player.send_short_message("Unable to parse arguments.");
+                    return;
+                }
+            };
+            player.set_show_map(show);
+            player.send_short_message(if show { "The map will be shown." } else { "The map will be hidden." });
+        })
+}
+
+/// Shows or hides the time of day in area dialogue.
+/// Usage: `tclock [<true | false> | reset]`
+/// Examples: `tclock f`, `tclock reset`
+fn show_clock_command() -> Command {
+    Command::action_only(
+        "tclock <t|f>", "Show or hide the time of day.",
+        |args, player| {
+            let show = match parse_first_argument(args) {
+                Boolean(b) => b,
+                Reset => true,
+                TooShort => {
+                    player.send_short_message("You need to specify true or false.");
+                    return;
+                }
+                _ => {
+                    player.send_short_message("Unable to parse arguments.");
+                    return;
+                }
+            };
+            player.set_show_clock(show);
+            player.send_short_message(if show { "The time will be shown." } else { "The time will be hidden." });
+        })
+}
+
 /// The result of parsing an argument for the
 /// entire settings dialogue.
 enum ParseResult {
@@ -242,32 +902,118 @@ fn parse_first_argument(args: &Vec<&str>) -> ParseResult {
     }
 }
 
-/// Displays all currently-connected players and
-/// their locations.
-/// Usage: `players`
-fn players_command() -> Command {
-    Command::action_only(
-        "players", "Display all active players.",
-        |_args, player| {
-            let message = get_players_message();
-            player.send_message(General, &message);
-    })
+/// Lists every connected player who has finished
+/// creating their character.
+/// Usage: `players`, `who`
+pub fn players_command(player: &PlayerMeta) {
+    player.send_message(General, &get_players_message());
 }
 
 fn get_players_message() -> String {
-    let mut message = String::from("Connected players:");
-
-    PLAYER_META.lock()
+    // Clone references out of the registry first so that
+    // the lock isn't held while the areas are accessed.
+    let players: Vec<Arc<PlayerMeta>> = PLAYER_META.lock()
         .iter()
-        .filter(|p| p.is_active())
-        .for_each(|p| {
-            let coords = p.get_coordinates();
-            let area_name = access::area(coords, |a| a.get_title()).unwrap();
-            message += &format!("\n * {} (T: {}; A: {})", p.get_name(), coords.0, area_name);
-        });
+        .filter(|p| p.is_active() && p.has_chosen_name())
+        .cloned()
+        .collect();
+
+    let mut message = String::from("Connected players:");
+    for p in players {
+        let coords = p.get_coordinates();
+        // Players still creating their character aren't in the
+        // world yet. Looking up their area would generate it.
+        if !access::area_exists(coords) || access::entity(p.get_accessor(), |_| ()).is_none() {
+            continue;
+        }
+        let area_name = access::area(coords, |a| a.get_title()).unwrap();
+        message += &format!("\n * {} (T: {}; A: {})", p.get_name(), coords.0, area_name);
+    }
     message
 }
 
+/// Changes the player's name for `RENAME_PRICE`. Anyone
+/// who knew the old name will have to learn the new one.
+/// Usage: `rename <name>`
+pub fn rename_command(player: &PlayerMeta, args: &Vec<&str>) {
+    let name = args.join(" ");
+    let name = name.trim();
+
+    if !player.has_chosen_name() {
+        player.send_short_message("Finish creating your character first.");
+        return;
+    }
+    if let Err(e) = player_data::validate_name(name) {
+        player.send_short_message(&e);
+        return;
+    }
+    let id = player.get_player_id();
+    if player_data::is_name_taken(name, id) {
+        player.send_short_message("Someone else already goes by that name.");
+        return;
+    }
+    let renamed = access::area(player.get_coordinates(), |area| {
+        let mut entities = area.lock_entities();
+        let entity = match entities.iter_mut().find(|e| e.get_id() == id) {
+            Some(e) => e,
+            None => return Err("You can't do that right now."),
+        };
+        if !entity.can_afford(RENAME_PRICE) {
+            return Err("You can't afford to change your name.");
+        }
+        entity.take_money(RENAME_PRICE);
+        entity.set_name(name.to_string());
+        Ok(())
+    })
+    .unwrap_or(Err("You can't do that right now."));
+
+    match renamed {
+        Ok(()) => {
+            player.set_name(name.to_string());
+            player_data::forget_name_for_all(id);
+            player.add_short_message(&format!("You paid {}g. You are now known as {}.", RENAME_PRICE, name));
+            player.get_send_area_options();
+        }
+        Err(e) => player.send_short_message(e),
+    }
+}
+
+/// Lists the player's most recent combat events, oldest
+/// first.
+/// Usage: `log`, `combat`
+pub fn log_command(player: &PlayerMeta) {
+    let log = player.get_combat_log();
+    if log.is_empty() {
+        player.send_short_message("You haven't been in any fights.");
+        return;
+    }
+    let now = game_time();
+    let mut message = String::from("Recent combat:");
+    for event in log {
+        message += &format!(
+            "\n * {} ago: {} hit {} for {}.",
+            format_elapsed(now.saturating_sub(event.time)),
+            event.attacker,
+            event.target,
+            event.amount
+        );
+    }
+    player.send_message(General, &message);
+}
+
+/// Formats a duration in ms as the largest whole unit,
+/// e.g. `3s` or `2m`.
+fn format_elapsed(ms: u64) -> String {
+    let seconds = ms / 1000;
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else if seconds < 3600 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}h", seconds / 3600)
+    }
+}
+
 /// Usage: `msg <username> [<message>]`
 /// Examples: `msg personthecat Hello, world.`
 fn message_command() -> Command {
@@ -286,4 +1032,153 @@ fn message_command() -> Command {
             player.send_short_message("To-do: Come back to this when Discord is integrated.",);
         },
     )
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data::{self, DEFAULT_NAME};
+    use crate::traits::EntityCore;
+    use crate::types::entities::mobs::Mob;
+
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+    #[test]
+    fn players_lists_only_named_active_players() {
+        crate::test_init();
+        let named = player_data::test_player_in_town(327);
+        named.set_name(String::from("Listed Tester"));
+        let _unnamed = player_data::test_player_in_town(327);
+        let gone = player_data::test_player_in_town(327);
+        gone.set_name(String::from("Gone Tester"));
+        gone.set_active(false);
+
+        let message = get_players_message();
+        assert!(message.contains("Listed Tester (T: 327;"));
+        assert!(!message.contains("Gone Tester"));
+        assert!(!message.contains(DEFAULT_NAME));
+    }
+
+    #[test]
+    fn players_skips_anyone_still_creating_their_character() {
+        crate::test_init();
+        let placed = player_data::test_player_in_town(346);
+        placed.set_name(String::from("Placed Tester"));
+        // Where `new_player_event()` leaves them.
+        let creating = player_data::test_player();
+        creating.set_name(String::from("Creating Tester"));
+        creating.set_coordinates((0, 0, 0));
+
+        let message = get_players_message();
+        assert!(message.contains("Placed Tester (T: 346;"));
+        assert!(!message.contains("Creating Tester"));
+    }
+
+    #[test]
+    fn speed_and_tspeed_set_the_same_value() {
+        let player = player_data::test_player();
+        set_text_speed(&player, 5);
+        assert_eq!(4_500, player.get_text_speed());
+        speed_command(&player, &vec!["9000"]);
+        assert_eq!(MAX_TEXT_SPEED, player.get_text_speed());
+        set_text_speed(&player, 3);
+        assert_eq!(TEXT_SPEED, player.get_text_speed());
+    }
+
+    #[test]
+    fn width_and_tlength_refuse_the_same_values() {
+        let player = player_data::test_player();
+        width_command(&player, &vec!["5"]);
+        set_text_length(&player, 5);
+        assert_eq!(LINE_LENGTH, player.get_text_length());
+
+        width_command(&player, &vec!["30"]);
+        assert_eq!(30, player.get_text_length());
+        set_text_length(&player, MAX_LINE_LENGTH as i32 + 1);
+        assert_eq!(30, player.get_text_length());
+    }
+
+    #[test]
+    fn registered_command_is_listed_and_dispatched() {
+        static RAN: AtomicBool = AtomicBool::new(false);
+        register_global_command(GlobalCommand {
+            name: "test_ping",
+            aliases: &["tping"],
+            description: "Answers with a pong.",
+            run: |_, args, _| {
+                RAN.store(args == &vec!["pong"], SeqCst);
+                true
+            },
+        });
+        assert!(get_help_message().contains("\n * test_ping, tping: Answers with a pong."));
+
+        let message = GameMessage {
+            message: String::from("tping pong"),
+            channel_info: Local,
+            kind: MessageKind::Input,
+        };
+        let mut is_running = true;
+        assert!(dispatch("tping", &message, &vec!["pong"], &mut is_running));
+        assert!(RAN.load(SeqCst));
+        assert!(!dispatch("test_pong", &message, &vec![], &mut is_running));
+    }
+
+    #[test]
+    fn quit_needs_to_be_confirmed() {
+        crate::test_init();
+        let message = GameMessage {
+            message: String::from("quit"),
+            channel_info: Local,
+            kind: MessageKind::Input,
+        };
+        let mut is_running = true;
+
+        assert!(dispatch("quit", &message, &vec![], &mut is_running));
+        assert!(!crate::SHUTDOWN_REQUESTED.load(SeqCst));
+
+        assert!(dispatch("quit", &message, &vec![], &mut is_running));
+        assert!(crate::SHUTDOWN_REQUESTED.load(SeqCst));
+
+        crate::SHUTDOWN_REQUESTED.store(false, SeqCst);
+        QUIT_REQUESTED.store(0, SeqCst);
+    }
+
+    #[test]
+    fn inspect_reports_a_placed_mob() {
+        crate::test_init();
+        let player = player_data::test_player_in_town(337);
+        let (town, x, z) = player.get_coordinates();
+        let mob = Mob::new();
+        let mob_id = mob.get_id();
+        player.area(|a| a.add_entity(Box::new(mob)).ok());
+
+        let inspect = |id: &str| {
+            let (town, x, z) = (town.to_string(), x.to_string(), z.to_string());
+            (inspect_command().run)(&vec![&town, &x, &z, id], &player).unwrap();
+            player.get_general_message()
+        };
+
+        let report = inspect(&mob_id.to_string());
+        assert!(report.contains(&format!("Ordinary Spider (mob): {}", mob_id)));
+        assert!(report.contains("Health: 5 /"));
+        assert!(report.contains("Damage: 5"));
+
+        let missing = inspect(&mob_id.wrapping_add(1).to_string());
+        assert!(missing.contains("There is no entity with that id in this area."));
+    }
+
+    #[cfg(feature = "remote_clients")]
+    #[test]
+    fn only_listed_remote_users_are_admins() {
+        let from = |channel_info| GameMessage {
+            message: String::from("announce hi"),
+            channel_info,
+            kind: MessageKind::Input,
+        };
+        assert!(is_admin(&from(Local)));
+        assert!(!is_admin(&from(Remote(String::from("not_an_admin")))));
+        if let Some(admin) = ADMIN_USERNAME {
+            assert!(is_admin(&from(Remote(admin.to_string()))));
+        }
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * Diagnostic messages for the game's operator. These
+ * are kept separate from any player-facing output and
+ * are tagged with the thread they came from, as the
+ * game loop and the server thread both write to stdout.
+ *
+ * Use the `debug!`, `info!`, and `warn!` macros, which
+ * accept the same arguments as `format!()`.
+ */
+
+use atomic::Atomic;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+
+/// The severity of a log message. Messages are only
+/// written when at or above the current level.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+}
+
+lazy_static! {
+    static ref LEVEL: Atomic<Level> = Atomic::new(Level::Info);
+    static ref TO_STDOUT: Atomic<bool> = Atomic::new(true);
+    static ref LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+/// Messages below this level will be ignored.
+pub fn set_level(level: Level) {
+    LEVEL.store(level, SeqCst);
+}
+
+pub fn get_level() -> Level {
+    LEVEL.load(SeqCst)
+}
+
+/// Determines whether log messages are written to stdout.
+pub fn set_stdout(enabled: bool) {
+    TO_STDOUT.store(enabled, SeqCst);
+}
+
+/// Additionally appends every log message to `path`.
+pub fn set_log_file(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+
+    *LOG_FILE.lock() = Some(file);
+    Ok(())
+}
+
+/// Writes a message to each sink, if `level` passes the
+/// current filter. Called by the logging macros.
+pub fn write(level: Level, msg: &str) {
+    if level < get_level() {
+        return;
+    }
+    let line = format!(
+        "[{:?}][{}] {}",
+        level,
+        thread::current().name().unwrap_or("unnamed"),
+        msg
+    );
+
+    if TO_STDOUT.load(SeqCst) {
+        println!("{}", line);
+    }
+    if let Some(ref mut file) = *LOG_FILE.lock() {
+        // Logging should never bring down the game.
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log::write($crate::log::Level::Debug, &format!($($arg)*)));
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log::write($crate::log::Level::Info, &format!($($arg)*)));
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log::write($crate::log::Level::Warn, &format!($($arg)*)));
+}
@@ -4,6 +4,9 @@
 #[macro_use]
 extern crate test_game_derive; // To-do: convert to `use` statements.
 
+// Declared first so that its macros are available everywhere.
+#[macro_use]
+pub mod log;
 pub mod messages;
 pub mod player_data;
 pub mod text;
@@ -35,13 +38,15 @@ use self::util::{
 use self::messages::ChannelInfo::*;
 use self::types::areas::area_settings;
 use self::types::items::item_settings;
+use self::types::towns;
 
 use std::{
-    io, process,
+    io,
     sync::atomic::Ordering::SeqCst,
     sync::mpsc::{self, Receiver, Sender},
     sync::Arc,
-    thread,
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use lazy_static::lazy_static;
@@ -61,22 +66,83 @@ use self::util::server_host;
 ///                  # Settings
 /// //////////////////////////////////////////////////
 
-const UPDATES_PER_SECOND: u16 = 10;
+const UPDATES_PER_SECOND: u16 = 10; // The default. See `set_update_rate()`.
+const MIN_UPDATES_PER_SECOND: u16 = 1;
+const MAX_UPDATES_PER_SECOND: u16 = 100;
 const NUM_SPACES: u8 = 50; // Separate by lines until a TUI is implemented.
 const MAX_SHORT_MESSAGES: usize = 3;
 pub const TEXT_SPEED: u64 = 2500;
 pub const TEMP_DIALOGUE_DURATION: u64 = 20_000;
 pub const LINE_LENGTH: usize = 40; // Should probably be no lower than 40.
+pub const MIN_LINE_LENGTH: usize = 20; // Player settings are clamped to this.
 const PRINT_FRAMES: bool = false;
+const LOG_LEVEL: log::Level = log::Level::Info;
+const LOG_FILE: Option<&str> = None; // e.g. Some("test_game.log")
 const CHEATS_ENABLED: bool = true;
+const ADMIN_USERNAME: Option<&str> = None; // e.g. Some("admin") for remote logins.
+const WORLD_SEED: Option<usize> = None; // e.g. Some(12345) for repeatable maps.
+const MS_PER_GAME_MINUTE: u64 = 1000; // A day lasts 24 real minutes.
+const DAY_START_HOUR: u64 = 8; // The time of day when the game starts.
+const SHUTDOWN_DELAY_MS: u64 = 2_000; // Time for clients to display the closing message.
 
 // Don't edit these.
-const MS_BETWEEN_UPDATES: u16 = 1000 / UPDATES_PER_SECOND;
+const MINUTES_PER_DAY: u64 = 24 * 60;
 
 lazy_static! {
     /// A global singleton used for updating the current
     /// time in-game.
     static ref GAME_TIME: Atomic<u64> = Atomic::new(0);
+
+    /// The number of times the game loop tries to update
+    /// each second. Can be changed while the game runs.
+    static ref UPDATE_RATE: Atomic<u16> = Atomic::new(UPDATES_PER_SECOND);
+
+    /// Timing information recorded by the game loop.
+    static ref TICK_STATS: Atomic<TickStats> = Atomic::new(TickStats::default());
+
+    /// Set when the game has been asked to close. The
+    /// game loop shuts down after its current update.
+    static ref SHUTDOWN_REQUESTED: Atomic<bool> = Atomic::new(false);
+
+    /// Set once the game loop has finished shutting down,
+    /// telling any other threads to stop.
+    static ref STOPPING: Atomic<bool> = Atomic::new(false);
+}
+
+/// Measurements of how quickly the game loop is actually
+/// running, which may fall behind `update_rate()` on
+/// slower hosts.
+#[derive(Copy, Clone, Default)]
+pub struct TickStats {
+    /// Updates completed during the last full second.
+    pub ticks_per_second: f32,
+    /// The average `time_since_update` over that second.
+    pub avg_interval_ms: f32,
+    window_start: u64,
+    window_ticks: u32,
+    window_ms: u64,
+}
+
+impl TickStats {
+    /// Counts one update, refreshing the reported values
+    /// once a full second has been measured.
+    fn record(&mut self, now: u64, time_since_update: u64) {
+        if self.window_start == 0 {
+            self.window_start = now;
+            return;
+        }
+        self.window_ticks += 1;
+        self.window_ms += time_since_update;
+
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed >= 1000 {
+            self.ticks_per_second = self.window_ticks as f32 * 1000.0 / elapsed as f32;
+            self.avg_interval_ms = self.window_ms as f32 / self.window_ticks as f32;
+            self.window_start = now;
+            self.window_ticks = 0;
+            self.window_ms = 0;
+        }
+    }
 }
 
 /// The main function and primary event handler.
@@ -93,22 +159,42 @@ fn pre_init() {
     // item_settings::setup_item_pools();
     // player_data::setup_player_registry();
     // towns::setup_town_registry();
+    towns::set_world_seed(WORLD_SEED);
 }
 
 /// Registers vanilla settings into the various registries.
 fn init() {
+    log::set_level(LOG_LEVEL);
+    if let Some(path) = LOG_FILE {
+        if let Err(e) = log::set_log_file(path) {
+            warn!("Unable to open log file {}: {}", path, e);
+        }
+    }
     area_settings::register_vanilla_settings();
     item_settings::register_vanilla_settings();
     global_commands::register_global_commands();
 }
 
+/// Registers the vanilla settings once for every test.
+/// Log messages are hidden to keep the output readable.
+#[cfg(test)]
+pub fn test_init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        log::set_stdout(false);
+        init();
+    });
+}
+
 /// Starts the main game loop.
 fn run() {
     // Local variable declarations for managing
     // the current game state.
     let mut last_update = current_time();
     let mut is_running = true;
-    let input = handle_inputs();
+    let (input, threads) = handle_inputs();
 
     println!("\nStarting game loop. Press enter to begin...");
 
@@ -121,31 +207,89 @@ fn run() {
             // Use the reported delay since `last_update` to update
             // the current real-world time.
             last_update += time_since_update;
+            record_tick(last_update, time_since_update);
             // Attempt to process one message from a user.
             let message = input.try_iter().next();
+            update(message, time_since_update, &mut is_running);
 
-            if let Some(ref msg) = message {
-                // Always process global commands, regardless of
-                // whether the game `is_running`.
-                handle_global_commands(msg, &mut is_running);
+            if SHUTDOWN_REQUESTED.load(SeqCst) {
+                shutdown(threads);
+                return;
             }
-            if is_running {
-                // Updates the current game-time using the reported
-                // `time_since_update`.
-                GAME_TIME.store(game_time() + time_since_update, SeqCst);
-                // Process all current timed-events in the current
-                // thread only.
-                timed_events::update_timed_events();
-
-                if let Some(msg) = message {
-                    // Manage player dialogue using the received
-                    // `GameMessage`.
-                    handle_player_commands(&msg);
-                }
-                if PRINT_FRAMES {
-                    println!("Game time: {} ms.", game_time());
-                }
+        }
+    }
+}
+
+/// Runs a single update of the game loop, handling up to
+/// one `message` from a user.
+fn update(message: Option<GameMessage>, time_since_update: u64, is_running: &mut bool) {
+    // Advance the game time before handling pauses so
+    // that each tick is counted exactly once. Ticks
+    // spent paused are never added.
+    if *is_running {
+        advance_game_time(time_since_update);
+    }
+    let mut handled = false;
+    if let Some(ref msg) = message {
+        // Always process global commands and disconnects,
+        // regardless of whether the game `is_running`.
+        handled = match msg.kind {
+            MessageKind::Input => handle_global_commands(msg, is_running),
+            MessageKind::Disconnect => {
+                handle_disconnect(msg);
+                true
             }
+        };
+    }
+    if *is_running {
+        // Process all current timed-events in the current
+        // thread only.
+        timed_events::update_timed_events();
+
+        if let Some(msg) = message.filter(|_| !handled) {
+            // Manage player dialogue using the received
+            // `GameMessage`.
+            handle_player_commands(&msg);
+        }
+        if PRINT_FRAMES {
+            debug!("Game time: {} ms.", game_time());
+        }
+    }
+    // Retry any refreshes blocked by a locked registry,
+    // then send any messages that were queued this tick.
+    player_options::run_deferred_refreshes();
+    player_data::flush_messages();
+}
+
+/// Asks the game loop to close the game once its current
+/// update has finished. See `shutdown()`.
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, SeqCst);
+}
+
+/// Whether threads other than the game loop should stop.
+pub fn is_stopping() -> bool {
+    STOPPING.load(SeqCst)
+}
+
+/// Tells everyone that the game is closing and gives
+/// remote clients a moment to display it. Afterward,
+/// `threads` are told to stop and joined. The stdin
+/// thread can't be interrupted while reading, so it's
+/// left to end along with the process.
+fn shutdown(threads: Vec<JoinHandle<()>>) {
+    info!("Closing the game.");
+    send_global_message("The server is closing. Goodbye!");
+    player_data::flush_messages();
+
+    // To-do: Flush any pending saves here once the game
+    // can be saved.
+    thread::sleep(Duration::from_millis(SHUTDOWN_DELAY_MS));
+    STOPPING.store(true, SeqCst);
+
+    for handle in threads {
+        if handle.join().is_err() {
+            warn!("A thread panicked while shutting down.");
         }
     }
 }
@@ -160,7 +304,43 @@ fn time_since(last_update: u64) -> u64 {
 /// Determines whether sufficient time has passed for the
 /// main game loop to continue.
 fn can_continue(time_since_update: u64) -> bool {
-    time_since_update >= MS_BETWEEN_UPDATES as u64
+    time_since_update >= ms_between_updates()
+}
+
+/// The current number of updates attempted per second.
+pub fn update_rate() -> u16 {
+    UPDATE_RATE.load(SeqCst)
+}
+
+/// Changes how often the game loop updates, clamped between
+/// `MIN_UPDATES_PER_SECOND` and `MAX_UPDATES_PER_SECOND`.
+/// Returns the rate that was actually set.
+pub fn set_update_rate(rate: u16) -> u16 {
+    let rate = rate.max(MIN_UPDATES_PER_SECOND).min(MAX_UPDATES_PER_SECOND);
+    UPDATE_RATE.store(rate, SeqCst);
+    rate
+}
+
+fn ms_between_updates() -> u64 {
+    1000 / update_rate() as u64
+}
+
+fn record_tick(now: u64, time_since_update: u64) {
+    let mut stats = TICK_STATS.load(SeqCst);
+    stats.record(now, time_since_update);
+    TICK_STATS.store(stats, SeqCst);
+}
+
+/// The latest timing information from the game loop.
+pub fn tick_stats() -> TickStats {
+    TICK_STATS.load(SeqCst)
+}
+
+/// Updates the current game time using the reported
+/// interval. This is skipped while the game is paused,
+/// freezing every timed event in place.
+fn advance_game_time(time_since_update: u64) {
+    GAME_TIME.store(game_time() + time_since_update, SeqCst);
 }
 
 /// A public accessor which reports the current game time.
@@ -168,19 +348,50 @@ pub fn game_time() -> u64 {
     GAME_TIME.load(SeqCst)
 }
 
+/// The current in-game time of day as `(hour, minute)`.
+pub fn time_of_day() -> (u64, u64) {
+    get_time_of_day(game_time())
+}
+
+/// Converts a game time into `(hour, minute)`, wrapping
+/// around at the end of each day.
+pub fn get_time_of_day(time: u64) -> (u64, u64) {
+    let minutes = (time / MS_PER_GAME_MINUTE + DAY_START_HOUR * 60) % MINUTES_PER_DAY;
+    (minutes / 60, minutes % 60)
+}
+
+/// Whether it is currently dark outside in-game.
+pub fn is_night() -> bool {
+    let (hour, _) = time_of_day();
+    hour < 5 || hour > 20
+}
+
+/// Formats the current time of day, e.g. `08:30 (morning)`.
+pub fn format_time_of_day() -> String {
+    let (hour, minute) = time_of_day();
+    let period = match hour {
+        5..=11 => "morning",
+        12..=16 => "afternoon",
+        17..=20 => "evening",
+        _ => "night",
+    };
+    format!("{:02}:{:02} ({})", hour, minute, period)
+}
+
 /// Retrieves the current real-world time in milliseconds.
 fn current_time() -> u64 {
     time::precise_time_ns() / 1_000_000
 }
 
 /// Spawns a channel for sending messages into the main
-/// game thread through various sources.
-fn handle_inputs() -> Receiver<GameMessage> {
+/// game thread through various sources. Also returns the
+/// threads that should be joined when the game closes.
+fn handle_inputs() -> (Receiver<GameMessage>, Vec<JoinHandle<()>>) {
     let (tx, rx) = mpsc::channel();
     handle_stdio(tx.clone());
     handle_discord(tx.clone());
-    handle_server(tx);
-    rx
+    let threads = handle_server(tx).into_iter().collect();
+    (rx, threads)
 }
 
 /// A simple loop which awaits inputs from the user
@@ -195,6 +406,7 @@ fn handle_stdio(tx: Sender<GameMessage>) {
         let message = GameMessage {
             message: input.trim().to_string(),
             channel_info: Local,
+            kind: MessageKind::Input,
         };
 
         tx.send(message)
@@ -207,7 +419,10 @@ fn handle_stdio(tx: Sender<GameMessage>) {
 /// does not work, at the moment.
 #[cfg(feature = "discord")]
 fn handle_discord(tx: Sender<GameMessage>) {
-    thread::spawn(move || Bot::load(tx));
+    thread::Builder::new()
+        .name(String::from("discord"))
+        .spawn(move || Bot::load(tx))
+        .expect("Error spawning the Discord thread.");
 }
 
 #[cfg(not(feature = "discord"))]
@@ -216,12 +431,18 @@ fn handle_discord(_tx: Sender<GameMessage>) {}
 /// An optional method that spawns the dedicated server
 /// and triggers it to listen for `GameMessage`s.
 #[cfg(feature = "remote_clients")]
-fn handle_server(tx: Sender<GameMessage>) {
-    thread::spawn(move || server_host::init_listener(tx));
+fn handle_server(tx: Sender<GameMessage>) -> Option<JoinHandle<()>> {
+    let handle = thread::Builder::new()
+        .name(String::from("server"))
+        .spawn(move || server_host::init_listener(tx))
+        .expect("Error spawning the server thread.");
+    Some(handle)
 }
 
 #[cfg(not(feature = "remote_clients"))]
-fn handle_server(_tx: Sender<GameMessage>) {}
+fn handle_server(_tx: Sender<GameMessage>) -> Option<JoinHandle<()>> {
+    None
+}
 
 /// The actual contents of messages that will be sent into
 /// the main game thread, containing the actual message
@@ -229,25 +450,44 @@ fn handle_server(_tx: Sender<GameMessage>) {}
 pub struct GameMessage {
     pub message: String,
     pub channel_info: ChannelInfo,
+    pub kind: MessageKind,
+}
+
+/// What the game thread should do with a `GameMessage`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MessageKind {
+    /// Text entered by the sender.
+    Input,
+    /// The sender's client disconnected. These have no
+    /// text and are only sent by the server.
+    Disconnect,
 }
 
 
-/// global commands to be used even when the game is paused.
-fn handle_global_commands(message: &GameMessage, is_running: &mut bool) {
-    match message.message.as_str() {
-        "pause" | "p" => toggle_pause(is_running),
-        "end" | "quit" => process::exit(0),
-        _ => {}
+/// Forwards the message to the global command registry,
+/// allowing these commands to be used even when the game
+/// is paused. Returns whether the message was handled, in
+/// which case it will not be forwarded to the player's
+/// dialogue.
+fn handle_global_commands(message: &GameMessage, is_running: &mut bool) -> bool {
+    let mut split = message.message.split_whitespace();
+    match split.next() {
+        Some(name) => {
+            let args: Vec<&str> = split.collect();
+            global_commands::dispatch(name, message, &args, is_running)
+        }
+        None => false,
     }
 }
 
-/// Pauses or unpauses the game and reports the updated
-/// status to the local output stream.
-fn toggle_pause(is_running: &mut bool) {
-    *is_running = !*is_running;
-    println!("Game is now {}.",
-        if *is_running { "unpaused" } else { "paused" }
-    );
+/// Takes the sender of `message` out of the world until
+/// they return. The server sends these instead of doing
+/// so itself, as players are only removed from this
+/// thread.
+fn handle_disconnect(message: &GameMessage) {
+    if let Some(player) = access::player_meta_sender(&message.channel_info) {
+        player_data::disconnect_player(&player);
+    }
 }
 
 /// Processes game messages sent the main game thread
@@ -255,7 +495,16 @@ fn toggle_pause(is_running: &mut bool) {
 /// forwarding it to `process_options()`.
 fn handle_player_commands(message: &GameMessage) {
     match access::player_meta_sender(&message.channel_info) {
-        Some(player) => process_options(&*player, &message.message),
+        Some(player) => {
+            // Players who left are back once they send anything.
+            // Their old options are gone, so the message itself
+            // only brings them back.
+            if !player.is_active() {
+                player_data::reconnect_player(&player);
+                return;
+            }
+            process_options(&*player, &message.message)
+        }
         None => player_data::new_player_event(message)
     }
 }
@@ -263,6 +512,11 @@ fn handle_player_commands(message: &GameMessage) {
 /// To-do: Update this to potentially send error messages
 /// and ensure that players have dialogue.
 fn process_options(player: &PlayerMeta, input: &str) {
+    // Pressing enter skips to the end of a blocking
+    // message instead of redrawing the screen.
+    if input.trim().is_empty() && player.skip_blocking_message() {
+        return;
+    }
     // Clone references out of the lock to release it
     // and allow it to be reused.
     let matches: Vec<Arc<Dialogue>> = CURRENT_OPTIONS.lock()
@@ -278,15 +532,125 @@ fn process_options(player: &PlayerMeta, input: &str) {
     }
 
     let mut start_at = 1;
+    let mut invalid_number = false;
     for option in matches {
         match option.run(input, player, start_at) {
-            Success => break,
-            NoArgs => return,
+            Success => return,
+            // An empty input redraws the screen.
+            NoArgs => {
+                player.force_refresh_message();
+                return;
+            }
             NoneFound => continue,
             InvalidNumber(max) => {
                 start_at += max;
+                invalid_number = true;
                 continue;
             }
         };
     }
+    // The number was beyond every dialogue's responses.
+    if invalid_number {
+        if start_at == 1 {
+            player.send_short_message("There are no responses to choose from.");
+        } else {
+            player.send_short_message(&format!(
+                "Invalid response. Choose a number from 1 to {}.",
+                start_at - 1
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::timed_events::DelayedEvent;
+
+    fn numbered_dialogue(title: &str, count: usize, player_id: usize) -> Dialogue {
+        let responses = (0..count)
+            .map(|i| Response::_text_only(format!("{} {}", title, i)))
+            .collect();
+        Dialogue::simple(title.to_string(), String::new(), responses, player_id)
+    }
+
+    fn local_message(message: &str) -> Option<GameMessage> {
+        Some(GameMessage {
+            message: message.to_string(),
+            channel_info: Local,
+            kind: MessageKind::Input,
+        })
+    }
+
+    #[test]
+    fn pausing_keeps_remaining_time() {
+        test_init();
+        let _guard = timed_events::test_lock();
+        let fired = Arc::new(Atomic::new(false));
+        let fired2 = fired.clone();
+        DelayedEvent::new_for_flag(60_000, "test_pause", move || fired2.store(true, SeqCst));
+
+        // The tick that pauses the game is still counted.
+        let mut is_running = true;
+        update(None, 29_000, &mut is_running);
+        update(local_message("pause"), 1_000, &mut is_running);
+        assert!(!is_running);
+
+        // Half a minute passes while paused.
+        for _ in 0..30 {
+            update(None, 1_000, &mut is_running);
+        }
+        update(local_message("pause"), 1_000, &mut is_running);
+        assert!(is_running);
+        assert!(!fired.load(SeqCst));
+
+        update(None, 29_999, &mut is_running);
+        assert!(!fired.load(SeqCst));
+        update(None, 1, &mut is_running);
+        assert!(fired.load(SeqCst));
+    }
+
+    #[test]
+    fn time_of_day_wraps_at_midnight() {
+        let _guard = timed_events::test_lock();
+        let start = game_time();
+        let (hour, minute) = time_of_day();
+        let to_midnight = (MINUTES_PER_DAY - (hour * 60 + minute)) * MS_PER_GAME_MINUTE
+            - start % MS_PER_GAME_MINUTE;
+
+        timed_events::test_advance_time(to_midnight - 1);
+        assert_eq!((23, 59), time_of_day());
+        timed_events::test_advance_time(1);
+        assert_eq!((0, 0), time_of_day());
+    }
+
+    #[test]
+    fn disconnects_remove_players_while_paused() {
+        test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(340);
+        let coords = player.get_coordinates();
+        let message = GameMessage {
+            message: String::new(),
+            channel_info: player.get_channel(),
+            kind: MessageKind::Disconnect,
+        };
+
+        let mut is_running = false;
+        update(Some(message), 0, &mut is_running);
+        assert!(!player.is_active());
+        assert!(!access::area(coords, |a| a.contains_entity(player.get_player_id())).unwrap());
+    }
+
+    #[test]
+    fn response_beyond_every_dialogue_is_reported() {
+        let player = player_data::test_player();
+        let id = player.get_player_id();
+        register_options(numbered_dialogue("First", 2, id));
+        register_options(numbered_dialogue("Second", 3, id));
+
+        process_options(&player, "6");
+        assert!(player.get_general_message().contains("Choose a number from 1 to 5."));
+        remove_all_options(id);
+    }
 }
@@ -38,19 +38,34 @@ pub fn temp_send_short_message(id: usize, msg: &str) {
     access::player_meta(id).send_short_message(msg)
 }
 
-/// A function used for sending messages to all players.
+/// A function used for sending messages to all active
+/// players and any observers.
 pub fn send_global_message(msg: &str) {
-    for player in PLAYER_META.lock().iter() {
+    for player in PLAYER_META.lock().iter().filter(|p| p.is_active()) {
         player.send_short_message(msg);
     }
+    #[cfg(feature = "remote_clients")]
+    server_host::send_message_to_observers(msg);
 }
 
-pub fn send_message_to_channel(channel: &ChannelInfo, message: &mut ReusableMessage, ms_speed: u64) -> DelayHandler {
-    separate_messages(channel);
+/// Sends `message`, splitting it into sections at each
+/// `∫`. Sections are scheduled under `flag` so that they
+/// can be found and sent early. Immediate messages are
+/// skipped when they would display exactly what was last
+/// sent; paced messages are always sent.
+pub fn send_message_to_channel(channel: &ChannelInfo, message: &mut ReusableMessage, ms_speed: u64, flag: &str) -> DelayHandler {
+    let formatted = message.format();
 
     if ms_speed == 0 {
-        return single_message(channel, message);
+        if formatted == message.last_sent {
+            return DelayHandler::new(0);
+        }
+        message.last_sent = formatted;
+        separate_messages(channel);
+        return single_message(channel, &message.last_sent);
     }
+    message.last_sent = formatted;
+    separate_messages(channel);
 
     lazy_static! {
         static ref speed_pattern: Regex = Regex::new(r"^(\d{1,2}(\.\d{1,2})?)?").unwrap();
@@ -62,7 +77,7 @@ pub fn send_message_to_channel(channel: &ChannelInfo, message: &mut ReusableMess
     if general.len() > 0 {
         let mut iter = general.split("∫");
 
-        schedule_message(channel, &iter.next().unwrap().to_string(), delay_ms);
+        schedule_message(channel, &iter.next().unwrap().to_string(), delay_ms, flag);
 
         for mut part in iter {
             let find = speed_pattern.find(part);
@@ -75,7 +90,7 @@ pub fn send_message_to_channel(channel: &ChannelInfo, message: &mut ReusableMess
                 part = &part[num..];
             }
             delay_ms += (ms_speed as f32 * multiplier) as u64;
-            schedule_message(channel, &part.to_string(), delay_ms);
+            schedule_message(channel, &part.to_string(), delay_ms, flag);
         }
     }
 
@@ -92,7 +107,7 @@ pub fn send_message_to_channel(channel: &ChannelInfo, message: &mut ReusableMess
         correct_server_spacing(channel, &mut main_info);
         main_info += "\n";
         delay_ms += ms_speed;
-        schedule_message(channel, &main_info, delay_ms);
+        schedule_message(channel, &main_info, delay_ms, flag);
     }
 
     DelayHandler::new(delay_ms)
@@ -108,33 +123,35 @@ fn correct_server_spacing(channel: &ChannelInfo, msg: &mut String) {
 #[cfg(not(feature = "remote_clients"))]
 fn correct_server_spacing(_channel: &ChannelInfo, _msg: &mut String) {}
 
-fn single_message(channel: &ChannelInfo, message: &ReusableMessage) -> DelayHandler {
+fn single_message(channel: &ChannelInfo, message: &str) -> DelayHandler {
+    let formatted = channel.get_encoding().encode(message);
+
     match channel {
-        Local => println!("{}", message.format()),
+        Local => println!("{}", formatted),
         #[cfg(feature = "remote_clients")]
         Remote(ref username) => {
-            server_host::send_message_to_client(username, &(message.format() + "\n\n"));
+            server_host::send_message_to_client(username, &(formatted + "\n\n"));
         }
         // Calls a rudimentary function that just
         // determines whether to edit a previous
         // message or send a new one.
         #[cfg(feature = "discord")]
         Discord(channel_id, user_id) => {
-            discord_bot::handle_discord_message(channel_id, user_id, &message.format());
+            discord_bot::handle_discord_message(channel_id, user_id, &formatted);
         }
     };
     DelayHandler::new(0)
 }
 
 // Same as single message, but uses DelayedEvents.
-fn schedule_message(channel: &ChannelInfo, message: &str, delay_ms: u64) {
-    let owned = message.to_string();
+fn schedule_message(channel: &ChannelInfo, message: &str, delay_ms: u64, flag: &str) {
+    let owned = channel.get_encoding().encode(message);
 
     match channel {
         // Manually flush the output to allow for
         // better control over message formatting.
         Local => {
-            DelayedEvent::no_flags(delay_ms, move || {
+            DelayedEvent::new_for_flag(delay_ms, flag, move || {
                 io::stdout().write(owned.as_bytes()).unwrap();
                 io::stdout().flush().unwrap();
             });
@@ -142,14 +159,17 @@ fn schedule_message(channel: &ChannelInfo, message: &str, delay_ms: u64) {
         #[cfg(feature = "remote_clients")]
         Remote(ref username) => {
             let user_owned = username.clone();
-            DelayedEvent::no_flags(delay_ms, move || {
+            DelayedEvent::new_for_flag(delay_ms, flag, move || {
                 server_host::send_message_to_client(&user_owned, &owned);
             });
         }
         #[cfg(feature = "discord")]
         Discord(channel_id, user_id) => {
-            DelayedEvent::no_flags(delay_ms, move || {
-                discord_bot::handle_discord_message(channel_id, user_id, &owned);
+            // Copy the ids so that the event doesn't borrow
+            // from the player's channel.
+            let (channel_id, user_id) = (*channel_id, *user_id);
+            DelayedEvent::new_for_flag(delay_ms, flag, move || {
+                discord_bot::handle_discord_message(&channel_id, &user_id, &owned);
             });
         }
     };
@@ -202,6 +222,8 @@ pub struct ReusableMessage {
     pub general: Vec<String>,
     pub options: String,
     pub last_input: String, //Not ready for use.
+    /// The formatted contents as they were last sent.
+    last_sent: String,
 }
 
 impl ReusableMessage {
@@ -211,6 +233,7 @@ impl ReusableMessage {
             general: Vec::new(),
             options: String::new(),
             last_input: String::new(),
+            last_sent: String::new(),
         }
     }
 
@@ -233,7 +256,7 @@ impl ReusableMessage {
         ret
     }
 
-    pub fn add_to_general(&mut self, length: usize, mut message: String) {
+    pub fn add_to_general(&mut self, length: usize, max_messages: usize, mut message: String) {
         // This might be redundant. Need to verify.
         if message.starts_with("§") {
             message = text::auto_break(0, length, &message[2..]);
@@ -244,12 +267,30 @@ impl ReusableMessage {
                 self.general.clear();
             }
         }
-        if self.general.len() >= MAX_SHORT_MESSAGES {
+        while self.general.len() >= max_messages {
             self.general.remove(0);
         }
         self.general.push(message);
     }
 
+    /// Removes every section, leaving nothing to display.
+    pub fn clear(&mut self) {
+        self.health_bar.clear();
+        self.general.clear();
+        self.options.clear();
+        self.last_input.clear();
+    }
+
+    pub fn clear_general(&mut self) {
+        self.general.clear();
+    }
+
+    /// Ensures that the next message gets sent, even if
+    /// its contents haven't changed.
+    pub fn force_next_send(&mut self) {
+        self.last_sent.clear();
+    }
+
     pub fn format(&self) -> String {
         lazy_static! {
             static ref full_speed_pattern: Regex =
@@ -302,3 +343,129 @@ pub enum ChannelInfo {
     #[cfg(feature = "discord")]
     Discord(ChannelId, UserId),
 }
+
+impl ChannelInfo {
+    /// The type of output this channel's client is able
+    /// to display correctly.
+    pub fn get_encoding(&self) -> Encoding {
+        match self {
+            Local => Encoding::Terminal,
+            // Remote clients may be connected through any
+            // kind of terminal. Stick to the basics.
+            #[cfg(feature = "remote_clients")]
+            Remote(_) => Encoding::Ascii,
+            #[cfg(feature = "discord")]
+            Discord(_, _) => Encoding::Rich,
+        }
+    }
+}
+
+/// Determines how text is rendered immediately before
+/// being sent to a client. Any formatting markers (`§`
+/// and `∫`) should already have been processed by this
+/// point; leftovers are removed where they can't be
+/// displayed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Encoding {
+    /// Unicode text with all formatting markers removed.
+    Terminal,
+    /// Terminal output converted to plain ASCII.
+    Ascii,
+    /// The text is left exactly as-is.
+    Rich,
+}
+
+impl Encoding {
+    pub fn encode(self, text: &str) -> String {
+        match self {
+            Encoding::Terminal => strip_markers(text),
+            Encoding::Ascii => to_ascii(&strip_markers(text)),
+            Encoding::Rich => text.to_string(),
+        }
+    }
+}
+
+fn strip_markers(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '§' && *c != '∫')
+        .collect()
+}
+
+/// Substitutes each of the game's non-ASCII characters
+/// with something similar. Any others become `?`.
+fn to_ascii(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if c.is_ascii() {
+            ret.push(c);
+            continue;
+        }
+        match c {
+            '∞' => ret += "inf",
+            '·' => ret.push('.'),
+            'á' | 'à' | 'â' | 'ä' => ret.push('a'),
+            'é' | 'è' | 'ê' | 'ë' => ret.push('e'),
+            'í' | 'ì' | 'î' | 'ï' => ret.push('i'),
+            'ó' | 'ò' | 'ô' | 'ö' => ret.push('o'),
+            'ú' | 'ù' | 'û' | 'ü' => ret.push('u'),
+            'Á' | 'À' | 'Â' | 'Ä' => ret.push('A'),
+            'É' | 'È' | 'Ê' | 'Ë' => ret.push('E'),
+            'Í' | 'Ì' | 'Î' | 'Ï' => ret.push('I'),
+            'Ó' | 'Ò' | 'Ô' | 'Ö' => ret.push('O'),
+            'Ú' | 'Ù' | 'Û' | 'Ü' => ret.push('U'),
+            _ => ret.push('?'),
+        };
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "§Durability: ∞ · Café∫";
+
+    #[test]
+    fn terminals_only_lose_markers() {
+        assert_eq!("Durability: ∞ · Café", Encoding::Terminal.encode(MESSAGE));
+        assert_eq!(Encoding::Terminal, Local.get_encoding());
+    }
+
+    #[test]
+    fn ascii_clients_get_substitutions() {
+        assert_eq!("Durability: inf . Cafe", Encoding::Ascii.encode(MESSAGE));
+        assert_eq!("?", Encoding::Ascii.encode("★"));
+    }
+
+    #[test]
+    fn rich_clients_are_unchanged() {
+        assert_eq!(MESSAGE, Encoding::Rich.encode(MESSAGE));
+    }
+
+    #[test]
+    fn cleared_messages_render_nothing() {
+        let mut message = ReusableMessage::new();
+        message.health_bar = String::from("HP: 10/10\n");
+        message.set_general(40, "You found a sword.");
+        message.add_to_general(40, 5, String::from("* Hello.\n"));
+        message.options = String::from("1. Leave");
+        message.last_input = String::from("1");
+        assert!(!message.format().is_empty());
+
+        message.clear_general();
+        assert!(message.get_general().is_empty());
+        assert!(!message.format().is_empty());
+
+        message.clear();
+        assert!(message.format().is_empty());
+        assert!(message.last_input.is_empty());
+    }
+
+    #[cfg(feature = "remote_clients")]
+    #[test]
+    fn remote_clients_use_ascii() {
+        let channel = Remote(String::from("test"));
+        assert_eq!(Encoding::Ascii, channel.get_encoding());
+    }
+}
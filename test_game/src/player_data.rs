@@ -1,9 +1,12 @@
 use crate::messages::{ChannelInfo, ReusableMessage};
-use crate::util::timed_events::DelayHandler;
+use crate::util::timed_events::{self, DelayHandler, RepeatHandler, RepeatedEvent};
 use crate::types::classes::Class::{self, *};
 use crate::messages::MessageComponent::*;
 use crate::util::access::EntityAccessor;
 use crate::traits::{Area, Entity};
+use crate::types::effects::Effect;
+use crate::types::entities::players::Player;
+use crate::types::items::item_settings::ItemSave;
 use crate::types::towns::Town;
 use crate::util::access;
 use crate::GameMessage;
@@ -14,10 +17,12 @@ use atomic::Atomic;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
-use rand::random;
+use rand::{random, thread_rng, Rng};
 
 use std::cmp::Ordering::{self, *};
+use std::collections::VecDeque;
 use std::sync::atomic::Ordering::*;
+use std::str::FromStr;
 use std::sync::Arc;
 
 /// Player registry is stored in a mutex so that only the game thread
@@ -36,6 +41,85 @@ pub type AreaRecords = HashMap<(usize, usize, usize), HashMap<&'static str, u8>>
 
 lazy_static! {
     pub static ref PLAYER_META: Mutex<PlayerRegistry> = Mutex::new(Vec::new());
+
+    /// The entities of players whose clients disconnected,
+    /// held by player ID until they return.
+    static ref DISCONNECTED: Mutex<HashMap<usize, Box<Entity>>> = Mutex::new(HashMap::new());
+}
+
+/// Ambient text is checked for at a random interval
+/// within this range (ms), chosen per area visited.
+const MIN_AMBIENCE_INTERVAL: u64 = 45_000;
+const MAX_AMBIENCE_INTERVAL: u64 = 90_000;
+
+/// The number of screens a player can go back through.
+const MAX_BACK_DEPTH: usize = 5;
+
+/// The number of hits remembered in each combat log.
+const MAX_COMBAT_EVENTS: usize = 10;
+
+/// Sent to players who return when there is no room for
+/// them. They try again the next time they send anything.
+const WORLD_IS_FULL: &str = "The world is full right now. Please try again later.";
+
+/// The chance of ambient text being shown per interval.
+const AMBIENCE_CHANCE: f32 = 0.5;
+
+/// Ambient text stops if the player stays idle long
+/// enough to exceed this duration.
+const AMBIENCE_DURATION: u64 = 3_600_000; // 1 hour
+
+/// The name given to players until they finish
+/// creating their character.
+pub const DEFAULT_NAME: &str = "New Player";
+
+/// The god of players who haven't chosen one.
+pub const DEFAULT_GOD: &str = "Godless heathen";
+
+/// Limits on the length of a player's name, in chars.
+/// These match the client's limits for usernames.
+pub const MIN_NAME_LENGTH: usize = 3;
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// Checks that `name` can be used by a player who is
+/// renaming themselves.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let len = name.chars().count();
+    if !(MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&len) {
+        return Err(format!("Names must be between {} and {} characters long.", MIN_NAME_LENGTH, MAX_NAME_LENGTH));
+    }
+    if name.contains('§') || name.contains('∫') {
+        return Err(String::from("Names can't contain § or ∫."));
+    }
+    if name == DEFAULT_NAME {
+        return Err(String::from("You'll need a more memorable name than that."));
+    }
+    Ok(())
+}
+
+/// Whether any player other than `player_id` already
+/// goes by `name`, ignoring case.
+pub fn is_name_taken(name: &str, player_id: usize) -> bool {
+    let name = name.to_lowercase();
+    PLAYER_META.lock()
+        .iter()
+        .any(|p| p.get_player_id() != player_id && p.get_name().to_lowercase() == name)
+}
+
+/// Makes every player forget the name of the entity with
+/// `entity_id`, e.g. after it changes. They will have to
+/// learn it again.
+pub fn forget_name_for_all(entity_id: usize) {
+    // Clone references out of the registry first so that
+    // it isn't locked alongside anyone's knowledge.
+    let players: Vec<Arc<PlayerMeta>> = PLAYER_META.lock()
+        .iter()
+        .cloned()
+        .collect();
+
+    for player in players {
+        player.forget_name(entity_id);
+    }
 }
 
 /// ##To-do:
@@ -43,6 +127,158 @@ lazy_static! {
 /// from the disk.
 pub fn setup_player_registry() {}
 
+/// Preferences that each player can change through the
+/// `settings` dialogue. Stored together so that they can
+/// be saved and loaded as a single blob.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PlayerSettings {
+    pub text_speed: u64,
+    pub text_length: usize,
+    pub max_short_messages: usize,
+    pub show_map: bool,
+    pub show_clock: bool,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> PlayerSettings {
+        PlayerSettings {
+            text_speed: TEXT_SPEED,
+            text_length: LINE_LENGTH,
+            max_short_messages: MAX_SHORT_MESSAGES,
+            show_map: true,
+            show_clock: true,
+        }
+    }
+}
+
+impl PlayerSettings {
+    /// Formats the settings as `key=value` pairs, i.e.
+    /// `text_speed=2500;text_length=40;...`.
+    pub fn to_save_string(&self) -> String {
+        format!(
+            "text_speed={};text_length={};max_short_messages={};show_map={};show_clock={}",
+            self.text_speed, self.text_length, self.max_short_messages, self.show_map, self.show_clock
+        )
+    }
+
+    /// Reads the output of `to_save_string()`. Missing or
+    /// unreadable values keep their defaults, so older save
+    /// data can still be loaded.
+    pub fn from_save_string(blob: &str) -> PlayerSettings {
+        let mut settings = PlayerSettings::default();
+
+        for pair in blob.split(';') {
+            let mut split = pair.splitn(2, '=');
+            let (key, value) = match (split.next(), split.next()) {
+                (Some(k), Some(v)) => (k.trim(), v.trim()),
+                _ => continue,
+            };
+            match key {
+                "text_speed" => read_setting(value, &mut settings.text_speed),
+                "text_length" => read_setting(value, &mut settings.text_length),
+                "max_short_messages" => read_setting(value, &mut settings.max_short_messages),
+                "show_map" => read_setting(value, &mut settings.show_map),
+                "show_clock" => read_setting(value, &mut settings.show_clock),
+                _ => warn!("Ignoring unknown player setting: {}.", key),
+            }
+        }
+        settings.text_length = settings.text_length.max(MIN_LINE_LENGTH);
+        settings.max_short_messages = settings.max_short_messages.max(1);
+        settings
+    }
+}
+
+fn read_setting<T: FromStr>(value: &str, setting: &mut T) {
+    if let Ok(v) = value.parse() {
+        *setting = v;
+    }
+}
+
+/// A single hit, as shown by the `log` command.
+#[derive(Clone)]
+pub struct CombatEvent {
+    pub attacker: String,
+    pub target: String,
+    pub amount: u32,
+    pub time: u64,
+}
+
+impl CombatEvent {
+    pub fn new(attacker: &str, target: &str, amount: u32) -> CombatEvent {
+        CombatEvent {
+            attacker: attacker.to_string(),
+            target: target.to_string(),
+            amount,
+            time: game_time(),
+        }
+    }
+}
+
+/// Adds `event` to the combat log of each player that
+/// was involved. This locks the player registry, so it
+/// should be called after any areas are released.
+pub fn record_combat(attacker_id: Option<usize>, target_id: usize, event: CombatEvent) {
+    for id in attacker_id.iter().chain(Some(target_id).iter()) {
+        if let Some(player) = find_player(*id) {
+            player.record_combat(event.clone());
+        }
+    }
+}
+
+/// A record of everything needed to rebuild a player
+/// with `restore_player()`. Other entities only fill in
+/// the fields common to all entities.
+#[derive(Clone)]
+pub struct PlayerSave {
+    pub player_id: usize,
+    pub name: String,
+    pub god: String,
+    pub class: Class,
+    pub settings: PlayerSettings,
+    pub coordinates: (usize, usize, usize),
+    pub health: u32,
+    pub health_bonus: u32,
+    pub max_health: u32,
+    pub base_damage: u32,
+    pub defense: u32,
+    pub attack_speed: i32,
+    pub item_speed: i32,
+    pub money: u32,
+    pub effects: Vec<Effect>,
+    pub inventory_size: usize,
+    pub items: Vec<ItemSave>,
+    pub weapon: Option<ItemSave>,
+    pub offhand: Option<ItemSave>,
+    pub armor: Option<ItemSave>,
+}
+
+impl Default for PlayerSave {
+    fn default() -> PlayerSave {
+        PlayerSave {
+            player_id: 0,
+            name: String::from(DEFAULT_NAME),
+            god: String::from(DEFAULT_GOD),
+            class: Melee,
+            settings: PlayerSettings::default(),
+            coordinates: (0, 0, 0),
+            health: 0,
+            health_bonus: 0,
+            max_health: 0,
+            base_damage: 0,
+            defense: 0,
+            attack_speed: 0,
+            item_speed: 0,
+            money: 0,
+            effects: Vec::new(),
+            inventory_size: 0,
+            items: Vec::new(),
+            weapon: None,
+            offhand: None,
+            armor: None,
+        }
+    }
+}
+
 pub struct PlayerMeta {
     channel: Mutex<ChannelInfo>,
     player_id: usize,
@@ -54,8 +290,14 @@ pub struct PlayerMeta {
     class: Atomic<Class>,
     active: Atomic<bool>,
     reusable_message: Mutex<ReusableMessage>,
-    text_speed: Atomic<u64>,
-    text_length: Atomic<usize>
+    settings: Atomic<PlayerSettings>,
+    send_pending: Atomic<bool>,
+    /// The event which ends the player's most recent
+    /// blocking message. See `skip_blocking_message()`.
+    blocking_message: Mutex<Option<usize>>,
+    ambience: Mutex<Option<RepeatHandler>>,
+    previous_dialogues: Mutex<Vec<Regenerator>>,
+    combat_log: Mutex<VecDeque<CombatEvent>>,
 }
 
 impl PlayerMeta {
@@ -64,6 +306,14 @@ impl PlayerMeta {
         self._send(0);
     }
 
+    /// Variant of `refresh_message()` which re-sends the
+    /// message even if nothing has changed since it was
+    /// last sent, e.g. to redraw the player's screen.
+    pub fn force_refresh_message(&self) {
+        self.reusable_message.lock().force_next_send();
+        self._send(0);
+    }
+
     /// Standard dialogue to the player. Returns a DelayHandler
     /// for spawning new events upon completion.
     pub fn send_message(&self, typ: MessageComponent, msg: &str) -> DelayHandler {
@@ -71,10 +321,9 @@ impl PlayerMeta {
         self._send(self.get_text_speed())
     }
 
-    /// Sends an immediate message. Currently allows up to 3
-    /// short messages to be displayed at once. In the future,
-    /// this will be stored as a setting that each player can
-    /// choose.
+    /// Sends an immediate message. The number of short
+    /// messages displayed at once is chosen by the player
+    /// through their settings.
     pub fn send_short_message(&self, msg: &str) {
         self.add_short_message(msg);
         self._send(0);
@@ -91,16 +340,41 @@ impl PlayerMeta {
         register_options(empty);
 
         let handler = self.send_message(General, msg);
-        handler.clone().then(move || {
+        let event_id = handler.clone().then(move || {
             delete_options(empty_id);
             for dialogue in dialogues {
                 _register_options(dialogue);
             }
             temp_update_options(player_id);
         });
+        *self.blocking_message.lock() = Some(event_id);
         handler
     }
 
+    /// Cuts the player's blocking message short, sending
+    /// the rest of it and restoring their options right
+    /// away. Returns whether there was anything to skip.
+    /// Must not be called from inside of an event.
+    pub fn skip_blocking_message(&self) -> bool {
+        let event_id = match self.blocking_message.lock().take() {
+            Some(id) => id,
+            None => return false,
+        };
+        // The message already finished on its own.
+        let restore = match timed_events::delete_event(event_id) {
+            Some(event) => event,
+            None => return false,
+        };
+        // Later messages share the flag; leave them be.
+        let mut sections = timed_events::delete_flag_until(&self.message_flag(), restore.min_exe_time());
+        sections.sort_by_key(|e| e.min_exe_time());
+        for section in sections {
+            section.run();
+        }
+        restore.run();
+        true
+    }
+
     pub fn send_current_options(&self) {
         let options_text = get_options_text(self.get_player_id());
         self.update_message(Options, &options_text);
@@ -145,6 +419,18 @@ impl PlayerMeta {
         };
     }
 
+    /// Discards everything the player is currently seeing.
+    /// Does not update immediately.
+    pub fn clear_message(&self) {
+        self.reusable_message.lock().clear();
+    }
+
+    /// Discards the player's dialogue and short messages
+    /// so that they don't carry over into a new area.
+    pub fn clear_general(&self) {
+        self.reusable_message.lock().clear_general();
+    }
+
     /// Send a short message to the player. Does not update
     /// immediately. Use this to avoid repeatedly refreshing
     /// the text.
@@ -154,11 +440,78 @@ impl PlayerMeta {
         } else {
             format!("* {}\n", msg)
         };
-        self.reusable_message.lock().add_to_general(self.get_text_length(), fmt);
+        let settings = self.get_settings();
+        self.reusable_message.lock().add_to_general(settings.text_length, settings.max_short_messages, fmt);
+    }
+
+    /// The dialogue and short messages the player is
+    /// currently seeing, for checking what they were told.
+    #[cfg(test)]
+    pub fn get_general_message(&self) -> String {
+        self.reusable_message.lock().get_general()
     }
 
+    /// Immediate sends are not transmitted right away.
+    /// Instead, they are coalesced and sent once at the
+    /// end of the current tick by `flush_messages()`.
     fn _send(&self, ms_speed: u64) -> DelayHandler {
-        messages::send_message_to_channel(&self.channel.lock(), &mut *self.reusable_message.lock(), ms_speed)
+        if ms_speed == 0 {
+            self.send_pending.store(true, SeqCst);
+            return DelayHandler::new(0);
+        }
+        self._send_now(ms_speed)
+    }
+
+    fn _send_now(&self, ms_speed: u64) -> DelayHandler {
+        let flag = self.message_flag();
+        messages::send_message_to_channel(&self.channel.lock(), &mut *self.reusable_message.lock(), ms_speed, &flag)
+    }
+
+    /// Marks the events which send sections of this
+    /// player's messages.
+    fn message_flag(&self) -> String {
+        format!("message_{}", self.player_id)
+    }
+
+    /// Begins occasionally showing the ambient messages of
+    /// the area at `coords`, replacing those of the previous
+    /// area. Each player uses a random interval so that they
+    /// don't all receive ambient text at the same time.
+    pub fn start_ambience(&self, coords: (usize, usize, usize)) {
+        self.stop_ambience();
+
+        let has_messages = access::area(coords, |a| !a.ambient_messages().is_empty())
+            .unwrap_or(false);
+
+        if !has_messages {
+            return;
+        }
+        let player_id = self.player_id;
+        let interval = thread_rng().gen_range(MIN_AMBIENCE_INTERVAL, MAX_AMBIENCE_INTERVAL);
+
+        let handler = RepeatedEvent::no_flags(interval, AMBIENCE_DURATION, move || {
+            let player = match find_player(player_id) {
+                Some(p) => p,
+                None => return false,
+            };
+            if player.get_coordinates() != coords {
+                return false;
+            }
+            if random::<f32>() < AMBIENCE_CHANCE {
+                let line = access::area(coords, |a| text::choose_text(a.ambient_messages()));
+                if let Some(Some(line)) = line {
+                    player.send_short_message(&line);
+                }
+            }
+            true
+        });
+        *self.ambience.lock() = Some(handler);
+    }
+
+    pub fn stop_ambience(&self) {
+        if let Some(handler) = self.ambience.lock().take() {
+            handler.cancel();
+        }
     }
 
     /// Used for retrieving the actual entity controlled by the
@@ -217,6 +570,14 @@ impl PlayerMeta {
         self.area_records.lock().contains_key(&area)
     }
 
+    /// Whether the player has entered any area in the
+    /// given town.
+    pub fn has_visited_town(&self, town_num: usize) -> bool {
+        self.area_records.lock()
+            .keys()
+            .any(|coords| coords.0 == town_num)
+    }
+
     pub fn add_record_book(&self, area: (usize, usize, usize)) {
         self.area_records.lock().insert(area, HashMap::new());
     }
@@ -280,6 +641,45 @@ impl PlayerMeta {
             .is_ok()
     }
 
+    /// Records that the player has learned this entity's
+    /// real name, e.g. after being introduced to them.
+    pub fn learn_name(&self, entity_id: usize) {
+        self.add_entity_knowledge(entity_id);
+
+        let mut knowledge = self.entity_knowledge.lock();
+        if let Ok(index) = knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id)) {
+            knowledge[index].knows_name = true;
+        }
+    }
+
+    /// Variant of `learn_name()` which marks the name
+    /// as unknown again.
+    pub fn forget_name(&self, entity_id: usize) {
+        let mut knowledge = self.entity_knowledge.lock();
+        if let Ok(index) = knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id)) {
+            knowledge[index].knows_name = false;
+        }
+    }
+
+    pub fn knows_name(&self, entity_id: usize) -> bool {
+        let knowledge = self.entity_knowledge.lock();
+        knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id))
+            .ok()
+            .map_or(false, |index| knowledge[index].knows_name)
+    }
+
+    /// Records how far the player has progressed through
+    /// this entity's dialogue. A knowledge container will
+    /// be created if the player has never met the entity.
+    pub fn set_dialogue_marker(&self, entity_id: usize, marker: u8) {
+        self.add_entity_knowledge(entity_id);
+
+        let mut knowledge = self.entity_knowledge.lock();
+        if let Ok(index) = knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id)) {
+            knowledge[index].dialogue_marker = marker;
+        }
+    }
+
     pub fn get_dialogue_marker(&self, entity_id: usize) -> Option<u8> {
         let knowledge = self.entity_knowledge.lock();
         knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id))
@@ -290,6 +690,24 @@ impl PlayerMeta {
             })
     }
 
+    /// Records that the player waved to this entity just
+    /// now. See `get_last_wave()`.
+    pub fn set_last_wave(&self, entity_id: usize) {
+        self.add_entity_knowledge(entity_id);
+
+        let mut knowledge = self.entity_knowledge.lock();
+        if let Ok(index) = knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id)) {
+            knowledge[index].last_wave = Some(game_time());
+        }
+    }
+
+    pub fn get_last_wave(&self, entity_id: usize) -> Option<u64> {
+        let knowledge = self.entity_knowledge.lock();
+        knowledge.binary_search_by(|e| e.entity_id.cmp(&entity_id))
+            .ok()
+            .and_then(|index| knowledge[index].last_wave)
+    }
+
     pub fn set_name(&self, name: String) {
         *self.name.lock() = name;
     }
@@ -300,6 +718,45 @@ impl PlayerMeta {
         self.name.lock().clone()
     }
 
+    /// Adds a hit to this player's combat log, forgetting
+    /// the oldest once there are `MAX_COMBAT_EVENTS`.
+    pub fn record_combat(&self, event: CombatEvent) {
+        let mut log = self.combat_log.lock();
+        if log.len() >= MAX_COMBAT_EVENTS {
+            log.pop_front();
+        }
+        log.push_back(event);
+    }
+
+    /// Returns a copy of this player's combat log,
+    /// oldest first.
+    pub fn get_combat_log(&self) -> Vec<CombatEvent> {
+        self.combat_log.lock().iter().cloned().collect()
+    }
+
+    /// Remembers a previous screen so that the player can
+    /// return to it. Only the most recent `MAX_BACK_DEPTH`
+    /// screens are kept.
+    pub fn push_previous_dialogue(&self, regenerator: Regenerator) {
+        let mut previous = self.previous_dialogues.lock();
+        if previous.len() >= MAX_BACK_DEPTH {
+            previous.remove(0);
+        }
+        previous.push(regenerator);
+    }
+
+    pub fn pop_previous_dialogue(&self) -> Option<Regenerator> {
+        self.previous_dialogues.lock().pop()
+    }
+
+    pub fn has_previous_dialogue(&self) -> bool {
+        !self.previous_dialogues.lock().is_empty()
+    }
+
+    pub fn clear_previous_dialogues(&self) {
+        self.previous_dialogues.lock().clear();
+    }
+
     pub fn set_god(&self, god: String) {
         *self.god.lock() = god;
     }
@@ -324,20 +781,100 @@ impl PlayerMeta {
         self.active.load(SeqCst)
     }
 
+    /// Whether the player has finished creating their
+    /// character and chosen a name.
+    pub fn has_chosen_name(&self) -> bool {
+        *self.name.lock() != DEFAULT_NAME
+    }
+
+    pub fn set_settings(&self, settings: PlayerSettings) {
+        self.settings.store(settings, SeqCst);
+    }
+
+    pub fn get_settings(&self) -> PlayerSettings {
+        self.settings.load(SeqCst)
+    }
+
+    /// Applies `update` to a copy of the player's settings
+    /// and stores the result.
+    fn update_settings<F: FnOnce(&mut PlayerSettings)>(&self, update: F) {
+        let mut settings = self.get_settings();
+        update(&mut settings);
+        self.set_settings(settings);
+    }
+
+    /// Serializes every setting into a single string.
+    pub fn save_settings(&self) -> String {
+        self.get_settings().to_save_string()
+    }
+
+    /// Replaces the player's settings with those read
+    /// from `save_settings()`.
+    pub fn load_settings(&self, blob: &str) {
+        self.set_settings(PlayerSettings::from_save_string(blob));
+    }
+
     pub fn set_text_speed(&self, val: u64) {
-        self.text_speed.store(val, SeqCst);
+        self.update_settings(|s| s.text_speed = val);
     }
 
     pub fn get_text_speed(&self) -> u64 {
-        self.text_speed.load(SeqCst)
+        self.get_settings().text_speed
     }
 
+    /// Narrower lines can't reliably be broken up, so
+    /// this will never go below `MIN_LINE_LENGTH`.
     pub fn set_text_length(&self, val: usize) {
-        self.text_length.store(val, SeqCst);
+        self.update_settings(|s| s.text_length = val.max(MIN_LINE_LENGTH));
     }
 
     pub fn get_text_length(&self) -> usize {
-        self.text_length.load(SeqCst)
+        self.get_settings().text_length
+    }
+
+    /// The player will always be able to see at least
+    /// one short message.
+    pub fn set_max_short_messages(&self, val: usize) {
+        self.update_settings(|s| s.max_short_messages = val.max(1));
+    }
+
+    pub fn get_max_short_messages(&self) -> usize {
+        self.get_settings().max_short_messages
+    }
+
+    pub fn set_show_map(&self, b: bool) {
+        self.update_settings(|s| s.show_map = b);
+    }
+
+    /// Whether the town map is displayed with the
+    /// player's area dialogue.
+    pub fn shows_map(&self) -> bool {
+        self.get_settings().show_map
+    }
+
+    pub fn set_show_clock(&self, b: bool) {
+        self.update_settings(|s| s.show_clock = b);
+    }
+
+    /// Whether the time of day is displayed with the
+    /// player's area dialogue.
+    pub fn shows_clock(&self) -> bool {
+        self.get_settings().show_clock
+    }
+}
+
+/// Sends the latest message to every player who
+/// requested an update during the current tick.
+pub fn flush_messages() {
+    // Clone references out of the lock before sending.
+    let pending: Vec<Arc<PlayerMeta>> = PLAYER_META.lock()
+        .iter()
+        .filter(|p| p.send_pending.swap(false, SeqCst))
+        .map(|p| p.clone())
+        .collect();
+
+    for player in pending {
+        player._send_now(0);
     }
 }
 
@@ -348,13 +885,17 @@ pub fn new_player_event(message: &GameMessage) {
         coordinates: Atomic::new((0, 0, 0)),
         area_records: Mutex::new(HashMap::new()),
         entity_knowledge: Mutex::new(Vec::new()),
-        name: Mutex::new(String::from("New Player")),
-        god: Mutex::new(String::from("Godless heathen")),
+        name: Mutex::new(String::from(DEFAULT_NAME)),
+        god: Mutex::new(String::from(DEFAULT_GOD)),
         class: Atomic::new(Melee),
         active: Atomic::new(true),
         reusable_message: Mutex::new(ReusableMessage::new()),
-        text_speed: Atomic::new(TEXT_SPEED),
-        text_length: Atomic::new(LINE_LENGTH)
+        settings: Atomic::new(PlayerSettings::default()),
+        send_pending: Atomic::new(false),
+        blocking_message: Mutex::new(None),
+        ambience: Mutex::new(None),
+        previous_dialogues: Mutex::new(Vec::new()),
+        combat_log: Mutex::new(VecDeque::new()),
     };
     let id = new.player_id;
     register_options(text::new_player_name(id));
@@ -364,16 +905,179 @@ pub fn new_player_event(message: &GameMessage) {
     registered.send_blocking_message(&text::rand_new_sender());
 }
 
+/// Rebuilds a player from `save`, sending its messages
+/// to `channel`. The player is returned to their saved
+/// area, or to the starting area of the same town if it
+/// no longer exists or has no room.
+pub fn restore_player(save: &PlayerSave, channel: ChannelInfo) -> Arc<PlayerMeta> {
+    let meta = PlayerMeta {
+        channel: Mutex::new(channel),
+        player_id: save.player_id,
+        coordinates: Atomic::new(save.coordinates),
+        area_records: Mutex::new(HashMap::new()),
+        entity_knowledge: Mutex::new(Vec::new()),
+        name: Mutex::new(save.name.clone()),
+        god: Mutex::new(save.god.clone()),
+        class: Atomic::new(save.class),
+        active: Atomic::new(true),
+        reusable_message: Mutex::new(ReusableMessage::new()),
+        settings: Atomic::new(save.settings),
+        send_pending: Atomic::new(false),
+        blocking_message: Mutex::new(None),
+        ambience: Mutex::new(None),
+        previous_dialogues: Mutex::new(Vec::new()),
+        combat_log: Mutex::new(VecDeque::new()),
+    };
+    register_player_meta(meta);
+    let registered = access::player_meta(save.player_id);
+    let entity: Box<Entity> = Box::new(Player::from_save(registered.clone(), save));
+
+    match place_player(&registered, entity) {
+        Ok(_) => registered.get_send_area_options(),
+        Err(entity) => wait_for_room(&registered, entity),
+    }
+    registered
+}
+
+/// Adds the player's entity back into their current area,
+/// or to the starting area of the same town if it no
+/// longer exists or has no room. The entity is returned
+/// if neither area has room for it.
+fn place_player(player: &PlayerMeta, entity: Box<Entity>) -> Result<(), Box<Entity>> {
+    let coords = player.get_coordinates();
+    let entity = if access::area_exists(coords) {
+        access::area(coords, |area| area.add_entity(entity))
+            .expect("Area was removed while placing a player.")
+    } else {
+        Err(entity)
+    };
+
+    match entity {
+        // `add_entity()` updates the player's coordinates.
+        Err(entity) => access::starting_area(coords.0, |area| area.add_entity(entity)),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Holds the entity of a player who couldn't be placed
+/// in the world until they send another message.
+fn wait_for_room(player: &PlayerMeta, entity: Box<Entity>) {
+    player.set_active(false);
+    DISCONNECTED.lock().insert(player.get_player_id(), entity);
+    player.send_short_message(WORLD_IS_FULL);
+}
+
+/// Takes the player out of the world when their client
+/// disconnects. Their entity is held until they send
+/// another message. See `reconnect_player()`.
+pub fn disconnect_player(player: &PlayerMeta) {
+    let id = player.get_player_id();
+    player.set_active(false);
+    player.stop_ambience();
+    remove_all_options(id);
+
+    if DISCONNECTED.lock().contains_key(&id) {
+        return;
+    }
+    // The player may be between areas, in which case
+    // they aren't held by either one.
+    let entity = access::area(player.get_coordinates(), |area| {
+        if area.contains_entity(id) {
+            area.remove_entity(id)
+        } else {
+            None
+        }
+    });
+    match entity {
+        Some(Some(entity)) => {
+            DISCONNECTED.lock().insert(id, entity);
+        }
+        _ => warn!("Could not find {} to remove after disconnecting.", player.get_name()),
+    }
+}
+
+/// Returns a disconnected player to the world and shows
+/// them their options again.
+pub fn reconnect_player(player: &PlayerMeta) {
+    player.set_active(true);
+
+    let entity = DISCONNECTED.lock().remove(&player.get_player_id());
+    if let Some(entity) = entity {
+        if let Err(entity) = place_player(player, entity) {
+            wait_for_room(player, entity);
+            return;
+        }
+    }
+    player.get_send_area_options();
+}
+
+/// Variant of `access::player_meta()` which does not
+/// panic if the player has since been removed.
+pub fn find_player(player_id: usize) -> Option<Arc<PlayerMeta>> {
+    PLAYER_META.lock()
+        .iter()
+        .find(|p| p.get_player_id() == player_id)
+        .cloned()
+}
+
 pub fn register_player_meta(meta: PlayerMeta) {
     PLAYER_META.lock().push(Arc::new(meta));
 }
 
+/// Registers a player who isn't placed in the world.
+/// Their messages are held for a remote client who
+/// never connects. Their coordinates point to the
+/// starting coordinates of town 0, which is never
+/// generated.
+#[cfg(test)]
+pub fn test_player() -> Arc<PlayerMeta> {
+    let player_id = random();
+    let (x, z) = crate::types::towns::STARTING_COORDS;
+    register_player_meta(PlayerMeta {
+        channel: Mutex::new(ChannelInfo::Remote(format!("test_{}", player_id))),
+        player_id,
+        coordinates: Atomic::new((0, x, z)),
+        area_records: Mutex::new(HashMap::new()),
+        entity_knowledge: Mutex::new(Vec::new()),
+        name: Mutex::new(String::from(DEFAULT_NAME)),
+        god: Mutex::new(String::from(DEFAULT_GOD)),
+        class: Atomic::new(Melee),
+        active: Atomic::new(true),
+        reusable_message: Mutex::new(ReusableMessage::new()),
+        settings: Atomic::new(PlayerSettings::default()),
+        send_pending: Atomic::new(false),
+        blocking_message: Mutex::new(None),
+        ambience: Mutex::new(None),
+        previous_dialogues: Mutex::new(Vec::new()),
+        combat_log: Mutex::new(VecDeque::new()),
+    });
+    access::player_meta(player_id)
+}
+
+/// Variant of `test_player()` whose entity is placed in
+/// the starting area of `town_num`.
+#[cfg(test)]
+pub fn test_player_in_town(town_num: usize) -> Arc<PlayerMeta> {
+    let player = test_player();
+    let entity = Box::new(Player::new(player.clone()));
+    access::starting_area(town_num, |area| {
+        player.set_coordinates(area.get_coordinates());
+        if let Err(_) = area.add_entity(entity) {
+            panic!("Starting area is full while placing a test player.");
+        }
+    });
+    player
+}
+
 /// Intended for storing whatever information the
 /// player knows about any given entity.
 pub struct EntityKnowledge {
     pub entity_id: usize,
     pub knows_name: bool,
     pub dialogue_marker: u8,
+    /// The `game_time()` at which the player last waved
+    /// to this entity, if ever.
+    pub last_wave: Option<u64>,
 }
 
 impl EntityKnowledge {
@@ -382,6 +1086,7 @@ impl EntityKnowledge {
             entity_id,
             knows_name: false,
             dialogue_marker: 0,
+            last_wave: None,
         }
     }
 }
@@ -410,4 +1115,156 @@ impl PartialEq for EntityKnowledge {
     }
 }
 
-impl Eq for EntityKnowledge {}
\ No newline at end of file
+impl Eq for EntityKnowledge {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::EntityCore;
+    use crate::types::entities::belongings::LostBelongings;
+    use crate::util::server_host;
+
+    fn username(player: &PlayerMeta) -> String {
+        match player.get_channel() {
+            ChannelInfo::Remote(name) => name,
+            _ => panic!("Test players should be remote."),
+        }
+    }
+
+    #[test]
+    fn updates_in_one_tick_are_sent_once() {
+        // Starting the server would deliver these instead.
+        let _guard = timed_events::test_lock();
+        let player = test_player();
+        let name = username(&player);
+        player.send_short_message("One.");
+        player.send_short_message("Two.");
+        player.send_short_message("Three.");
+        assert!(server_host::take_pending_messages(&name).is_empty());
+
+        flush_messages();
+        let sent = server_host::take_pending_messages(&name);
+        // The separating lines, then the message itself.
+        assert_eq!(2, sent.len());
+        assert!(sent[1].contains("One.") && sent[1].contains("Three."));
+
+        flush_messages();
+        assert!(server_host::take_pending_messages(&name).is_empty());
+    }
+
+    #[test]
+    fn back_stack_keeps_only_the_latest_screens() {
+        let player = test_player();
+        for i in 0..MAX_BACK_DEPTH + 3 {
+            player.push_previous_dialogue(Arc::new(move |p: &PlayerMeta| {
+                Dialogue::simple(format!("Screen {}", i), String::new(), Vec::new(), p.get_player_id())
+            }));
+        }
+        let mut titles = Vec::new();
+        while let Some(regenerator) = player.pop_previous_dialogue() {
+            titles.push(regenerator(&player).title);
+        }
+        assert_eq!(MAX_BACK_DEPTH, titles.len());
+        assert_eq!(format!("Screen {}", MAX_BACK_DEPTH + 2), titles[0]);
+    }
+
+    #[test]
+    fn settings_survive_save_and_reload() {
+        let saved = PlayerSettings {
+            text_speed: TEXT_SPEED + 1000,
+            text_length: LINE_LENGTH + 10,
+            max_short_messages: MAX_SHORT_MESSAGES + 2,
+            show_map: false,
+            show_clock: false,
+        };
+        let player = test_player();
+        player.set_settings(saved);
+
+        let reloaded = test_player();
+        reloaded.load_settings(&player.save_settings());
+        let settings = reloaded.get_settings();
+
+        assert_eq!(saved.text_speed, settings.text_speed);
+        assert_eq!(saved.text_length, settings.text_length);
+        assert_eq!(saved.max_short_messages, settings.max_short_messages);
+        assert_eq!(saved.show_map, settings.show_map);
+        assert_eq!(saved.show_clock, settings.show_clock);
+    }
+
+    #[test]
+    fn restored_players_wait_for_room() {
+        crate::test_init();
+        let player = test_player_in_town(338);
+        let coords = player.get_coordinates();
+        let mut save = player.entity(|e| e.save_state());
+        save.player_id = random();
+
+        let fillers = access::area(coords, |area| {
+            let mut fillers = Vec::new();
+            while area.has_room() {
+                let filler = LostBelongings::new("Filler", coords);
+                fillers.push(filler.get_id());
+                area.add_entity(Box::new(filler)).ok();
+            }
+            fillers
+        })
+        .unwrap();
+
+        let restored = restore_player(&save, player.get_channel());
+        assert!(!restored.is_active());
+        assert!(restored.get_general_message().contains(WORLD_IS_FULL));
+
+        access::area(coords, |area| area.remove_entity(fillers[0]));
+        reconnect_player(&restored);
+        assert!(restored.is_active());
+        assert!(access::area(coords, |a| a.contains_entity(save.player_id)).unwrap());
+
+        access::area(coords, |area| {
+            for id in fillers.iter().skip(1) {
+                area.remove_entity(*id);
+            }
+        });
+    }
+
+    #[test]
+    fn name_length_message_matches_the_limits() {
+        let expected = format!("between {} and {} characters", MIN_NAME_LENGTH, MAX_NAME_LENGTH);
+        let too_short = "a".repeat(MIN_NAME_LENGTH - 1);
+        let too_long = "a".repeat(MAX_NAME_LENGTH + 1);
+        assert!(validate_name(&too_short).unwrap_err().contains(&expected));
+        assert!(validate_name(&too_long).unwrap_err().contains(&expected));
+        assert_eq!(Ok(()), validate_name(&"a".repeat(MAX_NAME_LENGTH)));
+    }
+
+    #[test]
+    fn names_are_taken_regardless_of_case() {
+        let player = test_player();
+        player.set_name(String::from("Taken Tester"));
+        let other = test_player();
+
+        assert!(is_name_taken("taken tester", other.get_player_id()));
+        assert!(!is_name_taken("Taken Tester", player.get_player_id()));
+        assert!(!is_name_taken("Untaken Tester", other.get_player_id()));
+    }
+
+    #[test]
+    fn skipping_leaves_later_messages_scheduled() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = test_player_in_town(345);
+        let name = username(&player);
+        player.send_blocking_message("First.∫Last.");
+
+        let later = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let sent_later = later.clone();
+        let flag = player.message_flag();
+        timed_events::DelayedEvent::new_for_flag(60_000, &flag, move || sent_later.store(true, SeqCst));
+
+        assert!(player.skip_blocking_message());
+        let sent = server_host::take_pending_messages(&name).concat();
+        assert!(sent.contains("Last."));
+        assert!(!later.load(SeqCst));
+        assert!(timed_events::has_flags(None, None, Some(&flag)));
+        timed_events::delete_by_flags(None, None, Some(&flag));
+    }
+}
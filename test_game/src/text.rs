@@ -1,12 +1,15 @@
 use crate::player_data::PlayerMeta;
 use crate::traits::Entity;
-use crate::types::classes::Class::{self, *};
+use crate::types::classes::{self, Class::{self, *}};
 use crate::types::entities::players::Player;
+use crate::types::items::item_settings;
 use crate::util::access;
 use crate::util::player_options::{Dialogue, Response, TextHandler};
 use crate::*;
 
 use rand::{thread_rng, Rng};
+use std::cmp;
+use std::sync::atomic::AtomicBool;
 
 /// This class is for holding a bunch of miscellaneous
 /// dialogue to keep it away from the code inside of
@@ -54,14 +57,26 @@ pub fn generate_text(text: &[&str], replacements: &[(&str, String)]) -> String {
 /// game text that starts with `§`.
 pub fn auto_break(indent: u8, length: usize, text: &str) -> String {
     let mut chars: Vec<char> = text.chars().collect();
-    if chars.len() <= length as usize {
+    if length == 0 || chars.len() <= length as usize {
         return text.to_string();
     }
 
     let mut start_at = 0;
-    while start_at <= chars.len() - length {
-        let end = end_of_line(start_at, length, &chars);
-        chars[end] = '\n';
+    // Only break while the remaining text is too long.
+    while chars.len().saturating_sub(start_at) > length {
+        let end = match find_line_break(start_at, length, &chars) {
+            Some(i) => {
+                chars[i] = '\n';
+                i
+            }
+            None => {
+                // There is no space to break at, so the
+                // word has to be split.
+                let i = start_at + length;
+                chars.insert(i, '\n');
+                i
+            }
+        };
         for _ in 0..indent {
             chars.insert(end + 1, ' ');
         }
@@ -70,28 +85,18 @@ pub fn auto_break(indent: u8, length: usize, text: &str) -> String {
     chars.into_iter().collect()
 }
 
-fn end_of_line(start_at: usize, length: usize, text: &Vec<char>) -> usize {
-    let max_line = &text[start_at..(start_at + length)];
-    let mut final_space = max_line.len();
+/// Locates the index where the line beginning at
+/// `start_at` should end. Prefers an early new line,
+/// followed by the last space in the window. Returns
+/// `None` when neither exists.
+fn find_line_break(start_at: usize, length: usize, text: &[char]) -> Option<usize> {
+    let end = cmp::min(start_at + length, text.len());
+    let window = &text[start_at..end];
 
-    // Iterate forward in case of an early new line.
-    for (i, c) in max_line.iter().enumerate() {
-        match *c {
-            '\n' => return start_at + i,
-            ' ' => final_space = i,
-            _ => {}
-        };
-    }
-    start_at + final_space
-}
-
-fn get_last_char(ch: char, text: &[char]) -> usize {
-    for (i, c) in text.iter().enumerate().rev() {
-        if *c == ch {
-            return i;
-        }
-    }
-    text.len()
+    window.iter()
+        .position(|c| *c == '\n')
+        .or_else(|| window.iter().rposition(|c| *c == ' '))
+        .map(|i| start_at + i)
 }
 
 /// Designed for substituting placeholder text with a new string.
@@ -114,6 +119,54 @@ pub fn apply_replacements(text: &str, replacements: &[(&str, String)]) -> String
     ret
 }
 
+/// Variant of `apply_replacements()` which substitutes
+/// the tokens common to any dialogue using the player's
+/// current info: `<name>`, `<gold>`, `<hp>`, and
+/// `<town>`. Unknown tokens are left intact so that
+/// typos remain visible.
+pub fn apply_player_tokens(text: &str, player: &PlayerMeta) -> String {
+    if !text.contains('<') {
+        return text.to_string();
+    }
+    let mut replacements = vec![
+        ("<name>", player.get_name()),
+        ("<town>", player.get_coordinates().0.to_string()),
+    ];
+    if text.contains("<gold>") || text.contains("<hp>") {
+        // The entity is missing while the player is away.
+        let stats = access::entity(player.get_accessor(), |e| (e.get_money(), e.get_health()));
+        if let Some((gold, hp)) = stats {
+            replacements.push(("<gold>", gold.to_string()));
+            replacements.push(("<hp>", hp.to_string()));
+        }
+    }
+    apply_replacements(text, &replacements)
+}
+
+/// Removes the characters used for controlling how
+/// messages are displayed, i.e. `§` and `∫`. Used for
+/// text entered by players.
+pub fn strip_formatting(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '§' && *c != '∫')
+        .collect()
+}
+
+/// Variant of `strip_formatting()` which also removes
+/// control characters, including line breaks, and cuts
+/// the text down to at most `max_bytes`.
+pub fn sanitize(text: &str, max_bytes: usize) -> String {
+    let mut ret = String::new();
+
+    for c in strip_formatting(text).chars().filter(|c| !c.is_control()) {
+        if ret.len() + c.len_utf8() > max_bytes {
+            break;
+        }
+        ret.push(c);
+    }
+    ret
+}
+
 pub fn convert_to_vec(array: &[&str]) -> Vec<String> {
     let mut ret = Vec::new();
     for text in array {
@@ -454,16 +507,46 @@ pub fn rand_new_sender() -> &'static str {
 
 pub fn new_player_name(player_id: usize) -> Dialogue {
     let title = String::from("New Player");
-    Dialogue::handle_text(title, None, get_name(), player_id)
+    Dialogue::handle_text(title, None, get_name(0), player_id)
 }
 
-pub fn get_name() -> TextHandler {
+/// Stores the player's name. Blank input is rejected
+/// once, after which a random name is chosen instead.
+pub fn get_name(num_attempts: u8) -> TextHandler {
+    let accepted = Arc::new(AtomicBool::new(false));
+    let accepted_2 = accepted.clone();
+
     TextHandler {
         text: String::from("Enter your name:"),
         execute: Box::new(move |player, args| {
-            player.set_name(args.to_string())
+            let name = args.trim();
+            if !name.is_empty() {
+                player.set_name(name.to_string());
+            } else if num_attempts > 0 {
+                player.set_name(rand_npc_name());
+            } else {
+                accepted.store(false, SeqCst);
+                return;
+            }
+            accepted.store(true, SeqCst);
+        }),
+        next_dialogue: gen_dialogue(move |player| {
+            if accepted_2.load(SeqCst) {
+                new_player_name_confirm(player, 0)
+            } else {
+                new_player_name_retry(player, num_attempts + 1)
+            }
         }),
-        next_dialogue: gen_dialogue(move |player| new_player_name_confirm(player, 0)),
+    }
+}
+
+fn new_player_name_retry(player: &PlayerMeta, num_attempts: u8) -> Dialogue {
+    Dialogue {
+        title: String::from("New Player"),
+        info: Some(String::from("Come now, you must remember something.")),
+        text_handler: Some(get_name(num_attempts)),
+        player_id: player.get_player_id(),
+        ..Dialogue::default()
     }
 }
 
@@ -494,14 +577,14 @@ fn change_name(total_corrections: u8) -> TextHandler {
     TextHandler {
         text: String::from("Enter a different name:"),
         execute: Box::new(move |player, input| {
-            let name = if total_corrections > 0 {
+            let name = if total_corrections > 0 || input.trim().is_empty() {
                 rand_npc_name()
             } else {
-                input.to_string()
+                input.trim().to_string()
             };
             player.set_name(name);
         }),
-        next_dialogue: Generate(Box::new(move |player| {
+        next_dialogue: Generate(Arc::new(move |player| {
             if total_corrections > 0 {
                 new_player_class(player)
             } else {
@@ -566,6 +649,7 @@ fn choose_class(player_id: usize, class: Class) -> Response {
         text: class.to_string(),
         execute: Some(Box::new(move |player: &PlayerMeta| {
             player.set_class(class);
+            Ok(())
         })),
         next_dialogue: gen_dialogue(move |_| new_player_god(player_id, class)),
     }
@@ -623,6 +707,7 @@ fn set_god(god: String) -> Response {
         text: god.clone(),
         execute: Some(Box::new(move |player| {
             player.set_god(god.clone());
+            Ok(())
         })),
         next_dialogue: gen_dialogue(move |player| new_player_ready(player)),
     }
@@ -644,15 +729,214 @@ fn new_player_ready(player: &PlayerMeta) -> Dialogue {
 }
 
 fn new_player_finished(player: &PlayerMeta) -> Dialogue {
-    let rand_starting_town = thread_rng().gen_range(1, 4);
+    let mut towns = vec![1, 2, 3];
+    thread_rng().shuffle(&mut towns);
 
-    access::starting_area(rand_starting_town, move |area| {
+    let starting_town = match towns.into_iter().find(|t| access::starting_area(*t, |a| a.has_room())) {
+        Some(t) => t,
+        None => return world_is_full(player),
+    };
+
+    access::starting_area(starting_town, move |area| {
         let metadata = access::player_meta(player.get_player_id());
         let entity = Box::new(Player::new(metadata));
-        entity.give_money(1000);
+        give_starting_kit(&*entity, player.get_class());
 
         player.set_coordinates(area.get_coordinates());
-        area.add_entity(entity);
-        area.get_dialogue(player)
+        match area.add_entity(entity) {
+            Ok(_) => area.get_dialogue(player),
+            // Another player took the last spot.
+            Err(_) => world_is_full(player),
+        }
     })
 }
+
+/// Shown instead of starting the game when every starting
+/// area is full. The player keeps their character and can
+/// try again.
+fn world_is_full(player: &PlayerMeta) -> Dialogue {
+    let responses = vec![Response::goto_dialogue("Try again.", move |player| {
+        new_player_finished(player)
+    })];
+
+    Dialogue {
+        title: String::from("New Player"),
+        text: Some(String::from("The world is full right now. Please try again later.")),
+        responses,
+        player_id: player.get_player_id(),
+        ..Dialogue::default()
+    }
+}
+
+/// Gives a new player their class's starting gold and
+/// equips their starting weapon, if any.
+fn give_starting_kit(entity: &Entity, class: Class) {
+    let kit = classes::starting_kit(class);
+    entity.give_money(kit.money);
+
+    let weapon = match kit.weapon.and_then(|t| item_settings::item_from_type(t, "", 1)) {
+        Some(w) => w,
+        None => return,
+    };
+    let inventory = entity.get_inventory()
+        .expect("Player does not have an inventory.");
+
+    if !inventory.can_add_item(&*weapon) {
+        warn!("No room for a new player's starting {}.", weapon.get_type());
+        return;
+    }
+    let id = weapon.get_id();
+    inventory.add_item(weapon, Some(entity));
+    if let Some(slot) = inventory.get_slot_num(id) {
+        entity.equip_item(slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data::{self, DEFAULT_NAME};
+    use crate::traits::EntityCore;
+    use crate::types::entities::belongings::LostBelongings;
+
+    /// A string of `len` chars with a space every 7th char.
+    fn words_of_length(len: usize) -> String {
+        (0..len).map(|i| if i % 7 == 6 { ' ' } else { 'a' }).collect()
+    }
+
+    fn assert_fits(text: &str, broken: &str, length: usize) {
+        for line in broken.lines() {
+            assert!(line.chars().count() <= length, "Line too long: {:?}", line);
+        }
+        let strip = |s: &str| s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        assert_eq!(strip(text), strip(broken));
+    }
+
+    #[test]
+    fn break_at_line_length() {
+        let text = words_of_length(LINE_LENGTH);
+        assert_eq!(text, auto_break(0, LINE_LENGTH, &text));
+    }
+
+    #[test]
+    fn break_just_over_line_length() {
+        let text = words_of_length(LINE_LENGTH + 1);
+        let broken = auto_break(0, LINE_LENGTH, &text);
+        assert_eq!(2, broken.lines().count());
+        assert_fits(&text, &broken, LINE_LENGTH);
+    }
+
+    #[test]
+    fn break_just_under_two_lines() {
+        let text = words_of_length(2 * LINE_LENGTH - 1);
+        let broken = auto_break(2, LINE_LENGTH, &text);
+        assert_fits(&text, &broken, LINE_LENGTH);
+    }
+
+    #[test]
+    fn same_text_fits_every_width() {
+        let text = words_of_length(150);
+        let mut num_lines = usize::max_value();
+        for &width in &[30, 40, 60] {
+            let broken = auto_break(0, width, &text);
+            assert_fits(&text, &broken, width);
+            // Wider lines never need more of them.
+            assert!(broken.lines().count() <= num_lines);
+            num_lines = broken.lines().count();
+        }
+    }
+
+    #[test]
+    fn short_messages_use_the_players_width() {
+        let text = format!("§{}", words_of_length(150));
+        for &width in &[30, 40, 60] {
+            let player = player_data::test_player();
+            player.set_text_length(width);
+            player.add_short_message(&text);
+            let message = player.get_general_message().replacen("* ", "", 1);
+            assert_fits(&text[2..], &message, width);
+        }
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn break_at_trailing_space() {
+        assert_eq!(Some(9), find_line_break(0, 10, &chars("aaaa bbbb cc")));
+        assert_eq!(Some(14), find_line_break(5, 10, &chars("aaaa bbbb cccc dd")));
+    }
+
+    #[test]
+    fn break_at_leading_new_line() {
+        assert_eq!(Some(0), find_line_break(0, 10, &chars("\naaa bbbb cc")));
+        assert_eq!("\naaa bbbb\ncc", auto_break(0, 10, "\naaa bbbb cc"));
+    }
+
+    #[test]
+    fn break_without_space() {
+        assert_eq!(None, find_line_break(0, 5, &chars("aaaaaaaaaaaa")));
+        assert_eq!("aaaaa\naaaaa\naa", auto_break(0, 5, "aaaaaaaaaaaa"));
+    }
+
+    #[test]
+    fn zero_width_is_clamped() {
+        let player = player_data::test_player();
+        player.set_text_length(0);
+        assert_eq!(MIN_LINE_LENGTH, player.get_text_length());
+
+        let text = "A line which is far too long for any width of zero.";
+        assert_eq!(text, auto_break(0, 0, text));
+        assert_eq!(text, auto_break(3, 0, text));
+    }
+
+    #[test]
+    fn blank_name_is_rejected_once() {
+        let player = player_data::test_player();
+        (get_name(0).execute)(&player, "   ");
+        assert_eq!(DEFAULT_NAME, player.get_name());
+    }
+
+    #[test]
+    fn blank_name_falls_back_after_retry() {
+        let player = player_data::test_player();
+        (get_name(1).execute)(&player, "");
+        let name = player.get_name();
+        assert!(!name.trim().is_empty());
+        assert_ne!(DEFAULT_NAME, name);
+    }
+
+    #[test]
+    fn blank_correction_falls_back() {
+        let player = player_data::test_player();
+        player.set_name(String::from("Bob"));
+        (change_name(0).execute)(&player, " ");
+        let name = player.get_name();
+        assert!(!name.trim().is_empty());
+        assert_ne!("Bob", name);
+    }
+
+    #[test]
+    fn full_world_asks_new_players_to_wait() {
+        crate::test_init();
+        let player = player_data::test_player();
+        let mut fillers = Vec::new();
+        for town_num in 1..4 {
+            access::starting_area(town_num, |area| {
+                while area.has_room() {
+                    let filler = LostBelongings::new("Filler", area.get_coordinates());
+                    fillers.push((town_num, filler.get_id()));
+                    area.add_entity(Box::new(filler)).ok();
+                }
+            });
+        }
+        let dialogue = new_player_finished(&player);
+
+        for (town_num, id) in fillers {
+            access::starting_area(town_num, |area| area.remove_entity(id));
+        }
+        assert!(dialogue.text.unwrap().starts_with("The world is full"));
+        assert_eq!("Try again.", dialogue.responses[0].text);
+    }
+}
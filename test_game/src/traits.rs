@@ -1,19 +1,23 @@
-use crate::types::effects::Effect;
+use crate::messages::MessageComponent::General;
+use crate::types::classes::Class;
+use crate::types::effects::{Effect, EffectCategory, Stat};
 use crate::types::entities::{mobs::Mob, npcs::NPC, players::Player};
-use crate::types::items::{self, bows::Bow, display_info::ItemDisplayInfo, inventories::Inventory, swords::Sword};
-use crate::player_data::PlayerMeta;
+use crate::types::items::{self, bows::Bow, display_info::ItemDisplayInfo, equipment::Equipment, inventories::Inventory, swords::Sword};
+use crate::types::items::shops::{self, BuybackQueue};
+use crate::player_data::{self, PlayerMeta, PlayerSave};
 use crate::text;
 use crate::types::towns::Town;
 use crate::util::access::{self, EntityAccessor};
-use crate::util::player_options::{Command, Dialogue, Response};
+use crate::util::player_options::{ActionResult, Command, Dialogue, Response};
 use crate::*;
 
 use self::AttemptedPurchase::*;
 use self::AttemptedSale::*;
 
 use std::any::Any;
+use std::cmp;
 
-use parking_lot::RwLockReadGuard;
+use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
 use lazy_static::lazy_static;
 use rand::random;
 
@@ -39,30 +43,50 @@ pub trait Area: EntityHolder + AreaTools {
     /// binaries.
     fn get_type(&self) -> &'static str;
 
-    /// A typically three-character identifier that will
-    /// represent this area on the map.
-    fn get_map_icon(&self) -> &'static str;
-
     /// Determines whether the input `player` can enter this
     /// area at the given time.
     fn can_enter(&self, _player: &Player) -> bool {
         true
     }
 
+    /// The only class allowed to enter this area, if any.
+    /// Players of any other class will be turned away.
+    fn required_class(&self) -> Option<Class> {
+        None
+    }
+
     /// An optional message that will be displayed when the
     /// player first enters the area.
     fn get_entrance_message(&self) -> Option<String> {
         None
     }
 
+    /// Shown to players who enter the area while it
+    /// contains mobs, right before the fight begins.
+    fn get_ambush_message(&self) -> Option<String> {
+        Some(String::from("You're ambushed!"))
+    }
+
     /// This area's title.
     fn get_title(&self) -> String;
 
+    /// Lines of flavor text which will occasionally be shown
+    /// to players who remain in this area.
+    fn ambient_messages(&self) -> &[&'static str] {
+        &[]
+    }
+
     /// Whether mobs should spawn in this area.
     fn should_mobs_spawn(&self) -> bool {
         false
     }
 
+    /// The maximum number of entities, including players,
+    /// that this area can hold at once.
+    fn max_entities(&self) -> usize {
+        64
+    }
+
     /// Whether a particular item can be used in this area.
     /// May currently be unused.
     fn can_use_item(&self, _item: &Item) -> bool {
@@ -88,11 +112,29 @@ pub trait Area: EntityHolder + AreaTools {
     }
 
     /// Optionally provides info for the player's dialogue
-    /// while in this area. By default, this info is just a
-    /// map of the current town, but it would be possible to
-    /// concatenate additional info manually.
+    /// while in this area. By default, this info is the time
+    /// of day and a map of the current town, but it would be
+    /// possible to concatenate additional info manually.
+    /// Players can hide either through their settings.
     fn get_dialogue_info(&self, player: &PlayerMeta) -> Option<String> {
-        Town::find_map(self.get_coordinates().0, player)
+        let mut info = String::new();
+
+        if player.shows_clock() {
+            info += &format!("Time: {}", format_time_of_day());
+        }
+        if player.shows_map() {
+            if let Some(map) = Town::find_map(self.get_coordinates().0, player) {
+                if info.len() > 0 {
+                    info += "\n";
+                }
+                info += &map;
+            }
+        }
+        if info.len() > 0 {
+            Some(info)
+        } else {
+            None
+        }
     }
 
     /// To-do
@@ -109,15 +151,37 @@ pub trait Area: EntityHolder + AreaTools {
     /// indicate their purpose.
     fn get_movements(&self, _player: &PlayerMeta, responses: &mut Vec<Response>) {
         let current = self.get_coordinates();
-        let connections = self.get_connections();
+        let mut connections = self.get_connections();
         let num_connections = connections.len();
 
+        // Keep the numbering stable, regardless of the
+        // order in which connections were added.
+        connections.sort_by_key(|c| get_direction_order(current, *c));
+
         for coordinates in connections {
             let text = get_direction_label(num_connections, current, coordinates);
             responses.push(Response::_simple(text, move |p: &PlayerMeta| {
                 access::area(current, |old| {
                     access::area(coordinates, |new| {
-                        old.transfer_to_area(p.get_player_id(), new);
+                        // The dialogue is refreshed from the
+                        // current area, so the player will just
+                        // see this message with their old options.
+                        if let Some(class) = new.required_class() {
+                            if class != p.get_class() {
+                                p.add_short_message(&format!("Only the {} class may enter this area.", class));
+                                return;
+                            }
+                        }
+                        let can_enter = p.entity(|e| {
+                            e.as_player().map_or(true, |player| new.can_enter(player))
+                        });
+                        if !can_enter {
+                            p.add_short_message("You can't go that way right now.");
+                            return;
+                        }
+                        if let Err(e) = old.transfer_to_area(p.get_player_id(), new) {
+                            p.add_short_message(e);
+                        }
                     });
                 });}
             ));
@@ -141,8 +205,8 @@ pub trait Area: EntityHolder + AreaTools {
             }
             // Special interactions for other players.
             if let Some(_) = entity.as_player() {
-                responses.push(wave_response(&**entity));
-                responses.push(trade_response(&**entity));
+                responses.push(wave_response(&**entity, player));
+                responses.push(trade_response(&**entity, player));
             }
         }
     }
@@ -170,13 +234,15 @@ pub trait Area: EntityHolder + AreaTools {
                         .get_dialogue(player)
                 })
             },
-        ));
+        ).with_aliases(&["inv", "inventory"]));
 
         if player.entity(|e| e.get_secondary() != "None") {
             commands.push(Command::simple("s", "Use your secondary item.", |_, p| {
                 p.entity(|e| e.use_secondary());
             }));
         }
+        commands.push(say_command());
+        commands.push(look_command());
     }
 
     /// Handles generating the dialogue that will be
@@ -212,6 +278,7 @@ pub trait Area: EntityHolder + AreaTools {
             commands,
             text_handler: None,
             is_primary: true,
+            regenerator: None,
             player_id: player.get_player_id(),
             id: random(),
         }
@@ -237,42 +304,77 @@ fn get_new_area_title(coords: (usize, usize, usize)) -> String {
 /// Determines whether to display `Walk away from...` or
 /// `Go [direction]: [title]`
 fn get_direction_label(num_connections: usize, from: (usize, usize, usize), to: (usize, usize, usize)) -> String {
-    let direction = get_direction(from, to)
-        .expect("get_direction_label() did not error correctly.");
     if num_connections == 1 {
-        format!("Walk away from the {}", get_new_area_title(from))
-    } else {
-        format!("Go {}: {}", direction, get_new_area_title(to))
+        return format!("Walk away from the {}", get_new_area_title(from));
+    }
+    match get_direction(from, to) {
+        Some(direction) if get_distance(from, to) > 1 => {
+            format!("Go {}, across the gap: {}", direction, get_new_area_title(to))
+        }
+        Some(direction) => format!("Go {}: {}", direction, get_new_area_title(to)),
+        None => format!("Go to the {}", get_new_area_title(to)),
     }
 }
 
+/// Movements are always listed in this order:
+/// forward, left, right, backward, then anything
+/// that isn't in a straight line.
+fn get_direction_order(from: (usize, usize, usize), to: (usize, usize, usize)) -> u8 {
+    match get_direction(from, to) {
+        Some("forward") => 0,
+        Some("left") => 1,
+        Some("right") => 2,
+        Some(_) => 3,
+        None => 4,
+    }
+}
+
+/// The direction from one area to another. Connections
+/// may skip over any number of tiles, but `None` is
+/// returned if they aren't in a straight line.
 /// To-do: Possibly just use "next" / "previous."
-/// Would have to add on world gen. This is
-/// mildly bad.
 fn get_direction(from: (usize, usize, usize), to: (usize, usize, usize)) -> Option<&'static str> {
     if to.2 == from.2 {
-        if to.1 == from.1 + 1 {
+        if to.1 > from.1 {
             return Some("forward");
-        } else if to.1 == from.1 - 1 {
+        } else if to.1 < from.1 {
             return Some("backward");
         }
-        panic!("Error: Indirect connections are not yet implemented. Tried to skip a z coordinate.");
     } else if to.1 == from.1 {
-        if to.2 == from.2 + 1 {
+        if to.2 > from.2 {
             return Some("right");
-        } else if to.2 == from.2 - 1 {
+        } else {
             return Some("left");
         }
-        panic!("Error: Indirect connections are not yet implemented. Tried to skip an x coordinate.");
     }
-    panic!("Error: Indirect connections are not yet implemented. Tried to move diagonally.");
+    None
 }
 
+/// The number of tiles walked between two areas, not
+/// counting diagonals.
+fn get_distance(from: (usize, usize, usize), to: (usize, usize, usize)) -> usize {
+    to.1.abs_diff(from.1) + to.2.abs_diff(from.2)
+}
+
+/// How long a player must wait before waving to the
+/// same player again.
+const WAVE_COOLDOWN_MS: u64 = 10_000;
+
 /// Wave to another player.
-fn wave_response(entity: &Entity) -> Response {
+fn wave_response(entity: &Entity, player: &PlayerMeta) -> Response {
     let receiver_id = entity.get_id();
-    let text = format!("Wave to {}.", entity.get_name());
-    Response::_simple(text, move |p| {
+    let text = format!("Wave to {}.", entity.display_name_for(player));
+    Response::_fallible(text, move |p| {
+        let since_wave = p.get_last_wave(receiver_id)
+            .map(|t| game_time().saturating_sub(t));
+
+        if let Some(elapsed) = since_wave.filter(|t| *t < WAVE_COOLDOWN_MS) {
+            let remaining = (WAVE_COOLDOWN_MS - elapsed).div_ceil(1000);
+            p.send_short_message(&format!("You just waved. Wait {}s.", remaining));
+            return Err(());
+        }
+        p.set_last_wave(receiver_id);
+
         let msg = *choose(&[
             "<name> says hello!",
             "<name> says hi!",
@@ -289,21 +391,81 @@ fn wave_response(entity: &Entity) -> Response {
         let formatted = text::apply_replacements(msg, &[("<name>", p.get_name())]);
         temp_add_short_message(receiver_id, &formatted);
 
+        // Refreshing fails when the receiver has more than one
+        // dialogue open, e.g. a shop on top of their area, as
+        // it isn't clear which one to replace. Their current
+        // options are resent instead so that the message still
+        // shows up. The sender's options are refreshed once the
+        // response finishes, so they shouldn't be sent here.
         if !try_refresh_options(receiver_id) {
+            temp_send_current_options(receiver_id);
             p.add_short_message(*choose(&[
                 "§They were too busy to notice you, but heard your message.",
                 "They didn't see you there, but got your message.",
-            ]), );
+            ]));
+        }
+        Ok(())
+    })
+}
+
+/// Sends a message to every other player in the area.
+fn say_command() -> Command {
+    Command::fallible("say <text>", "Talk to everyone nearby.", |args, player| {
+        let msg = text::strip_formatting(&args.join(" "));
+        if msg.trim().is_empty() {
+            player.send_short_message("Say what?");
+            return Err(());
+        }
+        let sender_id = player.get_player_id();
+        // Collect the ids first so that the area isn't
+        // locked while each player's options refresh.
+        let receivers: Vec<usize> = player.area(|area| {
+            area.borrow_entity_lock()
+                .iter()
+                .filter_map(|e| e.as_player())
+                .map(|p| p.get_id())
+                .filter(|id| *id != sender_id)
+                .collect()
+        });
+
+        let formatted = format!("{} says: \"{}\"", player.get_name(), msg);
+        for receiver_id in receivers {
+            temp_add_short_message(receiver_id, &formatted);
+            if !try_refresh_options(receiver_id) {
+                temp_send_current_options(receiver_id);
+            }
+        }
+        player.add_short_message(&format!("You say: \"{}\"", msg));
+        Ok(())
+    })
+}
+
+/// Lists everyone else in the area, by the names that
+/// the player knows them by. These numbers are used to
+/// target others with items, e.g. `u 1 on 2`.
+fn look_command() -> Command {
+    Command::simple("look", "See who's nearby.", |_, player| {
+        let player_id = player.get_player_id();
+        let names: Vec<String> = player.area(|area| {
+            area.borrow_entity_lock()
+                .iter()
+                .filter(|e| e.get_id() != player_id)
+                .enumerate()
+                .map(|(index, e)| format!("#{}: {}", index + 1, e.display_name_for(player)))
+                .collect()
+        });
+
+        if names.is_empty() {
+            player.add_short_message("There's no one else here.");
+        } else {
+            player.add_short_message(&format!("You see {}.", names.join(", ")));
         }
-//        } else {
-//            p.send_current_options();
-//        } // Manually trigger refresh. There is a very strange bug associated.
     })
 }
 
 /// Currently does nothing.
-fn trade_response(entity: &Entity) -> Response {
-    Response::_text_only(format!("Trade with {}", entity.get_name()))
+fn trade_response(entity: &Entity, player: &PlayerMeta) -> Response {
+    Response::_text_only(format!("Trade with {}", entity.display_name_for(player)))
 }
 
 /// Derivable methods for `Area`.
@@ -330,17 +492,24 @@ pub trait EntityHolder {
     /// with the given type identifier.
     fn contains_type(&self, typ: &'static str) -> bool;
 
+    /// Whether another entity can be added without going
+    /// over `Area#max_entities()`.
+    fn has_room(&self) -> bool;
+
     /// Places a new entity in this area, calling
     /// `Entity#on_enter_area()` to handle related
-    /// events.
-    fn add_entity(&self, entity: Box<Entity>);
+    /// events. The entity is handed back if the area
+    /// is already full.
+    fn add_entity(&self, entity: Box<Entity>) -> Result<(), Box<Entity>>;
 
     /// Removes an entity from the area.
     fn remove_entity(&self, id: usize) -> Option<Box<Entity>>;
 
     /// Transfers an entity from this area to another
-    /// Entity holder.
-    fn transfer_entity(&self, id: usize, to: &EntityHolder);
+    /// Entity holder. The entity stays put if the other
+    /// holder is full. Fails without panicking if the
+    /// entity has already left this area.
+    fn transfer_entity(&self, id: usize, to: &EntityHolder) -> Result<(), &'static str>;
 
     /// Determines whether an entity with the given
     /// `id` currently exists in this area.
@@ -378,11 +547,44 @@ pub trait EntityHolder {
     /// be accessed by external processes.
     fn borrow_entity_lock(&self) -> RwLockReadGuard<Vec<Box<Entity>>>;
 
+    /// Locks the `entities` vector for writing. Used by
+    /// `transfer_entity()` to lock both holders at once.
+    fn lock_entities(&self) -> RwLockWriteGuard<Vec<Box<Entity>>>;
+
+    /// The coordinates of this holder. Transfers lock
+    /// holders in coordinate order.
+    fn get_holder_coordinates(&self) -> (usize, usize, usize);
+
+    /// The maximum number of entities this holder can
+    /// contain.
+    fn get_capacity(&self) -> usize;
+
+    /// Fires `on_enter_area()` for an entity which was just
+    /// added. Entities may lock other registries as they
+    /// enter, e.g. to start the area's ambience, so this is
+    /// only called once the holder is no longer locked for
+    /// writing.
+    fn on_entity_entered(&self, id: usize) {
+        let coords = self.get_holder_coordinates();
+        if let Some(entity) = self.borrow_entity_lock().iter().find(|e| e.get_id() == id) {
+            entity.on_enter_area(coords);
+        }
+    }
+
     /// A nicer-looking implementation of `transfer_
     /// entity`, which should look nicer in-use when
     /// transferring entities between actual `Area`s.
-    fn transfer_to_area(&self, id: usize, area: &Area) {
-        self.transfer_entity(id, area.as_entity_holder());
+    /// Warns players who walk into an ongoing fight.
+    fn transfer_to_area(&self, id: usize, area: &Area) -> Result<(), &'static str> {
+        self.transfer_entity(id, area.as_entity_holder())?;
+
+        if area.contains_mobs() {
+            let player = player_data::find_player(id);
+            if let (Some(p), Some(msg)) = (player, area.get_ambush_message()) {
+                p.add_short_message(&msg);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -394,15 +596,33 @@ pub trait EntityHolder {
 pub const ATTACK_SPEED_MIN: i32 = -5000;
 pub const ITEM_SPEED_MIN: i32 = -8000;
 
-/// The standard interface which allows dynamic dispatch
-/// for structs that serve as entities in-game.
-pub trait Entity: Send + Sync {
+/// Derivable methods for `Entity`.
+pub trait EntityCore: Send + Sync {
     /// This entity's unique identifier.
     fn get_id(&self) -> usize;
 
+    fn set_health(&self, health: u32);
+
+    fn get_health(&self) -> u32;
+
+    /// Returns information related to retrieving the entity
+    /// statically from its current position. Does not provide
+    /// a safe, updatable solution for accessing entities, but
+    /// is potentially faster than using reference counters.
+    /// Maybe not, though.
+    fn get_accessor(&self) -> EntityAccessor;
+}
+
+/// The standard interface which allows dynamic dispatch
+/// for structs that serve as entities in-game.
+pub trait Entity: EntityCore {
     /// This entity's in-game name.
     fn get_name(&self) -> &String;
 
+    /// Changes this entity's name. Requires the area's
+    /// entities to be locked for writing.
+    fn set_name(&mut self, _name: String) {}
+
     /// This entity's optional title, a subtext of their name.
     fn get_title(&self) -> Option<&String> {
         None
@@ -414,27 +634,34 @@ pub trait Entity: Send + Sync {
         None
     }
 
+    /// The name shown to `observer`. Entities with a
+    /// description are referred to by it, e.g. "the old
+    /// storyteller", until the observer learns their name.
+    fn display_name_for(&self, observer: &PlayerMeta) -> String {
+        match self.get_description() {
+            Some(desc) if !observer.knows_name(self.get_id()) => format!("the {}", desc),
+            _ => self.get_name().clone(),
+        }
+    }
+
     fn set_max_health(&self, _val: u32) {}
 
     fn get_max_health(&self) -> u32 {
         15
     }
 
-    fn set_health(&self, health: u32);
-
-    fn get_health(&self) -> u32;
-
     /// Display's this user's current health bar.
     fn get_health_bar(&self) -> String {
         format!(
             "HP: ({} / {}); Dps: ({}); Gold: {}g\n\
-             Prim: {}; Sec: {}",
+             Prim: {}; Sec: {}; Armor: {}",
             self.get_health(),
             self.get_max_health(),
             items::format_damage_2(self.get_base_damage(), self.get_attack_speed()),
             self.get_money(),
             self.get_primary(),
-            self.get_secondary()
+            self.get_secondary(),
+            self.get_armor()
         )
     }
 
@@ -445,7 +672,8 @@ pub trait Entity: Send + Sync {
     fn add_health(&self, health: i32) {
         let prior = self.get_health();
 
-        self.set_health((prior as i32 + health) as u32);
+        // Prevent the cast from returning a very large number.
+        self.set_health(cmp::max(prior as i32 + health, 0) as u32);
 
         let adjusted = self.get_health();
 
@@ -459,19 +687,35 @@ pub trait Entity: Send + Sync {
     fn remove_health(&self, health: u32) {
         let prior = self.get_health();
 
-        self.set_health(prior - health);
+        self.set_health(prior.saturating_sub(health));
 
         if self.get_health() == 0 {
             self.kill_entity()
         }
     }
 
+    /// Removes health dealt through combat. Damage is
+    /// reduced by the entity's defense, but always at
+    /// least 1 point gets through.
+    fn take_damage(&self, damage: u32) {
+        let mitigated = damage.saturating_sub(self.get_defense());
+        self.remove_health(mitigated.max(1));
+    }
+
     fn set_base_damage(&self, _val: u32) {}
 
     fn get_base_damage(&self) -> u32 {
         5
     }
 
+    /// Sets the amount of incoming combat damage that
+    /// this entity ignores. Armor adjusts this on equip.
+    fn set_defense(&self, _val: u32) {}
+
+    fn get_defense(&self) -> u32 {
+        0
+    }
+
     fn set_attack_speed(&self, _val: i32) {}
 
     fn add_attack_speed(&self, val: i32) {
@@ -540,14 +784,28 @@ pub trait Entity: Send + Sync {
     }
 
     /// A function called to equip an item from this entity's
-    /// inventory into one of their main slots.
-    fn equip_item(&self, _slot_num: usize) {}
+    /// inventory into one of their main slots. `index` is
+    /// 0-based.
+    fn equip_item(&self, _index: usize) {}
 
-    fn unequip_item(&self, _id: usize) {}
+    /// Moves the equipped item with `id` back into this
+    /// entity's inventory. Fails if there's no room.
+    fn unequip_item(&self, _id: usize) -> Result<(), &'static str> {
+        Err("There's nothing to unequip.")
+    }
 
-    /// A function called to use the item in the specified
-    /// slot, optionally applying its effect to `use_on`.
-    fn use_item(&self, _item_num: usize, _use_on: Option<&Entity>) {}
+    /// The slots this entity can equip items into, if any.
+    fn get_equipment(&self) -> Option<&Equipment> {
+        None
+    }
+
+    /// A function called to use the item at the 0-based
+    /// `index`, optionally applying its effect to `use_on`.
+    fn use_item(&self, _index: usize, _use_on: Option<&Entity>) {}
+
+    /// Variant of `use_item()` which locates the item by
+    /// its id, searching each of the entity's slots.
+    fn use_item_id(&self, _id: usize, _use_on: Option<&Entity>) {}
 
     /// Uses the item in the entity's primary slot on
     /// the entity.
@@ -569,6 +827,12 @@ pub trait Entity: Send + Sync {
         String::from("None")
     }
 
+    /// Retrieves text to display the entity's armor on
+    /// screen.
+    fn get_armor(&self) -> String {
+        String::from("None")
+    }
+
     fn give_money(&self, _amount: u32) {}
 
     fn take_money(&self, _amount: u32) {}
@@ -591,12 +855,39 @@ pub trait Entity: Send + Sync {
 
     fn remove_effect(&self, _name: &str) {}
 
+    /// Returns a copy of every effect currently active
+    /// on this entity.
+    fn get_active_effects(&self) -> Vec<Effect> {
+        Vec::new()
+    }
+
     fn clear_effects(&self) {}
 
+    /// Removes every active effect in the given category,
+    /// reverting any stats they changed.
+    fn clear_effects_of(&self, _category: EffectCategory) {}
+
+    /// The combined change that this entity's active
+    /// effects make to `stat`. Getters for each stat are
+    /// expected to include this.
+    fn get_modifier(&self, _stat: Stat) -> i32 {
+        0
+    }
+
     /// The event that will be called whenever the entity
     /// is killed.
     fn kill_entity(&self);
 
+    /// Records who is attacking this entity, so that the
+    /// killing blow can be credited to them.
+    fn set_last_attacker(&self, _id: usize) {}
+
+    /// Generates the items that this entity drops when
+    /// it is killed.
+    fn get_loot(&self) -> Vec<Box<Item>> {
+        Vec::new()
+    }
+
     /// A convenience method for casting entities to `Player`s.
     fn as_player(&self) -> Option<&Player> {
         None
@@ -621,24 +912,33 @@ pub trait Entity: Send + Sync {
     }
 
     /// An event called by `EntityHolder#add_entity()` that
-    /// fires as the player enters the area.
+    /// fires once the entity has entered the area. The area
+    /// is no longer locked for writing when this runs.
     fn on_enter_area(&self, _coords: (usize, usize, usize)) {}
 
-    /// This entity's type identifier.
-    fn get_type(&self) -> &'static str;
-
-    /// Returns information related to retrieving the entity
-    /// statically from its current position. Does not provide
-    /// a safe, updatable solution for accessing entities, but
-    /// is potentially faster than using reference counters.
-    /// Maybe not, though.
-    fn get_accessor(&self) -> EntityAccessor {
-        EntityAccessor {
+    /// Called regularly for each entity in an area with
+    /// players in it. The area is locked while this runs,
+    /// so entities should queue up anything that affects
+    /// other entities instead of doing it directly.
+    fn on_tick(&self, _area_coords: (usize, usize, usize)) {}
+
+    /// Records this entity's state so that it can be
+    /// restored later. Only players are fully saved.
+    fn save_state(&self) -> PlayerSave {
+        PlayerSave {
+            player_id: self.get_id(),
+            name: self.get_name().clone(),
             coordinates: self.get_coordinates(),
-            entity_id: self.get_id(),
-            is_player: self.get_type() == "player",
+            health: self.get_health(),
+            max_health: self.get_max_health(),
+            base_damage: self.get_base_damage(),
+            money: self.get_money(),
+            ..PlayerSave::default()
         }
     }
+
+    /// This entity's type identifier.
+    fn get_type(&self) -> &'static str;
 }
 
 lazy_static! {
@@ -670,6 +970,11 @@ pub trait Item: ItemTools {
         false
     }
 
+    /// Whether this item is worn in the armor slot.
+    fn is_armor(&self) -> bool {
+        false
+    }
+
     fn as_weapon(&self) -> Option<&Weapon> {
         None
     }
@@ -705,8 +1010,8 @@ pub trait Item: ItemTools {
         None
     }
 
-    /// Whether this item will apply effects to the Entity
-    /// who uses it. To-do: Verify that.
+    /// Whether this item applies its effect to the entity
+    /// it's used on, i.e. whether it can be used on others.
     fn has_entity_effect(&self) -> bool {
         false
     }
@@ -771,6 +1076,30 @@ pub trait Item: ItemTools {
             ),
         }
     }
+
+    /// Describes every stat of this item, for players who
+    /// want a closer look than `get_display_info()` gives.
+    fn get_detail(&self, price_factor: f32) -> String {
+        let mut detail = format!(
+            "{}\n  * Type: lvl {} {}",
+            self.get_name(),
+            self.get_level(),
+            self.get_type()
+        );
+        if let Some(weapon) = self.as_weapon() {
+            detail += &format!("\n  * Dps: ({})", items::format_damage(weapon.get_damage(), weapon.get_speed()));
+        }
+        // Single-use items don't keep count.
+        if self.get_max_uses() > 1 {
+            detail += &format!("\n  * Uses: ({})", items::format_num_uses(self.get_num_uses(), self.get_max_uses()));
+        }
+        detail += &format!(
+            "\n  * Tradable: {}\n  * Price: {}g",
+            if self.is_tradable() { "Yes" } else { "No" },
+            self.get_adjusted_price(price_factor)
+        );
+        detail
+    }
 }
 
 /// A derivable trait which can clone Atomics and Mutexes.
@@ -793,9 +1122,17 @@ pub trait Weapon: Item {
         5
     }
 
+    /// The delay between attacks with this weapon, in ms.
+    fn get_speed(&self) -> u32 {
+        1_000
+    }
+
     fn get_repair_price(&self) -> u32 {
         self.get_price() / 2
     }
+
+    /// An event that fires after this weapon is repaired.
+    fn on_repair(&self) {}
 }
 
 /// //////////////////////////////////////////////////////
@@ -818,18 +1155,24 @@ pub enum AttemptedPurchase {
     Purchase,
 }
 
-/// These are not stored as consistently as the other types,
-/// and thus temporarily require use of raw pointers.
+/// Shops should be created using `shops::register_shop()`
+/// so that their dialogue can locate them again by id.
 pub trait Shop: Send + Sync {
     /// Borrows a reference to this shops `Inventory`.
     fn borrow_inventory(&self) -> &Inventory;
 
-    /// A temporary method used for retrieving a permanent
-    /// reference to this shop. It is not possible to use
-    /// reference counters in this context, due to the fact
-    /// that shops can be stored in many different ways,
-    /// and thus I am looking for a better solution.
-    fn get_ptr(&self) -> *const Shop;
+    /// Borrows the record of items recently sold to this
+    /// shop, which players are allowed to buy back.
+    fn borrow_buyback(&self) -> &BuybackQueue;
+
+    /// This shop's unique identifier, used for retrieving
+    /// it from the shop registry.
+    fn get_id(&self) -> usize;
+
+    /// The title displayed above this shop's trades.
+    fn get_title(&self) -> String {
+        String::from("Trades")
+    }
 
     /// Attempts to sell an item to the shop, returning an
     /// `AttemptedSale` containing the result.
@@ -887,12 +1230,70 @@ pub trait Shop: Send + Sync {
 
             if self.should_restock() {
                 self.restock();
+                self.borrow_buyback().clear();
             }
 
             Purchase
         }
     }
 
+    /// Purchases an item which the player recently sold to
+    /// this shop, at the same price it was sold for.
+    fn buy_back(&self, player: &PlayerMeta, item_id: usize) -> AttemptedPurchase {
+        let player_id = player.get_player_id();
+        let buyback = self.borrow_buyback();
+
+        let price = match buyback.get_entries(player_id).iter().find(|e| e.0 == item_id) {
+            Some(entry) => entry.2,
+            None => return NotFound,
+        };
+        let inventory = self.borrow_inventory();
+        let slot_num = match inventory.get_slot_num(item_id) {
+            Some(num) => num,
+            None => {
+                // Someone else must have bought it.
+                buyback.remove(player_id, item_id);
+                return NotFound;
+            }
+        };
+
+        let (can_afford, can_hold) = inventory.get_item_info(slot_num, 0, |item| {
+            access::entity(player.get_accessor(), |player| {
+                (
+                    player.can_afford(price),
+                    player.get_inventory().unwrap().can_add_item(item),
+                )
+            })
+            .expect("Area no longer contains entity.")
+        });
+
+        if !can_afford {
+            CantAfford
+        } else if !can_hold {
+            CantHold
+        } else {
+            access::entity(player.get_accessor(), |entity| {
+                entity.give_item(inventory.take_item(slot_num, None));
+                entity.take_money(price);
+            });
+            buyback.remove(player_id, item_id);
+
+            Purchase
+        }
+    }
+
+    /// Whether players can purchase inventory upgrades
+    /// from this shop.
+    fn sells_bag_upgrades(&self) -> bool {
+        false
+    }
+
+    /// Whether players can repair their weapons at this
+    /// shop.
+    fn repairs_weapons(&self) -> bool {
+        false
+    }
+
     /// Whether this shop should currently replace its inventory.
     fn should_restock(&self) -> bool {
         self.borrow_inventory().current_size() == 0
@@ -914,7 +1315,7 @@ pub trait Shop: Send + Sync {
         self.get_commands(player, &info, allow_sales, price_factor, &mut commands);
 
         Dialogue {
-            title: String::from("Trades"),
+            title: self.get_title(),
             info: Some(Inventory::format_display_info(&info)),
             responses,
             commands,
@@ -927,63 +1328,202 @@ pub trait Shop: Send + Sync {
         responses.push(Response::text_only("Leave."));
     }
 
-    fn get_commands(&self, _player: &PlayerMeta, items: &Vec<ItemDisplayInfo>, allow_sales: bool, price_factor: f32, commands: &mut Vec<Command>) {
+    fn get_commands(&self, player: &PlayerMeta, items: &Vec<ItemDisplayInfo>, allow_sales: bool, price_factor: f32, commands: &mut Vec<Command>) {
         let mut item_ids = Vec::new();
         items.iter().for_each(|i| item_ids.push(i.item_id));
 
         commands.push(Command {
-            input: String::from("buy #"),
-            output_desc: String::from("Buy item #."),
-            run: self.process_buy(item_ids, price_factor),
+            input: String::from("buy # [#]"),
+            output_desc: String::from("Buy item #, or # of them."),
+            aliases: Vec::new(),
+            run: self.process_buy(item_ids.clone(), price_factor),
             next_dialogue: Generate(self.refresh_dialogue(allow_sales, price_factor)),
         });
 
+        commands.push(self.examine_command(item_ids, price_factor));
+
+        if self.sells_bag_upgrades() {
+            let price = player.entity(|e| {
+                e.get_inventory().and_then(|i| shops::bag_upgrade_price(i.get_max_size()))
+            });
+            if let Some(price) = price {
+                commands.push(Command {
+                    input: String::from("upgrade"),
+                    output_desc: format!("Upgrade your bag for {}g.", price),
+                    aliases: Vec::new(),
+                    run: Box::new(|_args: &Vec<&str>, player: &PlayerMeta| {
+                        match shops::buy_bag_upgrade(player) {
+                            Purchase => {
+                                player.add_short_message("Your bag has been upgraded.");
+                                return Ok(());
+                            }
+                            CantAfford => player.add_short_message("You can't afford that."),
+                            _ => player.add_short_message("Your bag can't get any bigger."),
+                        };
+                        Err(())
+                    }),
+                    next_dialogue: Generate(self.refresh_dialogue(allow_sales, price_factor)),
+                });
+            }
+        }
+
+        if self.repairs_weapons() {
+            commands.push(Command {
+                input: String::from("repair #"),
+                output_desc: String::from("Repair weapon # from inventory."),
+                aliases: Vec::new(),
+                run: self.process_repair(),
+                next_dialogue: Generate(self.refresh_dialogue(allow_sales, price_factor)),
+            });
+        }
+
         if allow_sales {
-            commands.push(Command::simple(
-                "sell #", "Sell item # from inventory.",
-                |_args, player| {
-                    player.send_short_message("Let's just pretend you sold that. ;)");
-                },
-            ));
+            // Items are referenced by id so that the player never
+            // sells something other than what they saw.
+            let sell_ids = player.entity(|e| {
+                e.get_inventory()
+                    .expect("Player does not have an inventory.")
+                    .get_display_info(1.0)
+                    .iter()
+                    .map(|i| i.item_id)
+                    .collect()
+            });
+            commands.push(Command {
+                input: String::from("sell #"),
+                output_desc: String::from("Sell item # from inventory."),
+                aliases: Vec::new(),
+                run: self.process_sell(sell_ids),
+                next_dialogue: Generate(self.refresh_dialogue(allow_sales, price_factor)),
+            });
+            commands.push(Command {
+                input: String::from("buyback #"),
+                output_desc: String::from("Buy back a recently sold item."),
+                aliases: Vec::new(),
+                run: self.process_buy_back(),
+                next_dialogue: Generate(self.refresh_dialogue(allow_sales, price_factor)),
+            });
         }
     }
 
-    // Stylistic improvements needed for the dialogue.
-    fn process_buy(&self, item_ids: Vec<usize>, price_factor: f32, ) -> Box<Fn(&Vec<&str>, &PlayerMeta)> {
-        let ptr = self.get_ptr();
+    fn process_sell(&self, item_ids: Vec<usize>) -> Box<Fn(&Vec<&str>, &PlayerMeta) -> ActionResult> {
+        let shop_id = self.get_id();
 
         Box::new(move |args: &Vec<&str>, player: &PlayerMeta| {
-            if args.len() == 0 {
-                return;
-            }
             if item_ids.len() == 0 {
-                player.send_short_message("There are no items to buy.");
-                return;
+                player.add_short_message("You don't have anything to sell.");
+                return Err(());
             }
-            let shop = unsafe {
-                match ptr.as_ref() {
-                    Some(s) => s,
+            let shop = match shops::get_shop(shop_id) {
+                Some(s) => s,
+                None => {
+                    player.add_short_message("The shop seems to have moved away.");
+                    return Err(());
+                }
+            };
+
+            player.entity(|entity| {
+                let inventory = entity.get_inventory()
+                    .expect("Player does not have an inventory.");
+
+                let index = Command::parse_slot(args, 0, item_ids.len(), player, "Invalid item #.")
+                    .ok_or(())?;
+                let item = match inventory.take_item_id(item_ids[index], Some(entity)) {
+                    Some(i) => i,
                     None => {
-                        player.add_short_message("The shop seems to have moved away.");
-                        return;
+                        player.add_short_message("You don't have that item anymore.");
+                        return Err(());
+                    }
+                };
+                let (item_id, item_name) = (item.get_id(), item.get_name().clone());
+
+                match shop.sell(item) {
+                    Sale(payback) => {
+                        let payback = payback as u32;
+                        entity.give_money(payback);
+                        player.add_short_message(&format!("Sold {} for {}g.", item_name, payback));
+                        shop.borrow_buyback()
+                            .push(player.get_player_id(), item_id, item_name, payback);
+                        Ok(())
+                    }
+                    StoreFull(item) => {
+                        inventory.add_item(item, Some(entity));
+                        player.add_short_message("The shop doesn't have room for that.");
+                        Err(())
                     }
                 }
-            };
-            let item_num: usize = match args[0].parse() {
-                Ok(num) => num,
-                Err(_) => {
-                    player.add_short_message("Not sure which item you're looking for.");
-                    return;
+            })
+        })
+    }
+
+    /// Restores a weapon in the player's inventory to its
+    /// maximum number of uses for `get_repair_price()`.
+    fn process_repair(&self) -> Box<Fn(&Vec<&str>, &PlayerMeta) -> ActionResult> {
+        Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
+            player.entity(|entity| {
+                let inventory = entity.get_inventory()
+                    .expect("Player does not have an inventory.");
+
+                let slot = Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")
+                    .ok_or(())?;
+                inventory.get_item_info(slot, 0, |item| {
+                    let weapon = match item.as_weapon() {
+                        Some(w) => w,
+                        None => {
+                            player.add_short_message("Only weapons can be repaired.");
+                            return Err(());
+                        }
+                    };
+                    if item.get_num_uses() >= item.get_max_uses() {
+                        player.add_short_message("That weapon doesn't need any repairs.");
+                        return Err(());
+                    }
+                    let price = weapon.get_repair_price();
+                    if !entity.can_afford(price) {
+                        player.add_short_message(&format!("You can't afford the {}g repair.", price));
+                        return Err(());
+                    }
+                    entity.take_money(price);
+                    item.set_num_uses(item.get_max_uses());
+                    weapon.on_repair();
+                    player.add_short_message(&format!("Repaired {} for {}g.", item.get_name(), price));
+                    Ok(())
+                })
+            })
+        })
+    }
+
+    /// Usage: `buyback` lists the player's recent sales.
+    /// `buyback #` repurchases sale # from that list.
+    fn process_buy_back(&self) -> Box<Fn(&Vec<&str>, &PlayerMeta) -> ActionResult> {
+        let shop_id = self.get_id();
+
+        Box::new(move |args: &Vec<&str>, player: &PlayerMeta| {
+            let shop = match shops::get_shop(shop_id) {
+                Some(s) => s,
+                None => {
+                    player.add_short_message("The shop seems to have moved away.");
+                    return Err(());
                 }
             };
-            if item_ids.len() < item_num || item_num < 1 {
-                player.add_short_message("I'm afraid I can't tell what you're looking for.");
-                return;
-            }
+            let entries = shop.borrow_buyback().get_entries(player.get_player_id());
 
-            let item_id: usize = item_ids[item_num - 1];
+            if entries.len() == 0 {
+                player.add_short_message("You haven't sold anything here recently.");
+                return Err(());
+            }
+            if args.len() == 0 {
+                let mut list = String::from("Recently sold:");
+                for (index, (_, name, price)) in entries.iter().enumerate() {
+                    list += &format!("\n#{}: {} ({}g)", index + 1, name, price);
+                }
+                player.add_short_message(&list);
+                return Ok(());
+            }
+            let index = Command::parse_slot(args, 0, entries.len(), player, "Invalid sale #.")
+                .ok_or(())?;
+            let (item_id, ref item_name, price) = entries[index];
 
-            match shop.buy(player, item_id, price_factor) {
+            match shop.buy_back(player, item_id) {
                 NotFound => {
                     player.add_short_message("Looks like someone already bought that item.");
                 }
@@ -994,19 +1534,111 @@ pub trait Shop: Send + Sync {
                     player.add_short_message("You don't have enough room.");
                 }
                 Purchase => {
+                    player.add_short_message(&format!("Bought back {} for {}g.", item_name, price));
+                    return Ok(());
+                }
+            };
+            Err(())
+        })
+    }
+
+    /// Usage: `examine #` shows everything about item #
+    /// without buying it.
+    fn examine_command(&self, item_ids: Vec<usize>, price_factor: f32) -> Command {
+        let shop_id = self.get_id();
+
+        Command::action_only("examine #", "Examine item #.", move |args, player| {
+            let index = match Command::parse_slot(args, 0, item_ids.len(), player, "I'm afraid I can't tell what you're looking for.") {
+                Some(i) => i,
+                None => return,
+            };
+            let detail = shops::get_shop(shop_id).and_then(|shop| {
+                let inventory = shop.borrow_inventory();
+                inventory.get_slot_num(item_ids[index])
+                    .map(|slot_num| inventory.get_item_detail(slot_num, price_factor))
+            });
+            match detail {
+                Some(detail) => {
+                    player.send_message(General, &detail);
+                }
+                None => player.send_short_message("Looks like someone already bought that item."),
+            }
+        })
+    }
+
+    // Stylistic improvements needed for the dialogue.
+    fn process_buy(&self, item_ids: Vec<usize>, price_factor: f32, ) -> Box<Fn(&Vec<&str>, &PlayerMeta) -> ActionResult> {
+        let shop_id = self.get_id();
+
+        Box::new(move |args: &Vec<&str>, player: &PlayerMeta| {
+            if args.len() == 0 {
+                return Ok(());
+            }
+            if item_ids.len() == 0 {
+                player.add_short_message("There are no items to buy.");
+                return Err(());
+            }
+            let shop = match shops::get_shop(shop_id) {
+                Some(s) => s,
+                None => {
+                    player.add_short_message("The shop seems to have moved away.");
+                    return Err(());
+                }
+            };
+            let index = Command::parse_slot(args, 0, item_ids.len(), player, "I'm afraid I can't tell what you're looking for.")
+                .ok_or(())?;
+
+            let quantity = if args.len() > 1 {
+                Command::parse_index(args, 1, 1, usize::max_value(), player, "Invalid # of items.")
+                    .ok_or(())?
+            } else {
+                1
+            };
+            let item_id: usize = item_ids[index];
+            let mut num_bought = 0;
+
+            // `buy()` locates the item by id each time, so
+            // a restock simply ends the purchase.
+            let result = loop {
+                if num_bought == quantity {
+                    break Purchase;
+                }
+                match shop.buy(player, item_id, price_factor) {
+                    Purchase => num_bought += 1,
+                    result => break result,
+                }
+            };
+
+            let reason = match result {
+                NotFound if num_bought > 0 => "There are no more left.",
+                NotFound => "Looks like someone already bought that item.",
+                CantAfford => "You can't afford that.",
+                CantHold => "You don't have enough room.",
+                Purchase if quantity == 1 => {
                     player.add_short_message("Purchase successful.");
+                    return Ok(());
+                }
+                Purchase => {
+                    player.add_short_message(&format!("Purchased {} items.", num_bought));
+                    return Ok(());
                 }
             };
+            if num_bought > 0 {
+                player.add_short_message(&format!("Purchased {} of {}. {}", num_bought, quantity, reason));
+                return Ok(());
+            }
+            player.add_short_message(reason);
+            Err(())
         })
     }
 
-    fn refresh_dialogue(&self, allow_sales: bool, price_factor: f32, ) -> Box<Fn(&PlayerMeta) -> Dialogue> {
-        let ptr = self.get_ptr();
+    fn refresh_dialogue(&self, allow_sales: bool, price_factor: f32, ) -> Regenerator {
+        let shop_id = self.get_id();
 
-        Box::new(move |player: &PlayerMeta| {
-            access::area(player.get_coordinates(), move |area| unsafe {
-                match ptr.as_ref() {
-                    Some(ref shop) => shop.get_dialogue(player, allow_sales, price_factor),
+        Arc::new(move |player: &PlayerMeta| {
+            access::area(player.get_coordinates(), move |area| {
+                match shops::get_shop(shop_id) {
+                    Some(shop) => shop.get_dialogue(player, allow_sales, price_factor),
                     None => area.get_dialogue(player),
                 }
             })
@@ -1014,3 +1646,127 @@ pub trait Shop: Send + Sync {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::entities::belongings::LostBelongings;
+    use crate::types::entities::mobs::Mob;
+    use crate::util::timed_events::{self, DelayedEvent};
+
+    use std::thread;
+
+    /// Every area in a freshly generated town that has room
+    /// for another entity and matches `predicate`.
+    fn open_areas<F>(town_num: usize, predicate: F) -> Vec<(usize, usize, usize)>
+        where F: Fn(&Area) -> bool
+    {
+        // `for_each_area_in_town()` skips ungenerated towns.
+        access::town(town_num);
+        let mut open = Vec::new();
+        access::for_each_area_in_town(town_num, |area| {
+            if area.has_room() && predicate(area) {
+                open.push(area.get_coordinates());
+            }
+        });
+        open
+    }
+
+    fn count_copies(id: usize, areas: &[(usize, usize, usize)]) -> usize {
+        areas.iter()
+            .map(|&coords| access::area(coords, |a| a.contains_entity(id)).unwrap())
+            .filter(|&found| found)
+            .count()
+    }
+
+    fn transfer_between(id: usize, a: (usize, usize, usize), b: (usize, usize, usize)) {
+        access::area(a, |from| {
+            access::area(b, |to| from.transfer_to_area(id, to).ok());
+        });
+    }
+
+    #[test]
+    fn racing_transfers_never_lose_an_entity() {
+        crate::test_init();
+        let open = open_areas(323, |_| true);
+        let (a, b) = (open[0], open[1]);
+        let player = player_data::test_player();
+        let id = player.get_player_id();
+        access::area(a, |area| area.add_entity(Box::new(Player::new(player))).ok());
+
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    for _ in 0..250 {
+                        if i % 2 == 0 {
+                            transfer_between(id, a, b);
+                        } else {
+                            transfer_between(id, b, a);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(1, count_copies(id, &[a, b]));
+    }
+
+    #[test]
+    fn transfer_from_inside_an_event() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        // Entering an area with ambience schedules events.
+        let a = open_areas(324, |area| area.ambient_messages().is_empty())[0];
+        let b = open_areas(324, |area| !area.ambient_messages().is_empty())[0];
+        let player = player_data::test_player();
+        let id = player.get_player_id();
+        access::area(a, |area| area.add_entity(Box::new(Player::new(player.clone()))).ok());
+
+        DelayedEvent::no_flags(0, move || transfer_between(id, a, b));
+        timed_events::test_advance_time(1);
+
+        assert_eq!(b, player.get_coordinates());
+        assert!(access::area(b, |area| area.contains_entity(id)).unwrap());
+    }
+
+    #[test]
+    fn ambush_notice_comes_before_the_fight() {
+        crate::test_init();
+        let open = open_areas(331, |_| true);
+        let (a, b) = (open[0], open[1]);
+        let player = player_data::test_player();
+        let id = player.get_player_id();
+        access::area(a, |area| area.add_entity(Box::new(Player::new(player.clone()))).ok());
+        access::area(b, |area| area.add_entity(Box::new(Mob::new())).ok());
+
+        transfer_between(id, a, b);
+        assert!(player.get_general_message().contains("You're ambushed!"));
+
+        let fight = access::area(b, |area| area.fight_sequence(&player)).unwrap();
+        assert_eq!(fight.title, Dialogue::from_area(&player).title);
+    }
+
+    #[test]
+    fn full_area_refuses_and_returns_the_entity() {
+        crate::test_init();
+        let coords = open_areas(329, |_| true)[0];
+
+        access::area(coords, |area| {
+            while area.has_room() {
+                let filler = LostBelongings::new("Filler", coords);
+                assert!(area.add_entity(Box::new(filler)).is_ok());
+            }
+            assert_eq!(area.max_entities(), area.borrow_entity_lock().len());
+
+            let extra = LostBelongings::new("Extra", coords);
+            let extra_id = extra.get_id();
+            match area.add_entity(Box::new(extra)) {
+                Err(returned) => assert_eq!(extra_id, returned.get_id()),
+                Ok(_) => panic!("A full area accepted another entity."),
+            }
+        });
+    }
+}
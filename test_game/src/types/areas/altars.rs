@@ -9,10 +9,12 @@ use crate::util::player_options::Response;
 
 use parking_lot::RwLock;
 use parking_lot::Mutex;
+use rand::random;
 
 #[derive(EntityHolder, AreaTools)]
 pub struct Altar {
     pub god_info: (&'static str, &'static str),
+    class: Class,
     entrance_message: String,
     pub area_title: String,
     pub area_num: usize,
@@ -28,6 +30,7 @@ impl Altar {
 
         Box::new(Altar {
             god_info,
+            class,
             entrance_message,
             area_title: String::from("Altar"),
             area_num,
@@ -51,8 +54,10 @@ impl Area for Altar {
         "altar"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        " A "
+    /// Each altar is a monument to a god of the town's
+    /// class, whose followers alone may approach it.
+    fn required_class(&self) -> Option<Class> {
+        Some(self.class)
     }
 
     fn get_entrance_message(&self) -> Option<String> {
@@ -73,7 +78,8 @@ impl Area for Altar {
     }
 
     fn get_specials(&self, player: &PlayerMeta, responses: &mut Vec<Response>) {
-        let num_uses = player.get_record(self.get_coordinates(), "num_uses");
+        let coords = self.get_coordinates();
+        let num_uses = player.get_record(coords, "num_uses");
 
         if num_uses != 0 {
             responses.push(Response::text_only(
@@ -81,28 +87,84 @@ impl Area for Altar {
             ));
             return;
         }
+        let town_num = self.get_town_num();
+        let prayer_price = get_price(PRAYER_PRICE, town_num);
+        let donation_price = get_price(DONATION_PRICE, town_num);
 
         if player.get_god() == self.god() {
-            responses.push(Response::simple("Pray to the god", |player| {
-                access::entity(player.get_accessor(), |entity| {
-                    let blessing = Effect::positive_altar_effect();
-                    blessing.apply(entity);
-
-                    player.incr_record(player.get_coordinates(), "num_uses");
-                })
-                .expect("Player data no longer exists.");
+            let text = format!("Pray to {} ({}g).", self.god(), prayer_price);
+            responses.push(Response::_simple(text, move |player| {
+                pray(player, coords, prayer_price, Effect::positive_altar_effect);
             }));
         } else {
-            responses.push(Response::simple("Pray to the god", |player| {
-                access::entity(player.get_accessor(), |entity| {
-                    let (blessing, curse) = Effect::normal_altar_effect();
-                    blessing.apply(entity);
-                    curse.apply(entity);
-
-                    player.incr_record(player.get_coordinates(), "num_uses");
-                })
-                .expect("Player data no longer exists.");
+            let text = format!("Pray for a blessing ({}g).", prayer_price);
+            responses.push(Response::_simple(text, move |player| {
+                pray(player, coords, prayer_price, Effect::random_permanent_blessing);
             }));
         }
+        let text = format!("Leave a donation ({}g).", donation_price);
+        responses.push(Response::_simple(text, move |player| {
+            donate(player, coords, donation_price);
+        }));
+        responses.push(Response::simple("§Offer yourself to the god (blessing and curse).", move |player| {
+            access::entity(player.get_accessor(), |entity| {
+                let (blessing, curse) = Effect::normal_altar_effect();
+                blessing.apply(entity);
+                curse.apply(entity);
+
+                player.incr_record(coords, "num_uses");
+            })
+            .expect("Player data no longer exists.");
+        }));
     }
 }
+
+const PRAYER_PRICE: u32 = 150;
+const DONATION_PRICE: u32 = 50;
+const LEVEL_RATE: f32 = 0.25;
+
+/// The chance of a donation being rewarded.
+const DONATION_CHANCE: f32 = 0.35;
+
+/// Prices increase by `LEVEL_RATE` per town.
+fn get_price(base: u32, town_num: usize) -> u32 {
+    (base as f32 * (1.0 + LEVEL_RATE * town_num as f32)) as u32
+}
+
+/// Charges the player and applies the blessing that
+/// `get_blessing` creates. Uses up the altar.
+fn pray(player: &PlayerMeta, coords: (usize, usize, usize), price: u32, get_blessing: fn() -> Effect) {
+    access::entity(player.get_accessor(), |entity| {
+        if !entity.can_afford(price) {
+            player.add_short_message("You can't afford this offering.");
+            return;
+        }
+        entity.take_money(price);
+        get_blessing().apply(entity);
+
+        player.incr_record(coords, "num_uses");
+    })
+    .expect("Player data no longer exists.");
+}
+
+/// Donations are cheaper than prayers, but are only
+/// rewarded some of the time. Rejected donations don't
+/// use up the altar.
+fn donate(player: &PlayerMeta, coords: (usize, usize, usize), price: u32) {
+    access::entity(player.get_accessor(), |entity| {
+        if !entity.can_afford(price) {
+            player.add_short_message("You can't afford this offering.");
+            return;
+        }
+        entity.take_money(price);
+
+        if random::<f32>() > DONATION_CHANCE {
+            player.add_short_message(text::rand_donation_rejected());
+            return;
+        }
+        Effect::random_permanent_blessing().apply(entity);
+
+        player.incr_record(coords, "num_uses");
+    })
+    .expect("Player data no longer exists.");
+}
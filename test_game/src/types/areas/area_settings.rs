@@ -3,11 +3,12 @@ use crate::types::classes::Class;
 
 use crate::types::areas::{
     altars::Altar, bosses::BossRoom, dungeons::Dungeon, fountains::Fountain,
-    gambling_den::GamblingDen, gates::Gate, shop_areas::Pub, stations::Station,
+    gambling_den::GamblingDen, gates::Gate, portals::Portal, shop_areas::Pub,
+    stations::Station,
 };
 
 // Center(deep), Depth
-use crate::types::towns::{CD, D};
+use crate::types::towns::{CD, CURRENT_ROOM_PAT, D, EMPTY_ROOM_PAT};
 
 use self::PathPreference::*;
 
@@ -17,8 +18,14 @@ use parking_lot::Mutex;
 lazy_static! {
     /** Area constructors are registered statically */
     pub static ref AREA_REGISTRY: Mutex<Vec<AreaSettings>> = Mutex::new(Vec::new());
+
+    /** Map icons are registered by area type */
+    static ref ICON_REGISTRY: Mutex<Vec<AreaIcon>> = Mutex::new(Vec::new());
 }
 
+/// Displayed for area types without a registered icon.
+pub const UNKNOWN_ICON: &str = " ? ";
+
 pub fn setup_area_registry() {}
 
 pub fn register(settings: AreaSettings) {
@@ -40,6 +47,47 @@ pub struct AreaSettings {
     pub constructor: fn(Class, usize, (usize, usize, usize)) -> Box<Area>,
 }
 
+/// How an area type appears on the map and in the
+/// legend.
+#[derive(Clone, Copy)]
+pub struct AreaIcon {
+    pub area_type: &'static str,
+    pub icon: &'static str,
+    pub label: &'static str,
+}
+
+/// Registers the icon for an area type, replacing any
+/// it had before. Panics if the icon is already taken,
+/// as the map could no longer be read.
+pub fn register_icon(icon: AreaIcon) {
+    if [EMPTY_ROOM_PAT, CURRENT_ROOM_PAT, UNKNOWN_ICON].contains(&icon.icon) {
+        panic!("The icon {} is reserved for the map itself.", icon.icon);
+    }
+    let mut registry = ICON_REGISTRY.lock();
+    if let Some(other) = registry.iter().find(|i| i.icon == icon.icon && i.area_type != icon.area_type) {
+        panic!(
+            "Area types \"{}\" and \"{}\" can't share the icon {}.",
+            other.area_type, icon.area_type, icon.icon
+        );
+    }
+    registry.retain(|i| i.area_type != icon.area_type);
+    registry.push(icon);
+}
+
+/// Locates the icon registered for `area_type`, if any.
+pub fn get_icon_info(area_type: &str) -> Option<AreaIcon> {
+    ICON_REGISTRY.lock()
+        .iter()
+        .find(|i| i.area_type == area_type)
+        .cloned()
+}
+
+/// Variant of `get_icon_info()` which only returns the
+/// icon, defaulting to `UNKNOWN_ICON`.
+pub fn get_icon(area_type: &str) -> &'static str {
+    get_icon_info(area_type).map_or(UNKNOWN_ICON, |i| i.icon)
+}
+
 pub fn register_vanilla_settings() {
     let gate = AreaSettings {
         min_x: D - 1, // Last area only.
@@ -105,6 +153,14 @@ pub fn register_vanilla_settings() {
         path_pref: OffPath,
         constructor: GamblingDen::new,
     };
+    let portal = AreaSettings {
+        min_x: CD, // Second half.
+        max_x: D - 2,
+        chance: 0.2,
+        class_limits: None,
+        path_pref: OffPath,
+        constructor: Portal::new,
+    };
 
     register(gate);
     register(altar);
@@ -113,5 +169,47 @@ pub fn register_vanilla_settings() {
     register(fountain);
     register(shops);
     register(station);
-    register(gambling_den)
+    register(gambling_den);
+    register(portal);
+
+    register_vanilla_icons();
+}
+
+fn register_vanilla_icons() {
+    let icons = [
+        ("path", "[ ]", "Path"),
+        ("gate", "[G]", "Gate"),
+        ("altar", " A ", "Altar"),
+        ("boss", "[B]", "Boss room"),
+        ("dungeon", " D ", "Dungeon"),
+        ("fountain", "[F]", "Fountain"),
+        ("shop", " S ", "Shop"),
+        ("station", " T ", "Train station"),
+        ("gambling", " M ", "Gambling den"),
+        ("portal", "[O]", "Portal"),
+    ];
+    for &(area_type, icon, label) in icons.iter() {
+        register_icon(AreaIcon { area_type, icon, label });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::classes::Class::Melee;
+
+    #[test]
+    fn every_area_type_has_its_own_icon() {
+        crate::test_init();
+        let icons = ICON_REGISTRY.lock().clone();
+        for (i, icon) in icons.iter().enumerate() {
+            let shared = icons[i + 1..].iter().find(|other| other.icon == icon.icon);
+            assert!(shared.is_none(), "{} shares the icon {}.", icon.area_type, icon.icon);
+        }
+
+        for settings in AREA_REGISTRY.lock().iter() {
+            let area = (settings.constructor)(Melee, 344, (344, 0, 0));
+            assert!(get_icon_info(area.get_type()).is_some(), "{} has no icon.", area.get_type());
+        }
+    }
 }
@@ -34,10 +34,6 @@ impl Area for Dungeon {
         "dungeon"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        " D "
-    }
-
     fn get_entrance_message(&self) -> Option<String> {
         Some(self.entrance_message.clone())
     }
@@ -34,15 +34,17 @@ impl Fountain {
     }
 }
 
+const FOUNTAIN_AMBIENCE: [&str; 3] = [
+    "The water trickles softly.",
+    "A coin glints at the bottom of the fountain.",
+    "The statue atop the fountain seems to be watching you.",
+];
+
 impl Area for Fountain {
     fn get_type(&self) -> &'static str {
         "fountain"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        "[F]"
-    }
-
     fn can_enter(&self, _player: &Player) -> bool {
         true
     }
@@ -51,6 +53,10 @@ impl Area for Fountain {
         Some(self.entrance_message.clone())
     }
 
+    fn ambient_messages(&self) -> &[&'static str] {
+        &FOUNTAIN_AMBIENCE
+    }
+
     fn get_title(&self) -> String {
         self.area_title.clone()
     }
@@ -62,8 +68,11 @@ impl Area for Fountain {
         if successful_donations == 0 {
             let num_donations = player.get_record(coords, "num_donations");
             let price = get_price(num_donations, self.get_town_num());
+            let handful = price * HANDFUL_MULTIPLIER;
             let text = format!("Throw a coin into the fountain ({}g).", price);
-            responses.push(donate_response(text, price, coords));
+            responses.push(donate_response(text, price, price, coords));
+            let text = format!("Throw a handful of coins into the fountain ({}g).", handful);
+            responses.push(donate_response(text, handful, price, coords));
         } else {
             responses.push(Response::text_only(
                 "§The gods have already spoken in your favor (do nothing).",
@@ -75,21 +84,30 @@ impl Area for Fountain {
 const BASE_PRICE: u32 = 10;
 const LEVEL_RATE: f32 = 7.5;
 
+/// How many coins are thrown in a handful.
+const HANDFUL_MULTIPLIER: u32 = 3;
+
 fn get_price(num_donations: u8, level_num: usize) -> u32 {
     (BASE_PRICE + (level_num as f32 * LEVEL_RATE) as u32) * (num_donations as u32 + 1)
 }
 
-fn donate_response(text: String, price: u32, coords: (usize, usize, usize)) -> Response {
+/// A single coin has even odds of being accepted. Each
+/// additional coin halves the chance of rejection.
+fn get_success_chance(amount: u32, price: u32) -> f32 {
+    1.0 - 0.5f32.powf(amount as f32 / price as f32)
+}
+
+fn donate_response(text: String, amount: u32, price: u32, coords: (usize, usize, usize)) -> Response {
     Response::_simple(text, move |player| {
         access::context(player, |town, _, entity| {
-            if !entity.can_afford(price) {
+            if !entity.can_afford(amount) {
                 player.add_short_message("You can't afford this offering.");
                 return;
             }
             player.incr_record(coords, "num_donations");
-            entity.take_money(price);
+            entity.take_money(amount);
 
-            if random() {
+            if random::<f32>() >= get_success_chance(amount, price) {
                 player.add_short_message(text::rand_donation_rejected());
                 return;
             }
@@ -97,7 +115,7 @@ fn donate_response(text: String, price: u32, coords: (usize, usize, usize)) -> R
             player.incr_record(coords, "successful_donations");
 
             let effect = Effect::get_fountain_effect(town.town_num);
-            println!("applying effect.");
+            debug!("Applying fountain effect: {}.", effect.name);
             effect.apply(entity);
 
             if let Temporary(duration) = effect.effect_type {
@@ -42,10 +42,6 @@ impl Area for GamblingDen {
         "gambling"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        " M "
-    }
-
     fn get_title(&self) -> String {
         String::from("Gambling Den")
     }
@@ -1,6 +1,7 @@
 use crate::player_data::PlayerMeta;
 use crate::traits::{Area, Entity};
 use crate::types::classes::Class;
+use crate::types::entities::players::Player;
 use crate::util::access;
 use crate::util::player_options::Response;
 
@@ -39,8 +40,13 @@ impl Area for Gate {
         "gate"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        "[G]"
+    /// The first gate in each town can only be reached
+    /// once the previous town's end gate is unlocked.
+    fn can_enter(&self, _player: &Player) -> bool {
+        if self.is_end_gate() || self.is_starting_town() {
+            return true;
+        }
+        access::town(self.get_town_num() - 1).unlocked()
     }
 
     /**
@@ -78,20 +84,44 @@ impl Area for Gate {
         let current_area = self.coordinates;
 
         if self.is_end_gate() {
+            let town = access::town(self.get_town_num());
             let next_town = self.get_town_num() + 1;
 
-            responses.push(Response::goto_dialogue(
-                "Test going to the next area",
-                move |player| {
-                    access::area(current_area, |old_area| {
-                        access::starting_area(next_town, |new_area| {
-                            old_area.transfer_to_area(player.get_player_id(), new_area);
-                            new_area.get_dialogue(player)
+            if town.unlocked() {
+                responses.push(Response::goto_dialogue(
+                    "Go through the gate",
+                    move |player| {
+                        access::area(current_area, |old_area| {
+                            access::starting_area(next_town, |new_area| {
+                                let can_enter = player.entity(|e| {
+                                    e.as_player().map_or(true, |p| new_area.can_enter(p))
+                                });
+                                if !can_enter {
+                                    player.add_short_message("The gate will not budge.");
+                                    return old_area.get_dialogue(player);
+                                }
+                                if let Err(e) = old_area.transfer_to_area(player.get_player_id(), new_area) {
+                                    player.add_short_message(e);
+                                    return old_area.get_dialogue(player);
+                                }
+                                new_area.get_dialogue(player)
+                            })
                         })
-                    })
-                    .expect("The player's current area could not be relocated.")
-                },
-            ))
+                        .expect("The player's current area could not be relocated.")
+                    },
+                ))
+            } else if town.key_found() {
+                let town_num = self.get_town_num();
+
+                responses.push(Response::goto_dialogue(
+                    "Unlock the gate",
+                    move |player| {
+                        access::town(town_num).set_unlocked(true);
+                        player.add_short_message("The key turns and the gate swings open.");
+                        player.area(|area| area.get_dialogue(player))
+                    },
+                ))
+            }
         } else if !self.is_starting_town() {
             let previous_town = self.get_town_num() - 1;
 
@@ -101,7 +131,10 @@ impl Area for Gate {
                     access::area(current_area, |old_area| {
                         let town = access::town(previous_town);
                         access::area(town.end_gate(), |new_area| {
-                            old_area.transfer_to_area(player.get_player_id(), new_area);
+                            if let Err(e) = old_area.transfer_to_area(player.get_player_id(), new_area) {
+                                player.add_short_message(e);
+                                return old_area.get_dialogue(player);
+                            }
                             new_area.get_dialogue(player)
                         })
                         .expect("Invalid town # or gate coordinates.")
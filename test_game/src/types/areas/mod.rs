@@ -6,5 +6,6 @@ pub mod fountains;
 pub mod gambling_den;
 pub mod gates;
 pub mod paths;
+pub mod portals;
 pub mod shop_areas;
 pub mod stations;
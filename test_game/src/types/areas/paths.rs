@@ -25,19 +25,25 @@ impl Path {
     }
 }
 
+const PATH_AMBIENCE: [&str; 3] = [
+    "A cold breeze passes through.",
+    "You hear footsteps somewhere in the distance.",
+    "Something rustles in the grass nearby.",
+];
+
 impl Area for Path {
     fn get_type(&self) -> &'static str {
         "path"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        "[ ]"
-    }
-
     fn get_entrance_message(&self) -> Option<String> {
         None
     }
 
+    fn ambient_messages(&self) -> &[&'static str] {
+        &PATH_AMBIENCE
+    }
+
     fn get_title(&self) -> String {
         self.area_title.clone()
     }
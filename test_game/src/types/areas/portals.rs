@@ -0,0 +1,145 @@
+use crate::player_data::PlayerMeta;
+use crate::traits::{Area, Entity};
+use crate::types::classes::Class;
+use crate::types::entities::players::Player;
+use crate::util::access;
+use crate::util::player_options::Response;
+use crate::*;
+
+use parking_lot::RwLock;
+use parking_lot::Mutex;
+
+/// The type of area that portals lead to by default.
+const DEFAULT_DESTINATION: &str = "station";
+
+const PORTAL_AMBIENCE: [&str; 3] = [
+    "The portal hums quietly.",
+    "Something on the other side of the portal shifts.",
+    "The air around the portal crackles.",
+];
+
+/// An area containing a portal, which takes players
+/// directly to an area in another town. Portals do not
+/// require a pass.
+#[derive(EntityHolder, AreaTools)]
+pub struct Portal {
+    area_title: String,
+    area_num: usize,
+    entities: RwLock<Vec<Box<Entity>>>,
+    coordinates: (usize, usize, usize),
+    connections: Mutex<Vec<(usize, usize, usize)>>,
+    destination_town: usize,
+    destination_type: &'static str,
+}
+
+impl Portal {
+    /// Generates a portal leading to the next town's
+    /// station.
+    pub fn new(class: Class, area_num: usize, coordinates: (usize, usize, usize)) -> Box<Area> {
+        Self::leading_to(class, area_num, coordinates, coordinates.0 + 1, DEFAULT_DESTINATION)
+    }
+
+    /// Generates a portal leading to the first area of
+    /// type `destination_type` in `destination_town`.
+    /// The destination is not located until the portal
+    /// is used, as its town may not exist yet.
+    pub fn leading_to(
+        _class: Class,
+        area_num: usize,
+        coordinates: (usize, usize, usize),
+        destination_town: usize,
+        destination_type: &'static str,
+    ) -> Box<Area> {
+        Box::new(Portal {
+            area_title: String::from("Portal"),
+            area_num,
+            coordinates,
+            entities: RwLock::new(Vec::new()),
+            connections: Mutex::new(Vec::new()),
+            destination_town,
+            destination_type,
+        })
+    }
+}
+
+impl Area for Portal {
+    fn get_type(&self) -> &'static str {
+        "portal"
+    }
+
+    fn can_enter(&self, _player: &Player) -> bool {
+        true
+    }
+
+    fn get_entrance_message(&self) -> Option<String> {
+        Some(format!(
+            "A shimmering portal stands before you. Through it, \
+             you can just make out town #{}.",
+            self.destination_town
+        ))
+    }
+
+    fn ambient_messages(&self) -> &[&'static str] {
+        &PORTAL_AMBIENCE
+    }
+
+    fn get_title(&self) -> String {
+        self.area_title.clone()
+    }
+
+    fn get_specials(&self, _player: &PlayerMeta, responses: &mut Vec<Response>) {
+        let town_num = self.destination_town;
+        let typ = self.destination_type;
+
+        responses.push(Response::action_only("Step through the portal.", move |player| {
+            match locate_destination(town_num, typ) {
+                Some(coords) => handle_teleport(player, coords),
+                None => {
+                    player.add_short_message("The portal flickers, but nothing happens.");
+                    player.send_current_options();
+                }
+            }
+        }));
+    }
+}
+
+/// Locates the area that a portal leads to, generating
+/// its town if necessary. Returns `None` if the town
+/// has no such area.
+fn locate_destination(town_num: usize, typ: &str) -> Option<(usize, usize, usize)> {
+    if town_num == 0 {
+        return None;
+    }
+    access::town(town_num).locate_area(typ)
+}
+
+/// Responsible for transferring the player through the
+/// portal and displaying the "animation" to the screen.
+fn handle_teleport(player: &PlayerMeta, new_coords: (usize, usize, usize)) {
+    if !access::area(new_coords, |a| a.has_room()).unwrap_or(false) {
+        player.add_short_message("§Something on the other side is blocking the portal.");
+        player.send_current_options();
+        return;
+    }
+    if let Err(_) = try_delete_options(player.get_player_id()) {
+        player.send_short_message(
+            "§You should finish your current \
+             dialogues before moving on."
+        );
+        return;
+    }
+    access::area(player.get_coordinates(), |current_area| {
+        access::area(new_coords, |new_area| {
+            if let Err(e) = current_area.transfer_to_area(player.get_player_id(), new_area) {
+                player.add_short_message(e);
+                register_options(current_area.get_dialogue(player));
+                player.send_current_options();
+                return;
+            }
+            let next = new_area.get_dialogue(player);
+            register_options(next);
+            player.update_options();
+            player.send_blocking_message("∫0.3.∫0.3 .∫0.3 .∫0.3 .∫0.3 .");
+        })
+    });
+}
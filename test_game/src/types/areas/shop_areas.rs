@@ -1,7 +1,7 @@
 use crate::text;
 use crate::traits::{Area, Entity};
 use crate::types::classes::Class;
-use crate::types::entities::npcs::{Shopkeeper, NPC};
+use crate::types::entities::npcs::{QuestGiver, Shopkeeper, Storyteller, NPC};
 use crate::*;
 
 use lazy_static::lazy_static;
@@ -65,6 +65,11 @@ impl Pub {
             class,
             coordinates,
         )));
+        entities.push(Box::new(Storyteller::new(coordinates)));
+        entities.push(Box::new(QuestGiver::new(coordinates)));
+        let num_npcs = entities.iter()
+            .filter(|e| e.get_type() == "npc")
+            .count();
 
         Box::new(Pub {
             owner_name,
@@ -73,7 +78,7 @@ impl Pub {
             area_num,
             coordinates,
             entities: RwLock::new(entities),
-            location_order: random_pub_location_order(2),
+            location_order: random_pub_location_order(num_npcs),
             connections: Mutex::new(Vec::new()),
         })
     }
@@ -84,10 +89,6 @@ impl Area for Pub {
         "shop"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        " S "
-    }
-
     fn get_entrance_message(&self) -> Option<String> {
         let entities = self.entities.read();
         let mut index = 0;
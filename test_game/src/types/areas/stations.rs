@@ -3,7 +3,7 @@ use crate::text;
 use crate::traits::{Area, Entity, Item};
 use crate::types::classes::Class;
 use crate::types::items::pass_books::PassBook;
-use crate::util::access;
+use crate::util::{self, access};
 use crate::util::player_options::{Command, Dialogue, Response};
 use crate::*;
 
@@ -11,6 +11,12 @@ use rand::{thread_rng, Rng};
 use parking_lot::RwLock;
 use parking_lot::Mutex;
 
+/// Reported when a player asks to travel beyond the
+/// range of the current station.
+const OUT_OF_BOUNDS_MESSAGE: &str =
+    "§Sorry, but we can't quite take you home from here. \
+     You'll need to make a connection to get that far.";
+
 static ENTRANCE_TEXT: [&str; 5] = [
     "§Welcome to station #<station>. Our trains can make it \
      as far as <south>km south, while our north-bound travels \
@@ -148,10 +154,6 @@ impl Area for Station {
         "station"
     }
 
-    fn get_map_icon(&self) -> &'static str {
-        " T "
-    }
-
     fn get_entrance_message(&self) -> Option<String> {
         let replacements = vec![
             ("<station>", self.get_town_num().to_string()),
@@ -301,27 +303,10 @@ fn use_pass_command(north_bound: usize, south_bound: usize) -> Command {
 /// `use_pass_command()`. Informs the player of
 /// anything that goes wrong.
 fn parse_use_pass_arguments(args: &Vec<&str>, player: &PlayerMeta, north_bound: usize, south_bound: usize) -> Result<(usize, usize, usize), ()> {
-    // Ensure that there are enough arguments.
-    if args.len() < 1 {
-        player.send_short_message("Excuse me?");
-        return Err(());
-    }
-    // Ensure that the town number can be parsed correctly.
-    let town_num: usize = match args[0].parse() {
-        Ok(num) => num,
-        Err(_) => {
-            player.send_short_message("§I'm not sure exactly where you're trying to go.");
-            return Err(());
-        }
-    };
     // Ensure that the town number is within this station's bounds.
-    if town_num > north_bound || town_num < south_bound {
-        player.send_short_message(
-            "§Sorry, but we can't quite take you home from here. \
-             You'll need to make a connection to get that far."
-        );
-        return Err(());
-    }
+    let voice = ["Excuse me?", "§I'm not sure exactly where you're trying to go.", OUT_OF_BOUNDS_MESSAGE];
+    let town_num = Command::parse_index_voiced(args, 0, south_bound, north_bound, player, voice)
+        .ok_or(())?;
     // Ensure that the player has a valid pass.
     if !player_has_pass(player, town_num) {
         player.send_short_message(
@@ -330,6 +315,13 @@ fn parse_use_pass_arguments(args: &Vec<&str>, player: &PlayerMeta, north_bound:
         );
         return Err(());
     }
+    let station = access::town(town_num).locate_area("station")
+        .expect("This town's station did not generate correctly.");
+
+    if !access::area(station, |a| a.has_room()).unwrap_or(false) {
+        player.send_short_message("§That station is too crowded right now. Try again later.");
+        return Err(());
+    }
     // Assume that the player's current dialogue is an
     // area dialogue and delete it, if so.
     if let Err(_) = try_delete_options(player.get_player_id()) {
@@ -340,8 +332,7 @@ fn parse_use_pass_arguments(args: &Vec<&str>, player: &PlayerMeta, north_bound:
         return Err(());
     }
 
-    Ok(access::town(town_num).locate_area("station")
-        .expect("This town's station did not generate correctly."))
+    Ok(station)
 }
 
 /// Determines whether the entity associated with `player`
@@ -361,7 +352,13 @@ fn player_has_pass(player: &PlayerMeta, town_num: usize) -> bool {
 fn handle_use_pass(player: &PlayerMeta, new_coords: (usize, usize, usize)) {
     access::area(player.get_coordinates(), |current_area| {
         access::area(new_coords, |new_area| {
-            current_area.transfer_to_area(player.get_player_id(), new_area);
+            // The area's dialogue was already deleted.
+            if let Err(e) = current_area.transfer_to_area(player.get_player_id(), new_area) {
+                player.add_short_message(e);
+                register_options(current_area.get_dialogue(player));
+                player.send_current_options();
+                return;
+            }
             let next = new_area.get_dialogue(player);
             register_options(next);
             player.update_options();
@@ -456,7 +453,7 @@ pub fn confirm_purchase_booklet(player: &PlayerMeta, price: u32) {
         );
     };
 
-    let dialogue = Dialogue::confirm_action(player.get_player_id(), true, on_yes, on_no);
+    let dialogue = Dialogue::confirm_action_timeout(player.get_player_id(), on_yes, on_no);
     register_options(dialogue);
     player.update_options();
 }
@@ -503,12 +500,11 @@ fn purchase_pass_command(town_num: usize, north_bound: usize, south_bound: usize
     Command {
         input: String::from("buy #x #y"),
         output_desc: String::from("Buy a pass for town #x with #y uses."),
+        aliases: Vec::new(),
         run: Box::new(move |args: &Vec<&str>, player: &PlayerMeta| {
-            parse_purchase_pass_arguments(args, player, north_bound, south_bound)
-                .ok()
-                .and_then(|(travel_to, num_uses)| {
-                    Some(handle_purchase_pass(player, town_num, travel_to, num_uses))
-                });
+            let (travel_to, num_uses) = parse_purchase_pass_arguments(args, player, north_bound, south_bound)?;
+            handle_purchase_pass(player, town_num, travel_to, num_uses);
+            Ok(())
         }),
         next_dialogue: Ignore,
     }
@@ -517,37 +513,17 @@ fn purchase_pass_command(town_num: usize, north_bound: usize, south_bound: usize
 /// Parses the arguments sent to `purchase_pass_command()`
 /// and informs the user if anything goes wrong.
 fn parse_purchase_pass_arguments(args: &Vec<&str>, player: &PlayerMeta, north_bound: usize, south_bound: usize) -> Result<(usize, u32), ()> {
-    // Make sure enough arguments were specified.
-    if args.len() < 1 {
-        player.send_short_message("Excuse me?");
-        return Err(());
-    }
-    // Make sure the arguments can be parsed correctly.
-    let travel_to: usize = match args[0].parse() {
-        Ok(num) => num,
-        Err(_) => {
-            player.send_short_message("You may need to speak up, there.");
-            return Err(());
-        }
-    };
     // Make sure the station is willing to travel this far.
-    if travel_to > north_bound || travel_to < south_bound {
-        player.send_short_message(
-            "§Sorry, but we can't quite take you home from here. \
-                     You'll need to make a connection to get that far."
-        );
-        return Err(());
-    }
+    let voice = ["Excuse me?", "You may need to speak up, there.", OUT_OF_BOUNDS_MESSAGE];
+    let travel_to = Command::parse_index_voiced(args, 0, south_bound, north_bound, player, voice)
+        .ok_or(())?;
     // Determine the number of uses to purchase the pass with.
-    let num_uses: u32 = if args.len() > 1 {
-        if let Ok(num) = args[1].parse() {
-            num
-        } else { // Invalid argument at position 3
+    let num_uses: u32 = match util::parse_arg_or(args, 1, 1) {
+        Ok(num) => num,
+        Err(_) => {
             player.send_short_message("§I'm not really sure how many uses you're looking for.");
             return Err(());
         }
-    } else {
-        1
     };
     return Ok((travel_to, num_uses))
 }
@@ -611,7 +587,7 @@ fn confirm_purchase_pass(player: &PlayerMeta, price: u32, travel_to: usize, num_
     let on_no = |player: &PlayerMeta| {
         player.add_short_message("That's too bad∫0.2.∫0.2.∫0.2.∫0.3 Let me know if you\nneed anything else.");
     };
-    register_options(Dialogue::confirm_action(player.get_player_id(), true, on_yes, on_no));
+    register_options(Dialogue::confirm_action_timeout(player.get_player_id(), on_yes, on_no));
     player.update_options();
     player.send_blocking_message(&text);
 }
@@ -1,6 +1,6 @@
 extern crate rand;
 
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use std::fmt::{Display, Formatter, Result};
 
@@ -23,8 +23,29 @@ impl Display for Class {
     }
 }
 
-pub fn random_class() -> Class {
-    match thread_rng().gen_range(0, 3) {
+/// The gold and weapon that a class begins the game
+/// with.
+pub struct StartingKit {
+    pub money: u32,
+    /// The type id of the weapon, as understood by
+    /// `item_settings::item_from_type()`.
+    pub weapon: Option<&'static str>,
+}
+
+/// Every class's starting kit, kept in one place so
+/// that they're easy to balance.
+pub fn starting_kit(class: Class) -> StartingKit {
+    match class {
+        Melee => StartingKit { money: 1000, weapon: Some("sword") },
+        Ranged => StartingKit { money: 1000, weapon: Some("bow") },
+        // Staves don't exist yet. Mages get more gold to
+        // make up for it.
+        Magic => StartingKit { money: 1250, weapon: None },
+    }
+}
+
+pub fn random_class<R: Rng>(rng: &mut R) -> Class {
+    match rng.gen_range(0, 3) {
         0 => Melee,
         1 => Ranged,
         2 => Magic,
@@ -1,12 +1,14 @@
 use crate::traits::Entity;
 use crate::util::access::{self, EntityAccessor};
-use crate::util::timed_events::{DelayedEvent, RepeatedEvent};
+use crate::util::timed_events::{self, DelayedEvent, RepeatedEvent};
 use crate::*;
 
+use self::EffectCategory::*;
 use self::EffectType::*;
 
 use rand::distributions::{Sample, Weighted, WeightedChoice};
 use rand::{thread_rng, Rng};
+use std::cmp;
 use std::sync::Arc;
 
 #[derive(Clone, Eq, PartialEq)]
@@ -16,6 +18,26 @@ pub enum EffectType {
     Repeat(u64, u64),
 }
 
+/// Broad groupings of effects, used for clearing
+/// several of them at once.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EffectCategory {
+    Buff,
+    Debuff,
+    Neutral,
+}
+
+/// The stats which temporary effects modify for as long
+/// as they're active. These are never written back to
+/// the entity, so there is nothing to revert on expiry.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Stat {
+    MaxHealth,
+    BaseDamage,
+    AttackSpeed,
+    ItemSpeed,
+}
+
 #[derive(Clone)]
 pub struct Effect {
     pub name: &'static str,
@@ -177,7 +199,7 @@ impl Effect {
         let duration = 60_000 * ((level as u64 / 3) + 1);
 
         Effect {
-            name: "Absorption",
+            name: "Fragile Skin",
             max_health: value,
             health: value,
             level,
@@ -240,7 +262,7 @@ impl Effect {
         }
 
         Effect {
-            name: "Strength",
+            name: "Weakness",
             base_damage: value,
             level,
             effect_type: Temporary(duration),
@@ -405,6 +427,38 @@ impl Effect {
         }
     }
 
+    /// +1 level per 3 * town_num
+    /// +1 variability per 5 * town_num
+    pub fn get_leveled_poison(town_num: usize) -> Effect {
+        let base_level = (town_num / 3) + 1; // Start at level = 1
+        let variability = town_num / 5; // Start at variability = 0;
+
+        let level = thread_rng().gen_range(base_level - variability, base_level + variability + 1);
+
+        Self::leveled_poison(level as u32)
+    }
+
+    /// Max level: 10
+    /// -1 hp per level every 3 seconds
+    /// 15 + (5 seconds per level)
+    pub fn leveled_poison(mut level: u32) -> Effect {
+        if level > 10 {
+            level = 10;
+        } else if level < 1 {
+            level = 1;
+        }
+
+        let duration = 15_000 + (5_000 * level as u64);
+
+        Effect {
+            name: "Poison",
+            health: -1 * level as i32,
+            level,
+            effect_type: Repeat(3_000, duration),
+            ..Self::default()
+        }
+    }
+
     pub fn random_permanent_blessing() -> Effect {
         let mut blessings = [
             Weighted { weight: 1, item: HEALTH },
@@ -642,17 +696,78 @@ impl Effect {
         }
     }
 
+    /// Determines whether this effect is a buff or a
+    /// debuff from the sign of its dominant stat. Speeds
+    /// are delays, so lower values are considered better.
+    pub fn get_category(&self) -> EffectCategory {
+        let stats = [
+            self.health,
+            self.max_health,
+            self.base_damage,
+            self.attack_speed / -100,
+            self.item_speed / -100,
+            self.money / 100,
+        ];
+
+        let dominant = stats.iter()
+            .max_by_key(|s| s.abs())
+            .cloned()
+            .unwrap_or(0);
+
+        if dominant > 0 {
+            Buff
+        } else if dominant < 0 {
+            Debuff
+        } else {
+            Neutral
+        }
+    }
+
+    /// Whether this effect can be removed by a `Cure`.
+    /// Only temporary debuffs are curable, as these are
+    /// the only effects which can be taken back.
+    pub fn is_curable(&self) -> bool {
+        match self.effect_type {
+            Temporary(_) => self.get_category() == Debuff,
+            _ => false,
+        }
+    }
+
+    /// How much this effect changes `stat` while active.
+    pub fn get_modifier(&self, stat: Stat) -> i32 {
+        match stat {
+            Stat::MaxHealth => self.max_health,
+            Stat::BaseDamage => self.base_damage,
+            Stat::AttackSpeed => self.attack_speed,
+            Stat::ItemSpeed => self.item_speed,
+        }
+    }
+
+    /// Whether this effect counts toward its entity's
+    /// `get_modifier()` while active. Repeating effects
+    /// change the entity's stats directly on each tick.
+    pub fn is_modifier(&self) -> bool {
+        match self.effect_type {
+            Repeat(_, _) => false,
+            _ => true,
+        }
+    }
+
     pub fn apply(&self, to_entity: &Entity) {
         let generated = self.generate(to_entity);
         let potion_ref: &'static str = self.name;
         let accessor = to_entity.get_accessor();
 
-        // They store the effect object, but not the actual effect.
-        // generated() takes care of that.
-        to_entity.give_effect(self.clone());
+        // Only one instance of each effect can be active.
+        // Reapplying an effect refreshes it instead of
+        // stacking its stats.
+        let refreshing = to_entity.has_effect(self.name);
 
         match self.effect_type {
             Permanent => {
+                // They store the effect object, but not the actual
+                // effect. generated() takes care of that.
+                to_entity.give_effect(self.clone());
                 generated();
 
                 if let Some(player) = to_entity.as_player() {
@@ -661,7 +776,39 @@ impl Effect {
                 to_entity.remove_effect(potion_ref);
             }
             Temporary(duration) => {
-                generated();
+                if !refreshing {
+                    to_entity.give_effect(self.clone());
+
+                    // The other stats are read through the
+                    // entity's modifiers.
+                    if to_entity.has_effect(self.name) {
+                        self.update_instant(to_entity);
+                        to_entity.update_health_bar();
+                    }
+                }
+
+                // Removal is rescheduled to happen together with
+                // any existing instances, but never further out
+                // than the max. Entities which don't keep track of
+                // their effects still stack them.
+                let refreshed = timed_events::delete_by_flags(None, Some(to_entity.get_id()), Some(self.name));
+                let remaining = refreshed.iter()
+                    .map(|e| e.min_exe_time().saturating_sub(game_time()))
+                    .max()
+                    .unwrap_or(0);
+                let (duration, num_stacks) = if refreshing {
+                    (cmp::max(remaining, duration), cmp::max(refreshed.len(), 1))
+                } else {
+                    (remaining + duration, refreshed.len() + 1)
+                };
+                let duration = cmp::min(duration, MAX_EFFECT_DURATION);
+
+                if refreshing {
+                    if let Some(player) = to_entity.as_player() {
+                        player.send_short_message(&format!("Your {} effect was refreshed.", self.name));
+                    }
+                }
+
                 DelayedEvent::new(
                     duration,
                     None,
@@ -669,12 +816,21 @@ impl Effect {
                     Some(self.name.to_string()),
                     move || {
                         access::entity(accessor, |entity| {
-                            entity.remove_effect(potion_ref);
+                            for _ in 0..num_stacks {
+                                entity.remove_effect(potion_ref);
+                            }
                         });
                     },
                 );
             }
             Repeat(interval, duration) => {
+                // Start over in place of the current instance.
+                if refreshing {
+                    timed_events::delete_by_flags(None, Some(to_entity.get_id()), Some(self.name));
+                    to_entity.remove_effect(potion_ref);
+                }
+                to_entity.give_effect(self.clone());
+
                 RepeatedEvent::new(
                     interval,
                     duration,
@@ -684,36 +840,67 @@ impl Effect {
                     move || generated(),
                 );
 
-                DelayedEvent::no_flags(duration, move || {
+                DelayedEvent::new(
+                    duration,
+                    None,
+                    Some(to_entity.get_id()),
+                    Some(self.name.to_string()),
+                    move || {
+                        access::entity(accessor, |entity| {
+                            entity.remove_effect(potion_ref);
+                        });
+                    },
+                );
+            }
+        }
+    }
+
+    /// Gives back a temporary effect restored from a save.
+    /// Its health and money are already reflected in the
+    /// entity, so it is only scheduled to be removed again.
+    pub fn resume(&self, to_entity: &Entity) {
+        if let Temporary(duration) = self.effect_type {
+            let potion_ref: &'static str = self.name;
+            let accessor = to_entity.get_accessor();
+            to_entity.give_effect(self.clone());
+
+            DelayedEvent::new(
+                duration,
+                None,
+                Some(to_entity.get_id()),
+                Some(self.name.to_string()),
+                move || {
                     access::entity(accessor, |entity| {
                         entity.remove_effect(potion_ref);
                     });
-                });
-            }
+                },
+            );
         }
     }
 
+    /// Called once the effect has been taken away from
+    /// `from_entity`. Its modifiers are already gone, so
+    /// only health and money need to be given back.
     pub fn remove(&self, from_entity: &Entity) {
         if let Temporary(_len) = self.effect_type {
-            let opposite = self.get_opposite_effect();
-
-            if opposite.max_health != 0 {
-                opposite.update_max_health(from_entity);
-            }
-            if opposite.health != 0 {
-                opposite.update_health(from_entity);
+            if self.health > 0 {
+                let current = from_entity.get_health();
+                let health = current.saturating_sub(self.health as u32);
+                // Losing a buff should never be what kills you.
+                from_entity.set_health(cmp::max(health, cmp::min(current, 1)));
+            } else if self.health < 0 {
+                from_entity.add_health(self.health.abs());
             }
-            if opposite.attack_speed != 0 {
-                opposite.update_atk_speed(from_entity);
+            if self.money > 0 {
+                from_entity.take_money(self.money as u32);
+            } else if self.money < 0 {
+                from_entity.give_money(self.money.abs() as u32);
             }
-            if opposite.item_speed != 0 {
-                opposite.update_item_speed(from_entity);
-            }
-            if opposite.base_damage != 0 {
-                opposite.update_base_damage(from_entity);
-            }
-            if opposite.money != 0 {
-                opposite.update_money(from_entity);
+
+            // Don't leave them above their new max.
+            let max = from_entity.get_max_health();
+            if from_entity.get_health() > max {
+                from_entity.set_health(max);
             }
             from_entity.update_health_bar();
 
@@ -723,28 +910,49 @@ impl Effect {
         }
     }
 
-    pub fn get_opposite_effect(&self) -> Effect {
-        Effect {
-            health: self.health * -1,
-            max_health: self.max_health * -1,
-            base_damage: self.base_damage * -1,
-            attack_speed: self.attack_speed * -1,
-            item_speed: self.item_speed * -1,
-            money: self.money * -1,
-            ..Self::default()
+    fn generate(&self, entity: &Entity) -> Arc<Box<'static + Fn() -> bool + Send + Sync>> {
+        Arc::new(standard_effect(self.clone(), entity.get_accessor()))
+    }
+
+    /// Writes every stat this effect changes to `entity`.
+    fn update_stats(&self, entity: &Entity) {
+        if self.max_health != 0 {
+            self.update_max_health(entity);
+        }
+        if self.attack_speed != 0 {
+            self.update_atk_speed(entity);
+        }
+        if self.item_speed != 0 {
+            self.update_item_speed(entity);
         }
+        if self.base_damage != 0 {
+            self.update_base_damage(entity);
+        }
+        self.update_instant(entity);
     }
 
-    fn generate(&self, entity: &Entity) -> Arc<Box<'static + Fn() -> bool + Send + Sync>> {
-        Arc::new(match self.effect_type {
-            Temporary(_dur) if entity.get_type() == "player" => {
-                updatable_effect(self.name, entity.get_accessor())
-            }
-            _ => standard_effect(self.clone(), entity.get_accessor()),
-        })
+    /// Writes the stats which aren't modifiers to `entity`.
+    fn update_instant(&self, entity: &Entity) {
+        if self.health != 0 {
+            self.update_health(entity);
+        }
+        if self.money != 0 {
+            self.update_money(entity);
+        }
     }
 
     fn update_health(&self, entity: &Entity) {
+        // Damage over time never takes the last point of
+        // health. Entities can't be killed from inside of
+        // the event, as it holds their area.
+        if let Repeat(_, _) = self.effect_type {
+            if self.health < 0 {
+                let current = entity.get_health();
+                let damage = cmp::min(self.health.abs() as u32, current.saturating_sub(1));
+                entity.set_health(current - damage);
+                return;
+            }
+        }
         if self.break_health_cap {
             let current = entity.get_health();
             let mut new = current as i32 + self.health;
@@ -757,25 +965,6 @@ impl Effect {
         }
     }
 
-    fn mut_update_health(&mut self, entity: &Entity) {
-        let old = entity.get_health();
-        let mut new;
-
-        if self.break_health_cap {
-            new = old as i32 + self.health;
-            if new < 0 {
-                new = 0;
-            }
-            entity.set_health(new as u32);
-        } else {
-            entity.add_health(self.health);
-            new = entity.get_health() as i32;
-        }
-
-        let difference = new - old as i32;
-        self.health = difference;
-    }
-
     /// Potential clarity improvement:
     /// Don't check this operation here.
     fn update_max_health(&self, entity: &Entity) {
@@ -787,20 +976,6 @@ impl Effect {
         entity.set_max_health(updated as u32);
     }
 
-    fn mut_update_max_health(&mut self, entity: &Entity) {
-        let old = entity.get_max_health();
-
-        let mut new = entity.get_max_health() as i32 + self.max_health;
-        if new < 5 {
-            new = 5;
-        }
-
-        entity.set_max_health(new as u32);
-
-        let difference = new - old as i32;
-        self.max_health = difference;
-    }
-
     fn update_atk_speed(&self, entity: &Entity) {
         if self.break_attack_cap {
             let current = entity.get_attack_speed();
@@ -810,21 +985,6 @@ impl Effect {
         }
     }
 
-    fn mut_update_atk_speed(&mut self, entity: &Entity) {
-        let old = entity.get_attack_speed();
-
-        if self.break_attack_cap {
-            entity.set_attack_speed(old + self.attack_speed);
-        } else {
-            entity.add_attack_speed(self.attack_speed);
-        }
-
-        let new = entity.get_attack_speed();
-
-        let difference = new - old;
-        self.attack_speed = difference;
-    }
-
     fn update_item_speed(&self, entity: &Entity) {
         if self.break_item_cap {
             let current = entity.get_item_speed();
@@ -834,21 +994,6 @@ impl Effect {
         }
     }
 
-    fn mut_update_item_speed(&mut self, entity: &Entity) {
-        let old = entity.get_item_speed();
-
-        if self.break_item_cap {
-            entity.set_item_speed(old + self.item_speed);
-        } else {
-            entity.add_item_speed(self.item_speed);
-        }
-
-        let new = entity.get_item_speed();
-
-        let difference = new - old;
-        self.item_speed = difference;
-    }
-
     fn update_base_damage(&self, entity: &Entity) {
         let current = entity.get_base_damage();
         let mut new = current as i32 + self.base_damage;
@@ -858,18 +1003,6 @@ impl Effect {
         entity.set_base_damage(new as u32);
     }
 
-    fn mut_update_base_damage(&mut self, entity: &Entity) {
-        let old = entity.get_base_damage();
-        let mut new = old as i32 + self.base_damage;
-        if new < 0 {
-            new = 0;
-        } // Prevent the cast from returning a very large number.
-        entity.set_base_damage(new as u32);
-
-        let difference = new - old as i32;
-        self.base_damage = difference;
-    }
-
     fn update_money(&self, entity: &Entity) {
         if self.money < 0 {
             entity.take_money(self.money.abs() as u32);
@@ -877,45 +1010,13 @@ impl Effect {
             entity.give_money(self.money as u32);
         }
     }
-
-    fn mut_update_money(&mut self, entity: &Entity) {
-        let old = entity.get_money();
-
-        if self.money < 0 {
-            entity.take_money(self.money.abs() as u32);
-        } else {
-            entity.give_money(self.money as u32);
-        }
-
-        let new = entity.get_money();
-
-        let difference = new as i32 - old as i32;
-        self.money = difference;
-    }
 }
 
 fn standard_effect(effect: Effect, accessor: EntityAccessor) -> Box<'static + Fn() -> bool + Send + Sync> {
     Box::new(move || {
         match access::entity(accessor, |entity| {
             if entity.has_effect(effect.name) {
-                if effect.max_health != 0 {
-                    effect.update_max_health(entity);
-                }
-                if effect.health != 0 {
-                    effect.update_health(entity);
-                }
-                if effect.attack_speed != 0 {
-                    effect.update_atk_speed(entity);
-                }
-                if effect.item_speed != 0 {
-                    effect.update_item_speed(entity);
-                }
-                if effect.base_damage != 0 {
-                    effect.update_base_damage(entity);
-                }
-                if effect.money != 0 {
-                    effect.update_money(entity);
-                }
+                effect.update_stats(entity);
                 entity.update_health_bar();
                 true
             } else {
@@ -928,39 +1029,86 @@ fn standard_effect(effect: Effect, accessor: EntityAccessor) -> Box<'static + Fn
     })
 }
 
-/// This will update the original effect to ensure
-/// that it can be removed correctly.
-fn updatable_effect(potion_ref: &'static str, accessor: EntityAccessor) -> Box<'static + Fn() -> bool + Send + Sync> {
-    Box::new(move || {
-        match access::entity(accessor, |entity| {
-            if let Some(ref player) = entity.as_player() {
-                player.update_effect(potion_ref, |effect| {
-                    if effect.max_health != 0 {
-                        effect.mut_update_max_health(entity);
-                    }
-                    if effect.health != 0 {
-                        effect.mut_update_health(entity);
-                    }
-                    if effect.attack_speed != 0 {
-                        effect.mut_update_atk_speed(entity);
-                    }
-                    if effect.item_speed != 0 {
-                        effect.mut_update_item_speed(entity);
-                    }
-                    if effect.base_damage != 0 {
-                        effect.mut_update_base_damage(entity);
-                    }
-                    if effect.money != 0 {
-                        effect.mut_update_money(entity);
-                    }
-                    entity.update_health_bar();
-                })
-            } else {
-                false
-            }
-        }) {
-            Some(response) => response,
-            None => false,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data;
+    use crate::types::entities::players::Player;
+
+    #[test]
+    fn clearing_debuffs_keeps_buffs() {
+        let player = Player::new(player_data::test_player());
+        let strength = Effect::leveled_strength(1);
+        let weakness = Effect::leveled_weakness(1);
+        assert_eq!(Buff, strength.get_category());
+        assert_eq!(Debuff, weakness.get_category());
+
+        player.give_effect(strength);
+        player.give_effect(weakness);
+        player.clear_effects_of(Debuff);
+
+        assert!(player.has_effect("Strength"));
+        assert!(!player.has_effect("Weakness"));
+    }
+
+    #[test]
+    fn refreshing_never_extends_past_the_max() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(322);
+        let effect = Effect::leveled_strength(30);
+
+        for _ in 0..5 {
+            player.entity(|e| effect.apply(e));
+            timed_events::test_advance_time(60_000);
         }
-    })
+        player.entity(|e| effect.apply(e));
+
+        timed_events::test_advance_time(MAX_EFFECT_DURATION - 1);
+        assert!(player.entity(|e| e.has_effect("Strength")));
+        timed_events::test_advance_time(1);
+        assert!(!player.entity(|e| e.has_effect("Strength")));
+    }
+
+    #[test]
+    fn opposite_effects_dont_cancel_each_other() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(336);
+        let damage = player.entity(|e| e.get_base_damage());
+        let pairs = [
+            (Effect::leveled_strength(1), Effect::leveled_weakness(1)),
+            (Effect::leveled_absorption(1), Effect::leveled_fragile_skin(1)),
+        ];
+
+        for (buff, debuff) in pairs.iter() {
+            player.entity(|e| buff.apply(e));
+            player.entity(|e| debuff.apply(e));
+            assert!(player.entity(|e| e.has_effect(buff.name)));
+            assert!(player.entity(|e| e.has_effect(debuff.name)));
+        }
+        // Both sides of each pair were applied.
+        assert_eq!(damage, player.entity(|e| e.get_base_damage()));
+    }
+
+    #[test]
+    fn overlapping_effects_expire_independently() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(343);
+        let damage = || player.entity(|e| e.get_base_damage());
+        let base = damage();
+
+        // Lasts two minutes.
+        player.entity(|e| Effect::leveled_strength(2).apply(e));
+        assert_eq!(base + 10, damage());
+        // Lasts one minute.
+        player.entity(|e| Effect::leveled_weakness(1).apply(e));
+        assert_eq!(base + 5, damage());
+
+        timed_events::test_advance_time(60_000);
+        assert_eq!(base + 10, damage());
+        timed_events::test_advance_time(60_000);
+        assert_eq!(base, damage());
+    }
 }
@@ -0,0 +1,156 @@
+use crate::player_data::PlayerMeta;
+use crate::traits::{Entity, EntityCore, Item};
+use crate::types::items::inventories::Inventory;
+use crate::util::access::EntityAccessor;
+use crate::util::player_options::{Dialogue, Response};
+
+use atomic::Ordering::*;
+use atomic::Atomic;
+use rand::random;
+
+/// Gold and items left behind when a player dies or a
+/// mob drops loot. Any player in the same area can
+/// search through and take them, after which they
+/// disappear.
+pub struct LostBelongings {
+    id: usize,
+    name: String,
+    inventory: Inventory,
+    money: Atomic<u32>,
+    coordinates: Atomic<(usize, usize, usize)>,
+}
+
+impl LostBelongings {
+    pub fn new(owner: &str, coordinates: (usize, usize, usize)) -> LostBelongings {
+        LostBelongings {
+            id: random(),
+            name: format!("{}'s belongings", owner),
+            inventory: Inventory::new(usize::max_value()),
+            money: Atomic::new(0),
+            coordinates: Atomic::new(coordinates),
+        }
+    }
+
+    /// Variant of `new()` for loot dropped by a mob.
+    pub fn dropped_by(mob_name: &str, coordinates: (usize, usize, usize)) -> LostBelongings {
+        LostBelongings {
+            name: format!("Loot from {}", mob_name),
+            ..Self::new(mob_name, coordinates)
+        }
+    }
+
+    /// Variant of `new()` for items hidden in the world,
+    /// e.g. the key to each town's end gate.
+    pub fn hidden(name: &str, coordinates: (usize, usize, usize)) -> LostBelongings {
+        LostBelongings {
+            name: name.to_string(),
+            ..Self::new(name, coordinates)
+        }
+    }
+}
+
+/// Moves everything from the belongings with `id` to the
+/// player, removing them from the area.
+fn take_belongings(player: &PlayerMeta, id: usize) {
+    let belongings = match player.area(|a| a.remove_entity(id)) {
+        Some(b) => b,
+        None => {
+            player.add_short_message("Someone else got to them first.");
+            return;
+        }
+    };
+    player.entity(|entity| {
+        entity.give_money(belongings.get_money());
+
+        let inventory = belongings.get_inventory()
+            .expect("Belongings no longer have an inventory.");
+
+        while inventory.current_size() > 0 {
+            entity.give_item(inventory.take_item(0, None));
+        }
+    });
+    player.add_short_message("You took everything.");
+}
+
+impl EntityCore for LostBelongings {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        1
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for LostBelongings {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_inventory(&self) -> Option<&Inventory> {
+        Some(&self.inventory)
+    }
+
+    fn give_item(&self, item: Box<Item>) {
+        self.inventory.add_item(item, None);
+    }
+
+    fn give_money(&self, amount: u32) {
+        self.money.fetch_add(amount, SeqCst);
+    }
+
+    fn get_money(&self) -> u32 {
+        self.money.load(SeqCst)
+    }
+
+    fn get_response_text(&self, _player: &PlayerMeta) -> Option<String> {
+        Some(format!("§Search through {}.", self.name))
+    }
+
+    fn get_dialogue(&self, player: &PlayerMeta) -> Option<Dialogue> {
+        let items = self.inventory.get_display_info(1.0);
+        let mut info = format!("Gold: {}g", self.get_money());
+
+        if items.len() > 0 {
+            info += "\n";
+            info += &Inventory::format_display_info(&items);
+        }
+        let id = self.id;
+        let responses = vec![
+            Response::simple("Take everything.", move |p| take_belongings(p, id)),
+            Response::text_only("Leave them."),
+        ];
+
+        Some(Dialogue {
+            title: self.name.clone(),
+            info: Some(info),
+            responses,
+            player_id: player.get_player_id(),
+            ..Dialogue::default()
+        })
+    }
+
+    fn kill_entity(&self) {}
+
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "belongings"
+    }
+}
@@ -0,0 +1,269 @@
+use crate::player_data::PlayerMeta;
+use crate::text;
+use crate::traits::{Area, Entity, EntityCore, Shop};
+use crate::types::items::shops::{self, TravelingShop};
+use crate::util::access::{self, EntityAccessor};
+use crate::util::player_options::{self, Dialogue, Response};
+use crate::*;
+
+use atomic::Ordering::*;
+use atomic::Atomic;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::random;
+
+use std::sync::Arc;
+
+const NORMAL_DIALOGUE: u8 = 0;
+const TRADES: u8 = 1;
+
+/// How long a merchant stays in each area, in ms.
+const MS_BETWEEN_MOVES: u64 = 90_000;
+
+/// Merchants never set up shop in these types of areas.
+const AVOIDED_AREAS: [&str; 2] = ["gate", "station"];
+
+lazy_static! {
+    /// Moves queued by merchants during `on_tick()`. These
+    /// are carried out by `update_merchants()` once no
+    /// areas are locked.
+    static ref PENDING_MOVES: Mutex<Vec<PendingMove>> = Mutex::new(Vec::new());
+}
+
+/// Whether merchants are allowed to stop in `area`.
+pub fn can_visit(area: &Area) -> bool {
+    !AVOIDED_AREAS.contains(&area.get_type())
+}
+
+/// A merchant at `coords` who is ready to move on.
+struct PendingMove {
+    merchant_id: usize,
+    name: String,
+    trade_title: String,
+    coords: (usize, usize, usize),
+    last_move: Arc<Atomic<u64>>,
+}
+
+impl PendingMove {
+    fn resolve(self) {
+        let from_players = get_player_ids(self.coords);
+
+        // Wait for anyone still looking at the merchant's
+        // wares. They'll be asked again next tick.
+        let mid_trade = from_players.iter()
+            .any(|id| player_options::has_options_titled(*id, &self.trade_title));
+
+        if mid_trade {
+            return;
+        }
+        self.last_move.store(game_time(), SeqCst);
+
+        let destinations: Vec<(usize, usize, usize)> = access::area(self.coords, |a| a.get_connections())
+            .unwrap_or(Vec::new())
+            .into_iter()
+            .filter(|c| access::area(*c, |a| can_visit(a)).unwrap_or(false))
+            .collect();
+
+        if destinations.is_empty() {
+            return;
+        }
+        let to = *choose(&destinations);
+        let id = self.merchant_id;
+
+        let moved = access::area(self.coords, |from| {
+            // Another move may have gotten to it first.
+            if !from.contains_entity(id) {
+                return false;
+            }
+            access::area(to, |dest| from.transfer_to_area(id, dest).is_ok())
+                .unwrap_or(false)
+        })
+        .unwrap_or(false);
+
+        if !moved {
+            return;
+        }
+        let left = format!("{} packs up and moves on.", self.name);
+        let arrived = format!("{}, a traveling merchant, sets up shop nearby.", self.name);
+
+        notify_players(&from_players, &left);
+        notify_players(&get_player_ids(to), &arrived);
+    }
+}
+
+fn get_player_ids(coords: (usize, usize, usize)) -> Vec<usize> {
+    access::area(coords, |area| {
+        area.borrow_entity_lock()
+            .iter()
+            .filter_map(|e| e.as_player())
+            .map(|p| p.get_id())
+            .collect()
+    })
+    .unwrap_or(Vec::new())
+}
+
+fn notify_players(player_ids: &[usize], message: &str) {
+    for id in player_ids {
+        temp_add_short_message(*id, message);
+        if !try_refresh_options(*id) {
+            temp_send_current_options(*id);
+        }
+    }
+}
+
+/// Ticks every merchant and carries out any moves that
+/// they queue up. Unlike mobs, merchants keep traveling
+/// when no one is around.
+pub fn update_merchants() {
+    access::for_each_area(|area| {
+        let coords = area.get_coordinates();
+        area.borrow_entity_lock()
+            .iter()
+            .filter(|e| e.get_type() == "merchant")
+            .for_each(|e| e.on_tick(coords));
+    });
+
+    let moves: Vec<PendingMove> = PENDING_MOVES.lock()
+        .drain(..)
+        .collect();
+
+    for pending in moves {
+        pending.resolve();
+    }
+}
+
+/// An NPC who wanders between the areas of a town,
+/// selling whatever they happen to be carrying.
+pub struct Merchant {
+    id: usize,
+    name: String,
+    description: String,
+    shop: Arc<Shop>,
+    coordinates: Atomic<(usize, usize, usize)>,
+    last_move: Arc<Atomic<u64>>,
+}
+
+impl Merchant {
+    pub fn new(coordinates: (usize, usize, usize)) -> Merchant {
+        let name = text::rand_npc_name();
+        let shop = shops::register_shop(TravelingShop::new(&name, coordinates.0));
+
+        Merchant {
+            id: random(),
+            name,
+            description: String::from("traveling merchant"),
+            shop,
+            coordinates: Atomic::new(coordinates),
+            last_move: Arc::new(Atomic::new(game_time())),
+        }
+    }
+
+    fn get_main_dialogue(&self, player: &PlayerMeta, first_meeting: bool) -> Dialogue {
+        let text = if first_meeting {
+            format!("The name's {}. I don't stay in one place for long, so have a look while you can.", self.name)
+        } else {
+            String::from("Back again? Have a look.")
+        };
+        let mut responses = Vec::new();
+        self.get_trades(player.get_player_id(), &mut responses);
+        responses.push(Response::_text_only(format!("Walk away from {}.", self.display_name_for(player))));
+
+        Dialogue {
+            title: self.display_name_for(player),
+            text: Some(text),
+            responses,
+            player_id: player.get_player_id(),
+            ..Dialogue::default()
+        }
+    }
+}
+
+impl EntityCore for Merchant {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        10
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for Merchant {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_description(&self) -> Option<&String> {
+        Some(&self.description)
+    }
+
+    fn get_response_text(&self, player: &PlayerMeta) -> Option<String> {
+        Some(format!("§Browse the wares of {}.", self.display_name_for(player)))
+    }
+
+    fn get_dialogue(&self, player: &PlayerMeta) -> Option<Dialogue> {
+        let first_meeting = !player.knows_name(self.id);
+        if first_meeting {
+            player.learn_name(self.id);
+        }
+        Some(self.get_main_dialogue(player, first_meeting))
+    }
+
+    fn goto_dialogue(&self, marker: u8, player: &PlayerMeta) -> Option<Dialogue> {
+        match marker {
+            NORMAL_DIALOGUE => Some(self.get_main_dialogue(player, false)),
+            TRADES => Some(self.shop.get_dialogue(player, true, shops::current_price_factor())),
+            _ => panic!(
+                "Error: Somehow skipped to a nonexistent dialogue (#{}).",
+                marker
+            ),
+        }
+    }
+
+    fn get_trades(&self, _player_id: usize, trades: &mut Vec<Response>) {
+        trades.push(Response::goto_entity_dialogue("View wares", TRADES, self.get_accessor()));
+    }
+
+    fn kill_entity(&self) {}
+
+    fn on_enter_area(&self, coords: (usize, usize, usize)) {
+        self.set_coordinates(coords);
+    }
+
+    /// Queues a move once the merchant has stayed in the
+    /// area for `MS_BETWEEN_MOVES`.
+    fn on_tick(&self, area_coords: (usize, usize, usize)) {
+        if game_time().saturating_sub(self.last_move.load(SeqCst)) < MS_BETWEEN_MOVES {
+            return;
+        }
+        PENDING_MOVES.lock().push(PendingMove {
+            merchant_id: self.id,
+            name: self.name.clone(),
+            trade_title: self.shop.get_title(),
+            coords: area_coords,
+            last_move: self.last_move.clone(),
+        });
+    }
+
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "merchant"
+    }
+}
@@ -1,13 +1,196 @@
-use crate::traits::Entity;
+use crate::player_data::CombatEvent;
+use crate::traits::{Entity, Item};
+use crate::types::entities::{belongings::LostBelongings, players};
+use crate::types::items::item_settings;
+use crate::util::access;
+use crate::*;
 
 use atomic::Ordering::*;
 use atomic::Atomic;
-use rand::random;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::{random, thread_rng, Rng};
+
+/// The time between a mob's attacks, in ms, before its
+/// attack speed is applied.
+const BASE_ATTACK_COOLDOWN_MS: i32 = 3_000;
+
+/// Mobs never attack more often than this, in ms.
+const MIN_ATTACK_COOLDOWN_MS: i32 = 500;
+
+/// The chance that a mob drops an item when killed.
+const ITEM_DROP_CHANCE: f32 = 0.4;
+
+/// The most gold a mob can drop, per town number.
+const MAX_GOLD_PER_TOWN: u32 = 10;
+
+lazy_static! {
+    /// Attacks queued by mobs during `on_tick()`. These are
+    /// carried out by `update_mobs()` once no areas are
+    /// locked.
+    static ref PENDING_ATTACKS: Mutex<Vec<PendingAttack>> = Mutex::new(Vec::new());
+
+    /// Mobs killed while their area was locked. These are
+    /// removed by `update_mobs()`, which also hands out
+    /// their loot.
+    static ref PENDING_DEATHS: Mutex<Vec<PendingDeath>> = Mutex::new(Vec::new());
+}
+
+/// An attack on a random player in the area at `coords`.
+struct PendingAttack {
+    mob_name: String,
+    coords: (usize, usize, usize),
+    damage: u32,
+}
+
+impl PendingAttack {
+    fn resolve(self) {
+        let targets: Vec<usize> = access::area(self.coords, |area| {
+            area.borrow_entity_lock()
+                .iter()
+                .filter(|e| e.as_player().is_some() && e.get_health() > 0)
+                .map(|e| e.get_id())
+                .collect()
+        })
+        .unwrap_or(Vec::new());
+
+        if targets.is_empty() {
+            return;
+        }
+        let target = *choose(&targets);
+        let damage = self.damage;
+        let mob_name = self.mob_name;
+
+        // The target may have moved to another area since
+        // being chosen, so make sure they're still here.
+        let lethal = access::area(self.coords, |area| {
+            if !area.contains_entity(target) {
+                return None;
+            }
+            area.borrow_entity_lock()
+                .iter()
+                .find(|e| e.get_id() == target)
+                .map(|e| {
+                    let damage = damage.saturating_sub(e.get_defense()).max(1);
+                    let lethal = damage >= e.get_health();
+                    let event = CombatEvent::new(&mob_name, e.get_name(), damage.min(e.get_health()));
+                    if !lethal {
+                        e.remove_health(damage);
+                    }
+                    (lethal, event)
+                })
+        });
+
+        let (lethal, event) = match lethal {
+            Some(Some(hit)) => hit,
+            _ => return,
+        };
+        player_data::record_combat(None, target, event);
+
+        // Players can't be killed while their area is
+        // locked, so this happens afterward.
+        if lethal {
+            players::kill_player(target);
+        } else if let Some(player) = player_data::find_player(target) {
+            player.send_short_message(&format!("{} attacks you!", mob_name));
+        }
+    }
+}
+
+/// A mob at `coords` that has been killed by `killer`.
+struct PendingDeath {
+    mob_id: usize,
+    coords: (usize, usize, usize),
+    killer: Option<usize>,
+}
+
+impl PendingDeath {
+    /// Removes the mob, leaving its items behind in the
+    /// area and giving its gold to the killer.
+    fn resolve(self) {
+        let mob_id = self.mob_id;
+        let coords = self.coords;
+
+        let dropped = access::area(coords, |area| {
+            let mob = area.remove_entity(mob_id)?;
+            let loot = mob.get_loot();
+            let dropped_items = loot.len() > 0;
+
+            if dropped_items {
+                let belongings = LostBelongings::dropped_by(mob.get_name(), coords);
+                for item in loot {
+                    belongings.give_item(item);
+                }
+                // A full area means the items are lost.
+                let _ = area.add_entity(Box::new(belongings));
+            }
+            let gold = mob.as_mob().map_or(0, |m| m.get_gold_drop());
+            Some((mob.get_name().clone(), gold, dropped_items))
+        })
+        .and_then(|d| d);
+
+        let (name, gold, dropped_items) = match dropped {
+            Some(d) => d,
+            None => return,
+        };
+        let killer = match self.killer.and_then(|id| player_data::find_player(id)) {
+            Some(p) => p,
+            None => return,
+        };
+        killer.entity(|e| e.give_money(gold));
+
+        let mut msg = format!("You defeated {} and found {}g.", name, gold);
+        if dropped_items {
+            msg += " It dropped something.";
+        }
+        // Refresh so that any dropped loot can be searched.
+        killer.add_short_message(&msg);
+        if !try_refresh_options(killer.get_player_id()) {
+            killer.send_current_options();
+        }
+    }
+}
+
+/// Ticks every mob in an area with players and carries
+/// out any attacks that they queue up. Mobs that have
+/// been killed are removed afterward.
+pub fn update_mobs() {
+    access::for_each_area(|area| {
+        if area.contains_mobs() && area.contains_players() {
+            let coords = area.get_coordinates();
+            area.borrow_entity_lock()
+                .iter()
+                .filter(|e| e.as_mob().is_some() && e.get_health() > 0)
+                .for_each(|e| e.on_tick(coords));
+        }
+    });
+
+    let attacks: Vec<PendingAttack> = PENDING_ATTACKS.lock()
+        .drain(..)
+        .collect();
+
+    for attack in attacks {
+        attack.resolve();
+    }
+
+    let deaths: Vec<PendingDeath> = PENDING_DEATHS.lock()
+        .drain(..)
+        .collect();
+
+    for death in deaths {
+        death.resolve();
+    }
+}
+
+#[derive(EntityCore)]
 pub struct Mob {
     id: usize,
     name: String,
     health: Atomic<u32>,
     base_damage: Atomic<u32>,
+    last_attack: Atomic<u64>,
+    last_attacker: Atomic<Option<usize>>,
+    coordinates: Atomic<(usize, usize, usize)>,
 }
 
 impl Mob {
@@ -17,34 +200,149 @@ impl Mob {
             name: String::from("Ordinary Spider"),
             health: Atomic::new(5),
             base_damage: Atomic::new(5),
+            last_attack: Atomic::new(game_time()),
+            last_attacker: Atomic::new(None),
+            coordinates: Atomic::new((0, 0, 0)),
         }
     }
-}
 
-impl Entity for Mob {
-    fn get_id(&self) -> usize {
-        self.id
+    /// A random amount of gold, scaled by the town that
+    /// this mob was killed in.
+    pub fn get_gold_drop(&self) -> u32 {
+        let town_num = self.get_coordinates().0.max(1) as u32;
+        thread_rng().gen_range(1, MAX_GOLD_PER_TOWN * town_num + 1)
     }
+}
 
+impl Entity for Mob {
     fn get_name(&self) -> &String {
         &self.name
     }
 
-    fn set_health(&self, health: u32) {
-        self.health.store(health, SeqCst);
+    fn set_base_damage(&self, val: u32) {
+        self.base_damage.store(val, SeqCst);
     }
 
-    fn get_health(&self) -> u32 {
-        self.health.load(SeqCst)
+    fn get_base_damage(&self) -> u32 {
+        self.base_damage.load(SeqCst)
     }
 
-    fn kill_entity(&self) {}
+    /// Mobs are killed while their area is locked, so
+    /// their removal is queued up.
+    fn kill_entity(&self) {
+        PENDING_DEATHS.lock().push(PendingDeath {
+            mob_id: self.id,
+            coords: self.get_coordinates(),
+            killer: self.last_attacker.load(SeqCst),
+        });
+    }
+
+    fn set_last_attacker(&self, id: usize) {
+        self.last_attacker.store(Some(id), SeqCst);
+    }
+
+    fn get_loot(&self) -> Vec<Box<Item>> {
+        if thread_rng().gen::<f32>() >= ITEM_DROP_CHANCE {
+            return Vec::new();
+        }
+        let town_num = self.get_coordinates().0;
+        vec![item_settings::random_item_for_town(town_num, None)]
+    }
+
+    /// Attacks a random player in the area once the mob's
+    /// attack cooldown has passed.
+    fn on_tick(&self, area_coords: (usize, usize, usize)) {
+        let cooldown = (BASE_ATTACK_COOLDOWN_MS + self.get_attack_speed())
+            .max(MIN_ATTACK_COOLDOWN_MS) as u64;
+        let now = game_time();
+
+        if now.saturating_sub(self.last_attack.load(SeqCst)) < cooldown {
+            return;
+        }
+        self.last_attack.store(now, SeqCst);
+
+        PENDING_ATTACKS.lock().push(PendingAttack {
+            mob_name: self.name.clone(),
+            coords: area_coords,
+            damage: self.get_base_damage(),
+        });
+    }
 
     fn as_mob(&self) -> Option<&Mob> {
         Some(self)
     }
 
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn on_enter_area(&self, coords: (usize, usize, usize)) {
+        self.set_coordinates(coords);
+    }
+
     fn get_type(&self) -> &'static str {
         "mob"
     }
 }
+
+/// A temporary ally summoned by a player. Companions
+/// are not of type `mob`, so they never prevent their
+/// owner from leaving an area.
+#[derive(EntityCore)]
+pub struct Companion {
+    id: usize,
+    name: String,
+    owner_id: usize,
+    health: Atomic<u32>,
+    base_damage: Atomic<u32>,
+    coordinates: Atomic<(usize, usize, usize)>,
+}
+
+impl Companion {
+    pub fn new(name: &str, owner_id: usize, base_damage: u32, coordinates: (usize, usize, usize)) -> Companion {
+        Companion {
+            id: random(),
+            name: String::from(name),
+            owner_id,
+            health: Atomic::new(15),
+            base_damage: Atomic::new(base_damage),
+            coordinates: Atomic::new(coordinates),
+        }
+    }
+
+    pub fn get_owner_id(&self) -> usize {
+        self.owner_id
+    }
+}
+
+impl Entity for Companion {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn set_base_damage(&self, val: u32) {
+        self.base_damage.store(val, SeqCst);
+    }
+
+    fn get_base_damage(&self) -> u32 {
+        self.base_damage.load(SeqCst)
+    }
+
+    fn kill_entity(&self) {}
+
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "companion"
+    }
+}
@@ -1,3 +1,5 @@
+pub mod belongings;
+pub mod merchants;
 pub mod mobs;
 pub mod npcs;
 pub mod players;
@@ -1,16 +1,19 @@
 use crate::player_data::PlayerMeta;
 use crate::text;
-use crate::traits::Entity;
+use crate::traits::{Entity, EntityCore};
 use crate::traits::Shop;
 use crate::types::classes::Class;
-use crate::types::items::consumables::Consumable;
-use crate::types::items::shops::{BlacksmithShop, PersistentShop};
+use crate::types::items::consumables::{Bag, Consumable, Cure};
+use crate::types::items::shops::{self, BlacksmithShop, PersistentShop};
+use crate::util::access::{self, EntityAccessor};
 use crate::util::player_options::{Dialogue, Response};
 
 use atomic::Ordering::*;
 use atomic::Atomic;
 use rand::random;
 
+use std::sync::Arc;
+
 const NORMAL_DIALOGUE: u8 = 0;
 const TRADES: u8 = 1;
 const SPECIAL_TRADES: u8 = 2;
@@ -22,8 +25,8 @@ pub struct NPC {
     introduction_text: Option<String>,
     description: String,
     god: &'static str,
-    food_trades: Box<Shop>,
-    special_trades: Box<Shop>,
+    food_trades: Arc<Shop>,
+    special_trades: Arc<Shop>,
     coordinates: Atomic<(usize, usize, usize)>,
 }
 
@@ -39,10 +42,12 @@ impl NPC {
             introduction_text: None,
             description: info.1.to_string(),
             god: text::rand_god(class),
-            food_trades: Box::new(PersistentShop::new(vec![Box::new(
-                Consumable::poisonous_potato(),
-            )])),
-            special_trades: Box::new(BlacksmithShop::new(coordinates.0)),
+            food_trades: shops::register_shop(PersistentShop::new(vec![
+                Box::new(Consumable::poisonous_potato()),
+                Box::new(Cure::new()),
+                Box::new(Bag::new()),
+            ])),
+            special_trades: shops::register_shop(BlacksmithShop::new(coordinates.0)),
             coordinates: Atomic::new(coordinates),
         }
     }
@@ -58,8 +63,8 @@ impl NPC {
             introduction_text: Some(intro),
             description: info.1.to_string(),
             god: text::rand_god(class),
-            food_trades: Box::new(PersistentShop::new(Vec::new())),
-            special_trades: Box::new(BlacksmithShop::new(coordinates.0)),
+            food_trades: shops::register_shop(PersistentShop::new(Vec::new())),
+            special_trades: shops::register_shop(BlacksmithShop::new(coordinates.0)),
             coordinates: Atomic::new(coordinates),
         }
     }
@@ -114,20 +119,36 @@ impl NPC {
 
     /// Normal Trades
     fn get_normal_trades(&self, player: &PlayerMeta) -> Dialogue {
-        self.food_trades.get_dialogue(player, true, 1.0)
+        self.food_trades.get_dialogue(player, true, shops::current_price_factor())
     }
 
     /// Special Trades
     fn get_special_trades(&self, player: &PlayerMeta) -> Dialogue {
-        self.special_trades.get_dialogue(player, false, 1.0)
+        self.special_trades.get_dialogue(player, false, shops::current_price_factor())
     }
 }
 
-impl Entity for NPC {
+impl EntityCore for NPC {
     fn get_id(&self) -> usize {
         self.id
     }
 
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        10
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for NPC {
     fn get_name(&self) -> &String {
         &self.name
     }
@@ -136,40 +157,29 @@ impl Entity for NPC {
         Some(&self.description)
     }
 
-    fn set_health(&self, _health: u32) {}
-
-    fn get_health(&self) -> u32 {
-        10
-    }
-
     fn get_response_text(&self, player: &PlayerMeta) -> Option<String> {
-        let ret = if player.has_entity_knowledge(self.id) {
+        let name = self.display_name_for(player);
+        let ret = if player.knows_name(self.id) {
             match self.title {
-                Some(ref t) => format!("§Speak to {}: {}", self.get_name(), t),
-                None => format!("§Speak to {}.", self.get_name()),
+                Some(ref t) => format!("§Speak to {}: {}", name, t),
+                None => format!("§Speak to {}.", name),
             }
         } else {
-            format!(
-                "§Speak to the {}. (debug: {})",
-                self.get_description().unwrap().clone(),
-                self.god
-            )
+            format!("§Speak to {}. (debug: {})", name, self.god)
         };
 
         Some(ret)
     }
 
     fn get_dialogue(&self, player: &PlayerMeta) -> Option<Dialogue> {
-        let marker = match player.get_dialogue_marker(self.id) {
-            Some(num) => num as i16,
-            None => -1,
-        };
-        if marker == -1 {
-            player.add_entity_knowledge(self.id);
-            Some(self.get_main_dialogue(player, true))
-        } else {
-            self.goto_dialogue(marker as u8, player)
+        // Trades are not stages of a conversation, so
+        // players always return to the main dialogue.
+        let first_meeting = !player.has_entity_knowledge(self.id);
+        if first_meeting {
+            // NPCs introduce themselves when first met.
+            player.learn_name(self.id);
         }
+        Some(self.get_main_dialogue(player, first_meeting))
     }
 
     fn goto_dialogue(&self, marker: u8, player: &PlayerMeta) -> Option<Dialogue> {
@@ -203,12 +213,310 @@ impl Entity for NPC {
     }
 }
 
+/// An NPC who tells a story over several conversations.
+/// Each player's progress is stored as their dialogue
+/// marker, so they can walk away and pick up later.
+pub struct Storyteller {
+    id: usize,
+    name: String,
+    description: String,
+    coordinates: Atomic<(usize, usize, usize)>,
+}
+
+const STORY: [&str; 4] = [
+    "§Long before the gates were sealed, the towns \
+     were one. Merchants walked freely from end to end.",
+    "§Then the gods quarreled. Each claimed a town \
+     for themselves and sealed it away from the rest.",
+    "§They say the keys to each gate were hidden where \
+     only the faithful would find them.",
+    "§And that, friend, is why you'll find no one who \
+     has ever seen the final gate.",
+];
+
+impl Storyteller {
+    pub fn new(coordinates: (usize, usize, usize)) -> Storyteller {
+        Storyteller {
+            id: random(),
+            name: text::rand_npc_name(),
+            description: String::from("old storyteller"),
+            coordinates: Atomic::new(coordinates),
+        }
+    }
+
+    fn continue_response(&self, next_stage: u8) -> Response {
+        Response::goto_entity_dialogue("Keep listening.", next_stage, self.get_accessor())
+    }
+
+    fn restart_response(&self) -> Response {
+        Response::goto_entity_dialogue("Ask to hear it again.", 0, self.get_accessor())
+    }
+
+    fn walk_away_response(&self, player: &PlayerMeta) -> Response {
+        Response::_text_only(format!("Walk away from {}.", self.display_name_for(player)))
+    }
+}
+
+impl EntityCore for Storyteller {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        10
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for Storyteller {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_description(&self) -> Option<&String> {
+        Some(&self.description)
+    }
+
+    fn get_response_text(&self, player: &PlayerMeta) -> Option<String> {
+        Some(format!("§Speak to {}.", self.display_name_for(player)))
+    }
+
+    fn get_dialogue(&self, player: &PlayerMeta) -> Option<Dialogue> {
+        let marker = match player.get_dialogue_marker(self.id) {
+            Some(num) => num,
+            None => {
+                player.add_entity_knowledge(self.id);
+                0
+            }
+        };
+        self.goto_dialogue(marker, player)
+    }
+
+    fn goto_dialogue(&self, marker: u8, player: &PlayerMeta) -> Option<Dialogue> {
+        let stage = marker as usize;
+        let (text, responses) = if stage < STORY.len() {
+            let text = STORY[stage].to_string();
+            (text, vec![self.continue_response(marker + 1), self.walk_away_response(player)])
+        } else {
+            // The storyteller only gives their name once the
+            // story has been heard in full.
+            player.learn_name(self.id);
+            let text = format!("That's all there is to tell. The name's {}.", self.name);
+            (text, vec![self.restart_response(), self.walk_away_response(player)])
+        };
+
+        Some(Dialogue {
+            title: self.display_name_for(player),
+            text: Some(text),
+            responses,
+            player_id: player.get_player_id(),
+            ..Dialogue::default()
+        })
+    }
+
+    fn kill_entity(&self) {}
+
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "npc"
+    }
+}
+
+/// An NPC who pays the player once for helping them.
+/// Completion is stored in the player's area records,
+/// so the reward can't be claimed again.
+pub struct QuestGiver {
+    id: usize,
+    name: String,
+    description: String,
+    coordinates: Atomic<(usize, usize, usize)>,
+}
+
+const QUEST_OFFER: u8 = 0;
+const QUEST_CONFIRM: u8 = 1;
+
+/// The area record used to mark the quest as done.
+/// Records are keyed by a `&'static str`, so this must
+/// never change between dialogues.
+const QUEST_RECORD: &str = "quest_completed";
+
+const BASE_REWARD: u32 = 25;
+const REWARD_RATE: u32 = 10;
+
+impl QuestGiver {
+    pub fn new(coordinates: (usize, usize, usize)) -> QuestGiver {
+        QuestGiver {
+            id: random(),
+            name: text::rand_npc_name(),
+            description: String::from("weary porter"),
+            coordinates: Atomic::new(coordinates),
+        }
+    }
+
+    fn get_reward(&self) -> u32 {
+        BASE_REWARD + REWARD_RATE * self.get_coordinates().0 as u32
+    }
+
+    fn is_completed(&self, player: &PlayerMeta) -> bool {
+        player.get_record(self.get_coordinates(), QUEST_RECORD) > 0
+    }
+
+    fn get_offer(&self, player: &PlayerMeta) -> Dialogue {
+        let text = format!(
+            "§These crates won't carry themselves down to the \
+             cellar. Give me a hand and I'll pay you {}g.",
+            self.get_reward()
+        );
+        let responses = vec![
+            Response::goto_entity_dialogue("Offer to help.", QUEST_CONFIRM, self.get_accessor()),
+            self.walk_away_response(player),
+        ];
+        Dialogue::simple(self.display_name_for(player), text, responses, player.get_player_id())
+    }
+
+    fn get_confirmation(&self, player: &PlayerMeta) -> Dialogue {
+        let accessor = self.get_accessor();
+        let coords = self.get_coordinates();
+        let reward = self.get_reward();
+
+        Dialogue::confirm_action_then(
+            player.get_player_id(),
+            move |player| complete_quest(player, coords, reward),
+            move |player| get_entity_dialogue(accessor, QUEST_OFFER, player),
+            move |player| get_entity_dialogue(accessor, QUEST_OFFER, player),
+        )
+    }
+
+    fn get_completed(&self, player: &PlayerMeta) -> Dialogue {
+        // The player only learns their name after helping.
+        player.learn_name(self.id);
+        let text = format!("Thanks again for the help. The name's {}.", self.name);
+        let responses = vec![self.walk_away_response(player)];
+        Dialogue::simple(self.display_name_for(player), text, responses, player.get_player_id())
+    }
+
+    fn walk_away_response(&self, player: &PlayerMeta) -> Response {
+        Response::_text_only(format!("Walk away from {}.", self.display_name_for(player)))
+    }
+}
+
+/// Pays the player and records that the quest is done.
+/// Checks the record again in case the player already
+/// confirmed from another dialogue.
+fn complete_quest(player: &PlayerMeta, coords: (usize, usize, usize), reward: u32) {
+    if player.get_record(coords, QUEST_RECORD) > 0 {
+        player.add_short_message("You've already helped with this.");
+        return;
+    }
+    player.entity(|e| e.give_money(reward));
+    player.incr_record(coords, QUEST_RECORD);
+    player.add_short_message(&format!("You carried the crates and received {}g.", reward));
+}
+
+/// Variant of `Response::goto_entity_dialogue()` for use
+/// inside of other dialogues' closures.
+fn get_entity_dialogue(accessor: EntityAccessor, marker: u8, player: &PlayerMeta) -> Dialogue {
+    let dialogue = access::entity(accessor, |e| e.goto_dialogue(marker, player));
+    match dialogue {
+        Some(Some(d)) => d,
+        _ => player.area(|a| {
+            player.add_short_message("They got bored and walked away.");
+            a.get_dialogue(player)
+        }),
+    }
+}
+
+impl EntityCore for QuestGiver {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        10
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for QuestGiver {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_description(&self) -> Option<&String> {
+        Some(&self.description)
+    }
+
+    fn get_response_text(&self, player: &PlayerMeta) -> Option<String> {
+        Some(format!("§Speak to {}.", self.display_name_for(player)))
+    }
+
+    fn get_dialogue(&self, player: &PlayerMeta) -> Option<Dialogue> {
+        player.add_entity_knowledge(self.id);
+        self.goto_dialogue(QUEST_OFFER, player)
+    }
+
+    fn goto_dialogue(&self, marker: u8, player: &PlayerMeta) -> Option<Dialogue> {
+        // The record is checked first so that returning
+        // players never see the offer again.
+        if self.is_completed(player) {
+            return Some(self.get_completed(player));
+        }
+        match marker {
+            QUEST_OFFER => Some(self.get_offer(player)),
+            QUEST_CONFIRM => Some(self.get_confirmation(player)),
+            _ => panic!(
+                "Error: Somehow skipped to a nonexistent dialogue (#{}).",
+                marker
+            ),
+        }
+    }
+
+    fn kill_entity(&self) {}
+
+    fn set_coordinates(&self, coords: (usize, usize, usize)) {
+        self.coordinates.store(coords, SeqCst);
+    }
+
+    fn get_coordinates(&self) -> (usize, usize, usize) {
+        self.coordinates.load(SeqCst)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "npc"
+    }
+}
+
 pub struct Shopkeeper {
     id: usize,
     name: String,
     title: String,
     god: &'static str,
-    shop: Box<Shop>,
+    shop: Arc<Shop>,
 }
 
 impl Shopkeeper {
@@ -219,16 +527,32 @@ impl Shopkeeper {
             name: text::rand_npc_name(),
             title: String::from("Ordinary Blacksmith"),
             god: text::rand_babylonian_god(),
-            shop: Box::new(BlacksmithShop::new(0)),
+            shop: shops::register_shop(BlacksmithShop::new(0)),
         }
     }
 }
 
-impl Entity for Shopkeeper {
+impl EntityCore for Shopkeeper {
     fn get_id(&self) -> usize {
         self.id
     }
 
+    fn set_health(&self, _health: u32) {}
+
+    fn get_health(&self) -> u32 {
+        10
+    }
+
+    fn get_accessor(&self) -> EntityAccessor {
+        EntityAccessor {
+            coordinates: self.get_coordinates(),
+            entity_id: self.id,
+            is_player: false,
+        }
+    }
+}
+
+impl Entity for Shopkeeper {
     fn get_name(&self) -> &String {
         &self.name
     }
@@ -237,12 +561,6 @@ impl Entity for Shopkeeper {
         Some(&self.title)
     }
 
-    fn set_health(&self, _health: u32) {}
-
-    fn get_health(&self) -> u32 {
-        10
-    }
-
     fn kill_entity(&self) {}
 
     fn get_type(&self) -> &'static str {
@@ -1,30 +1,98 @@
+use crate::types::items::equipment::Equipment;
 use crate::types::items::inventories::Inventory;
-use crate::util::timed_events::DelayHandler;
+use crate::util::timed_events::{DelayHandler, DelayedEvent};
 use crate::messages::MessageComponent::*;
-use crate::types::{effects::Effect};
-use crate::traits::{Entity, Item};
+use crate::types::effects::{Effect, EffectCategory, EffectType::Temporary, Stat::{self, *}};
+use crate::types::items::item_settings;
+use crate::player_data::PlayerSave;
+use crate::traits::{Entity, EntityCore, Item, ATTACK_SPEED_MIN, ITEM_SPEED_MIN};
+use crate::types::entities::belongings::LostBelongings;
+use crate::util::access::EntityAccessor;
+use crate::util::player_options::{register_options, remove_all_options};
 use crate::util::access;
 use crate::*;
 
 use atomic::Ordering::*;
 use atomic::Atomic;
 use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
 
+use std::cmp;
 use std::sync::Arc;
 
+/// Kills the player with `player_id` from outside of
+/// their area. Must only be called when no areas are
+/// locked, as the player is removed from their area
+/// before being respawned. `kill_entity()` queues this
+/// for callers that may be holding an area.
+pub fn kill_player(player_id: usize) {
+    let meta = match player_data::find_player(player_id) {
+        Some(m) => m,
+        None => return,
+    };
+    let coords = meta.get_coordinates();
+    let entity = match access::area(coords, |a| a.remove_entity(player_id)) {
+        Some(Some(e)) => e,
+        _ => return,
+    };
+    remove_all_options(player_id);
+    meta.clear_message();
+
+    let belongings = {
+        let player = entity.as_player()
+            .expect("Tried to kill an entity that isn't a player.");
+        player.set_health(player.get_max_health());
+        player.drop_belongings()
+    };
+
+    let dropped = access::area(coords, |current| {
+        access::starting_area(coords.0, |new| {
+            // Stay where they fell if the town is too crowded.
+            let respawn = match new.add_entity(entity) {
+                Ok(_) => new,
+                Err(entity) => {
+                    current.lock_entities().push(entity);
+                    current
+                }
+            };
+            // Nothing is dropped if there's no room for it.
+            let dropped = match belongings {
+                Some(b) if current.has_room() => current.add_entity(b).is_ok(),
+                _ => false,
+            };
+            register_options(respawn.get_dialogue(&meta));
+            dropped
+        })
+    });
+    if dropped == Some(true) {
+        meta.send_blocking_message("You have died. Some of your belongings were left behind.");
+    } else {
+        meta.send_blocking_message("You have died.");
+    }
+}
+
+/// Applies an effect's `modifier` to a speed. As with
+/// `add_attack_speed()`, effects can't take the speed
+/// below `soft_min` on their own.
+fn apply_speed_modifier(base: i32, modifier: i32, soft_min: i32) -> i32 {
+    cmp::max(base + modifier, cmp::min(base, soft_min))
+}
+
+/// Stats are stored without any of the player's active
+/// effects. Each getter adds them back in on read.
 pub struct Player {
     name: String,
     metadata: Arc<PlayerMeta>,
     health: Atomic<u32>,
     base_damage: Atomic<u32>,
+    defense: Atomic<u32>,
     max_health: Atomic<u32>,
     health_bonus: Atomic<u32>, //to-do: convert this into armor points.
     attack_speed: Atomic<i32>,
     item_speed: Atomic<i32>,
     pub main_inventory: Inventory,
     money: Atomic<u32>,
-    weapon_slot: Inventory,
-    offhand_slot: Inventory,
+    equipment: Equipment,
     current_effects: Mutex<Vec<Effect>>,
 }
 
@@ -37,24 +105,64 @@ impl Player {
     pub const MAX_ITEM_SPEED: i32 = 10000;
     pub const MIN_ITEM_SPEED: i32 = -10000;
 
+    /// The fraction of gold and items dropped on death.
+    pub const DEATH_DROP_RATE: f32 = 0.25;
+
     pub fn new(meta: Arc<PlayerMeta>) -> Player {
         Player {
             name: meta.get_name(),
             metadata: meta,
             health: Atomic::new(20),
             base_damage: Atomic::new(5),
+            defense: Atomic::new(0),
             max_health: Atomic::new(20),
             health_bonus: Atomic::new(0),
             attack_speed: Atomic::new(0),
             item_speed: Atomic::new(0),
             main_inventory: Inventory::new(15),
             money: Atomic::new(0),
-            weapon_slot: Inventory::new(1),
-            offhand_slot: Inventory::new(1),
+            equipment: Equipment::new(),
             current_effects: Mutex::new(Vec::new()),
         }
     }
 
+    /// Rebuilds a player from `save`. Items which can no
+    /// longer be constructed are dropped. Only temporary
+    /// effects are restored.
+    pub fn from_save(meta: Arc<PlayerMeta>, save: &PlayerSave) -> Player {
+        let player = Player::new(meta);
+        player.health.store(save.health, SeqCst);
+        player.health_bonus.store(save.health_bonus, SeqCst);
+        player.max_health.store(save.max_health, SeqCst);
+        player.base_damage.store(save.base_damage, SeqCst);
+        player.defense.store(save.defense, SeqCst);
+        player.attack_speed.store(save.attack_speed, SeqCst);
+        player.item_speed.store(save.item_speed, SeqCst);
+        player.money.store(save.money, SeqCst);
+        player.main_inventory.set_max_size(save.inventory_size);
+
+        // Stats were saved with equipment applied, so nothing
+        // is equipped a second time. Effects are saved apart
+        // from them and are resumed below.
+        for item in save.items.iter().filter_map(item_settings::item_from_save) {
+            player.main_inventory.add_item(item, None);
+        }
+        let slots = vec![
+            (&save.weapon, &player.equipment.primary),
+            (&save.offhand, &player.equipment.secondary),
+            (&save.armor, &player.equipment.armor),
+        ];
+        for (item, slot) in slots {
+            if let Some(item) = item.as_ref().and_then(item_settings::item_from_save) {
+                slot.add_item(item, None);
+            }
+        }
+        for effect in &save.effects {
+            effect.resume(&player);
+        }
+        player
+    }
+
     pub fn send_message(&self, typ: MessageComponent, msg: &str) -> DelayHandler {
         self.metadata.send_message(typ, msg)
     }
@@ -63,16 +171,6 @@ impl Player {
         self.metadata.send_short_message(msg);
     }
 
-    /// This is used to correct effect values so that removing
-    /// the effect will properly restore the original levels.
-    pub fn update_effect<F: FnOnce(&mut Effect)>(&self, name: &str, callback: F) -> bool {
-        self.current_effects.lock()
-            .iter_mut()
-            .find(|e| e.name == name)
-            .and_then(|e| Some(callback(e)))
-            .is_some()
-    }
-
     pub fn has_special_item(&self, typ: &str, _info: Option<&str>) -> bool {
         self.main_inventory.for_each_item(|item| {
             if item.get_type() == typ {
@@ -83,29 +181,34 @@ impl Player {
         })
         .is_some()
     }
-}
-
-impl Entity for Player {
-    fn get_id(&self) -> usize {
-        self.metadata.get_player_id()
-    }
 
-    fn get_name(&self) -> &String {
-        &self.name
-    }
+    /// Takes `DEATH_DROP_RATE` of the player's gold and a
+    /// random selection of their items. Returns `None` if
+    /// there was nothing to drop.
+    fn drop_belongings(&self) -> Option<Box<Entity>> {
+        let money = (self.get_money() as f32 * Self::DEATH_DROP_RATE) as u32;
+        let num_slots = self.main_inventory.current_size();
+        let num_items = (num_slots as f32 * Self::DEATH_DROP_RATE).ceil() as usize;
 
-    fn set_max_health(&self, val: u32) {
-        if val > Self::MAX_HEALTH {
-            self.max_health.store(Self::MAX_HEALTH, SeqCst);
-        } else if val < Self::MIN_HEALTH {
-            self.max_health.store(Self::MIN_HEALTH, SeqCst);
-        } else {
-            self.max_health.store(val, SeqCst);
+        if money == 0 && num_items == 0 {
+            return None;
         }
+        let belongings = LostBelongings::new(&self.name, self.get_coordinates());
+        self.take_money(money);
+        belongings.give_money(money);
+
+        let mut rng = thread_rng();
+        for _ in 0..num_items {
+            let slot = rng.gen_range(0, self.main_inventory.current_size());
+            belongings.give_item(self.main_inventory.take_item(slot, Some(self)));
+        }
+        Some(Box::new(belongings))
     }
+}
 
-    fn get_max_health(&self) -> u32 {
-        self.max_health.load(SeqCst)
+impl EntityCore for Player {
+    fn get_id(&self) -> usize {
+        self.metadata.get_player_id()
     }
 
     fn set_health(&self, health: u32) {
@@ -117,48 +220,70 @@ impl Entity for Player {
         self.health.load(SeqCst) + self.health_bonus.load(SeqCst)
     }
 
+    fn get_accessor(&self) -> EntityAccessor {
+        self.metadata.get_accessor()
+    }
+}
+
+impl Entity for Player {
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn set_max_health(&self, val: u32) {
+        let base = val as i32 - self.get_modifier(MaxHealth);
+        self.max_health.store(cmp::max(base, 0) as u32, SeqCst);
+    }
+
+    fn get_max_health(&self) -> u32 {
+        let max = self.max_health.load(SeqCst) as i32 + self.get_modifier(MaxHealth);
+        cmp::min(cmp::max(max, Self::MIN_HEALTH as i32), Self::MAX_HEALTH as i32) as u32
+    }
+
     fn update_health_bar(&self) {
         self.metadata.update_message(HealthBar, &self.get_health_bar());
     }
 
     fn set_base_damage(&self, val: u32) {
-        if val < Self::MIN_DAMAGE {
-            self.base_damage.store(Self::MIN_DAMAGE, SeqCst);
-        } else {
-            self.base_damage.store(val, SeqCst);
-        }
+        let base = val as i32 - self.get_modifier(BaseDamage);
+        self.base_damage.store(cmp::max(base, 0) as u32, SeqCst);
     }
 
     fn get_base_damage(&self) -> u32 {
-        self.base_damage.load(SeqCst)
+        let damage = self.base_damage.load(SeqCst) as i32 + self.get_modifier(BaseDamage);
+        cmp::max(damage, Self::MIN_DAMAGE as i32) as u32
+    }
+
+    fn set_defense(&self, val: u32) {
+        self.defense.store(val, SeqCst);
+    }
+
+    fn get_defense(&self) -> u32 {
+        self.defense.load(SeqCst)
     }
 
     fn set_attack_speed(&self, val: i32) {
-        if val > Self::MAX_ATK_SPEED {
-            self.attack_speed.store(Self::MAX_ATK_SPEED, SeqCst);
-        } else if val < Self::MIN_ATK_SPEED {
-            self.attack_speed.store(Self::MIN_ATK_SPEED, SeqCst);
-        } else {
-            self.attack_speed.store(val, SeqCst);
-        }
+        let val = cmp::min(cmp::max(val, Self::MIN_ATK_SPEED), Self::MAX_ATK_SPEED);
+        self.attack_speed.store(val - self.get_modifier(AttackSpeed), SeqCst);
     }
 
     fn get_attack_speed(&self) -> i32 {
-        self.attack_speed.load(SeqCst)
+        let speed = apply_speed_modifier(self.attack_speed.load(SeqCst), self.get_modifier(AttackSpeed), ATTACK_SPEED_MIN);
+        cmp::min(cmp::max(speed, Self::MIN_ATK_SPEED), Self::MAX_ATK_SPEED)
     }
 
     fn set_item_speed(&self, val: i32) {
-        if val > Self::MAX_ITEM_SPEED {
-            self.item_speed.store(Self::MAX_ITEM_SPEED, SeqCst);
-        } else if val < Self::MIN_ITEM_SPEED {
-            self.item_speed.store(Self::MIN_ITEM_SPEED, SeqCst);
-        } else {
-            self.item_speed.store(val, SeqCst);
-        }
+        let val = cmp::min(cmp::max(val, Self::MIN_ITEM_SPEED), Self::MAX_ITEM_SPEED);
+        self.item_speed.store(val - self.get_modifier(ItemSpeed), SeqCst);
     }
 
     fn get_item_speed(&self) -> i32 {
-        self.item_speed.load(SeqCst)
+        let speed = apply_speed_modifier(self.item_speed.load(SeqCst), self.get_modifier(ItemSpeed), ITEM_SPEED_MIN);
+        cmp::min(cmp::max(speed, Self::MIN_ITEM_SPEED), Self::MAX_ITEM_SPEED)
     }
 
     fn get_inventory(&self) -> Option<&Inventory> {
@@ -173,28 +298,27 @@ impl Entity for Player {
         if let Some(item) = self.main_inventory.take_item_id(id, Some(self)) {
             return Some(item);
         }
-        if let Some(item) = self.weapon_slot.take_item_id(id, Some(self)) {
-            return Some(item);
+        let item = self.equipment.take_item_id(id, self);
+        if item.is_some() {
+            self.update_health_bar();
         }
-        self.offhand_slot.take_item_id(id, Some(self))
+        item
+    }
+
+    fn get_equipment(&self) -> Option<&Equipment> {
+        Some(&self.equipment)
     }
 
-    fn equip_item(&self, slot_num: usize) {
-        if slot_num > self.main_inventory.current_size() {
+    fn equip_item(&self, index: usize) {
+        if index >= self.main_inventory.current_size() {
             return;
         }
 
-        let is_weapon = self.main_inventory.get_item_info(slot_num - 1, 0, |item| {
+        let slot = self.main_inventory.get_item_info(index, 0, |item| {
             item.on_equip(self);
-            item.is_weapon()
+            self.equipment.slot_for(item)
         });
 
-        let slot = if is_weapon {
-            &self.weapon_slot
-        } else {
-            &self.offhand_slot
-        };
-
         if slot.current_size() > 0 {
             slot.get_item_info(0, 0, |item| {
                 item.on_unequip(self);
@@ -202,62 +326,96 @@ impl Entity for Player {
 
             slot.transfer(0, &self.main_inventory, None, None);
         }
-        self.main_inventory.transfer(slot_num - 1, slot, None, None);
+        self.main_inventory.transfer(index, slot, None, None);
+
+        self.update_health_bar();
+    }
+
+    fn unequip_item(&self, id: usize) -> Result<(), &'static str> {
+        let slot = self.equipment.find_slot(id)
+            .ok_or("That item isn't equipped.")?;
+
+        if !self.main_inventory.can_hold_more() {
+            return Err("Your inventory is full.");
+        }
+        slot.get_item_info(0, 0, |item| {
+            item.on_unequip(self);
+        });
+        slot.transfer(0, &self.main_inventory, None, None);
 
         self.update_health_bar();
+        Ok(())
     }
 
-    fn use_item(&self, item_num: usize, use_on: Option<&Entity>) {
-        if self.main_inventory.current_size() < item_num {
+    fn use_item(&self, index: usize, use_on: Option<&Entity>) {
+        if index >= self.main_inventory.current_size() {
             temp_send_short_message(self.get_id(), "Invalid item #.");
             return;
         }
 
         access::area(self.get_coordinates(), |area| {
             self.main_inventory
-                .on_use_item(item_num - 1, Some(self), use_on, area);
+                .on_use_item(index, Some(self), use_on, area);
+        })
+        .expect("The player's current area could not be found.");
+    }
+
+    fn use_item_id(&self, id: usize, use_on: Option<&Entity>) {
+        let found = self.main_inventory.get_slot_num(id)
+            .map(|slot_num| (&self.main_inventory, slot_num))
+            .or_else(|| {
+                self.equipment.primary.get_slot_num(id)
+                    .map(|slot_num| (&self.equipment.primary, slot_num))
+            });
+
+        let (inventory, slot_num) = match found {
+            Some(f) => f,
+            None => {
+                self.metadata.send_short_message("This item no longer exists.");
+                return;
+            }
+        };
+
+        access::area(self.get_coordinates(), |area| {
+            inventory.on_use_item(slot_num, Some(self), use_on, area);
         })
         .expect("The player's current area could not be found.");
     }
 
     fn use_primary(&self) {
-        if self.weapon_slot.current_size() < 1 {
+        if self.equipment.primary.current_size() < 1 {
             self.metadata.send_short_message("This item no longer exists.");
             return;
         }
 
         access::area(self.get_coordinates(), |area| {
-            self.weapon_slot.on_use_item(0, Some(self), None, area);
+            self.equipment.primary.on_use_item(0, Some(self), None, area);
         })
         .expect("The player's current area could not be found.");
     }
 
     fn use_secondary(&self) {
-        if self.offhand_slot.current_size() < 1 {
+        if self.equipment.secondary.current_size() < 1 {
             self.metadata.send_short_message("This item no longer exists.");
             return;
         }
 
         access::area(self.get_coordinates(), |area| {
-            self.offhand_slot.on_use_item(0, Some(self), None, area);
+            self.equipment.secondary.on_use_item(0, Some(self), None, area);
         })
         .expect("The player's current area could not be found.");
     }
 
     fn get_primary(&self) -> String {
-        if self.weapon_slot.current_size() > 0 {
-            return self.weapon_slot
-                .get_item_info(0, 0, |item| item.get_name().clone());
-        }
-        String::from("None")
+        Equipment::get_item_name(&self.equipment.primary)
     }
 
     fn get_secondary(&self) -> String {
-        if self.offhand_slot.current_size() > 0 {
-            return self.offhand_slot
-                .get_item_info(0, 0, |item| item.get_name().clone());
-        }
-        String::from("None")
+        Equipment::get_item_name(&self.equipment.secondary)
+    }
+
+    fn get_armor(&self) -> String {
+        Equipment::get_item_name(&self.equipment.armor)
     }
 
     fn give_money(&self, amount: u32) {
@@ -296,25 +454,58 @@ impl Entity for Player {
     }
 
     fn remove_effect(&self, name: &str) {
-        let mut effects = self.current_effects.lock();
-        effects.iter()
-            .position(|e| e.name == name)
-            .and_then(|i| {
-                let effect = effects.remove(i);
-                Some(effect.remove(self))
-            });
+        // Release the lock before reverting the effect.
+        let removed = {
+            let mut effects = self.current_effects.lock();
+            effects.iter()
+                .position(|e| e.name == name)
+                .map(|i| effects.remove(i))
+        };
+        if let Some(effect) = removed {
+            effect.remove(self);
+        }
+    }
+
+    fn get_modifier(&self, stat: Stat) -> i32 {
+        self.current_effects.lock()
+            .iter()
+            .filter(|e| e.is_modifier())
+            .map(|e| e.get_modifier(stat))
+            .sum()
+    }
+
+    fn get_active_effects(&self) -> Vec<Effect> {
+        self.current_effects.lock().clone()
     }
 
     fn clear_effects(&self) {
         self.current_effects.lock().clear();
     }
 
+    fn clear_effects_of(&self, category: EffectCategory) {
+        // Release the lock before reverting each effect.
+        let removed: Vec<Effect> = self.current_effects.lock()
+            .drain_filter(|e| e.get_category() == category)
+            .collect();
+
+        for effect in removed {
+            effect.remove(self);
+        }
+    }
+
+    /// Whoever took the last of this player's health is
+    /// most likely holding their area, so the player is
+    /// killed once it has been released. Only the first
+    /// lethal hit counts.
     fn kill_entity(&self) {
-        self.metadata.area(|current| {
-            let current_town = current.get_coordinates().0;
-            access::starting_area(current_town, |new| {
-                current.transfer_to_area(self.get_id(), new)
-            });
+        let player_id = self.get_id();
+        DelayedEvent::no_flags(0, move || {
+            let dead = player_data::find_player(player_id)
+                .and_then(|p| access::entity(p.get_accessor(), |e| e.get_health() == 0))
+                .unwrap_or(false);
+            if dead {
+                kill_player(player_id);
+            }
         });
     }
 
@@ -332,9 +523,96 @@ impl Entity for Player {
 
     fn on_enter_area(&self, coords: (usize, usize, usize)) {
         self.set_coordinates(coords);
+        self.metadata.clear_general();
+        self.metadata.start_ambience(coords);
+    }
+
+    fn save_state(&self) -> PlayerSave {
+        let effects = self.get_active_effects()
+            .into_iter()
+            .filter(|e| match e.effect_type {
+                Temporary(_) => true,
+                _ => false,
+            })
+            .collect();
+
+        PlayerSave {
+            player_id: self.get_id(),
+            name: self.name.clone(),
+            god: self.metadata.get_god(),
+            class: self.metadata.get_class(),
+            settings: self.metadata.get_settings(),
+            coordinates: self.get_coordinates(),
+            health: self.health.load(SeqCst),
+            health_bonus: self.health_bonus.load(SeqCst),
+            max_health: self.max_health.load(SeqCst),
+            base_damage: self.base_damage.load(SeqCst),
+            defense: self.get_defense(),
+            attack_speed: self.attack_speed.load(SeqCst),
+            item_speed: self.item_speed.load(SeqCst),
+            money: self.get_money(),
+            effects,
+            inventory_size: self.main_inventory.get_max_size(),
+            items: self.main_inventory.get_save_info(),
+            weapon: self.equipment.primary.get_save_info().pop(),
+            offhand: self.equipment.secondary.get_save_info().pop(),
+            armor: self.equipment.armor.get_save_info().pop(),
+        }
     }
 
     fn get_type(&self) -> &'static str {
         "player"
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::timed_events;
+
+    #[test]
+    fn lethal_hit_respawns_in_the_starting_area() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(326);
+        let start = player.get_coordinates();
+        player.entity(|e| {
+            e.give_money(100);
+            e.remove_health(e.get_health());
+            e.remove_health(1);
+        });
+
+        timed_events::test_advance_time(1);
+        assert_eq!(start, player.get_coordinates());
+        assert_eq!(75, player.entity(|e| e.get_money()));
+        assert!(player.entity(|e| e.get_health() == e.get_max_health()));
+        assert!(player.get_general_message().contains("belongings were left behind"));
+    }
+
+    #[test]
+    fn dying_with_nothing_drops_nothing() {
+        crate::test_init();
+        let player = player_data::test_player_in_town(339);
+        let coords = player.get_coordinates();
+        let entities = access::area(coords, |a| a.borrow_entity_lock().len());
+
+        kill_player(player.get_player_id());
+        let message = player.get_general_message();
+        assert!(message.contains("You have died."));
+        assert!(!message.contains("belongings"));
+        assert_eq!(entities, access::area(coords, |a| a.borrow_entity_lock().len()));
+    }
+
+    #[test]
+    fn defense_reduces_combat_damage() {
+        let player = Player::new(player_data::test_player());
+        player.set_defense(5);
+        let health = player.get_health();
+
+        player.take_damage(20);
+        assert_eq!(health - 15, player.get_health());
+
+        player.take_damage(3);
+        assert_eq!(health - 16, player.get_health());
+    }
+}
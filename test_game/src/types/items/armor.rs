@@ -0,0 +1,166 @@
+use crate::traits::{Entity, Item};
+use crate::types::items::display_info::ItemDisplayInfo;
+
+use atomic::Ordering::*;
+use atomic::Atomic;
+use rand::random;
+
+/// Armor occupies its own equipment slot. Equipping it
+/// raises the wearer's defense and, for higher levels,
+/// their max health.
+#[derive(AtomicClone, ItemTools)]
+pub struct Armor {
+    pub id: usize,
+    pub name: String,
+    pub level: u32,
+    pub defense: u32,
+    pub health_bonus: u32,
+    pub price: u32,
+    /// The max health actually added on equip, which is
+    /// less than `health_bonus` near the wearer's cap.
+    /// Armor restored from a save assumes the full bonus.
+    applied_health: Atomic<u32>,
+}
+
+const DEFENSE_PER_LEVEL: u32 = 2;
+const HEALTH_PER_LEVEL: u32 = 5;
+const PRICE_PER_LEVEL: u32 = 150;
+
+impl Armor {
+    /// 2 defense per level
+    /// 5 max health per level, starting at level 2
+    /// 150g per level
+    pub fn from_level(level: u32) -> Box<Item> {
+        let health_bonus = HEALTH_PER_LEVEL * level.saturating_sub(1);
+
+        Box::new(Armor {
+            id: random(),
+            name: String::from("Armor"),
+            level,
+            defense: DEFENSE_PER_LEVEL * level,
+            health_bonus,
+            price: PRICE_PER_LEVEL * level,
+            applied_health: Atomic::new(health_bonus),
+        })
+    }
+}
+
+impl Item for Armor {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    fn is_armor(&self) -> bool {
+        true
+    }
+
+    fn get_price(&self) -> u32 {
+        self.price
+    }
+
+    fn max_stack_size(&self) -> u32 {
+        1
+    }
+
+    fn get_type(&self) -> &'static str {
+        "armor"
+    }
+
+    fn on_equip(&self, entity: &Entity) {
+        entity.set_defense(entity.get_defense() + self.defense);
+
+        if self.health_bonus > 0 {
+            let before = entity.get_max_health();
+            entity.set_max_health(before + self.health_bonus);
+
+            let applied = entity.get_max_health().saturating_sub(before);
+            self.applied_health.store(applied, SeqCst);
+        }
+    }
+
+    fn on_unequip(&self, entity: &Entity) {
+        entity.set_defense(entity.get_defense().saturating_sub(self.defense));
+
+        if self.health_bonus > 0 {
+            let applied = self.applied_health.load(SeqCst);
+            let max = entity.get_max_health().saturating_sub(applied);
+            entity.set_max_health(max);
+
+            // Don't leave the wearer above their new max.
+            let max = entity.get_max_health();
+            if entity.get_health() > max {
+                entity.set_health(max);
+            }
+        }
+    }
+
+    fn get_display_info(&self, price_factor: f32) -> ItemDisplayInfo {
+        let mut info = format!(
+            "{}\n  * Type: lvl {} {}\n  * Defense: {}",
+            self.name,
+            self.level,
+            self.get_type(),
+            self.defense,
+        );
+
+        if self.health_bonus > 0 {
+            info += &format!("\n  * Max health: +{}", self.health_bonus);
+        }
+        info += &format!("\n  * Price: {}g", self.get_adjusted_price(price_factor));
+
+        ItemDisplayInfo {
+            item_id: self.get_id(),
+            info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data;
+    use crate::types::entities::players::Player;
+
+    fn equip_armor(player: &Player, level: u32) -> usize {
+        let armor = Armor::from_level(level);
+        let id = armor.get_id();
+        player.give_item(armor);
+        let index = player.get_inventory().unwrap().get_slot_num(id).unwrap();
+        player.equip_item(index);
+        id
+    }
+
+    #[test]
+    fn unequipping_armor_restores_the_baseline() {
+        let player = Player::new(player_data::test_player());
+        let (defense, max_health) = (player.get_defense(), player.get_max_health());
+
+        let id = equip_armor(&player, 3);
+        assert_eq!(defense + 6, player.get_defense());
+        assert_eq!(max_health + 10, player.get_max_health());
+
+        player.unequip_item(id).unwrap();
+        assert_eq!(defense, player.get_defense());
+        assert_eq!(max_health, player.get_max_health());
+    }
+
+    #[test]
+    fn capped_health_bonus_is_not_overdrawn() {
+        let player = Player::new(player_data::test_player());
+        player.set_max_health(Player::MAX_HEALTH - 2);
+
+        let id = equip_armor(&player, 3);
+        assert_eq!(Player::MAX_HEALTH, player.get_max_health());
+
+        player.unequip_item(id).unwrap();
+        assert_eq!(Player::MAX_HEALTH - 2, player.get_max_health());
+    }
+}
@@ -1,9 +1,24 @@
-use crate::traits::{Item, Weapon};
+use crate::player_data::{self, CombatEvent, PlayerMeta};
+use crate::traits::{Area, Entity, Item, Weapon};
 use crate::types::items::{self, display_info::ItemDisplayInfo};
+use crate::util::access;
+use crate::util::player_options::{self, Dialogue, Response};
+use crate::util::timed_events::{self, DelayedEvent};
 
 use atomic::Ordering::*;
 use atomic::Atomic;
 
+/// The delay between shots before attack speed is
+/// applied, in ms.
+const BASE_COOLDOWN_MS: i32 = 4_000;
+const MIN_COOLDOWN_MS: i32 = 1_000;
+
+/// How long it takes for an arrow to land, in ms.
+const ARROW_FLIGHT_MS: u64 = 750;
+
+/// Flags the shooter's cooldown event.
+const COOLDOWN_FLAG: &str = "bow_cooldown";
+
 #[derive(AtomicClone, ItemTools)]
 pub struct Bow {
     pub id: usize,
@@ -20,14 +35,20 @@ pub struct Bow {
 
 impl Bow {
     pub fn new(_town_num: usize) -> Box<Item> {
+        Self::from_level(1)
+    }
+
+    /// 5 damage per level
+    /// 100g per level
+    pub fn from_level(level: u32) -> Box<Item> {
         Box::new(Bow {
             id: rand::random(),
             name: String::from("to-do"),
-            level: 1,
-            damage: Atomic::new(5),
+            level,
+            damage: Atomic::new(5 * level),
             piercing: 0,
             speed: 15,
-            price: 500,
+            price: 400 + (100 * level),
             num_repairs: Atomic::new(0),
             num_uses: Atomic::new(100),
             max_uses: 100,
@@ -35,6 +56,101 @@ impl Bow {
     }
 }
 
+impl Bow {
+    /// Gives back the use taken by `on_use_item()` when
+    /// no arrow was actually fired.
+    fn refund_use(&self) {
+        self.num_uses.fetch_add(1, SeqCst);
+    }
+
+    /// Starts the shooter's cooldown and schedules the
+    /// arrow to land on `target`. The target is looked up
+    /// again on landing, as it may have died or left.
+    fn fire(&self, user: &Entity, target: &Entity) {
+        let cooldown = (BASE_COOLDOWN_MS + user.get_attack_speed()).max(MIN_COOLDOWN_MS);
+        DelayedEvent::new(cooldown as u64, None, Some(user.get_id()), Some(COOLDOWN_FLAG.to_string()), || {});
+
+        let shooter_id = user.get_id();
+        let shooter_name = user.get_name().clone();
+        let target_id = target.get_id();
+        let accessor = target.get_accessor();
+        let target_name = target.get_name().clone();
+        let damage = self.get_damage();
+
+        DelayedEvent::no_flags(ARROW_FLIGHT_MS, move || {
+            let landed = access::entity(accessor, |t| {
+                if t.get_health() == 0 {
+                    return None;
+                }
+                let before = t.get_health();
+                t.set_last_attacker(shooter_id);
+                t.take_damage(damage);
+                Some(before.saturating_sub(t.get_health()))
+            })
+            .and_then(|d| d);
+
+            let msg = match landed {
+                Some(dealt) => {
+                    let event = CombatEvent::new(&shooter_name, &target_name, dealt);
+                    player_data::record_combat(Some(shooter_id), target_id, event);
+                    format!("Your arrow hit {}.", target_name)
+                }
+                None => format!("Your arrow missed. {} is no longer there.", target_name),
+            };
+            if let Some(player) = player_data::find_player(shooter_id) {
+                player.send_short_message(&msg);
+            }
+        });
+    }
+}
+
+/// Lets the player choose which mob in their current area
+/// to fire the bow with `bow_id` at.
+fn show_targets(player: &PlayerMeta, bow_id: usize) {
+    let targets: Vec<(usize, String)> = player.area(|area| {
+        area.borrow_entity_lock()
+            .iter()
+            .filter(|e| e.get_type() == "mob" && e.get_health() > 0)
+            .map(|e| (e.get_id(), e.get_name().clone()))
+            .collect()
+    });
+
+    if targets.len() == 0 {
+        player.send_short_message("There's nothing here to shoot at.");
+        return;
+    }
+    let mut responses: Vec<Response> = targets.into_iter()
+        .map(|(target_id, name)| {
+            Response::_simple(format!("Shoot at {}.", name), move |p| {
+                fire_at(p, bow_id, target_id)
+            })
+        })
+        .collect();
+    responses.push(Response::text_only("Lower your bow."));
+
+    let player_id = player.get_player_id();
+    player_options::remove_all_options(player_id);
+    player_options::register_options(Dialogue::no_message("Choose a Target", responses, Vec::new(), player_id));
+    player.send_current_options();
+}
+
+/// Uses the bow on the target, if both it and the
+/// player are still in the same area.
+fn fire_at(player: &PlayerMeta, bow_id: usize, target_id: usize) {
+    player.area(|area| {
+        let entities = area.borrow_entity_lock();
+        let shooter = entities.iter()
+            .find(|e| e.get_id() == player.get_player_id());
+        let target = entities.iter()
+            .find(|e| e.get_id() == target_id && e.get_health() > 0);
+
+        match (shooter, target) {
+            (Some(s), Some(t)) => s.use_item_id(bow_id, Some(&**t)),
+            _ => player.add_short_message("Your target is no longer here."),
+        }
+    });
+}
+
 impl Weapon for Bow {
     fn set_damage(&self, val: u32) {
         self.damage.store(val, SeqCst);
@@ -44,10 +160,18 @@ impl Weapon for Bow {
         self.damage.load(SeqCst)
     }
 
+    fn get_speed(&self) -> u32 {
+        BASE_COOLDOWN_MS as u32
+    }
+
     fn get_repair_price(&self) -> u32 {
         let base = self.get_price() / 2;
         base + ((base as f32 / 2.0).ceil() as u32 * self.num_repairs.load(SeqCst))
     }
+
+    fn on_repair(&self) {
+        self.num_repairs.fetch_add(1, SeqCst);
+    }
 }
 
 impl Item for Bow {
@@ -67,6 +191,10 @@ impl Item for Bow {
         true
     }
 
+    fn as_weapon(&self) -> Option<&Weapon> {
+        Some(self)
+    }
+
     fn get_price(&self) -> u32 {
         self.price
     }
@@ -83,6 +211,35 @@ impl Item for Bow {
         Some(&self)
     }
 
+    /// Fires at `use_on`. Without a target, the player is
+    /// instead asked to choose one from their area.
+    fn use_item(&self, user: Option<&Entity>, use_on: Option<&Entity>, _area: &Area) -> Option<String> {
+        let user = match user {
+            Some(u) => u,
+            None => return Some(String::from("This item has no effect here.")),
+        };
+        if timed_events::has_flags(None, Some(user.get_id()), Some(COOLDOWN_FLAG)) {
+            self.refund_use();
+            return Some(String::from("You're still nocking another arrow."));
+        }
+        match use_on {
+            Some(target) => self.fire(user, target),
+            None => {
+                self.refund_use();
+                let player_id = user.get_id();
+                let bow_id = self.id;
+
+                // The area's entities are already locked.
+                DelayedEvent::no_flags(0, move || {
+                    if let Some(player) = player_data::find_player(player_id) {
+                        show_targets(&player, bow_id);
+                    }
+                });
+            }
+        }
+        None
+    }
+
     fn get_max_uses(&self) -> u32 {
         self.max_uses
     }
@@ -1,6 +1,11 @@
-use crate::traits::{Area, Entity, Item, ItemTools};
+use crate::traits::{Area, Entity, EntityCore, Item, ItemTools};
 use crate::types::effects::Effect;
+use crate::types::entities::mobs::Companion;
 use crate::types::items::display_info::ItemDisplayInfo;
+use crate::types::items::shops::MAX_BAG_SIZE;
+use crate::util::timed_events::{DelayedEvent, RepeatedEvent};
+use crate::player_data::{self, CombatEvent};
+use crate::util::access;
 
 use std::any::Any;
 
@@ -34,6 +39,22 @@ impl Consumable {
             num_uses: Atomic::new(0),
         }
     }
+
+    /// Heals the user according to `Effect::leveled_health`.
+    pub fn healing_potion(level: u32) -> Consumable {
+        let effect = Effect::leveled_health(level);
+        let level = effect.level;
+
+        Consumable {
+            id: random(),
+            name: String::from("Healing Potion"),
+            level,
+            effect,
+            stack_size: 4,
+            price: 20 * level,
+            num_uses: Atomic::new(0),
+        }
+    }
 }
 
 impl Item for Consumable {
@@ -57,6 +78,10 @@ impl Item for Consumable {
         "consumable"
     }
 
+    fn has_entity_effect(&self) -> bool {
+        true
+    }
+
     fn use_item(&self, user: Option<&Entity>, use_on: Option<&Entity>, _area: &Area) -> Option<String> {
         if let Some(entity) = use_on {
             self.effect.apply(entity);
@@ -104,3 +129,499 @@ impl ItemTools for Consumable {
         self
     }
 }
+
+/// Removes all temporary debuffs from the entity it's
+/// used on. Permanent curses can't be cured.
+pub struct Cure {
+    pub id: usize,
+    pub name: String,
+    pub level: u32,
+    pub price: u32,
+    pub num_uses: Atomic<u32>,
+}
+
+impl Cure {
+    pub fn new() -> Cure {
+        Cure {
+            id: random(),
+            name: String::from("Cure"),
+            level: 1,
+            price: 50,
+            num_uses: Atomic::new(0),
+        }
+    }
+}
+
+impl Item for Cure {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    fn get_price(&self) -> u32 {
+        self.price
+    }
+
+    fn get_type(&self) -> &'static str {
+        "consumable"
+    }
+
+    fn has_entity_effect(&self) -> bool {
+        true
+    }
+
+    fn use_item(&self, user: Option<&Entity>, use_on: Option<&Entity>, _area: &Area) -> Option<String> {
+        let entity = use_on.or(user)?;
+        let cured: Vec<&'static str> = entity.get_active_effects()
+            .iter()
+            .filter(|e| e.is_curable())
+            .map(|e| e.name)
+            .collect();
+
+        if cured.is_empty() {
+            return Some(format!("{} had nothing to cure.", entity.get_name()));
+        }
+        for name in cured {
+            entity.remove_effect(name);
+        }
+        Some(format!("{} was cured of all debuffs.", entity.get_name()))
+    }
+
+    fn set_num_uses(&self, val: u32) {
+        self.num_uses.store(val, SeqCst);
+    }
+
+    fn get_num_uses(&self) -> u32 {
+        self.num_uses.load(SeqCst)
+    }
+}
+
+impl Clone for Cure {
+    fn clone(&self) -> Cure {
+        Cure {
+            id: self.id,
+            name: self.name.clone(),
+            level: self.level,
+            price: self.price,
+            num_uses: Atomic::new(self.num_uses.load(SeqCst)),
+        }
+    }
+}
+
+impl ItemTools for Cure {
+    fn clone_box(&self) -> Box<Item> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// How often a summoned companion attacks, in ms.
+const COMPANION_ATTACK_INTERVAL: u64 = 3_000;
+
+/// Calls a companion to fight alongside its user for a
+/// limited time, after which it disappears.
+pub struct Summon {
+    pub id: usize,
+    pub name: String,
+    pub level: u32,
+    pub duration_ms: u64,
+    pub price: u32,
+    pub num_uses: Atomic<u32>,
+}
+
+impl Summon {
+    /// 3 damage per level
+    /// 30s per level
+    pub fn new(level: u32) -> Summon {
+        Summon {
+            id: random(),
+            name: String::from("Summoning Stone"),
+            level,
+            duration_ms: 30_000 * level as u64,
+            price: 75 * level,
+            num_uses: Atomic::new(0),
+        }
+    }
+}
+
+impl Item for Summon {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    fn get_price(&self) -> u32 {
+        self.price
+    }
+
+    fn get_type(&self) -> &'static str {
+        "consumable"
+    }
+
+    /// The companion can't be added while the user's area
+    /// is being borrowed, so this is deferred to the next
+    /// update, along with its attacks and removal.
+    fn use_item(&self, user: Option<&Entity>, _use_on: Option<&Entity>, area: &Area) -> Option<String> {
+        let user = user?;
+        if !area.has_room() {
+            return Some(String::from("There's no room for a companion here."));
+        }
+        let coords = area.get_coordinates();
+        let owner_id = user.get_id();
+        let companion = Companion::new("Spirit Wolf", owner_id, 3 * self.level, coords);
+        let companion_id = companion.get_id();
+        let companion_name = companion.get_name().clone();
+        let damage = companion.get_base_damage();
+
+        let stone = self.clone_box();
+        DelayedEvent::no_flags(0, move || {
+            // Anything that filled the area since will win out.
+            let added = access::area(coords, |a| a.add_entity(Box::new(companion)).is_ok())
+                .unwrap_or(false);
+            if added {
+                return;
+            }
+            if let Some(owner) = player_data::find_player(owner_id) {
+                access::entity(owner.get_accessor(), |e| e.give_item(stone));
+                owner.send_short_message("There was no room for your companion. The stone was returned.");
+            }
+        });
+
+        RepeatedEvent::no_flags(COMPANION_ATTACK_INTERVAL, self.duration_ms, move || {
+            let hit = access::area(coords, |a| {
+                let entities = a.borrow_entity_lock();
+                if !entities.iter().any(|e| e.get_id() == companion_id) {
+                    return None;
+                }
+                let hit = entities.iter()
+                    .find(|e| e.get_type() == "mob" && e.get_health() > 0)
+                    .map(|mob| {
+                        let before = mob.get_health();
+                        // The owner is credited with the kill.
+                        mob.set_last_attacker(owner_id);
+                        mob.take_damage(damage);
+                        (mob.get_id(), mob.get_name().clone(), before.saturating_sub(mob.get_health()))
+                    });
+                Some(hit)
+            })
+            .and_then(|h| h);
+
+            match hit {
+                Some(Some((mob_id, mob_name, dealt))) => {
+                    let event = CombatEvent::new(&companion_name, &mob_name, dealt);
+                    player_data::record_combat(Some(owner_id), mob_id, event);
+                    true
+                }
+                Some(None) => true,
+                None => false,
+            }
+        });
+
+        DelayedEvent::no_flags(self.duration_ms, move || {
+            let removed = access::area(coords, |a| a.remove_entity(companion_id))
+                .and_then(|e| e);
+
+            if let (Some(companion), Some(owner)) = (removed, player_data::find_player(owner_id)) {
+                owner.send_short_message(&format!("Your {} fades away.", companion.get_name()));
+            }
+        });
+
+        Some(String::from("A Spirit Wolf answers your call."))
+    }
+
+    fn set_num_uses(&self, val: u32) {
+        self.num_uses.store(val, SeqCst);
+    }
+
+    fn get_num_uses(&self) -> u32 {
+        self.num_uses.load(SeqCst)
+    }
+
+    fn get_display_info(&self, price_factor: f32) -> ItemDisplayInfo {
+        ItemDisplayInfo {
+            item_id: self.get_id(),
+            info: format!(
+                "{}\n  * Type: lvl {} {}\n  * Duration: {}s\n  * Price: {}g",
+                self.get_name(),
+                self.level,
+                self.get_type(),
+                self.duration_ms / 1000,
+                self.get_adjusted_price(price_factor)
+            ),
+        }
+    }
+}
+
+impl Clone for Summon {
+    fn clone(&self) -> Summon {
+        Summon {
+            id: self.id,
+            name: self.name.clone(),
+            level: self.level,
+            duration_ms: self.duration_ms,
+            price: self.price,
+            num_uses: Atomic::new(self.num_uses.load(SeqCst)),
+        }
+    }
+}
+
+impl ItemTools for Summon {
+    fn clone_box(&self) -> Box<Item> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// The number of slots added to the user's inventory
+/// by a `Bag`.
+const BAG_SLOTS: usize = 5;
+
+/// Permanently increases the size of its user's main
+/// inventory by `BAG_SLOTS`, up to `MAX_BAG_SIZE`.
+pub struct Bag {
+    pub id: usize,
+    pub name: String,
+    pub level: u32,
+    pub price: u32,
+    pub num_uses: Atomic<u32>,
+}
+
+impl Bag {
+    pub fn new() -> Bag {
+        Bag {
+            id: random(),
+            name: String::from("Traveler's Bag"),
+            level: 1,
+            price: 300,
+            num_uses: Atomic::new(0),
+        }
+    }
+}
+
+impl Item for Bag {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    fn get_price(&self) -> u32 {
+        self.price
+    }
+
+    fn get_type(&self) -> &'static str {
+        "consumable"
+    }
+
+    fn use_item(&self, user: Option<&Entity>, _use_on: Option<&Entity>, _area: &Area) -> Option<String> {
+        let inventory = user?.get_inventory()?;
+        let max_size = inventory.get_max_size();
+
+        if max_size >= MAX_BAG_SIZE {
+            // Keeps the bag from being used up.
+            self.set_num_uses(1);
+            return Some(String::from("Your bag can't get any bigger."));
+        }
+        inventory.expand(BAG_SLOTS.min(MAX_BAG_SIZE - max_size));
+
+        Some(format!("Your bag can now hold {} items.", inventory.get_max_size()))
+    }
+
+    fn set_num_uses(&self, val: u32) {
+        self.num_uses.store(val, SeqCst);
+    }
+
+    fn get_num_uses(&self) -> u32 {
+        self.num_uses.load(SeqCst)
+    }
+}
+
+impl Clone for Bag {
+    fn clone(&self) -> Bag {
+        Bag {
+            id: self.id,
+            name: self.name.clone(),
+            level: self.level,
+            price: self.price,
+            num_uses: Atomic::new(self.num_uses.load(SeqCst)),
+        }
+    }
+}
+
+impl ItemTools for Bag {
+    fn clone_box(&self) -> Box<Item> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::entities::belongings::LostBelongings;
+    use crate::util::timed_events;
+
+    fn count_companions(coords: (usize, usize, usize)) -> usize {
+        access::area(coords, |a| {
+            a.borrow_entity_lock()
+                .iter()
+                .filter(|e| e.get_type() == "companion")
+                .count()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn summoned_companion_leaves_after_its_duration() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(328);
+        let id = player.get_player_id();
+        let coords = player.get_coordinates();
+        let summon = Summon::new(1);
+
+        let used = access::area(coords, |area| {
+            let entities = area.borrow_entity_lock();
+            let user = entities.iter().find(|e| e.get_id() == id).unwrap();
+            summon.use_item(Some(&**user), None, area)
+        });
+        assert!(used.unwrap().is_some());
+        assert_eq!(0, count_companions(coords));
+
+        timed_events::test_advance_time(1);
+        assert_eq!(1, count_companions(coords));
+        assert!(access::area(coords, |a| !a.contains_mobs()).unwrap());
+
+        timed_events::test_advance_time(summon.duration_ms);
+        assert_eq!(0, count_companions(coords));
+    }
+
+    #[test]
+    fn stone_is_returned_when_the_area_fills_first() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(348);
+        let id = player.get_player_id();
+        let coords = player.get_coordinates();
+        let summon = Summon::new(1);
+
+        let fillers = access::area(coords, |area| {
+            let entities = area.borrow_entity_lock();
+            let user = entities.iter().find(|e| e.get_id() == id).unwrap();
+            summon.use_item(Some(&**user), None, area);
+            drop(entities);
+
+            let mut fillers = Vec::new();
+            while area.has_room() {
+                let filler = LostBelongings::new("Filler", coords);
+                fillers.push(filler.get_id());
+                area.add_entity(Box::new(filler)).ok();
+            }
+            fillers
+        })
+        .unwrap();
+
+        timed_events::test_advance_time(1);
+        assert_eq!(0, count_companions(coords));
+        assert!(player.entity(|e| e.get_inventory().unwrap().get_slot_num(summon.id).is_some()));
+        assert!(player.get_general_message().contains("The stone was returned."));
+
+        access::area(coords, |area| {
+            for filler in fillers {
+                area.remove_entity(filler);
+            }
+        });
+    }
+
+    #[test]
+    fn second_strength_potion_does_not_double_damage() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(341);
+        let id = player.get_player_id();
+        let damage = player.entity(|e| e.get_base_damage());
+        let strength = Effect::leveled_strength(2);
+        let bonus = strength.base_damage as u32;
+
+        for _ in 0..2 {
+            let potion = Consumable {
+                id: random(),
+                name: String::from("Strength Potion"),
+                level: 2,
+                effect: strength.clone(),
+                stack_size: 4,
+                price: 40,
+                num_uses: Atomic::new(0),
+            };
+            access::area(player.get_coordinates(), |area| {
+                let entities = area.borrow_entity_lock();
+                let user = entities.iter().find(|e| e.get_id() == id).unwrap();
+                potion.use_item(Some(&**user), None, area);
+            });
+        }
+        assert_eq!(damage + bonus, player.entity(|e| e.get_base_damage()));
+    }
+
+    #[test]
+    fn bags_stop_growing_at_the_max_size() {
+        crate::test_init();
+        let player = player_data::test_player_in_town(332);
+        let id = player.get_player_id();
+        let coords = player.get_coordinates();
+        player.entity(|e| e.get_inventory().unwrap().set_max_size(MAX_BAG_SIZE - 2));
+
+        let use_bag = |bag_id| {
+            access::area(coords, |area| {
+                let entities = area.borrow_entity_lock();
+                let user = entities.iter().find(|e| e.get_id() == id).unwrap();
+                let inventory = user.get_inventory().unwrap();
+                let slot_num = inventory.get_slot_num(bag_id).unwrap();
+                inventory.on_use_item(slot_num, Some(&**user), None, area);
+                inventory.get_slot_num(bag_id).is_some()
+            })
+            .unwrap()
+        };
+        let give_bag = || {
+            let bag = Bag::new();
+            let bag_id = bag.get_id();
+            player.entity(|e| e.give_item(Box::new(bag)));
+            bag_id
+        };
+
+        assert!(!use_bag(give_bag()));
+        assert_eq!(MAX_BAG_SIZE, player.entity(|e| e.get_inventory().unwrap().get_max_size()));
+
+        // A refused bag is kept.
+        assert!(use_bag(give_bag()));
+        assert_eq!(MAX_BAG_SIZE, player.entity(|e| e.get_inventory().unwrap().get_max_size()));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::traits::{Entity, Item};
+use crate::types::items::inventories::Inventory;
+
+/// The slots an entity can equip items into. Each slot
+/// is a single-item inventory.
+pub struct Equipment {
+    pub primary: Inventory,
+    pub secondary: Inventory,
+    pub armor: Inventory,
+}
+
+impl Equipment {
+    pub fn new() -> Equipment {
+        Equipment {
+            primary: Inventory::new(1),
+            secondary: Inventory::new(1),
+            armor: Inventory::new(1),
+        }
+    }
+
+    /// Every slot, in display order.
+    pub fn slots(&self) -> [&Inventory; 3] {
+        [&self.primary, &self.secondary, &self.armor]
+    }
+
+    /// The slot that `item` should be equipped into.
+    /// Weapons go in the primary slot, armor in the
+    /// armor slot, and anything else in the secondary.
+    pub fn slot_for(&self, item: &Item) -> &Inventory {
+        if item.is_weapon() {
+            &self.primary
+        } else if item.is_armor() {
+            &self.armor
+        } else {
+            &self.secondary
+        }
+    }
+
+    /// Locates a slot by the name a player would type.
+    pub fn slot_by_name(&self, name: &str) -> Option<&Inventory> {
+        match name {
+            "primary" | "prim" | "weapon" => Some(&self.primary),
+            "secondary" | "sec" | "offhand" => Some(&self.secondary),
+            "armor" => Some(&self.armor),
+            _ => None,
+        }
+    }
+
+    /// Finds the slot currently holding the item with `id`.
+    pub fn find_slot(&self, id: usize) -> Option<&Inventory> {
+        self.slots()
+            .iter()
+            .find(|slot| slot.get_slot_num(id).is_some())
+            .map(|slot| *slot)
+    }
+
+    /// The id of the item in `slot`, if there is one.
+    pub fn get_item_id(slot: &Inventory) -> Option<usize> {
+        if slot.current_size() > 0 {
+            Some(slot.get_item_info(0, 0, |item| item.get_id()))
+        } else {
+            None
+        }
+    }
+
+    /// The name of the item in `slot`, for display.
+    pub fn get_item_name(slot: &Inventory) -> String {
+        if slot.current_size() > 0 {
+            slot.get_item_info(0, 0, |item| item.get_name().clone())
+        } else {
+            String::from("None")
+        }
+    }
+
+    /// Takes an item with `id` out of any slot, removing
+    /// its effects from `owner`.
+    pub fn take_item_id(&self, id: usize, owner: &Entity) -> Option<Box<Item>> {
+        let item = self.find_slot(id)?
+            .take_item_id(id, Some(owner))?;
+
+        item.on_unequip(owner);
+        Some(item)
+    }
+}
@@ -1,13 +1,18 @@
+use crate::messages::MessageComponent::General;
 use crate::player_data::PlayerMeta;
 use crate::traits::{Area, Entity, Item};
 use crate::types::items::display_info::ItemDisplayInfo;
+use crate::types::items::equipment::Equipment;
+use crate::types::items::item_settings::ItemSave;
 use crate::util::access;
-use crate::util::player_options::{Command, Dialogue, Response};
+use crate::util::player_options::{ActionResult, Command, Dialogue, Response};
 use crate::*;
 
+use atomic::Atomic;
 use parking_lot::RwLock;
 
 use std::boxed::Box;
+use std::sync::atomic::Ordering::SeqCst;
 
 pub struct ItemSlot {
     stack: RwLock<Vec<Box<Item>>>,
@@ -80,21 +85,48 @@ impl ItemSlot {
         info.info = format!("({}x) {}", self.current_size(), info.info);
         info
     }
+
+    /// Gets the save information for every item in the slot.
+    pub fn get_save_info(&self) -> Vec<ItemSave> {
+        self.stack.read()
+            .iter()
+            .map(|item| ItemSave::new(&**item))
+            .collect()
+    }
 }
 
 pub struct Inventory {
     slots: RwLock<Vec<ItemSlot>>,
-    pub max_size: usize,
+    max_size: Atomic<usize>,
+    filter: RwLock<Option<String>>,
 }
 
 impl Inventory {
     pub fn new(max_size: usize) -> Inventory {
         Inventory {
             slots: RwLock::new(Vec::new()),
-            max_size,
+            max_size: Atomic::new(max_size),
+            filter: RwLock::new(None),
         }
     }
 
+    /// The number of slots this inventory can hold.
+    pub fn get_max_size(&self) -> usize {
+        self.max_size.load(SeqCst)
+    }
+
+    /// Changes the number of slots available. Items in any
+    /// slots beyond the new size are not removed.
+    pub fn set_max_size(&self, val: usize) {
+        self.max_size.store(val, SeqCst);
+    }
+
+    /// Adds `by` slots to the inventory. Existing slots
+    /// keep their positions.
+    pub fn expand(&self, by: usize) {
+        self.max_size.fetch_add(by, SeqCst);
+    }
+
     /// Performs an operation for each slot in the inventory.
     /// Does not allow any return information.
     pub fn for_each_slot<F>(&self, callback: F) where F: Fn(usize, &ItemSlot) {
@@ -135,7 +167,7 @@ impl Inventory {
     /// Determines whether the inventory can hold any further items
     /// of any kind.
     pub fn can_hold_more(&self) -> bool {
-        self.current_size() < self.max_size
+        self.current_size() < self.get_max_size()
     }
 
     /// Determines whether the inventory can hold the specified
@@ -206,6 +238,47 @@ impl Inventory {
         item
     }
 
+    /// Variant of `take_item()` which takes up to `count`
+    /// items from the top of `slot_num` at once. Asking for
+    /// more items than the slot holds takes all of them.
+    pub fn take_n(&self, slot_num: usize, count: usize, from: Option<&Entity>) -> Vec<Box<Item>> {
+        let mut slots = self.slots.write();
+        let (items, slot_size) = {
+            let slot = slots.get(slot_num).expect("Invalid slot #.");
+            let mut stack = slot.stack.write();
+            let start = stack.len() - count.min(stack.len());
+            (stack.split_off(start), stack.len())
+        };
+
+        for item in &items {
+            item.on_lose(from);
+        }
+        // Make sure no slot is left empty.
+        if slot_size < 1 {
+            slots.remove(slot_num);
+        }
+        items
+    }
+
+    /// Moves `count` items from the top of `slot_num` into
+    /// a new slot. Fails if there is no room for another
+    /// slot or if the whole stack would be moved.
+    pub fn split_slot(&self, slot_num: usize, count: usize) -> Result<(), &'static str> {
+        if !self.can_hold_more() {
+            return Err("You don't have room for another stack.");
+        }
+        if count >= self.get_slot_info(slot_num, |items| items.len()) {
+            return Err("You can only split off part of a stack.");
+        }
+        // Items stay in the same inventory, so their
+        // effects are neither removed nor reapplied.
+        let mut items = self.take_n(slot_num, count, None).into_iter();
+        let slot = ItemSlot::new(items.next().expect("Tried to split off an empty stack."));
+        items.for_each(|item| slot.add_item(item));
+        self.add_slot(slot);
+        Ok(())
+    }
+
     /// Allows temporary access into the inventory for retrieving
     /// information about an item. Items are not reference counted,
     /// and thus each of the four calls found in this function must
@@ -224,6 +297,12 @@ impl Inventory {
         callback(&**item)
     }
 
+    /// Variant of `get_item_info()` which formats every
+    /// stat of the first item in `slot_num`.
+    pub fn get_item_detail(&self, slot_num: usize, price_factor: f32) -> String {
+        self.get_item_info(slot_num, 0, |item| item.get_detail(price_factor))
+    }
+
     /// Variant of `get_item_info()` which instead retrieves
     /// information about the specified `slot_num`, ignoring
     /// the particular items in that slot.
@@ -259,7 +338,8 @@ impl Inventory {
     pub fn on_use_item(&self, slot_num: usize, user: Option<&Entity>, use_on: Option<&Entity>, area: &Area) {
         let (num_uses, response) = self.get_item_info(slot_num, 0, |item| {
             item.decrement_uses();
-            (item.get_num_uses(), item.use_item(user, use_on, area))
+            let response = item.use_item(user, use_on, area);
+            (item.get_num_uses(), response)
         });
 
         if let Some(usr) = user {
@@ -313,6 +393,15 @@ impl Inventory {
         info
     }
 
+    /// Variant of `get_display_info()` used for saving. Each
+    /// item in every stack is recorded individually.
+    pub fn get_save_info(&self) -> Vec<ItemSave> {
+        self.slots.read()
+            .iter()
+            .flat_map(|slot| slot.get_save_info())
+            .collect()
+    }
+
     /// Converts the display info from `get_display_info()` into
     /// something a little bit more appealing.
     pub fn format_display_info(info: &Vec<ItemDisplayInfo>) -> String {
@@ -329,6 +418,44 @@ impl Inventory {
         ret
     }
 
+    /// Variant of `format_display_info()` which hides any
+    /// slots not matching the current filter. The remaining
+    /// slots keep their original numbers so that commands
+    /// still refer to the correct items.
+    fn format_filtered_info(&self, info: &Vec<ItemDisplayInfo>) -> String {
+        let filter = match *self.filter.read() {
+            Some(ref kind) => kind.clone(),
+            None => return Self::format_display_info(info),
+        };
+        let slots = self.slots.read();
+        let lines: Vec<String> = info.iter()
+            .enumerate()
+            .filter(|(index, _)| slots.get(*index).map_or(false, |s| s.kind == filter))
+            .map(|(index, item)| format!("#{}: {}", index + 1, item.info))
+            .collect();
+
+        if lines.is_empty() {
+            return format!("(No items of type \"{}\")", filter);
+        }
+        lines.join("\n")
+    }
+
+    /// Reorders every slot using a key taken from the first
+    /// item in each slot. Items themselves are unaffected, so
+    /// their ids remain valid.
+    pub fn sort_slots<K: Ord, F: Fn(&Item) -> K>(&self, key: F) {
+        self.slots.write().sort_by_key(|slot| {
+            let items = slot.stack.read();
+            key(&**items.get(0).expect("A slot existed, but there were no items in it."))
+        });
+    }
+
+    /// Only slots containing items of this type will be
+    /// displayed. `None` displays every slot.
+    pub fn set_filter(&self, kind: Option<String>) {
+        *self.filter.write() = kind;
+    }
+
     /// Generates the dialogue used for interacting with the
     /// inventory's contents.
     pub fn get_dialogue(&self, player: &PlayerMeta) -> Dialogue {
@@ -341,7 +468,7 @@ impl Inventory {
 
         Dialogue {
             title: String::from("Inventory"),
-            info: Some(Self::format_display_info(&info)),
+            info: Some(self.format_filtered_info(&info)),
             responses,
             commands,
             player_id: player.get_player_id(),
@@ -355,36 +482,142 @@ impl Inventory {
 
     pub fn get_commands(&self, _player: &PlayerMeta, _items: &Vec<ItemDisplayInfo>, commands: &mut Vec<Command>) {
         commands.push(Self::equip_command());
+        commands.push(Self::unequip_command());
         commands.push(Self::use_command());
+        commands.push(Self::examine_command());
+        commands.push(Self::split_command());
+        commands.push(Self::sort_command());
+        commands.push(Self::filter_command());
+    }
+
+    fn examine_command() -> Command {
+        Command::action_only("examine #", "Examine item #.", |args, player| {
+            let detail = player.entity(|entity| {
+                let inventory = entity
+                    .get_inventory()
+                    .expect("Player does not have an inventory.");
+
+                Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")
+                    .map(|slot| inventory.get_item_detail(slot, 1.0))
+            });
+            if let Some(detail) = detail {
+                player.send_message(General, &detail);
+            }
+        })
+    }
+
+    fn split_command() -> Command {
+        Command {
+            input: String::from("split # #"),
+            output_desc: String::from("Split item #, moving # of them to a new slot."),
+            aliases: Vec::new(),
+            run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
+                player.entity(|entity| {
+                    let inventory = entity
+                        .get_inventory()
+                        .expect("Player does not have an inventory.");
+
+                    let slot = Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")
+                        .ok_or(())?;
+                    let count = Command::parse_index(args, 1, 1, usize::max_value(), player, "Invalid # of items.")
+                        .ok_or(())?;
+
+                    inventory.split_slot(slot, count).map_err(|e| {
+                        player.add_short_message(e);
+                    })
+                })
+            }),
+            next_dialogue: Self::get_next_dialogue()
+        }
+    }
+
+    fn sort_command() -> Command {
+        Command {
+            input: String::from("sort <price|type|name>"),
+            output_desc: String::from("Sort your items."),
+            aliases: Vec::new(),
+            run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
+                player.entity(|entity| {
+                    let inventory = entity
+                        .get_inventory()
+                        .expect("Player does not have an inventory.");
+
+                    match args.get(0) {
+                        Some(&"price") => inventory.sort_slots(|item| item.get_price()),
+                        Some(&"type") => inventory.sort_slots(|item| item.get_type()),
+                        Some(&"name") => inventory.sort_slots(|item| item.get_name().to_lowercase()),
+                        _ => {
+                            player.add_short_message("Sort by price, type, or name.");
+                            return Err(());
+                        }
+                    };
+                    Ok(())
+                })
+            }),
+            next_dialogue: Self::get_next_dialogue()
+        }
+    }
+
+    fn filter_command() -> Command {
+        Command {
+            input: String::from("filter <type>"),
+            output_desc: String::from("Only show one type of item. Omit to show all."),
+            aliases: Vec::new(),
+            run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
+                player.entity(|entity| {
+                    entity.get_inventory()
+                        .expect("Player does not have an inventory.")
+                        .set_filter(args.get(0).map(|kind| kind.to_lowercase()));
+                });
+                Ok(())
+            }),
+            next_dialogue: Self::get_next_dialogue()
+        }
     }
 
     fn equip_command() -> Command {
         Command {
             input: String::from("e #"),
             output_desc: String::from("Equip item #."),
+            aliases: Vec::new(),
             run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
-                if args.len() < 1 {
-                    player.add_short_message("You must specify the item #.");
-                    return;
-                }
-                let slot_num: usize = match args[0].parse() {
-                    Ok(num) => num,
-                    Err(_e) => {
-                        player.add_short_message("Not sure what you're trying to do, there.");
-                        return;
-                    }
-                };
-
                 player.entity(move |entity| {
                     let inventory = entity
                         .get_inventory()
                         .expect("Player does not have an inventory.");
 
-                    if inventory.current_size() < slot_num || slot_num == 0 {
-                        player.add_short_message("Invalid item #.");
-                        return;
-                    }
-                    entity.equip_item(slot_num);
+                    let slot = Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")
+                        .ok_or(())?;
+                    entity.equip_item(slot);
+                    Ok(())
+                })
+            }),
+            next_dialogue: Self::get_next_dialogue()
+        }
+    }
+
+    fn unequip_command() -> Command {
+        Command {
+            input: String::from("unequip <prim|sec|armor>"),
+            output_desc: String::from("Move an equipped item back into your bag."),
+            aliases: Vec::new(),
+            run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
+                player.entity(|entity| {
+                    let equipment = entity
+                        .get_equipment()
+                        .expect("Player does not have any equipment.");
+
+                    let id = args.get(0)
+                        .and_then(|name| equipment.slot_by_name(&name.to_lowercase()))
+                        .and_then(|slot| Equipment::get_item_id(slot));
+
+                    let result = match id {
+                        Some(id) => entity.unequip_item(id),
+                        None => Err("Nothing is equipped there."),
+                    };
+                    result.map_err(|e| {
+                        player.add_short_message(e);
+                    })
                 })
             }),
             next_dialogue: Self::get_next_dialogue()
@@ -393,40 +626,100 @@ impl Inventory {
 
     fn use_command() -> Command {
         Command {
-            input: String::from("u #"),
-            output_desc: String::from("Use item #."),
+            input: String::from("u # [on #]"),
+            output_desc: String::from("Use item #, optionally on someone from `look`."),
+            aliases: Vec::new(),
             run: Box::new(|args: &Vec<&str>, player: &PlayerMeta| {
-                if args.len() < 1 {
-                    player.add_short_message("You must specify the item #.");
-                    return;
-                }
-                let item_num = match args[0].parse::<usize>() {
-                    Ok(num) if num > 0 => num - 1,
-                    _ => {
-                        player.add_short_message("Not sure what you're trying to do, there.");
-                        return;
+                match args.get(1) {
+                    None => (),
+                    Some(&"on") => return Self::use_on_target(args, player),
+                    Some(_) => {
+                        player.add_short_message("Try \"u # on #\".");
+                        return Err(());
                     }
-                };
-
+                }
                 access::context(player, |_, a, e| {
                     let inventory = e
                         .get_inventory()
                         .expect("Player no longer has an inventory.");
 
-                    if inventory.current_size() < item_num || item_num == 0 {
-                        player.add_short_message("Invalid item #.");
-                        return;
-                    }
-                    inventory.on_use_item(item_num, Some(e), None, a);
+                    let slot = Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")
+                        .ok_or(())?;
+                    inventory.on_use_item(slot, Some(e), None, a);
+                    Ok(())
                 })
-                    .expect("Player data no longer exists.");
+                    .expect("Player data no longer exists.")
             }),
             next_dialogue: Self::get_next_dialogue()
         }
     }
 
+    /// Handles `u # on #`. Targets are numbered in the
+    /// same order as the `look` command displays them.
+    fn use_on_target(args: &Vec<&str>, player: &PlayerMeta) -> ActionResult {
+        let player_id = player.get_player_id();
+        let target_ids: Vec<usize> = player.area(|area| {
+            area.borrow_entity_lock()
+                .iter()
+                .map(|e| e.get_id())
+                .filter(|id| *id != player_id)
+                .collect()
+        });
+        let index = Command::parse_slot(args, 2, target_ids.len(), player, "Invalid target #. Type `look` to see who's nearby.")
+            .ok_or(())?;
+        let target_id = target_ids[index];
+
+        let (slot, item_name) = player.entity(|entity| {
+            let inventory = entity.get_inventory()
+                .expect("Player does not have an inventory.");
+
+            let slot = Command::parse_slot(args, 0, inventory.current_size(), player, "Invalid item #.")?;
+            inventory.get_item_info(slot, 0, |item| {
+                if item.as_weapon().is_some() {
+                    player.add_short_message("Weapons can only be used in a fight.");
+                    None
+                } else if !item.has_entity_effect() {
+                    player.add_short_message("That item can't be used on others.");
+                    None
+                } else {
+                    Some((slot, item.get_name().clone()))
+                }
+            })
+        })
+            .ok_or(())?;
+
+        // The target may have left since the ids were read.
+        let target_is_player = player.area(|area| {
+            let entities = area.borrow_entity_lock();
+            let target = match entities.iter().find(|e| e.get_id() == target_id) {
+                Some(t) => t,
+                None => {
+                    player.add_short_message("They're no longer here.");
+                    return Err(());
+                }
+            };
+            let user = entities.iter()
+                .find(|e| e.get_id() == player_id)
+                .expect("Area no longer contains entity.");
+
+            user.use_item(slot, Some(&**target));
+            Ok(target.as_player().is_some())
+        })?;
+
+        // Refreshed after the area is unlocked, as doing so
+        // reads its entities again.
+        if target_is_player {
+            let msg = format!("{} used {} on you.", player.get_name(), item_name);
+            temp_add_short_message(target_id, &msg);
+            if !try_refresh_options(target_id) {
+                temp_send_current_options(target_id);
+            }
+        }
+        Ok(())
+    }
+
     fn get_next_dialogue() -> DialogueOption {
-        Generate(Box::new(move |player: &PlayerMeta| {
+        Generate(Arc::new(move |player: &PlayerMeta| {
             player.entity(|entity: &Entity| {
                 entity.get_inventory()
                     .expect("Player not longer has an inventory")
@@ -1,11 +1,12 @@
 use crate::traits::Item;
 use crate::types::classes::Class::{self, *};
-use crate::types::items::{bows::Bow, swords::Sword};
+use crate::types::items::{armor::Armor, bows::Bow, pass_books::PassBook, swords::Sword};
+use crate::types::items::consumables::{Bag, Consumable, Cure, Summon};
 
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use rand::distributions::{Sample, Weighted, WeightedChoice};
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 
 type ItemRegistry = Vec<ItemSettings>;
 
@@ -82,6 +83,110 @@ fn rand_item(registry: &ItemRegistry, class: Option<Class>, town_num: usize) ->
     WeightedChoice::new(&mut choices).sample(&mut thread_rng())(town_num)
 }
 
+const SWORD: u8 = 0;
+const BOW: u8 = 1;
+const POTION: u8 = 2;
+const CONSUMABLE: u8 = 3;
+
+/// Generates any kind of item suitable for the given
+/// town. Weapons become more common as `town_num` grows
+/// and all items are leveled according to the town.
+/// Weapons are limited to `class`, if provided.
+pub fn random_item_for_town(town_num: usize, class: Option<Class>) -> Box<Item> {
+    let weapon_weight = 2 + town_num as u32;
+    let mut choices: Vec<Weighted<u8>> = vec![
+        Weighted { weight: weapon_weight, item: SWORD },
+        Weighted { weight: weapon_weight, item: BOW },
+        Weighted { weight: 6, item: POTION },
+        Weighted { weight: 4, item: CONSUMABLE },
+    ];
+    choices.retain(|c| match c.item {
+        SWORD => class.map_or(true, |c| c == Melee),
+        BOW => class.map_or(true, |c| c == Ranged),
+        _ => true,
+    });
+    let level = get_item_level(town_num);
+
+    match WeightedChoice::new(&mut choices).sample(&mut thread_rng()) {
+        SWORD => Sword::from_level(level),
+        BOW => Bow::from_level(level),
+        POTION => Box::new(Consumable::healing_potion(level)),
+        _ => Box::new(Cure::new()),
+    }
+}
+
+/// +1 level per 3 * town_num
+/// +1 variability per 4 * town_num
+pub fn get_item_level(town_num: usize) -> u32 {
+    let base_level = (town_num / 3) + 1; // Start at level = 1
+    let variability = town_num / 4; // Start at variability = 0;
+
+    thread_rng().gen_range(base_level - variability, base_level + variability + 1) as u32
+}
+
+/// The information needed to reconstruct an item
+/// with `item_from_save()`.
+#[derive(Clone, Debug)]
+pub struct ItemSave {
+    pub type_id: String,
+    pub name: String,
+    pub level: u32,
+    pub num_uses: u32,
+}
+
+impl ItemSave {
+    pub fn new(item: &Item) -> ItemSave {
+        ItemSave {
+            type_id: item.get_type().to_string(),
+            name: item.get_name().clone(),
+            level: item.get_level(),
+            num_uses: item.get_num_uses(),
+        }
+    }
+}
+
+/// The names of consumables which `item_from_type()`
+/// knows how to construct.
+pub const CONSUMABLE_NAMES: [&str; 5] = [
+    "Healing Potion",
+    "Poisonous Potato (Test Item)",
+    "Cure",
+    "Summoning Stone",
+    "Traveler's Bag",
+];
+
+/// Constructs a new item from its type id. Items which
+/// are generated randomly will not be identical to the
+/// original, only equivalent at the same level. Several
+/// consumables share a type, so they're told apart by
+/// `name`. Returns `None` for unknown types.
+pub fn item_from_type(type_id: &str, name: &str, level: u32) -> Option<Box<Item>> {
+    let item: Box<Item> = match type_id {
+        "sword" => Sword::from_level(level),
+        "bow" => Bow::from_level(level),
+        "armor" => Armor::from_level(level),
+        "pass_book" => Box::new(PassBook::new()),
+        "consumable" => match name {
+            "Healing Potion" => Box::new(Consumable::healing_potion(level)),
+            "Poisonous Potato (Test Item)" => Box::new(Consumable::poisonous_potato()),
+            "Cure" => Box::new(Cure::new()),
+            "Summoning Stone" => Box::new(Summon::new(level)),
+            "Traveler's Bag" => Box::new(Bag::new()),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(item)
+}
+
+/// Variant of `item_from_type()` which also restores
+/// the item's remaining uses.
+pub fn item_from_save(save: &ItemSave) -> Option<Box<Item>> {
+    let item = item_from_type(&save.type_id, &save.name, save.level)?;
+    item.set_num_uses(save.num_uses);
+    Some(item)
+}
+
 fn is_class_allowed(class: Option<Class>, limits: &Option<Vec<Class>>) -> bool {
     let c = if let Some(clazz) = class {
         clazz
@@ -135,3 +240,26 @@ pub fn register_vanilla_settings() {
     register_weapon(procedural_swords);
     register_weapon(procedural_bows);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn average_level(town_num: usize) -> f32 {
+        let samples = 500;
+        let total: u32 = (0..samples)
+            .map(|_| random_item_for_town(town_num, None).get_level())
+            .sum();
+        total as f32 / samples as f32
+    }
+
+    #[test]
+    fn higher_towns_give_higher_level_items() {
+        let levels: Vec<f32> = [1, 10, 30].iter()
+            .map(|&town_num| average_level(town_num))
+            .collect();
+
+        assert!(levels[0] < levels[1], "{:?}", levels);
+        assert!(levels[1] < levels[2], "{:?}", levels);
+    }
+}
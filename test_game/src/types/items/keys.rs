@@ -1,8 +1,20 @@
-use crate::traits::Item;
+use crate::traits::{Entity, Item};
+use crate::util::access;
 
+/// Opens the end gate of the town it was found in.
 #[derive(AtomicClone, ItemTools)]
 pub struct TownKey {
     pub id: usize,
+    pub town_num: usize,
+}
+
+impl TownKey {
+    pub fn new(town_num: usize) -> Box<Item> {
+        Box::new(TownKey {
+            id: rand::random(),
+            town_num,
+        })
+    }
 }
 
 impl Item for TownKey {
@@ -13,4 +25,83 @@ impl Item for TownKey {
     fn get_type(&self) -> &'static str {
         "town_key"
     }
+
+    /// The key belongs to the whole town, so any player
+    /// who finds it unlocks the gate for everyone. It
+    /// starts out hidden in a chest, which doesn't count.
+    fn on_get(&self, entity: Option<&Entity>) {
+        if entity.map_or(false, |e| e.as_player().is_some()) {
+            access::town(self.town_num).set_key_found(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data::{self, PlayerMeta};
+    use crate::types::entities::players::Player;
+    use crate::util::player_options::Response;
+
+    fn find_keys(town_num: usize) -> Vec<((usize, usize, usize), usize, usize)> {
+        // `for_each_area_in_town()` skips ungenerated towns.
+        access::town(town_num);
+        let mut keys = Vec::new();
+        access::for_each_area_in_town(town_num, |area| {
+            for entity in area.borrow_entity_lock().iter() {
+                let key = entity.get_inventory().and_then(|inv| {
+                    inv.for_each_item(|i| Some(i.get_id()).filter(|_| i.get_type() == "town_key"))
+                });
+                if let Some(key_id) = key {
+                    keys.push((area.get_coordinates(), entity.get_id(), key_id));
+                }
+            }
+        });
+        keys
+    }
+
+    fn gate_responses(town_num: usize, player: &PlayerMeta) -> Vec<String> {
+        let mut responses: Vec<Response> = Vec::new();
+        access::area(access::town(town_num).end_gate(), |gate| {
+            gate.get_specials(player, &mut responses)
+        });
+        responses.into_iter().map(|r| r.text).collect()
+    }
+
+    #[test]
+    fn every_town_hides_one_key() {
+        crate::test_init();
+        for town_num in 310..315 {
+            assert_eq!(1, find_keys(town_num).len());
+            assert!(!access::town(town_num).key_found());
+        }
+    }
+
+    #[test]
+    fn key_found_by_one_player_opens_gate_for_another() {
+        crate::test_init();
+        let town_num = 320;
+        let (coords, chest_id, key_id) = find_keys(town_num)[0];
+
+        let finder = Player::new(player_data::test_player());
+        let other = player_data::test_player();
+        assert!(gate_responses(town_num, &other).is_empty());
+
+        let key = access::area(coords, |area| {
+            area.borrow_entity_lock()
+                .iter()
+                .find(|e| e.get_id() == chest_id)
+                .and_then(|chest| chest.get_inventory())
+                .and_then(|inv| inv.take_item_id(key_id, None))
+        })
+        .and_then(|k| k)
+        .expect("The key could not be taken.");
+        finder.give_item(key);
+
+        assert!(access::town(town_num).key_found());
+        assert_eq!(vec!["Unlock the gate"], gate_responses(town_num, &other));
+
+        access::town(town_num).set_unlocked(true);
+        assert_eq!(vec!["Go through the gate"], gate_responses(town_num, &other));
+    }
 }
@@ -1,5 +1,7 @@
+pub mod armor;
 pub mod bows;
 pub mod curses;
+pub mod equipment;
 pub mod inventories;
 pub mod potions;
 //pub mod staves;
@@ -1,18 +1,205 @@
+use crate::types::items::armor::Armor;
 use crate::types::items::item_settings;
-use crate::traits::{Item, Shop};
+use crate::player_data::PlayerMeta;
+use crate::traits::{AttemptedPurchase::{self, *}, Item, Shop};
 use crate::types::items::inventories::Inventory;
+use crate::util::access;
+
+use crate::{game_time, is_night};
+
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use rand::random;
+
+use std::sync::{Arc, Weak};
+
+lazy_static! {
+    /// Shops are owned by whoever sells from them. The
+    /// registry only holds weak references, so dialogue
+    /// can find a shop again without keeping it alive.
+    static ref SHOP_REGISTRY: RwLock<HashMap<usize, Weak<Shop>>> = RwLock::new(HashMap::new());
+}
+
+/// Wraps the shop in a reference counter and registers
+/// it so that it can be located by its id. Shops whose
+/// owners no longer exist are pruned at the same time.
+pub fn register_shop<S: Shop + 'static>(shop: S) -> Arc<Shop> {
+    let shop: Arc<Shop> = Arc::new(shop);
+    let mut registry = SHOP_REGISTRY.write();
+    registry.retain(|_, s| s.upgrade().is_some());
+    registry.insert(shop.get_id(), Arc::downgrade(&shop));
+    shop
+}
+
+/// Locates a shop by its id. Returns `None` if the
+/// shop's owner no longer exists.
+pub fn get_shop(id: usize) -> Option<Arc<Shop>> {
+    let shop = SHOP_REGISTRY.read()
+        .get(&id)
+        .and_then(|s| s.upgrade());
+
+    if shop.is_none() {
+        SHOP_REGISTRY.write().remove(&id);
+    }
+    shop
+}
+
+/// Prices are multiplied by this at night, when most
+/// shopkeepers would rather be asleep.
+const NIGHT_PRICE_FACTOR: f32 = 1.25;
+
+/// The price factor shops should currently charge. Reads
+/// the global clock, so every player sees the same prices.
+pub fn current_price_factor() -> f32 {
+    if is_night() {
+        NIGHT_PRICE_FACTOR
+    } else {
+        1.0
+    }
+}
+
+/// The number of slots added by each bag upgrade.
+pub const BAG_UPGRADE_SLOTS: usize = 5;
+
+/// Bags can't be upgraded beyond this many slots.
+pub const MAX_BAG_SIZE: usize = 30;
+
+/// The price of upgrading a bag with `max_size` slots.
+/// Returns `None` if it can't be upgraded any further.
+pub fn bag_upgrade_price(max_size: usize) -> Option<u32> {
+    if max_size >= MAX_BAG_SIZE {
+        None
+    } else {
+        Some(max_size as u32 * 40)
+    }
+}
+
+/// Attempts to increase the size of the player's main
+/// inventory by `BAG_UPGRADE_SLOTS`. `NotFound` means
+/// that the bag is already as large as it can get.
+pub fn buy_bag_upgrade(player: &PlayerMeta) -> AttemptedPurchase {
+    access::entity(player.get_accessor(), |entity| {
+        let inventory = entity.get_inventory()
+            .expect("Player does not have an inventory.");
+        let max_size = inventory.get_max_size();
+
+        let price = match bag_upgrade_price(max_size) {
+            Some(p) => p,
+            None => return NotFound,
+        };
+        if !entity.can_afford(price) {
+            return CantAfford;
+        }
+        entity.take_money(price);
+        inventory.set_max_size((max_size + BAG_UPGRADE_SLOTS).min(MAX_BAG_SIZE));
+        Purchase
+    })
+    .expect("Area no longer contains entity.")
+}
+
+/// How long a sold item can be bought back, in ms.
+const BUYBACK_EXPIRY_MS: u64 = 5 * 60 * 1000;
+
+/// The number of sales remembered for each player.
+const MAX_BUYBACKS: usize = 3;
+
+/// A record of an item sold to a shop.
+struct Buyback {
+    player_id: usize,
+    item_id: usize,
+    item_name: String,
+    price: u32,
+    sold_at: u64,
+}
+
+/// Tracks the items recently sold by each player so that
+/// they can be purchased again for the same price. Items
+/// age out after `BUYBACK_EXPIRY_MS` or when the shop
+/// restocks.
+pub struct BuybackQueue {
+    entries: Mutex<Vec<Buyback>>,
+}
+
+impl BuybackQueue {
+    pub fn new() -> BuybackQueue {
+        BuybackQueue {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a sale, forgetting this player's oldest
+    /// sale if they have too many.
+    pub fn push(&self, player_id: usize, item_id: usize, item_name: String, price: u32) {
+        let mut entries = self.entries.lock();
+        Self::remove_expired(&mut entries);
+
+        let num_sales = entries.iter()
+            .filter(|b| b.player_id == player_id)
+            .count();
+
+        if num_sales >= MAX_BUYBACKS {
+            let oldest = entries.iter()
+                .position(|b| b.player_id == player_id)
+                .unwrap();
+            entries.remove(oldest);
+        }
+        entries.push(Buyback {
+            player_id,
+            item_id,
+            item_name,
+            price,
+            sold_at: game_time(),
+        });
+    }
+
+    /// Retrieves `(item_id, item_name, price)` for each
+    /// of the player's sales, most recent first.
+    pub fn get_entries(&self, player_id: usize) -> Vec<(usize, String, u32)> {
+        let mut entries = self.entries.lock();
+        Self::remove_expired(&mut entries);
+
+        entries.iter()
+            .rev()
+            .filter(|b| b.player_id == player_id)
+            .map(|b| (b.item_id, b.item_name.clone(), b.price))
+            .collect()
+    }
+
+    /// Removes a sale from the queue, returning its price.
+    pub fn remove(&self, player_id: usize, item_id: usize) -> Option<u32> {
+        let mut entries = self.entries.lock();
+        let index = entries.iter()
+            .position(|b| b.player_id == player_id && b.item_id == item_id)?;
+
+        Some(entries.remove(index).price)
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    fn remove_expired(entries: &mut Vec<Buyback>) {
+        let now = game_time();
+        entries.retain(|b| now.saturating_sub(b.sold_at) < BUYBACK_EXPIRY_MS);
+    }
+}
 
 /// Persistent refers to the fact that
 /// the same items are used on restock.
 pub struct PersistentShop {
+    pub id: usize,
     pub inventory: Inventory,
+    pub buyback: BuybackQueue,
     items: Vec<Box<Item>>,
 }
 
 impl PersistentShop {
     pub fn new(items: Vec<Box<Item>>) -> PersistentShop {
         let ret = PersistentShop {
+            id: random(),
             inventory: Inventory::new(items.len()),
+            buyback: BuybackQueue::new(),
             items,
         };
         ret.restock();
@@ -25,14 +212,22 @@ impl Shop for PersistentShop {
         &self.inventory
     }
 
-    fn get_ptr(&self) -> *const Shop {
-        self as *const PersistentShop
+    fn borrow_buyback(&self) -> &BuybackQueue {
+        &self.buyback
+    }
+
+    fn get_id(&self) -> usize {
+        self.id
     }
 
     fn sell_to_rate(&self) -> f32 {
         0.0
     }
 
+    fn sells_bag_upgrades(&self) -> bool {
+        true
+    }
+
     fn buy_from_rate(&self) -> f32 {
         1.0
     }
@@ -44,15 +239,77 @@ impl Shop for PersistentShop {
     }
 }
 
+/// Sells a random assortment of items from its town.
+/// Each has a title naming its owner, so that players
+/// trading with them can be told apart.
+pub struct TravelingShop {
+    pub id: usize,
+    pub title: String,
+    pub inventory: Inventory,
+    pub buyback: BuybackQueue,
+    pub town_num: usize,
+}
+
+impl TravelingShop {
+    pub fn new(owner: &str, town_num: usize) -> TravelingShop {
+        let ret = TravelingShop {
+            id: random(),
+            title: format!("{}'s wares", owner),
+            inventory: Inventory::new(4),
+            buyback: BuybackQueue::new(),
+            town_num,
+        };
+        ret.restock();
+        ret
+    }
+}
+
+impl Shop for TravelingShop {
+    fn borrow_inventory(&self) -> &Inventory {
+        &self.inventory
+    }
+
+    fn borrow_buyback(&self) -> &BuybackQueue {
+        &self.buyback
+    }
+
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn sell_to_rate(&self) -> f32 {
+        0.5
+    }
+
+    fn buy_from_rate(&self) -> f32 {
+        1.0
+    }
+
+    fn restock(&self) {
+        for _ in 0..self.inventory.get_max_size() {
+            self.inventory
+                .add_item(item_settings::random_item_for_town(self.town_num, None), None);
+        }
+    }
+}
+
 pub struct BlacksmithShop {
+    pub id: usize,
     pub inventory: Inventory,
+    pub buyback: BuybackQueue,
     pub town_num: usize,
 }
 
 impl BlacksmithShop {
     pub fn new(town_num: usize) -> BlacksmithShop {
         let ret = BlacksmithShop {
+            id: random(),
             inventory: Inventory::new(5),
+            buyback: BuybackQueue::new(),
             town_num,
         };
         ret.restock();
@@ -65,8 +322,12 @@ impl Shop for BlacksmithShop {
         &self.inventory
     }
 
-    fn get_ptr(&self) -> *const Shop {
-        self as *const BlacksmithShop
+    fn borrow_buyback(&self) -> &BuybackQueue {
+        &self.buyback
+    }
+
+    fn get_id(&self) -> usize {
+        self.id
     }
 
     fn sell_to_rate(&self) -> f32 {
@@ -77,14 +338,77 @@ impl Shop for BlacksmithShop {
         1.0
     }
 
+    fn repairs_weapons(&self) -> bool {
+        true
+    }
+
     /**
      * Will need some work when more
      * items get added.
      */
     fn restock(&self) {
-        for _ in 0..self.inventory.max_size {
+        // The last slot always holds a piece of armor.
+        for _ in 1..self.inventory.get_max_size() {
             self.inventory
                 .add_item(item_settings::rand_weapon(None, self.town_num), None);
         }
+        let level = item_settings::get_item_level(self.town_num);
+        self.inventory.add_item(Armor::from_level(level), None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data;
+    use crate::util::timed_events;
+
+    #[test]
+    fn dropped_shops_are_pruned() {
+        let dropped = register_shop(PersistentShop::new(Vec::new()));
+        let dropped_id = dropped.get_id();
+        drop(dropped);
+
+        let kept = register_shop(PersistentShop::new(Vec::new()));
+        assert!(!SHOP_REGISTRY.read().contains_key(&dropped_id));
+        assert!(get_shop(kept.get_id()).is_some());
+    }
+
+    #[test]
+    fn selling_then_buying_back_costs_nothing() {
+        crate::test_init();
+        // Sales expire when another test advances the clock.
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(321);
+        player.entity(|e| e.give_money(10_000));
+
+        let shop = register_shop(BlacksmithShop::new(1));
+        let item_id = shop.borrow_inventory().get_display_info(1.0)[0].item_id;
+        assert!(match shop.buy(&player, item_id, 1.0) { Purchase => true, _ => false });
+
+        let gold_before = player.entity(|e| e.get_money());
+        assert_eq!(Ok(()), shop.process_sell(vec![item_id])(&vec!["1"], &player));
+        assert!(player.entity(|e| e.get_money()) > gold_before);
+
+        assert_eq!(Ok(()), shop.process_buy_back()(&vec!["1"], &player));
+        assert_eq!(gold_before, player.entity(|e| e.get_money()));
+        assert!(player.entity(|e| e.get_inventory().unwrap().get_slot_num(item_id).is_some()));
+        assert!(shop.borrow_buyback().get_entries(player.get_player_id()).is_empty());
+    }
+
+    #[test]
+    fn bag_upgrade_adds_slots_for_gold() {
+        crate::test_init();
+        let player = player_data::test_player_in_town(321);
+        let size_before = player.entity(|e| e.get_inventory().unwrap().get_max_size());
+        let price = bag_upgrade_price(size_before).unwrap();
+
+        assert!(match buy_bag_upgrade(&player) { CantAfford => true, _ => false });
+        player.entity(|e| e.give_money(price));
+        assert!(match buy_bag_upgrade(&player) { Purchase => true, _ => false });
+
+        let size_after = player.entity(|e| e.get_inventory().unwrap().get_max_size());
+        assert_eq!(size_before + BAG_UPGRADE_SLOTS, size_after);
+        assert_eq!(0, player.entity(|e| e.get_money()));
     }
 }
@@ -1,5 +1,5 @@
 use crate::traits::{Area, Entity, Item, Weapon};
-use crate::types::effects::{Effect, EffectType::*};
+use crate::types::effects::Effect;
 use crate::types::items::{self, display_info::ItemDisplayInfo};
 
 use parking_lot::Mutex;
@@ -8,12 +8,6 @@ use atomic::Atomic;
 
 use rand::{random, thread_rng, Rng};
 
-// The only way to ensure that hold_effect always stays
-// accurate is to let entities use attribute modifiers
-// instead of directly manipulating their stats.
-// That would require a bit too much effort to change
-// for this project.
-
 #[derive(AtomicClone, ItemTools)]
 pub struct Sword {
     pub id: usize,
@@ -157,10 +151,18 @@ impl Weapon for Sword {
         (self.damage.load(SeqCst) as i32 + self.get_sharpness()) as u32
     }
 
+    fn get_speed(&self) -> u32 {
+        self.speed
+    }
+
     fn get_repair_price(&self) -> u32 {
         let base = self.get_price() / 2;
         base + ((base as f32 / 2.0).ceil() as u32 * self.num_repairs.load(SeqCst))
     }
+
+    fn on_repair(&self) {
+        self.num_repairs.fetch_add(1, SeqCst);
+    }
 }
 
 impl Item for Sword {
@@ -208,7 +210,7 @@ impl Item for Sword {
     // effects to the user on use.
     fn use_item(&self, _user: Option<&Entity>, use_on: Option<&Entity>, _area: &Area) -> Option<String> {
         if let Some(entity) = use_on {
-            entity.add_health(-1 * self.get_damage() as i32);
+            entity.take_damage(self.get_damage());
 
             if let Some(ref effect) = *self.use_effect.lock() {
                 effect.apply(entity);
@@ -219,23 +221,17 @@ impl Item for Sword {
         } // Bug: Num uses will still decrement.
     }
 
+    /// Hold effects are only given as modifiers, so they
+    /// last exactly as long as the sword is equipped.
     fn on_equip(&self, entity: &Entity) {
         if let Some(ref effect) = *self.hold_effect.lock() {
-            effect.apply(entity);
+            entity.give_effect(effect.clone());
         }
     }
 
     fn on_unequip(&self, entity: &Entity) {
-        let effect_cell = self.hold_effect.lock();
-
-        let effect = match *effect_cell {
-            Some(ref e) => e,
-            None => return,
-        };
-
-        if let Permanent = effect.effect_type {
-            // See notes about accuracy.
-            effect.get_opposite_effect().apply(entity);
+        if let Some(ref effect) = *self.hold_effect.lock() {
+            entity.remove_effect(effect.name);
         }
     }
 
@@ -1,18 +1,20 @@
 use crate::types::{
     areas::area_settings::PathPreference::*,
-    areas::area_settings::AREA_REGISTRY,
+    areas::area_settings::{self, AREA_REGISTRY},
     areas::gates::Gate,
     areas::paths::Path,
     classes::{self, Class},
+    entities::belongings::LostBelongings,
+    entities::merchants::{self, Merchant},
+    items::keys::TownKey,
 };
 
 use crate::player_data::PlayerMeta;
-use crate::traits::Area;
-use crate::*;
+use crate::traits::{Area, Entity};
 
 use self::Direction::*;
 
-use rand::{random, thread_rng, Rng};
+use rand::{random, Rng, SeedableRng, StdRng};
 use lazy_static::lazy_static;
 use array_init::array_init;
 use hashbrown::HashMap;
@@ -37,10 +39,19 @@ pub const STARTING_COORDS: (usize, usize) = (0, C);
 /// 1 => exactly straight.
 const STRAIGHTNESS_BIAS: f32 = 0.4;
 
+/// 0-1 chance for an area off of the path to be placed
+/// one tile further out, reached by a bridge over the
+/// empty tile in between.
+const BRIDGE_CHANCE: f32 = 0.2;
+
+/// The number of times a town's map can be regenerated
+/// before giving up.
+const MAX_GENERATION_ATTEMPTS: usize = 10;
+
 /// How empty rooms will appear on the map.
-const EMPTY_ROOM_PAT: &str = " · ";
+pub const EMPTY_ROOM_PAT: &str = " · ";
 
-const CURRENT_ROOM_PAT: &str = "(X)";
+pub const CURRENT_ROOM_PAT: &str = "(X)";
 
 /// Towns are mapped to their index instead of being
 /// stored in an array for two reasons:
@@ -64,6 +75,25 @@ pub type Locations = Vec<(&'static str, (usize, usize))>;
 lazy_static! {
     /// All towns are loaded statically.
     pub static ref TOWN_REGISTRY: RwLock<TownRegistry> = RwLock::new(HashMap::new());
+
+    /// When set, towns are generated from this seed instead
+    /// of a random one, so the same maps are produced every
+    /// time.
+    static ref WORLD_SEED: Atomic<Option<usize>> = Atomic::new(None);
+}
+
+/// Sets the seed used for generating every town after
+/// this point. `None` picks a new seed for each town.
+pub fn set_world_seed(seed: Option<usize>) {
+    WORLD_SEED.store(seed, SeqCst);
+}
+
+/// Combines the world seed with the town's number, so
+/// towns don't all share the same layout.
+fn get_town_rng(town_num: usize) -> StdRng {
+    let seed = WORLD_SEED.load(SeqCst).unwrap_or_else(random);
+    info!("Generating town #{} with seed {}.", town_num, seed);
+    StdRng::from_seed(&[seed, town_num][..])
 }
 
 pub fn setup_town_registry() {}
@@ -83,9 +113,29 @@ pub struct Town {
 }
 
 impl Town {
+    /// Generates and registers a new town. Maps that fail
+    /// `validate_map()` are discarded and regenerated, so
+    /// broken towns are caught here instead of whenever
+    /// their gate or station is first needed.
     pub fn generate(town_num: usize) {
-        let class = classes::random_class();
-        let (map, coords) = generate_map(town_num, class);
+        let mut rng = get_town_rng(town_num);
+        let class = classes::random_class(&mut rng);
+        let mut attempts = 0;
+
+        let (map, coords) = loop {
+            attempts += 1;
+            let (map, coords) = generate_map(&mut rng, town_num, class);
+
+            match validate_map(&map, &coords) {
+                Ok(()) => break (map, coords),
+                Err(e) if attempts < MAX_GENERATION_ATTEMPTS => {
+                    warn!("Regenerating town #{}: {}", town_num, e);
+                }
+                Err(e) => panic!("Unable to generate town #{} after {} attempts: {}", town_num, attempts, e),
+            }
+        };
+        place_merchant(&mut rng, &map);
+        place_key(&mut rng, &map, town_num);
 
         register_town(town_num, Town {
             name: String::from(""),
@@ -120,6 +170,14 @@ impl Town {
             .and_then(|t| Some(t.get_map(player)))
     }
 
+    /// Variant of `find_map()` which returns the legend
+    /// for the icons on the player's map instead.
+    pub fn find_legend(town: usize, player: &PlayerMeta) -> Option<String> {
+        TOWN_REGISTRY.read()
+            .get(&town)
+            .and_then(|t| Some(t.get_legend(player)))
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -182,10 +240,10 @@ impl Town {
             for (z, area) in z_axis.iter().enumerate() {
                 match area {
                     Some(a) if player.player_has_visited((self.town_num, x, z)) => {
-                        if area_coords_match(x, z, player.get_coordinates()) {
+                        if area_coords_match(self.town_num, x, z, player.get_coordinates()) {
                             ret += CURRENT_ROOM_PAT;
                         } else {
-                            ret += &format!("{}", a.get_map_icon());
+                            ret += area_settings::get_icon(a.get_type());
                         }
                     }
                     _ => ret+= EMPTY_ROOM_PAT
@@ -199,6 +257,71 @@ impl Town {
         ret += "\n";
         ret + &horizontal_border
     }
+
+    /// Explains each icon on the player's map of this town.
+    /// Only areas the player has visited are included, so
+    /// the legend doesn't give away what's unexplored.
+    pub fn get_legend(&self, player: &PlayerMeta) -> String {
+        let mut types = Vec::new();
+
+        for (x, z_axis) in self.areas.iter().enumerate() {
+            for (z, area) in z_axis.iter().enumerate() {
+                match area {
+                    Some(a) if player.player_has_visited((self.town_num, x, z)) => {
+                        if !types.contains(&a.get_type()) {
+                            types.push(a.get_type());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut ret = format!("{} You are here\n{} Unexplored", CURRENT_ROOM_PAT, EMPTY_ROOM_PAT);
+        for typ in types {
+            match area_settings::get_icon_info(typ) {
+                Some(info) => ret += &format!("\n{} {}", info.icon, info.label),
+                None => ret += &format!("\n{} Unknown ({})", area_settings::UNKNOWN_ICON, typ),
+            }
+        }
+        ret
+    }
+}
+
+/// Sets up a traveling merchant in a random area that
+/// they're allowed to visit.
+fn place_merchant<R: Rng>(rng: &mut R, map: &Map) {
+    let candidates: Vec<&Box<Area>> = map.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|a| a.as_ref())
+        .filter(|a| merchants::can_visit(&***a) && a.has_room())
+        .collect();
+
+    if let Some(area) = rng.choose(&candidates) {
+        let merchant = Merchant::new(area.get_coordinates());
+        let _ = area.add_entity(Box::new(merchant));
+    }
+}
+
+/// Hides the key to the town's end gate in a random
+/// area, away from the gates and the starting area.
+/// Areas that some classes can't enter are skipped.
+fn place_key<R: Rng>(rng: &mut R, map: &Map, town_num: usize) {
+    let (x, z) = STARTING_COORDS;
+    let candidates: Vec<&Box<Area>> = map.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|a| a.as_ref())
+        .filter(|a| a.get_type() != "gate" && a.required_class().is_none() && a.has_room())
+        .filter(|a| (a.get_coordinates().1, a.get_coordinates().2) != (x, z))
+        .collect();
+
+    match rng.choose(&candidates) {
+        Some(area) => {
+            let chest = LostBelongings::hidden("an old chest", area.get_coordinates());
+            chest.give_item(TownKey::new(town_num));
+            let _ = area.add_entity(Box::new(chest));
+        }
+        None => warn!("Town #{} has nowhere to hide its key.", town_num),
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -208,7 +331,7 @@ enum Direction {
     Right,
 }
 
-fn generate_map(town_num: usize, class: Class) -> (Map, Locations) {
+fn generate_map<R: Rng>(rng: &mut R, town_num: usize, class: Class) -> (Map, Locations) {
     let mut map = empty_map();
     let mut coords = Vec::new();
 
@@ -249,7 +372,7 @@ fn generate_map(town_num: usize, class: Class) -> (Map, Locations) {
         // Cycle the directions backward, recalculate next_dir.
         previous_dir = current_dir;
         current_dir = next_dir;
-        next_dir = get_next_dir(current_dir, previous_dir);
+        next_dir = get_next_dir(rng, current_dir, previous_dir);
 
         // Update the coordinates
         let previous_x = current_x;
@@ -262,13 +385,50 @@ fn generate_map(town_num: usize, class: Class) -> (Map, Locations) {
 
     // Relatively inefficient way to go back through
     // and connect areas. Must happen in this order.
-    modify_path(class, town_num, &mut coords, &mut map);
+    modify_path(rng, class, town_num, &mut coords, &mut map);
     trace_connect_backward(&mut current_x, &mut current_z, &map);
-    add_branches(class, town_num, &mut area_num, &mut coords, &mut map);
+    add_branches(rng, class, town_num, &mut area_num, &mut coords, &mut map);
 
     (map, coords)
 }
 
+/// Ensures that the map has exactly one end gate and a
+/// station which can be reached from the entrance.
+fn validate_map(map: &Map, coords: &Locations) -> Result<(), &'static str> {
+    let num_gates = coords.iter()
+        .filter(|(typ, _)| *typ == "gate")
+        .count();
+
+    if num_gates != 1 {
+        return Err("The map must have exactly one gate.");
+    }
+    let station = coords.iter()
+        .find(|(typ, _)| *typ == "station")
+        .map(|(_, coords)| *coords)
+        .ok_or("The map has no station.")?;
+
+    // Walk the connections outward from the entrance.
+    let mut visited = vec![STARTING_COORDS];
+    let mut index = 0;
+
+    while index < visited.len() {
+        let (x, z) = visited[index];
+        index += 1;
+
+        if let Some(ref area) = map[x][z] {
+            for (_, next_x, next_z) in area.get_connections() {
+                if !visited.contains(&(next_x, next_z)) {
+                    visited.push((next_x, next_z));
+                }
+            }
+        }
+    }
+    if !visited.contains(&station) {
+        return Err("The station can't be reached from the entrance.");
+    }
+    Ok(())
+}
+
 fn empty_map() -> Map {
     array_init(|_| array_init(|_| None))
 }
@@ -334,16 +494,17 @@ fn trace_connect_backward(current_x: &mut usize, current_z: &mut usize, map: &Ma
     }
 }
 
-fn modify_path(class: Class, town_num: usize, coords: &mut Locations, map: &mut Map) {
+fn modify_path<R: Rng>(rng: &mut R, class: Class, town_num: usize, coords: &mut Locations, map: &mut Map) {
     let registry = AREA_REGISTRY.lock();
-    let areas_on_path = registry
+    let areas_on_path: Vec<_> = registry
         .iter()
-        .filter(|s| s.path_pref == OnPath && random::<f32>() <= s.chance);
+        .filter(|s| s.path_pref == OnPath && rng.gen::<f32>() <= s.chance)
+        .collect();
 
     for settings in areas_on_path {
         let (mut x, mut z);
         while { // Do-while
-            x = thread_rng().gen_range(settings.min_x, settings.max_x + 1);
+            x = rng.gen_range(settings.min_x, settings.max_x + 1);
             z = get_z_of_path(x, &map);
             !is_replaceable(x, z, &map)
         } {}
@@ -362,21 +523,28 @@ fn modify_path(class: Class, town_num: usize, coords: &mut Locations, map: &mut
     }
 }
 
-fn add_branches(class: Class, town_num: usize, area_num: &mut usize, coords: &mut Locations, map: &mut Map) {
+fn add_branches<R: Rng>(rng: &mut R, class: Class, town_num: usize, area_num: &mut usize, coords: &mut Locations, map: &mut Map) {
     let registry = AREA_REGISTRY.lock();
-    let areas_off_path = registry
+    let areas_off_path: Vec<_> = registry
         .iter()
-        .filter(|s| s.path_pref == OffPath && random::<f32>() <= s.chance);
+        .filter(|s| s.path_pref == OffPath && rng.gen::<f32>() <= s.chance)
+        .collect();
 
     for settings in areas_off_path {
         let mut x;
         let mut on_off = None;
         while let None = on_off {
-            x = thread_rng().gen_range(settings.min_x, settings.max_x + 1);
-            on_off = get_coords_beside_path(x, &map);
+            x = rng.gen_range(settings.min_x, settings.max_x + 1);
+            on_off = get_coords_beside_path(rng, x, &map);
         }
 
-        let ((on_x, on_z), (off_x, off_z)) = on_off.unwrap();
+        let (on, mut off) = on_off.unwrap();
+        if rng.gen::<f32>() <= BRIDGE_CHANCE {
+            if let Some(far) = get_coords_past(on, off, &map) {
+                off = far;
+            }
+        }
+        let ((on_x, on_z), (off_x, off_z)) = (on, off);
         *area_num += 1;
 
         let new_area = (settings.constructor)(class, *area_num, (town_num, off_x, off_z));
@@ -391,9 +559,10 @@ fn add_branches(class: Class, town_num: usize, area_num: &mut usize, coords: &mu
 /// empty spot. Redundant code is used to avoid
 /// unnecessarily calculating a second. Could
 /// probably be cleaned up a bit, or at least
-/// ignored.
-fn get_coords_beside_path(x: usize, map: &Map) -> Option<((usize, usize), (usize, usize))> {
-    if random() { // Start on the left.
+/// ignored. The side is drawn from `rng` so that
+/// branches can be reproduced from a seed.
+fn get_coords_beside_path<R: Rng>(rng: &mut R, x: usize, map: &Map) -> Option<((usize, usize), (usize, usize))> {
+    if rng.gen() { // Start on the left.
         if let Some(coords) = get_coords_to_left(x, &map) {
             return Some(((coords.0, coords.1 + 1), coords));
         } else if let Some(coords) = get_coords_to_right(x, &map) {
@@ -409,6 +578,21 @@ fn get_coords_beside_path(x: usize, map: &Map) -> Option<((usize, usize), (usize
     return None;
 }
 
+/// Locates the tile one step past `off`, continuing in
+/// the direction from `on` to `off`. Returns `None` if
+/// that tile is off of the map or already in use.
+fn get_coords_past(on: (usize, usize), off: (usize, usize), map: &Map) -> Option<(usize, usize)> {
+    let z = if off.1 > on.1 {
+        off.1 + 1
+    } else {
+        off.1.checked_sub(1)?
+    };
+    match map[off.0].get(z) {
+        Some(None) => Some((off.0, z)),
+        _ => None,
+    }
+}
+
 fn get_coords_to_left(x: usize, map: &Map) -> Option<(usize, usize)> {
     for z in 0..map[x].len() {
         if let Some(ref area) = map[x][z] {
@@ -476,16 +660,16 @@ fn get_area_num(x: usize, z: usize, map: &Map) -> usize {
 ///   choice that has no practical significance.
 /// - If we previously went forward, we can go in any
 ///   direction at random, as it does not matter.
-fn get_next_dir(current_dir: Direction, previous_dir: Direction) -> Direction {
+fn get_next_dir<R: Rng>(rng: &mut R, current_dir: Direction, previous_dir: Direction) -> Direction {
     match current_dir {
         Forward => {
-            let rand_f32: f32 = thread_rng().gen_range(0.0, 1.0);
+            let rand_f32: f32 = rng.gen_range(0.0, 1.0);
 
             if rand_f32 <= STRAIGHTNESS_BIAS {
                 Forward
             } else {
                 match previous_dir {
-                    Forward => *choose(&[Left, Right]),
+                    Forward => if rng.gen() { Left } else { Right },
                     Left => Left,
                     Right => Right,
                 }
@@ -529,7 +713,7 @@ fn format_map(map: &Map) -> String {
     for (x, z_axis) in map.iter().enumerate() {
         for area in z_axis.iter() {
             match area {
-                Some(a) => ret += format!("{}", a.get_map_icon()).as_str(),
+                Some(a) => ret += area_settings::get_icon(a.get_type()),
                 None => ret += EMPTY_ROOM_PAT
             };
         }
@@ -540,6 +724,108 @@ fn format_map(map: &Map) -> String {
     ret
 }
 
-fn area_coords_match(x: usize, z: usize, coords: (usize, usize, usize)) -> bool {
-    x == coords.1 && z == coords.2
-}
\ No newline at end of file
+fn area_coords_match(town_num: usize, x: usize, z: usize, coords: (usize, usize, usize)) -> bool {
+    town_num == coords.0 && x == coords.1 && z == coords.2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data;
+    use crate::types::classes::Class::Melee;
+    use crate::util::access;
+
+    /// The type of every area in a map generated from `seed`.
+    fn seeded_layout(seed: usize) -> Vec<Option<&'static str>> {
+        let mut rng = StdRng::from_seed(&[seed, 1][..]);
+        let (map, _) = generate_map(&mut rng, 330, Melee);
+        map.iter()
+            .flat_map(|row| row.iter())
+            .map(|a| a.as_ref().map(|a| a.get_type()))
+            .collect()
+    }
+
+    /// The numbered movements from every area in a map
+    /// generated from `seed`, without the areas' titles.
+    fn seeded_movements(seed: usize) -> Vec<Vec<String>> {
+        let mut rng = StdRng::from_seed(&[seed, 1][..]);
+        let (map, _) = generate_map(&mut rng, 333, Melee);
+        let player = player_data::test_player();
+        map.iter()
+            .flat_map(|row| row.iter())
+            .filter_map(|a| a.as_ref())
+            .map(|area| {
+                let mut responses = Vec::new();
+                area.get_movements(&player, &mut responses);
+                responses.iter()
+                    .enumerate()
+                    .map(|(i, r)| format!("{}. {}", i + 1, r.text.split(':').next().unwrap()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_branches_are_placed_identically() {
+        crate::test_init();
+        for seed in 0..10 {
+            assert_eq!(seeded_layout(seed), seeded_layout(seed));
+        }
+    }
+
+    #[test]
+    fn many_seeds_generate_without_losing_the_path() {
+        crate::test_init();
+        // Any seed that panics can be reproduced from its number.
+        for seed in 0..200 {
+            let mut rng = StdRng::from_seed(&[seed, 1][..]);
+            let (map, _) = generate_map(&mut rng, 335, Melee);
+            assert!(map.iter().flat_map(|row| row.iter()).any(|a| a.is_some()));
+        }
+    }
+
+    #[test]
+    fn long_connections_are_listed_by_both_ends() {
+        crate::test_init();
+        let mut num_long = 0;
+        for seed in 0..200 {
+            let mut rng = StdRng::from_seed(&[seed, 1][..]);
+            let (map, _) = generate_map(&mut rng, 335, Melee);
+            let areas = map.iter().flat_map(|row| row.iter()).filter_map(|a| a.as_ref());
+
+            for area in areas {
+                let (_, x, z) = area.get_coordinates();
+                for (_, x2, z2) in area.get_connections() {
+                    let distance = (x as isize - x2 as isize).abs() + (z as isize - z2 as isize).abs();
+                    if distance < 2 {
+                        continue;
+                    }
+                    num_long += 1;
+                    let other = map[x2][z2].as_ref()
+                        .expect("A connection led to an empty tile.");
+                    assert!(other.get_connections().contains(&area.get_coordinates()));
+                }
+            }
+        }
+        assert!(num_long > 0, "No seed generated a long connection.");
+    }
+
+    #[test]
+    fn seeded_movements_are_numbered_identically() {
+        crate::test_init();
+        for seed in 0..10 {
+            assert_eq!(seeded_movements(seed), seeded_movements(seed));
+        }
+    }
+
+    #[test]
+    fn every_town_has_a_gate_and_a_reachable_station() {
+        crate::test_init();
+        for town_num in 400..430 {
+            let town = access::town(town_num);
+            assert_eq!(Ok(()), validate_map(&town.areas, &town.coords));
+            assert!(town.locate_area("station").is_some());
+            town.end_gate();
+        }
+    }
+}
@@ -105,9 +105,59 @@ pub fn town(num: usize) -> Arc<Town> {
     towns::TOWN_REGISTRY.read().get(&num).unwrap().clone()
 }
 
+/// Runs `callback` for every area in the town. Towns
+/// that haven't been generated are skipped rather than
+/// generated. The registry is only locked long enough
+/// to clone the town, so callbacks may access it.
+pub fn for_each_area_in_town<F>(town_num: usize, mut callback: F)
+    where F: FnMut(&Area)
+{
+    let town = match towns::TOWN_REGISTRY.read().get(&town_num) {
+        Some(t) => t.clone(),
+        None => return,
+    };
+    for row in town.get_areas().iter() {
+        for area in row.iter().filter_map(|a| a.as_ref()) {
+            callback(&**area);
+        }
+    }
+}
+
+/// Variant of `for_each_area_in_town()` which runs
+/// `callback` for every area in every generated town.
+pub fn for_each_area<F>(mut callback: F)
+    where F: FnMut(&Area)
+{
+    let town_nums: Vec<usize> = towns::TOWN_REGISTRY.read()
+        .keys()
+        .cloned()
+        .collect();
+
+    for town_num in town_nums {
+        for_each_area_in_town(town_num, &mut callback);
+    }
+}
+
+/// Variant of `for_each_area_in_town()` which runs
+/// `callback` for every player in the town. Each area's
+/// entities stay locked during its callbacks, so they
+/// must not add or remove entities from it.
+pub fn for_each_player_in_town<F>(town_num: usize, mut callback: F)
+    where F: FnMut(&Entity)
+{
+    for_each_area_in_town(town_num, |area| {
+        area.borrow_entity_lock().iter()
+            .filter(|e| e.as_player().is_some())
+            .for_each(|e| callback(&**e));
+    });
+}
+
 pub fn area_exists(coords: (usize, usize, usize)) -> bool {
     match towns::TOWN_REGISTRY.read().get(&coords.0) {
-        Some(t) => (&t.get_areas()[coords.1][coords.2]).is_some(),
+        Some(t) => t.get_areas()
+            .get(coords.1)
+            .and_then(|row| row.get(coords.2))
+            .map_or(false, |a| a.is_some()),
         _ => false
     }
 }
@@ -12,7 +12,7 @@ use std::io::prelude::*;
 use std::sync::mpsc::Sender;
 
 use crate::messages::ChannelInfo::Discord;
-use crate::GameMessage;
+use crate::{GameMessage, MessageKind};
 
 /**
  * To-do: Use one file instead of two.
@@ -108,7 +108,7 @@ impl Bot {
             .start()
             .expect("Error connecting to Discord's servers.");
 
-        println!("\nDiscord bot loaded successfully.");
+        info!("Discord bot loaded successfully.");
     }
 
     fn load_token() -> Option<String> {
@@ -138,7 +138,7 @@ impl Bot {
                         vec.push(num);
                         continue;
                     }
-                    println!("Error reading from {}. Ignoring.", CHANNELS_FILE);
+                    warn!("Error reading from {}. Ignoring.", CHANNELS_FILE);
                     break;
                 }
                 vec
@@ -163,7 +163,7 @@ impl Bot {
      */
 
     fn is_registered(&self, channel: u64) -> bool {
-        let channels = self.channels.lock().unwrap();
+        let channels = self.channels.lock();
         channels.contains(&channel)
     }
 
@@ -186,7 +186,7 @@ impl Bot {
     }
 
     fn add_channel(&self, num: u64) -> io::Result<String> {
-        let mut channels = self.channels.lock().unwrap();
+        let mut channels = self.channels.lock();
 
         if channels.contains(&num) {
             return Ok(String::from("This is already a game channel."));
@@ -201,7 +201,7 @@ impl Bot {
     }
 
     fn remove_channel(&self, num: u64) -> io::Result<String> {
-        let mut channels = self.channels.lock().unwrap();
+        let mut channels = self.channels.lock();
 
         if !channels.contains(&num) {
             return Ok(String::from("This is not a game channel."));
@@ -216,7 +216,7 @@ impl Bot {
 
         file.set_len(0)?;
         file.write(updated.as_bytes())?;
-        channels.remove_item(&num);
+        channels.retain(|c| *c != num);
 
         Ok(String::from("Channel removed successfully."))
     }
@@ -238,11 +238,12 @@ impl EventHandler for Bot {
                 None => { /* ignore */ }
             }
         } else if self.is_registered(msg.channel_id.0) {
-            let sender = self.sender.lock().unwrap();
+            let sender = self.sender.lock();
 
             let message = GameMessage {
                 channel_info: Discord(msg.channel_id, msg.author.id),
                 message: msg.content.to_owned(),
+                kind: MessageKind::Input,
             };
             sender
                 .send(message)
@@ -289,6 +290,7 @@ fn remove_line(text: &str, line: &str) -> String {
     for l in text.lines() {
         if l != line {
             updated += l;
+            updated += "\n";
         }
     }
     updated
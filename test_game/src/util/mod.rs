@@ -5,3 +5,85 @@ pub mod player_options;
 #[cfg(feature = "remote_clients")]
 pub mod server_host;
 pub mod timed_events;
+
+use std::str::FromStr;
+
+/// Errors reported by the argument parsing helpers.
+/// These are also suitable for sending to players.
+pub const MISSING_ARG: &str = "You must specify a number.";
+pub const INVALID_ARG: &str = "That isn't a valid number.";
+pub const OUT_OF_RANGE: &str = "That number is out of range.";
+
+/// Parses the command argument at `index`.
+pub fn parse_arg<T: FromStr>(args: &[&str], index: usize) -> Result<T, &'static str> {
+    match args.get(index) {
+        Some(arg) => arg.parse().map_err(|_| INVALID_ARG),
+        None => Err(MISSING_ARG),
+    }
+}
+
+/// Variant of `parse_arg()` which returns `default`
+/// when the argument was not specified.
+pub fn parse_arg_or<T: FromStr>(args: &[&str], index: usize, default: T) -> Result<T, &'static str> {
+    match parse_arg(args, index) {
+        Err(MISSING_ARG) => Ok(default),
+        result => result,
+    }
+}
+
+/// Variant of `parse_arg()` which also requires the
+/// value to be between `min` and `max`, inclusive.
+pub fn parse_arg_in_range<T: FromStr + PartialOrd>(args: &[&str], index: usize, min: T, max: T) -> Result<T, &'static str> {
+    let val = parse_arg(args, index)?;
+    if val < min || val > max {
+        return Err(OUT_OF_RANGE);
+    }
+    Ok(val)
+}
+
+/// Parses a 1-based slot number entered by a player,
+/// converting it into a 0-based index below `len`.
+pub fn parse_slot(args: &[&str], index: usize, len: usize) -> Result<usize, &'static str> {
+    let num: usize = parse_arg(args, index)?;
+    match num.checked_sub(1) {
+        Some(slot) if slot < len => Ok(slot),
+        _ => Err(OUT_OF_RANGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_index() {
+        assert_eq!(Err(MISSING_ARG), parse_arg::<u32>(&["buy"], 1));
+        assert_eq!(Err(MISSING_ARG), parse_arg_in_range::<u32>(&[], 0, 1, 5));
+        assert_eq!(Ok(1), parse_arg_or::<u32>(&["buy"], 1, 1));
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert_eq!(Err(INVALID_ARG), parse_arg::<u32>(&["buy", "two"], 1));
+        assert_eq!(Err(INVALID_ARG), parse_arg::<u32>(&["buy", "-1"], 1));
+        assert_eq!(Err(INVALID_ARG), parse_arg_or::<u32>(&["buy", "x"], 1, 1));
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(Ok(3), parse_arg_in_range::<u32>(&["buy", "3"], 1, 1, 5));
+        assert_eq!(Ok(5), parse_arg_in_range::<u32>(&["buy", "5"], 1, 1, 5));
+        assert_eq!(Err(OUT_OF_RANGE), parse_arg_in_range::<u32>(&["buy", "0"], 1, 1, 5));
+        assert_eq!(Err(OUT_OF_RANGE), parse_arg_in_range::<u32>(&["buy", "6"], 1, 1, 5));
+    }
+
+    #[test]
+    fn slot_numbers_start_at_one() {
+        let args = |num: &'static str| vec!["use", num];
+        assert_eq!(Ok(0), parse_slot(&args("1"), 1, 5));
+        assert_eq!(Ok(4), parse_slot(&args("5"), 1, 5));
+        assert_eq!(Err(OUT_OF_RANGE), parse_slot(&args("0"), 1, 5));
+        assert_eq!(Err(OUT_OF_RANGE), parse_slot(&args("6"), 1, 5));
+        assert_eq!(Err(OUT_OF_RANGE), parse_slot(&args("1"), 1, 0));
+    }
+}
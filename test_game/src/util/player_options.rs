@@ -1,8 +1,8 @@
 use crate::messages::MessageComponent::*;
-use crate::player_data::PlayerMeta;
+use crate::player_data::{PlayerMeta, PLAYER_META};
 use crate::text;
 use crate::util::access::{self, EntityAccessor};
-use crate::util::timed_events::{DelayHandler, DelayedEvent};
+use crate::util::timed_events::{self, DelayHandler, DelayedEvent};
 use crate::*;
 
 use std::iter::FromIterator;
@@ -20,15 +20,74 @@ use self::DialogueResult::*;
 /// from having no user.
 pub const GLOBAL_USER: usize = 01001010100101010;
 
+/// The maximum number of responses that any single
+/// dialogue may contain. Response numbers continue
+/// across dialogues, so this keeps them readable.
+pub const MAX_RESPONSES: usize = 20;
+
 lazy_static! {
     /// Player dialogue is stored statically.
     pub static ref CURRENT_OPTIONS: Mutex<Vec<Arc<Dialogue>>> = Mutex::new(Vec::new());
+
+    /// Refreshes which could not run because a registry
+    /// was already locked. These are retried next tick.
+    static ref DEFERRED_REFRESHES: Mutex<Vec<DeferredRefresh>> = Mutex::new(Vec::new());
+}
+
+/// A refresh that is waiting on `run_deferred_refreshes()`.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum DeferredRefresh {
+    SendCurrent(usize),
+    TryRefresh(usize),
+}
+
+/// Whether both the options and player registries can
+/// currently be locked. Neither lock is reentrant, so
+/// this is false whenever either is held further up
+/// the stack.
+fn registries_available() -> bool {
+    PLAYER_META.try_lock().is_some() && CURRENT_OPTIONS.try_lock().is_some()
+}
+
+fn defer_refresh(refresh: DeferredRefresh) {
+    let mut deferred = DEFERRED_REFRESHES.lock();
+    if !deferred.contains(&refresh) {
+        deferred.push(refresh);
+    }
+}
+
+/// Retries each refresh that had to be deferred. Should
+/// be called once per tick, while no registries are in
+/// use.
+pub fn run_deferred_refreshes() {
+    let deferred: Vec<DeferredRefresh> = DEFERRED_REFRESHES.lock()
+        .drain(..)
+        .collect();
+
+    for refresh in deferred {
+        match refresh {
+            DeferredRefresh::SendCurrent(id) => temp_send_current_options(id),
+            DeferredRefresh::TryRefresh(id) => if !try_refresh_options(id) {
+                // Callers expected a refresh, so don't leave
+                // the player with a stale screen.
+                temp_send_current_options(id);
+            },
+        }
+    }
 }
 
 /// A function used for registering new options,
 /// automatically wrapping them in reference
-/// counters.
-pub fn register_options(options: Dialogue) {
+/// counters. Responses beyond `MAX_RESPONSES` are
+/// dropped and logged so that they can be fixed.
+pub fn register_options(mut options: Dialogue) {
+    if options.responses.len() > MAX_RESPONSES {
+        warn!(
+            "Dialogue \"{}\" has {} responses. The maximum is {}.",
+            options.title, options.responses.len(), MAX_RESPONSES
+        );
+        options.responses.truncate(MAX_RESPONSES);
+    }
     _register_options(Arc::new(options));
 }
 
@@ -74,6 +133,14 @@ pub fn remove_all_options(player_id: usize) -> Vec<Arc<Dialogue>> {
         .collect()
 }
 
+/// Whether the player currently has a dialogue with this
+/// `title` open.
+pub fn has_options_titled(player_id: usize, title: &str) -> bool {
+    CURRENT_OPTIONS.lock()
+        .iter()
+        .any(|o| o.player_id == player_id && o.title == title)
+}
+
 /// Locates the player ID associated with this dialogue.
 pub fn get_player_for_options(option_id: usize) -> Option<usize> {
     CURRENT_OPTIONS.lock()
@@ -83,9 +150,12 @@ pub fn get_player_for_options(option_id: usize) -> Option<usize> {
 }
 
 /// Generates the formatted dialogue text for this player.
+/// Player tokens are substituted once the options are
+/// no longer locked.
 pub fn get_options_text(for_player: usize) -> String {
     let mut options_text = String::new();
-    let length = access::player_meta(for_player).get_text_length();
+    let player = access::player_meta(for_player);
+    let length = player.get_text_length();
     let mut first_response = 1;
     CURRENT_OPTIONS.lock()
         .iter()
@@ -94,7 +164,7 @@ pub fn get_options_text(for_player: usize) -> String {
             options_text += &format!("\n{}", o.get_display(length, first_response));
             first_response += o.responses.len();
         });
-    options_text
+    text::apply_player_tokens(&options_text, &player)
 }
 
 /// A convenience function used for deleting one dialogue
@@ -102,12 +172,9 @@ pub fn get_options_text(for_player: usize) -> String {
 pub fn replace_options(player_id: usize, old_options: usize, new_options: Dialogue) {
     if let Some(options) = delete_options(old_options) {
         if player_id != options.player_id {
-            println!(
-                "Debug: A call was somehow sent to replace dialogue\n\
-                 for one player with that of another. This message\n\
-                 Is temporary and should be fixed.\n\
-                 From id:{}\n\
-                 To id:{}",
+            warn!(
+                "A call was sent to replace dialogue for one player \
+                 with that of another. From id: {}; To id: {}.",
                 options.player_id, player_id
             );
             register_options(options);
@@ -123,8 +190,14 @@ pub fn replace_options(player_id: usize, old_options: usize, new_options: Dialog
 /// be associated with their current area. If there is
 /// more than one dialogue, the assumption is wrong
 /// and the function will fail. Returns a boolean
-/// indicating this outcome.
+/// indicating this outcome. If the registries are in
+/// use, the refresh is deferred until the next tick and
+/// is assumed to succeed.
 pub fn try_refresh_options(player_id: usize) -> bool {
+    if !registries_available() {
+        defer_refresh(DeferredRefresh::TryRefresh(player_id));
+        return true;
+    }
     try_delete_options(player_id)
         .and_then(|_| Ok(temp_get_send_area_options(player_id)))
         .is_ok()
@@ -132,13 +205,14 @@ pub fn try_refresh_options(player_id: usize) -> bool {
 
 /// Attempts to locate the player data associated with
 /// this id and display their current options to the
-/// screen. This will most likely freeze the game if
-/// run from the main thread, as it tries to acquire
-/// a lock on the player registry, which may already
-/// be taken in an earlier scope. To avoid this issue,
-/// only run this option when the user's PlayerMeta
-/// object is not currently in scope.
+/// screen. If the player or options registry is already
+/// locked in an earlier scope, the refresh is deferred
+/// until the next tick instead of freezing the game.
 pub fn temp_send_current_options(to_player: usize) {
+    if !registries_available() {
+        defer_refresh(DeferredRefresh::SendCurrent(to_player));
+        return;
+    }
     let options_text = get_options_text(to_player);
     temp_send_message_to_player(to_player, Options, &options_text);
 }
@@ -206,15 +280,20 @@ pub enum DialogueOption {
     /// function. Using `gen_dialogue` with a supplied
     /// closure may produce a cleaner syntax in many
     /// cases.
-    Generate(Box<Fn(&PlayerMeta) -> Dialogue>)
+    Generate(Regenerator)
 }
 
+/// A function which produces a specific dialogue. These
+/// are kept instead of the dialogues themselves so that
+/// players can return to previous screens.
+pub type Regenerator = Arc<Fn(&PlayerMeta) -> Dialogue + Send + Sync>;
+
 /// A shorthand function for creating `Generate()`
 /// dialogue options.
 pub fn gen_dialogue<F>(run: F) -> DialogueOption
-    where F: Fn(&PlayerMeta) -> Dialogue + 'static
+    where F: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
 {
-    Generate(Box::new(run))
+    Generate(Arc::new(run))
 }
 
 pub struct Dialogue {
@@ -250,6 +329,10 @@ pub struct Dialogue {
     /// to delete.
     pub is_primary: bool,
 
+    /// The function that generated this dialogue, if any.
+    /// Allows players to come back to it with `back`.
+    pub regenerator: Option<Regenerator>,
+
     /// The unique identifier of the player associated with
     /// this dialogue.
     pub player_id: usize,
@@ -270,6 +353,7 @@ impl Default for Dialogue {
             commands: Vec::new(),
             text_handler: None,
             is_primary: false,
+            regenerator: None,
             player_id: GLOBAL_USER,
             id: random()
         }
@@ -377,6 +461,17 @@ impl Dialogue {
         player.area( |a| a.get_dialogue(player))
     }
 
+    /// Retrieves a function that recreates this dialogue.
+    /// Primary dialogues are always recreated from the
+    /// player's current area.
+    pub fn get_regenerator(&self) -> Option<Regenerator> {
+        if self.is_primary {
+            Some(Arc::new(Dialogue::from_area))
+        } else {
+            self.regenerator.clone()
+        }
+    }
+
     /// A dialogue which features two events for handling `yes`
     /// or `no` from the user.
     pub fn confirm_action<F1, F2>(player_id: usize,temporary: bool, on_yes: F1, on_no: F2) -> Dialogue
@@ -402,12 +497,54 @@ impl Dialogue {
         }
     }
 
+    /// Variant of `confirm_action()` which runs `on_no` if
+    /// the player doesn't respond within the usual duration,
+    /// letting them know that their chance has expired.
+    pub fn confirm_action_timeout<F1, F2>(player_id: usize, on_yes: F1, on_no: F2) -> Dialogue
+        where F1: Fn(&PlayerMeta) + 'static,
+              F2: Fn(&PlayerMeta) + Send + Sync + 'static
+    {
+        let id = random();
+        let on_no = Arc::new(on_no);
+        let on_timeout = on_no.clone();
+
+        let event_id = DelayedEvent::no_flags(TEMP_DIALOGUE_DURATION, move || {
+            // Make sure the player didn't already answer.
+            if delete_options(id).is_some() {
+                let player = access::player_meta(player_id);
+                player.add_short_message("You took too long to respond.");
+                on_timeout(&*player);
+                player.send_current_options();
+            }
+        });
+        // Cancel the timeout as soon as either is chosen.
+        let responses = vec![
+            Response::delete_dialogue("Yes", move |player| {
+                timed_events::delete_event(event_id);
+                on_yes(player);
+            }),
+            Response::delete_dialogue("No", move |player| {
+                timed_events::delete_event(event_id);
+                on_no(player);
+            })
+        ];
+
+        Dialogue {
+            title: String::from("Confirm Action"),
+            info: Some(String::from("Are you sure?")),
+            responses,
+            player_id,
+            id,
+            ..Self::default()
+        }
+    }
+
     /// Variant of `confirm_action()` which specifies how
     /// the dialogue should be continued in either case.
     pub fn confirm_action_then<F1, F2, F3>(player_id: usize, on_yes: F1, then: F2, else_then: F3,) -> Dialogue
         where F1: Fn(&PlayerMeta) + 'static,
-              F2: Fn(&PlayerMeta) -> Dialogue + 'static,
-              F3: Fn(&PlayerMeta) -> Dialogue + 'static
+              F2: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static,
+              F3: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         let responses = vec![
             Response::new("Yes", on_yes, then),
@@ -445,7 +582,7 @@ impl Dialogue {
         };
 
         let num: usize = command.parse().unwrap_or(0);
-        let num = num - (first_response - 1);
+        let num = num.saturating_sub(first_response - 1);
 
         // Handle numbered responses.
         if num > 0 {
@@ -458,9 +595,7 @@ impl Dialogue {
         }
 
         // Handle commands
-        let cmd = self.commands.iter()
-            .find(|c| c.matches_input(command));
-        if let Some(c) = cmd {
+        if let Some(c) = self.find_command(command) {
             let args: Vec<&str> = Vec::from_iter(split);
             c.run(&args, player, &self);
             return Success;
@@ -474,6 +609,27 @@ impl Dialogue {
         NoneFound
     }
 
+    /// Locates the command being called by `input`. Exact
+    /// matches are preferred. Otherwise, `input` may be
+    /// the start of a single command's name or alias.
+    /// Prefixes are ignored when the dialogue accepts
+    /// text input, as the player may be typing freely.
+    pub fn find_command(&self, input: &str) -> Option<&Command> {
+        let exact = self.commands.iter()
+            .find(|c| c.matches_input(input));
+
+        if exact.is_some() || self.text_handler.is_some() {
+            return exact;
+        }
+        let mut prefixed = self.commands.iter()
+            .filter(|c| c.matches_prefix(input));
+
+        match (prefixed.next(), prefixed.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+
     /// Formats each component of this dialogue into a clean
     /// display, which will be sent to the user starting at
     /// the response number indicated by `first_response`.
@@ -525,11 +681,17 @@ impl Dialogue {
     }
 }
 
+/// The outcome of running a response or command. Actions
+/// that fail keep the player on their current dialogue
+/// instead of moving on to `next_dialogue`. They should
+/// inform the player of what went wrong themselves.
+pub type ActionResult = Result<(), ()>;
+
 /// A type of Dialogue option used for handling
 /// automatically-numbered responses.
 pub struct Response {
     pub text: String,
-    pub execute: Option<Box<Fn(&PlayerMeta) + 'static>>,
+    pub execute: Option<Box<Fn(&PlayerMeta) -> ActionResult + 'static>>,
     pub next_dialogue: DialogueOption,
 }
 
@@ -539,12 +701,12 @@ impl Response {
     /// contexts.
     pub fn new<F1, F2>(text: &str, run: F1, then: F2) -> Response
         where F1: Fn(&PlayerMeta) + 'static,
-              F2: Fn(&PlayerMeta) -> Dialogue + 'static
+              F2: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         Response {
             text: String::from(text),
-            execute: Some(Box::new(run)),
-            next_dialogue: Generate(Box::new(then)),
+            execute: Some(Box::new(move |p| { run(p); Ok(()) })),
+            next_dialogue: Generate(Arc::new(then)),
         }
     }
 
@@ -561,6 +723,26 @@ impl Response {
     /// string instead of a slice.
     pub fn _simple<F>(text: String, run: F) -> Response
         where F: Fn(&PlayerMeta) + 'static
+    {
+        Response {
+            text,
+            execute: Some(Box::new(move |p| { run(p); Ok(()) })),
+            next_dialogue: FromArea,
+        }
+    }
+
+    /// Variant of `simple()` which only refreshes the
+    /// dialogue if its closure succeeds.
+    pub fn fallible<F>(text: &str, run: F) -> Response
+        where F: Fn(&PlayerMeta) -> ActionResult + 'static
+    {
+        Self::_fallible(String::from(text), run)
+    }
+
+    /// Variant of `fallible()` which accepts an owned
+    /// string instead of a slice.
+    pub fn _fallible<F>(text: String, run: F) -> Response
+        where F: Fn(&PlayerMeta) -> ActionResult + 'static
     {
         Response {
             text,
@@ -584,7 +766,7 @@ impl Response {
     {
         Response {
             text,
-            execute: Some(Box::new(run)),
+            execute: Some(Box::new(move |p| { run(p); Ok(()) })),
             next_dialogue: Ignore,
         }
     }
@@ -604,7 +786,7 @@ impl Response {
     {
         Response {
             text,
-            execute: Some(Box::new(run)),
+            execute: Some(Box::new(move |p| { run(p); Ok(()) })),
             next_dialogue: Delete,
         }
     }
@@ -629,7 +811,7 @@ impl Response {
     /// Constructs a response which has no action, but directs
     ///  the dialogue to a new source using the input closure.
     pub fn goto_dialogue<F>(text: &str, next_dialogue: F) -> Response
-        where F: Fn(&PlayerMeta) -> Dialogue + 'static
+        where F: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         Self::_goto_dialogue(String::from(text), next_dialogue)
     }
@@ -637,12 +819,12 @@ impl Response {
     /// Variant of `goto_dialogue()` which accepts an owned
     /// string instead of a slice.
     pub fn _goto_dialogue<F>(text: String, next_dialogue: F) -> Response
-        where F: Fn(&PlayerMeta) -> Dialogue + 'static
+        where F: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         Response {
             text,
             execute: None,
-            next_dialogue: Generate(Box::new(next_dialogue)),
+            next_dialogue: Generate(Arc::new(next_dialogue)),
         }
     }
 
@@ -675,7 +857,9 @@ impl Response {
     }
 
     /// Variant of `get_entity_dialogue()` which returns the
-    /// player to the dialogue at the specified `marker`.
+    /// player to the dialogue at the specified `marker`. The
+    /// marker is stored as the player's progress with this
+    /// entity, allowing conversations to advance.
     pub fn goto_entity_dialogue(text: &str, marker: u8, accessor: EntityAccessor) -> Response {
         Self::_goto_entity_dialogue(String::from(text), marker, accessor)
     }
@@ -687,6 +871,8 @@ impl Response {
             text,
             execute: None,
             next_dialogue: gen_dialogue(move |player| {
+                player.set_dialogue_marker(accessor.entity_id, marker);
+
                 match access::entity(accessor, |e| {
                     e.goto_dialogue(marker, player)
                         .expect("Called goto_entity_dialogue() for an entity that does not have dialogue.")
@@ -708,7 +894,10 @@ impl Response {
     /// next dialogue that will follow.
     pub fn run(&self, player: &PlayerMeta, current_dialogue: &Dialogue) {
         if let Some(ref exe) = self.execute {
-            (exe)(player);
+            if (exe)(player).is_err() {
+                player.send_current_options();
+                return;
+            }
         }
         post_run(player, current_dialogue, &self.next_dialogue);
     }
@@ -734,7 +923,8 @@ impl Response {
 pub struct Command {
     pub input: String,
     pub output_desc: String,
-    pub run: Box<Fn(&Vec<&str>, &PlayerMeta) + 'static>,
+    pub aliases: Vec<String>,
+    pub run: Box<Fn(&Vec<&str>, &PlayerMeta) -> ActionResult + 'static>,
     pub next_dialogue: DialogueOption,
 }
 
@@ -743,13 +933,14 @@ impl Command {
     /// fields. May look nicer in some contexts.
     pub fn new<F1, F2>(input: &str, output: &str, run: F1, next_dialogue: F2) -> Command
         where F1: Fn(&Vec<&str>, &PlayerMeta) + 'static,
-              F2: Fn(&PlayerMeta) -> Dialogue + 'static
+              F2: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(run),
-            next_dialogue: Generate(Box::new(next_dialogue)),
+            aliases: Vec::new(),
+            run: Box::new(move |args, p| { run(args, p); Ok(()) }),
+            next_dialogue: Generate(Arc::new(next_dialogue)),
         }
     }
 
@@ -763,7 +954,8 @@ impl Command {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(run),
+            aliases: Vec::new(),
+            run: Box::new(move |args, p| { run(args, p); Ok(()) }),
             next_dialogue: FromArea,
         }
     }
@@ -776,18 +968,34 @@ impl Command {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(run),
+            aliases: Vec::new(),
+            run: Box::new(move |args, p| { run(args, p); Ok(()) }),
             next_dialogue: Ignore,
         }
     }
 
+    /// Variant of `simple()` which only refreshes the
+    /// dialogue if its closure succeeds.
+    pub fn fallible<F>(input: &str, output: &str, run: F) -> Command
+        where F: Fn(&Vec<&str>, &PlayerMeta) -> ActionResult + 'static
+    {
+        Command {
+            input: String::from(input),
+            output_desc: String::from(output),
+            aliases: Vec::new(),
+            run: Box::new(run),
+            next_dialogue: FromArea,
+        }
+    }
+
     /// Constructs a command that performs no action, refreshing
     /// the dialogue from the player's current area when run.
     pub fn text_only(input: &str, output: &str) -> Command {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(|_, _| {}),
+            aliases: Vec::new(),
+            run: Box::new(|_, _| Ok(())),
             next_dialogue: FromArea,
         }
     }
@@ -800,7 +1008,8 @@ impl Command {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(run),
+            aliases: Vec::new(),
+            run: Box::new(move |args, p| { run(args, p); Ok(()) }),
             next_dialogue: Delete,
         }
     }
@@ -809,13 +1018,57 @@ impl Command {
     /// from the input closure instead of executing a
     /// process for general purposes.
     pub fn goto_dialogue<F>(input: &str, output: &str, dialogue: F) -> Command
-        where F: Fn(&PlayerMeta) -> Dialogue + 'static
+        where F: Fn(&PlayerMeta) -> Dialogue + Send + Sync + 'static
     {
         Command {
             input: String::from(input),
             output_desc: String::from(output),
-            run: Box::new(|_, _| {}),
-            next_dialogue: Generate(Box::new(dialogue)),
+            aliases: Vec::new(),
+            run: Box::new(|_, _| Ok(())),
+            next_dialogue: Generate(Arc::new(dialogue)),
+        }
+    }
+
+    /// Parses the argument at `index` as a number from `min`
+    /// to `max`, inclusive. Informs the player and returns
+    /// `None` if it can't be used. `err_msg` is shown when
+    /// the number is out of range.
+    pub fn parse_index(args: &[&str], index: usize, min: usize, max: usize, player: &PlayerMeta, err_msg: &str) -> Option<usize> {
+        Self::report_parse_error(util::parse_arg_in_range(args, index, min, max), player, err_msg)
+    }
+
+    /// Variant of `parse_index()` for NPCs who speak in their
+    /// own voice. `voice` holds the messages shown when the
+    /// argument is missing, invalid, or out of range.
+    pub fn parse_index_voiced(args: &[&str], index: usize, min: usize, max: usize, player: &PlayerMeta, voice: [&str; 3]) -> Option<usize> {
+        let err_msg = match util::parse_arg_in_range(args, index, min, max) {
+            Ok(num) => return Some(num),
+            Err(util::MISSING_ARG) => voice[0],
+            Err(util::INVALID_ARG) => voice[1],
+            Err(_) => voice[2],
+        };
+        player.send_short_message(err_msg);
+        None
+    }
+
+    /// Variant of `parse_index()` for numbered lists. The
+    /// player enters a number starting at 1, which gets
+    /// converted into a 0-based index below `len`.
+    pub fn parse_slot(args: &[&str], index: usize, len: usize, player: &PlayerMeta, err_msg: &str) -> Option<usize> {
+        Self::report_parse_error(util::parse_slot(args, index, len), player, err_msg)
+    }
+
+    fn report_parse_error(result: Result<usize, &'static str>, player: &PlayerMeta, err_msg: &str) -> Option<usize> {
+        match result {
+            Ok(num) => Some(num),
+            Err(util::OUT_OF_RANGE) => {
+                player.send_short_message(err_msg);
+                None
+            }
+            Err(e) => {
+                player.send_short_message(e);
+                None
+            }
         }
     }
 
@@ -824,21 +1077,44 @@ impl Command {
     /// while blocking their input, and ultimately generating the
     /// next dialogue that will follow.
     pub fn run(&self, args: &Vec<&str>, player: &PlayerMeta, current_dialogue: &Dialogue) {
-        (self.run)(args, player);
+        if (self.run)(args, player).is_err() {
+            player.send_current_options();
+            return;
+        }
         post_run(player, current_dialogue, &self.next_dialogue);
     }
 
-    /// Determines whether the initial value inside of
-    /// `self.input` matches given string slice. Different
-    /// from using `self.input.starts_with()` in that it
-    /// requires the entire section to match.
-    pub fn matches_input(&self, input: &str) -> bool {
+    /// Adds alternate names that can be typed to call
+    /// this command.
+    pub fn with_aliases(mut self, aliases: &[&str]) -> Command {
+        self.aliases.extend(aliases.iter().map(|a| a.to_string()));
+        self
+    }
+
+    /// The portion of `self.input` preceding the first
+    /// space, i.e. what the player types to call this.
+    pub fn get_name(&self) -> &str {
         match self.input.find(" ") {
-            Some(index) => &self.input[0..index] == input,
-            None => &self.input == input
+            Some(index) => &self.input[0..index],
+            None => &self.input
         }
     }
 
+    /// Determines whether the initial value inside of
+    /// `self.input` or any alias matches given string
+    /// slice. Different from using `starts_with()` in
+    /// that it requires the entire section to match.
+    pub fn matches_input(&self, input: &str) -> bool {
+        self.get_name() == input || self.aliases.iter().any(|a| a == input)
+    }
+
+    /// Variant of `matches_input()` which also accepts
+    /// the beginning of this command's name or aliases.
+    pub fn matches_prefix(&self, input: &str) -> bool {
+        self.get_name().starts_with(input)
+            || self.aliases.iter().any(|a| a.starts_with(input))
+    }
+
     /// Formats this response to be displayed to the user.
     pub fn get_display(&self, length: usize) -> String {
         if self.output_desc.starts_with("§") {
@@ -888,10 +1164,20 @@ fn post_run(player: &PlayerMeta, current_dialogue: &Dialogue, next: &DialogueOpt
         // The author supplied a function for manually
         // generating the dialogue to follow. Trust that
         // this is the right choice.
-        Generate(ref d) => Some((d)(player)),
+        Generate(ref d) => {
+            let next = (d)(player);
+            // Refreshing the same screen shouldn't add a step.
+            if next.title != current_dialogue.title {
+                if let Some(previous) = current_dialogue.get_regenerator() {
+                    player.push_previous_dialogue(previous);
+                }
+            }
+            Some(with_regenerator(player, next, d.clone()))
+        },
         // The author has indicated that the following dialogue
         // should come from the player's current area.
         FromArea => {
+            player.clear_previous_dialogues();
             // Ensure that the current dialogue also originates
             // from the player's area. Prevents some duplicate
             // dialogues from generating. If the player does not
@@ -925,10 +1211,161 @@ fn post_run(player: &PlayerMeta, current_dialogue: &Dialogue, next: &DialogueOpt
             delete_options(current_dialogue.id);
             register_options(dialogue);
             player.update_options();
-            player.send_blocking_message(txt);
+            player.send_blocking_message(&text::apply_player_tokens(txt, player));
         } else {
             // There is no message. Just replace and refresh.
             player.replace_send_options(current_dialogue.id, dialogue);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Stores the function that produced this dialogue and
+/// lets the player go back from it, if they can.
+fn with_regenerator(player: &PlayerMeta, mut dialogue: Dialogue, regenerator: Regenerator) -> Dialogue {
+    dialogue.regenerator = Some(regenerator);
+
+    if player.has_previous_dialogue() {
+        let dialogue_id = dialogue.id;
+        dialogue.commands.push(Command::action_only(
+            "back", "Return to the previous screen.",
+            move |_, player| go_back(player, dialogue_id)
+        ));
+    }
+    dialogue
+}
+
+/// Replaces the dialogue with the screen the player saw
+/// before it.
+fn go_back(player: &PlayerMeta, dialogue_id: usize) {
+    let regenerator = match player.pop_previous_dialogue() {
+        Some(r) => r,
+        None => {
+            player.send_short_message("There is nothing to go back to.");
+            return;
+        }
+    };
+    let previous = (regenerator)(player);
+    let previous = with_regenerator(player, previous, regenerator);
+    player.replace_send_options(dialogue_id, previous);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_data;
+    use crate::util::{server_host, timed_events};
+
+    #[test]
+    fn extra_responses_are_dropped() {
+        let player = player_data::test_player();
+        let id = player.get_player_id();
+        let responses = (0..MAX_RESPONSES + 5)
+            .map(|i| Response::_text_only(format!("Response {}", i)))
+            .collect();
+        register_options(Dialogue::simple(String::from("Too Many"), String::new(), responses, id));
+
+        let registered = CURRENT_OPTIONS.lock()
+            .iter()
+            .find(|o| o.player_id == id)
+            .cloned()
+            .unwrap();
+        assert_eq!(MAX_RESPONSES, registered.responses.len());
+        remove_all_options(id);
+    }
+
+    #[test]
+    fn reentrant_refreshes_are_deferred() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(325);
+        let id = player.get_player_id();
+
+        {
+            let _options = CURRENT_OPTIONS.lock();
+            temp_send_current_options(id);
+            assert!(try_refresh_options(id));
+        }
+        let deferred = DEFERRED_REFRESHES.lock().clone();
+        assert!(deferred.contains(&DeferredRefresh::SendCurrent(id)));
+        assert!(deferred.contains(&DeferredRefresh::TryRefresh(id)));
+
+        run_deferred_refreshes();
+        assert!(DEFERRED_REFRESHES.lock().is_empty());
+        remove_all_options(id);
+    }
+
+    #[test]
+    fn failed_deferred_refreshes_resend_the_options() {
+        crate::test_init();
+        let _guard = timed_events::test_lock();
+        let player = player_data::test_player_in_town(347);
+        let id = player.get_player_id();
+        let name = format!("test_{}", id);
+        for title in &["First", "Second"] {
+            register_options(Dialogue::simple(title.to_string(), String::new(), Vec::new(), id));
+        }
+        player_data::flush_messages();
+        server_host::take_pending_messages(&name);
+
+        {
+            let _options = CURRENT_OPTIONS.lock();
+            assert!(try_refresh_options(id));
+        }
+        run_deferred_refreshes();
+        player_data::flush_messages();
+        assert!(!server_host::take_pending_messages(&name).is_empty());
+        remove_all_options(id);
+    }
+
+    #[test]
+    fn commands_match_exactly_by_alias_or_by_unique_prefix() {
+        let command = |input: &str| Command::action_only(input, "Test.", |_, _| {});
+        let dialogue = Dialogue::commands("Commands", vec![
+            command("inventory").with_aliases(&["i"]),
+            command("info"),
+            command("buy #"),
+            command("bank"),
+        ], 0);
+        let found = |input| dialogue.find_command(input).map(|c| c.get_name().to_string());
+
+        assert_eq!(Some(String::from("buy")), found("buy"));
+        assert_eq!(Some(String::from("inventory")), found("i"));
+        assert_eq!(Some(String::from("buy")), found("bu"));
+        assert_eq!(Some(String::from("inventory")), found("inv"));
+        assert_eq!(None, found("b"));
+        assert_eq!(None, found("in"));
+        assert_eq!(None, found("sell"));
+    }
+
+    fn registered_titles(id: usize) -> Vec<String> {
+        CURRENT_OPTIONS.lock()
+            .iter()
+            .filter(|o| o.player_id == id)
+            .map(|o| o.title.clone())
+            .collect()
+    }
+
+    #[test]
+    fn failing_actions_keep_the_dialogue() {
+        crate::test_init();
+        let player = player_data::test_player_in_town(334);
+        let id = player.get_player_id();
+        let responses = vec![
+            Response::fallible("Fail", |_| Err(())),
+            Response::fallible("Succeed", |_| Ok(())),
+        ];
+        register_options(Dialogue::simple(String::from("Shop"), String::new(), responses, id));
+        let dialogue = CURRENT_OPTIONS.lock()
+            .iter()
+            .find(|o| o.player_id == id)
+            .cloned()
+            .unwrap();
+
+        dialogue.responses[0].run(&player, &dialogue);
+        assert_eq!(vec![String::from("Shop")], registered_titles(id));
+
+        dialogue.responses[1].run(&player, &dialogue);
+        assert!(!registered_titles(id).contains(&String::from("Shop")));
+        remove_all_options(id);
+    }
+}
@@ -1,6 +1,8 @@
+use std::collections::VecDeque;
+use std::fmt;
 use std::io;
 use std::io::{ErrorKind::*, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::str::Lines;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
@@ -22,6 +24,23 @@ const REFRESH_RATE: u64 = 50;
 const MSG_SIZE: usize = 256;
 const MAX_USERS: usize = 8;
 const MAX_VISITORS: usize = 8;
+const MAX_OBSERVERS: usize = 8;
+
+/// Messages sent before the server starts are held until
+/// it does. The oldest are dropped beyond this amount.
+const MAX_PENDING_MESSAGES: usize = 64;
+
+/// Tokens expire after going unused for this long, in
+/// ms. Every standard message resets the timer.
+const TOKEN_TTL_MS: u64 = 30 * 60 * 1000;
+
+/// Messages sent to a disconnected user are held for
+/// them. The oldest are dropped beyond this amount.
+const MAX_QUEUED_MESSAGES: usize = 32;
+
+/// Users who haven't returned after this long, in ms,
+/// lose any messages that were held for them.
+const OUTBOX_TTL_MS: u64 = 5 * 60 * 1000;
 
 /// These users have not yet logged in.
 type Visitors = Vec<(SocketAddr, TcpStream)>;
@@ -29,67 +48,186 @@ type Visitors = Vec<(SocketAddr, TcpStream)>;
 /// A map of username -> stream info
 type Clients = HashMap<String, (SocketAddr, TcpStream)>;
 
-/// A map of token -> username
-type Tokens = HashMap<String, String>;
+/// A map of token -> username, time last used
+type Tokens = HashMap<String, (String, u64)>;
+
+/// A map of token -> stream info for read-only clients.
+/// These have no player and only receive global messages.
+type Observers = HashMap<String, (SocketAddr, TcpStream)>;
+
+/// A map of username -> time disconnected, messages held
+/// until that user logs in again.
+type Outboxes = HashMap<String, (u64, VecDeque<String>)>;
 
 /// Message, address it was sent from; might be local.
 struct MessageData(String, Option<SocketAddr>);
 
+/// The reasons a message could not be handled. Clients
+/// that send malformed messages are disconnected.
+#[derive(Debug)]
+enum ProtocolError {
+    /// The message contained no header.
+    Empty,
+    /// The header was not recognized.
+    UnknownHeader(String),
+    /// A `KEY|value` line was missing or out of order.
+    MissingField(&'static str),
+    /// The message was well-formed, but was refused.
+    Rejected(&'static str),
+}
+
+impl ProtocolError {
+    fn is_malformed(&self) -> bool {
+        match self {
+            ProtocolError::Rejected(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Empty => write!(f, "Message contained no info."),
+            ProtocolError::UnknownHeader(h) => write!(f, "Unregistered message header: {}.", h),
+            ProtocolError::MissingField(key) => write!(f, "Message is missing its {} line.", key),
+            ProtocolError::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+type ProtocolResult = Result<&'static str, ProtocolError>;
+
+/// Reads the next line, which must start with `key`,
+/// e.g. `USER|`. Returns whatever follows the key.
+fn parse_field<'a>(lines: &mut Lines<'a>, key: &'static str) -> Result<&'a str, ProtocolError> {
+    match lines.next() {
+        Some(l) if l.starts_with(key) => Ok(&l[key.len()..]),
+        _ => Err(ProtocolError::MissingField(key)),
+    }
+}
+
+/// Variant of `parse_field()` which also includes every
+/// line that follows.
+fn parse_remaining(lines: &mut Lines, key: &'static str) -> Result<String, ProtocolError> {
+    let mut ret = parse_field(lines, key)?.to_string();
+    while let Some(t) = lines.next() {
+        ret += "\n";
+        ret += t;
+    }
+    Ok(ret)
+}
+
 lazy_static! {
     static ref LOCAL_TX: Mutex<Option<Sender<MessageData>>> = Mutex::new(None);
+
+    /// Outgoing messages waiting on `init_listener()`.
+    /// Only locked while `LOCAL_TX` is held.
+    static ref PENDING_MESSAGES: Mutex<Vec<MessageData>> = Mutex::new(Vec::new());
 }
 
+/// Queues a message for the client. Messages sent before
+/// the server has started are delivered once it does.
 pub fn send_message_to_client(username: &str, msg: &str) {
+    let data = MessageData(format!("OUTGOING\nUSER|{}\nMSG|{}", username, msg), None);
     let tx = LOCAL_TX.lock();
     if let Some(ref t) = *tx {
-        t.send(MessageData(format!("OUTGOING\nUSER|{}\nMSG|{}", username, msg),None))
+        t.send(data)
             .expect("Unable to send message to server.");
     } else {
-        panic!("Tried to send a message before the server started.");
+        let mut pending = PENDING_MESSAGES.lock();
+        if pending.len() >= MAX_PENDING_MESSAGES {
+            warn!("The server hasn't started. Dropping a message to {}.", username);
+            pending.remove(0);
+        }
+        pending.push(data);
+    }
+}
+
+/// Removes and returns the messages being held for
+/// `username` until the server starts.
+#[cfg(test)]
+pub fn take_pending_messages(username: &str) -> Vec<String> {
+    let header = format!("OUTGOING\nUSER|{}\nMSG|", username);
+    let _tx = LOCAL_TX.lock();
+    PENDING_MESSAGES.lock()
+        .drain_filter(|data| data.0.starts_with(&header))
+        .map(|data| data.0[header.len()..].to_string())
+        .collect()
+}
+
+/// Forwards a global message to every observer. Nobody
+/// can be observing before the server starts, so these
+/// messages are not held.
+pub fn send_message_to_observers(msg: &str) {
+    let tx = LOCAL_TX.lock();
+    if let Some(ref t) = *tx {
+        t.send(MessageData(format!("BROADCAST\nMSG|{}", msg), None))
+            .expect("Unable to send message to server.");
+    }
+}
+
+/// Closes the connection to a client from the game
+/// thread, e.g. when a player chooses to leave.
+pub fn disconnect_client(username: &str) {
+    let tx = LOCAL_TX.lock();
+    if let Some(ref t) = *tx {
+        t.send(MessageData(format!("KICK\nUSER|{}", username), None))
+            .expect("Unable to send message to server.");
     }
 }
 
 pub fn init_listener(sender: Sender<GameMessage>) {
     let listener = match TcpListener::bind("0.0.0.0:12131") {
-        Ok(l) => { println!("\nListening on port 12131."); l },
-        Err(_) => { println!("\nError binding port 12131."); return; }
+        Ok(l) => { info!("Listening on port 12131."); l },
+        Err(_) => { warn!("Error binding port 12131."); return; }
     };
 
     listener.set_nonblocking(true)
         .expect("Error setting listener as non-blocking.");
 
     let (server_tx, server_rx) = mpsc::channel::<MessageData>();
-    *LOCAL_TX.lock() = Some(server_tx.clone());
-
+    open_local_channel(&server_tx);
     start_server(listener, server_tx, server_rx, sender);
 }
 
+/// Lets the game send messages to the server, delivering
+/// any that were held while it was starting.
+fn open_local_channel(server_tx: &Sender<MessageData>) {
+    let mut tx = LOCAL_TX.lock();
+    for data in PENDING_MESSAGES.lock().drain(..) {
+        server_tx.send(data)
+            .expect("Unable to send message to server.");
+    }
+    *tx = Some(server_tx.clone());
+}
+
 fn start_server(listener: TcpListener, server_tx: Sender<MessageData>, server_rx: Receiver<MessageData>, game_tx: Sender<GameMessage>) {
     let mut visitors: Visitors = Vec::new();
     let mut clients: Clients = HashMap::new();
     let mut tokens: Tokens = HashMap::new();
+    let mut observers: Observers = HashMap::new();
+    let mut outboxes: Outboxes = HashMap::new();
 
-    loop {
+    while !is_stopping() {
         if let Ok((mut socket, address)) = listener.accept() {
             // Hold visitors in a separate array from established
             // clients. They will get their own threads once they
             // have been registered successfully and have received
             // `LOGIN_OK` as well as a `TOKEN` for communicating
             // with the game.
-            println!("Received a connection from {}.", address);
+            info!("Received a connection from {}.", address);
 
             if visitors.len() > MAX_VISITORS {
                 // There were too many users waiting to log in.
-                write_directly("LOGIN_ERR\nREASON|MAX_VISITORS", &mut socket)
-                    .expect("Error writing to socket.");
+                write_directly("LOGIN_ERR\nREASON|MAX_VISITORS", &mut socket).ok();
                 continue;
             }
 
             // The user's IP will serve as a temporary identifier.
-            write_directly("ESTABLISH", &mut socket)
-                .expect("Error writing to socket.");
-
-            visitors.push((address, socket));
+            if write_directly("ESTABLISH", &mut socket).is_ok() {
+                visitors.push((address, socket));
+            }
         }
 
         // Process incoming messages from visitors in the current thread.
@@ -97,19 +235,39 @@ fn start_server(listener: TcpListener, server_tx: Sender<MessageData>, server_rx
         visitors.drain_filter(|(address, socket)|
             handle_reads(socket, &address, &server_tx).is_err());
 
+        remove_expired_outboxes(&mut outboxes);
+
         if let Ok(msg) = server_rx.try_recv() {
-            match handle_incoming_message(msg, &mut visitors, &mut clients, &mut tokens, &server_tx, &game_tx) {
+            let address = msg.1;
+            match handle_incoming_message(msg, &mut visitors, &mut clients, &mut tokens, &mut observers, &mut outboxes, &server_tx, &game_tx) {
                 Ok(_o) => (), //println!("Ok: {}", o),
+                Err(ref e) if e.is_malformed() => {
+                    // Don't let a broken client keep sending garbage.
+                    if let Some(address) = address {
+                        warn!("Dropping {}. {}", address, e);
+                        drop_connection(&address, &mut visitors, &mut clients, &mut tokens, &mut observers, &mut outboxes, &game_tx);
+                    }
+                }
                 Err(_) => ()//println!("Err: {}", e),
             };
         }
         sleep();
     }
+    // Close every connection so that clients aren't left
+    // waiting on the game.
+    let streams = visitors.drain(..)
+        .chain(clients.drain().map(|(_, c)| c))
+        .chain(observers.drain().map(|(_, o)| o));
+
+    for (_address, stream) in streams {
+        if let Err(_) = stream.shutdown(Shutdown::Both) { /* ignore */ }
+    }
+    info!("Stopped the server.");
 }
 
 fn spawn_client_thread(mut socket: TcpStream, address: SocketAddr, user_tx: Sender<MessageData>) {
     thread::spawn(move || loop {
-        if handle_reads(&mut socket, &address, &user_tx).is_err() {
+        if is_stopping() || handle_reads(&mut socket, &address, &user_tx).is_err() {
             break;
         };
         sleep();
@@ -124,8 +282,15 @@ fn handle_reads(socket: &mut TcpStream, address: &SocketAddr, server_tx: &Sender
             let msg: Vec<u8> = buf.into_iter()
                 .take_while(|b| *b != 0)
                 .collect();
-            let msg = String::from_utf8(msg)
-                .expect("Client sent an invalid utf8 message.");
+            let msg = match String::from_utf8(msg) {
+                Ok(m) => m,
+                Err(_) => {
+                    server_tx.send(MessageData("CLOSE".to_string(), Some(address.clone())))
+                        .expect("Failed to send user message");
+                    info!("Closing connection with: {}. It sent an invalid utf8 message.", address);
+                    return Err(io::Error::new(InvalidData, "Client sent an invalid utf8 message."));
+                }
+            };
 
             server_tx.send(MessageData(msg, Some(address.clone())))
                 .expect("Failed to send user message");
@@ -134,7 +299,7 @@ fn handle_reads(socket: &mut TcpStream, address: &SocketAddr, server_tx: &Sender
         Err(e) => {
             server_tx.send(MessageData("CLOSE".to_string(), Some(address.clone())))
                 .expect("Failed to send user message");
-            println!("Closing connection with: {}.", address);
+            info!("Closing connection with: {}.", address);
             return Err(e);
         }
     }
@@ -146,22 +311,30 @@ fn handle_incoming_message(
     visitors: &mut Visitors,
     clients: &mut Clients,
     tokens: &mut Tokens,
+    observers: &mut Observers,
+    outboxes: &mut Outboxes,
     server_tx: &Sender<MessageData>,
     game_tx: &Sender<GameMessage>
-) -> Result<&'static str, &'static str> {
+) -> ProtocolResult {
     let mut lines = msg.0.lines();
 
     let msg_type = match lines.next() {
         Some(s) => s,
-        None => return Err("Client message contained no info."),
+        None => return Err(ProtocolError::Empty),
     };
+    // Some headers are only ever sent by the game. Clients
+    // sending them are treated as sending unknown headers.
+    let from_game = msg.1.is_none();
 
     match msg_type {
-        "OUTGOING" => outgoing_message(lines, clients),
-        "STANDARD" => standard_message(lines, tokens, game_tx),
+        "OUTGOING" if from_game => outgoing_message(lines, clients, outboxes),
+        "BROADCAST" if from_game => broadcast_message(lines, observers),
+        "STANDARD" => standard_message(lines, clients, tokens, observers, outboxes, game_tx),
         "REGISTER" => register_user(lines, &msg, visitors, clients, tokens, server_tx),
-        "CLOSE" => disconnect_message(&msg, clients),
-        _ => Err("Unregistered message header"),
+        "OBSERVE" => register_observer(&msg, visitors, observers, server_tx),
+        "CLOSE" => disconnect_message(&msg, clients, tokens, observers, outboxes, game_tx),
+        "KICK" if from_game => kick_client(lines, clients),
+        _ => Err(ProtocolError::UnknownHeader(msg_type.to_string())),
     }
 }
 
@@ -172,26 +345,45 @@ fn handle_incoming_message(
  * USER|my_username
  * MSG|text_to_display
  * ```
- * Forwarding it out as a standard message.
+ * Forwarding it out as a standard message. Messages to
+ * users who recently disconnected are held until they
+ * log in again.
  */
-fn outgoing_message(mut lines: Lines, clients: &mut Clients) -> Result<&'static str, &'static str> {
-    let username = match lines.next() {
-        Some(u) if u.starts_with("USER|") => &u[5..],
-        _ => return Err("Outgoing call was sent incorrectly."),
-    };
-    let msg = match lines.next() {
-        Some(m) if m.starts_with("MSG|") => {
-            let mut ret = String::new();
-            ret += &m[4..];
-            while let Some(t) = lines.next() {
-                ret += "\n";
-                ret += t;
+fn outgoing_message(mut lines: Lines, clients: &mut Clients, outboxes: &mut Outboxes) -> ProtocolResult {
+    let username = parse_field(&mut lines, "USER|")?;
+    let msg = parse_remaining(&mut lines, "MSG|")?;
+    let was_connected = clients.contains_key(username);
+
+    if write_to_client(&msg, username, clients) {
+        return Ok("Success.");
+    }
+    if was_connected {
+        open_outbox(username, outboxes);
+    }
+    match outboxes.get_mut(username) {
+        Some((_disconnected, queue)) => {
+            if queue.len() >= MAX_QUEUED_MESSAGES {
+                warn!("Too many messages are waiting on {}. Dropping the oldest.", username);
+                queue.pop_front();
             }
-            ret
+            queue.push_back(msg);
+            Ok("Message was held until the user returns.")
         }
-        _ => return Err("Outgoing call was sent incorrectly."),
-    };
-    write_to_client(&msg, username, clients);
+        None => Err(ProtocolError::Rejected("Tried to message an unknown client.")),
+    }
+}
+
+/**
+ * Game sent a message in this format:
+ * ```
+ * BROADCAST
+ * MSG|text_to_display
+ * ```
+ * Forwarding it out to every observer.
+ */
+fn broadcast_message(mut lines: Lines, observers: &mut Observers) -> ProtocolResult {
+    let msg = parse_remaining(&mut lines, "MSG|")? + "\n";
+    observers.retain(|_, (_address, stream)| write_directly(&msg, stream).is_ok());
     Ok("Success.")
 }
 
@@ -202,35 +394,49 @@ fn outgoing_message(mut lines: Lines, clients: &mut Clients) -> Result<&'static
  * TOKEN|token
  * MSG|text_to_process
  * ```
- * To-do: Replace usernames with tokens.
+ * Expired tokens are forgotten and their clients are
+ * disconnected so that they can log in again. Messages
+ * held while the user was away are delivered first.
  */
-fn standard_message(mut lines: Lines, tokens: &Tokens, game_tx: &Sender<GameMessage>) -> Result<&'static str, &'static str> {
-    let token = match lines.next() {
-        Some(s) if s.starts_with("TOKEN|") => s[6..].to_string(),
-        _ => return Err("Standard call was sent incorrectly."),
-    };
-    let msg = match lines.next() {
-        Some(s) if s.starts_with("MSG|") => {
-            let mut msg = s[4..].to_string();
-            while let Some(line) = lines.next() {
-                msg += line;
+fn standard_message(
+    mut lines: Lines,
+    clients: &mut Clients,
+    tokens: &mut Tokens,
+    observers: &Observers,
+    outboxes: &mut Outboxes,
+    game_tx: &Sender<GameMessage>
+) -> ProtocolResult {
+    let token = parse_field(&mut lines, "TOKEN|")?.to_string();
+    let msg = parse_remaining(&mut lines, "MSG|")?;
+
+    if observers.contains_key(&token) {
+        return Err(ProtocolError::Rejected("Observers can't send commands."));
+    }
+    let username = match refresh_token(tokens, &token, current_time()) {
+        TokenState::Valid(username) => username,
+        TokenState::Expired(username) => {
+            write_to_client("LOGIN_ERR\nREASON|EXPIRED", &username, clients);
+            if let Some((_address, stream)) = clients.remove(&username) {
+                if let Err(_) = stream.shutdown(Shutdown::Both) { /* ignore */ }
             }
-            msg
+            remove_player(&username, game_tx);
+            return Err(ProtocolError::Rejected("An expired token was sent. The client must log in again."));
         }
-        _ => return Err("Standard call was sent incorrectly."),
-    };
-    let username = match tokens.get(&token) {
-        Some(u) => u.to_owned(),
-        None => return Err("An invalid token was sent. The client will not be informed."),
+        TokenState::Unknown => return Err(ProtocolError::Rejected("An invalid token was sent. The client will not be informed.")),
     };
+    // Waiting until the user sends a message keeps these
+    // from arriving alongside `LOGIN_OK`.
+    flush_outbox(&username, clients, outboxes);
+
     let game_message = GameMessage {
         message: msg,
         channel_info: Remote(username),
+        kind: MessageKind::Input,
     };
 
     match game_tx.send(game_message) {
         Ok(_) => Ok("Everything looks okay."),
-        Err(_) => Err("Unable to send message from server to game thread."),
+        Err(_) => Err(ProtocolError::Rejected("Unable to send message from server to game thread.")),
     }
 }
 
@@ -249,27 +455,26 @@ fn register_user(
     clients: &mut Clients,
     tokens: &mut Tokens,
     server_tx: &Sender<MessageData>
-) -> Result<&'static str, &'static str> {
-    let username = match lines.next() {
-        Some(s) if s.starts_with("USER|") => s[5..].to_string(),
-        _ => return Err("Register call was sent incorrectly."),
-    };
+) -> ProtocolResult {
+    let username = parse_field(&mut lines, "USER|")?.to_string();
     let address = data.1
-        .expect("A register call did not contain the user's address.");
+        .ok_or(ProtocolError::Rejected("A register call did not contain the user's address."))?;
+
+    remove_expired_tokens(tokens);
 
     if tokens.len() >= MAX_USERS {
         // Too many users are currently logged in.
         write_to_visitor("LOGIN_ERR\nREASON|CAPACITY", address, visitors);
-        Err("There were too many users logged in.")
+        Err(ProtocolError::Rejected("There were too many users logged in."))
     } else if is_logged_in(&username, clients) {
         // The username was already taken.
         write_to_visitor("LOGIN_ERR\nREASON|TAKEN", address, visitors);
-        Err("Username was already taken.")
+        Err(ProtocolError::Rejected("Username was already taken."))
     } else {
         // All seems well.
         let new_client = match locate_visitor(&address, visitors) {
             Some(v) => v,
-            None => return Err("Client disconnected before registration."),
+            None => return Err(ProtocolError::Rejected("Client disconnected before registration.")),
         };
 
         let token = yyid_string();
@@ -285,27 +490,213 @@ fn register_user(
         clients.insert(username.clone(), new_client);
         write_to_client(&response, &username, clients);
         send_global_message(&format!("{} has logged in.", username));
-        tokens.insert(token, username);
+        tokens.insert(token, (username, current_time()));
 
         Ok("Client registered successfully.")
     }
 }
 
+/**
+ * Client sent a message in this format:
+ * ```
+ * OBSERVE
+ * ```
+ * Observers receive a token, but are not players and
+ * do not count toward `MAX_USERS`.
+ */
+fn register_observer(
+    data: &MessageData,
+    visitors: &mut Visitors,
+    observers: &mut Observers,
+    server_tx: &Sender<MessageData>
+) -> ProtocolResult {
+    let address = data.1
+        .ok_or(ProtocolError::Rejected("An observe call did not contain the user's address."))?;
+
+    if observers.len() >= MAX_OBSERVERS {
+        write_to_visitor("LOGIN_ERR\nREASON|CAPACITY", address, visitors);
+        return Err(ProtocolError::Rejected("There were too many observers."));
+    }
+    let mut new_observer = match locate_visitor(&address, visitors) {
+        Some(v) => v,
+        None => return Err(ProtocolError::Rejected("Client disconnected before registration.")),
+    };
+
+    let token = yyid_string();
+    let response = format!(
+        "LOGIN_OK\n\
+         TOKEN|{}",
+        token
+    );
+    if let Err(_) = write_directly(&response, &mut new_observer.1) {
+        return Err(ProtocolError::Rejected("Observer disconnected before registration."));
+    }
+
+    let clone = clone_client_info(&new_observer);
+    spawn_client_thread(clone.1, clone.0, server_tx.clone());
+    observers.insert(token, new_observer);
+
+    Ok("Observer registered successfully.")
+}
+
 /**
  * Server sent a message in this format:
  * ```
  * CLOSE
  * ```
- * Using this to inform other users.
+ * Using this to inform other users and to remove the
+ * player from the world, which frees up their username.
+ * Messages are held for the user in case they return.
+ * Observers leave silently.
  */
-fn disconnect_message(msg: &MessageData, clients: &Clients) -> Result<&'static str, &'static str> {
+fn disconnect_message(
+    msg: &MessageData,
+    clients: &mut Clients,
+    tokens: &mut Tokens,
+    observers: &mut Observers,
+    outboxes: &mut Outboxes,
+    game_tx: &Sender<GameMessage>
+) -> ProtocolResult {
     if let Some(ref address) = msg.1 {
-        if let Some(username) = locate_client_username(address, clients) {
+        let num_observers = observers.len();
+        observers.retain(|_, (addr, _stream)| *addr != *address);
+
+        if observers.len() < num_observers {
+            return Ok("An observer disconnected.");
+        }
+        if let Some(username) = locate_client_username(address, clients).map(|u| u.to_string()) {
+            clients.remove(&username);
+            tokens.retain(|_, (u, _)| *u != username);
+            open_outbox(&username, outboxes);
+            remove_player(&username, game_tx);
             send_global_message(&format!("{} has disconnected.", username));
             return Ok("Users were informed.");
         }
     }
-    Err("Unable to inform users of disconnect.")
+    Err(ProtocolError::Rejected("Unable to inform users of disconnect."))
+}
+
+/**
+ * Game sent a message in this format:
+ * ```
+ * KICK
+ * USER|my_username
+ * ```
+ * Closing the connection to this user.
+ */
+fn kick_client(mut lines: Lines, clients: &mut Clients) -> ProtocolResult {
+    let username = parse_field(&mut lines, "USER|")?;
+    match clients.remove(username) {
+        Some((_address, stream)) => {
+            if let Err(_) = stream.shutdown(Shutdown::Both) { /* ignore */ }
+            Ok("Client was disconnected.")
+        }
+        None => Err(ProtocolError::Rejected("Tried to disconnect an unknown client.")),
+    }
+}
+
+/// Closes the connection at `address`, forgetting any
+/// visitor, client, token, or observer associated with it.
+fn drop_connection(
+    address: &SocketAddr,
+    visitors: &mut Visitors,
+    clients: &mut Clients,
+    tokens: &mut Tokens,
+    observers: &mut Observers,
+    outboxes: &mut Outboxes,
+    game_tx: &Sender<GameMessage>
+) {
+    let mut streams = Vec::new();
+
+    if let Some((_address, stream)) = locate_visitor(address, visitors) {
+        streams.push(stream);
+    }
+    if let Some(username) = locate_client_username(address, clients).map(|u| u.to_string()) {
+        tokens.retain(|_, (u, _)| *u != username);
+        if let Some((_address, stream)) = clients.remove(&username) {
+            streams.push(stream);
+        }
+        open_outbox(&username, outboxes);
+        remove_player(&username, game_tx);
+    }
+    let observer = observers.iter()
+        .find(|(_, (a, _))| *a == *address)
+        .map(|(token, _)| token.clone());
+    if let Some((_address, stream)) = observer.and_then(|t| observers.remove(&t)) {
+        streams.push(stream);
+    }
+    for stream in streams {
+        if let Err(_) = stream.shutdown(Shutdown::Both) { /* ignore */ }
+    }
+}
+
+/// Asks the game thread to take the player logged in as
+/// `username` out of the world until they log in again.
+/// The world must not be changed from this thread.
+fn remove_player(username: &str, game_tx: &Sender<GameMessage>) {
+    let message = GameMessage {
+        message: String::new(),
+        channel_info: Remote(username.to_string()),
+        kind: MessageKind::Disconnect,
+    };
+    if let Err(_) = game_tx.send(message) { /* The game is closing. */ }
+}
+
+fn is_expired(last_used: u64, now: u64) -> bool {
+    now.saturating_sub(last_used) >= TOKEN_TTL_MS
+}
+
+enum TokenState {
+    Valid(String),
+    Expired(String),
+    Unknown,
+}
+
+/// Looks up the user that `token` belongs to. Using a
+/// token resets its timer, while expired tokens are
+/// forgotten.
+fn refresh_token(tokens: &mut Tokens, token: &str, now: u64) -> TokenState {
+    match tokens.get_mut(token) {
+        Some((username, last_used)) if !is_expired(*last_used, now) => {
+            *last_used = now;
+            TokenState::Valid(username.to_owned())
+        }
+        Some(_) => {
+            let (username, _) = tokens.remove(token).unwrap();
+            TokenState::Expired(username)
+        }
+        None => TokenState::Unknown,
+    }
+}
+
+/// Forgets tokens that have gone unused for too long,
+/// freeing up their slots for new users.
+fn remove_expired_tokens(tokens: &mut Tokens) {
+    let now = current_time();
+    tokens.retain(|_, (_, last_used)| !is_expired(*last_used, now));
+}
+
+/// Starts holding messages for `username`. Does not
+/// reset the timer if their outbox is already open.
+fn open_outbox(username: &str, outboxes: &mut Outboxes) {
+    outboxes.entry(username.to_string())
+        .or_insert_with(|| (current_time(), VecDeque::new()));
+}
+
+/// Delivers any messages held for `username`.
+fn flush_outbox(username: &str, clients: &mut Clients, outboxes: &mut Outboxes) {
+    if let Some((_disconnected, queue)) = outboxes.remove(username) {
+        for msg in queue {
+            write_to_client(&msg, username, clients);
+        }
+    }
+}
+
+/// Forgets the messages held for users who never came
+/// back, so that they don't accumulate.
+fn remove_expired_outboxes(outboxes: &mut Outboxes) {
+    let now = current_time();
+    outboxes.retain(|_, (disconnected, _)| now.saturating_sub(*disconnected) < OUTBOX_TTL_MS);
 }
 
 fn clone_client_info(client: &(SocketAddr, TcpStream)) -> (SocketAddr, TcpStream) {
@@ -314,18 +705,21 @@ fn clone_client_info(client: &(SocketAddr, TcpStream)) -> (SocketAddr, TcpStream
     (client.0.clone(), socket_clone)
 }
 
-fn write_to_client(msg: &str, username: &str, clients: &mut Clients) {
+/// Returns whether the message was written. Clients
+/// that can't be written to are forgotten.
+fn write_to_client(msg: &str, username: &str, clients: &mut Clients) -> bool {
     let mut remove_user = false;
 
     if let Some((_address, stream)) = clients.get_mut(username) {
         match write_directly(msg, stream) {
-            Ok(_) => return,
+            Ok(_) => return true,
             Err(_) => remove_user = true,
         };
     }
     if remove_user {
         clients.remove(username);
     }
+    false
 }
 
 fn write_to_visitor(msg: &str, address: SocketAddr, visitors: &mut Visitors) {
@@ -372,3 +766,105 @@ fn locate_client_username<'a>(address: &SocketAddr, clients: &'a Clients) -> Opt
 fn sleep() {
     thread::sleep(Duration::from_millis(REFRESH_RATE))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::timed_events;
+
+    use std::sync::mpsc::channel;
+
+    fn handle(msg: MessageData) -> ProtocolResult {
+        let (server_tx, _server_rx) = channel();
+        let (game_tx, _game_rx) = channel();
+        handle_incoming_message(
+            msg,
+            &mut Vec::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &server_tx,
+            &game_tx
+        )
+    }
+
+    fn from_client(text: &str) -> MessageData {
+        MessageData(text.to_string(), Some("127.0.0.1:50000".parse().unwrap()))
+    }
+
+    #[test]
+    fn clients_cant_send_game_headers() {
+        for text in &["KICK\nUSER|someone", "OUTGOING\nUSER|someone\nMSG|hi", "BROADCAST\nMSG|hi"] {
+            match handle(from_client(text)) {
+                Err(ProtocolError::UnknownHeader(_)) => {}
+                _ => panic!("A client was allowed to send: {}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_registers_are_refused() {
+        for text in &["REGISTER", "REGISTER\nNAME|someone", "REGISTER\nMSG|USER|someone"] {
+            match handle(from_client(text)) {
+                Err(ProtocolError::MissingField("USER|")) => {}
+                _ => panic!("A malformed register was accepted: {}", text),
+            }
+        }
+        match handle(from_client("")) {
+            Err(ProtocolError::Empty) => {}
+            _ => panic!("An empty message was accepted."),
+        }
+    }
+
+    #[test]
+    fn disconnects_are_left_to_the_game() {
+        let (game_tx, game_rx) = channel();
+        remove_player("someone", &game_tx);
+
+        let message = game_rx.try_recv().unwrap();
+        assert_eq!(MessageKind::Disconnect, message.kind);
+        assert!(Remote(String::from("someone")) == message.channel_info);
+    }
+
+    #[test]
+    fn game_can_kick_clients() {
+        match handle(MessageData("KICK\nUSER|someone".to_string(), None)) {
+            Err(ProtocolError::Rejected(_)) => {}
+            _ => panic!("The game's kick was not processed."),
+        }
+    }
+
+    #[test]
+    fn early_messages_are_delivered_once_the_server_starts() {
+        // Other tests read the held messages.
+        let _guard = timed_events::test_lock();
+        send_message_to_client("early_bird", "Welcome!");
+
+        let (server_tx, server_rx) = channel();
+        open_local_channel(&server_tx);
+        *LOCAL_TX.lock() = None;
+
+        let delivered: Vec<_> = server_rx.try_iter()
+            .map(|data| data.0)
+            .collect();
+        assert!(delivered.contains(&"OUTGOING\nUSER|early_bird\nMSG|Welcome!".to_string()));
+    }
+
+    #[test]
+    fn tokens_expire_unless_they_are_used() {
+        let mut tokens: Tokens = HashMap::new();
+        tokens.insert("old".to_string(), ("someone".to_string(), 0));
+        tokens.insert("fresh".to_string(), ("someone_else".to_string(), 0));
+
+        // Using a token keeps it alive.
+        let halfway = TOKEN_TTL_MS / 2;
+        assert!(match refresh_token(&mut tokens, "fresh", halfway) { TokenState::Valid(_) => true, _ => false });
+
+        let now = TOKEN_TTL_MS;
+        assert!(match refresh_token(&mut tokens, "old", now) { TokenState::Expired(_) => true, _ => false });
+        assert!(match refresh_token(&mut tokens, "fresh", now) { TokenState::Valid(_) => true, _ => false });
+        // Expired tokens can't be used again.
+        assert!(match refresh_token(&mut tokens, "old", now) { TokenState::Unknown => true, _ => false });
+    }
+}
@@ -1,19 +1,32 @@
+use crate::types::entities::{merchants, mobs};
 use crate::*;
 
 use lazy_static::lazy_static;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard};
 use rand::random;
 
 use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
 
 pub type EventRegistry = Vec<Box<TimedEvent>>;
 
 lazy_static! {
     static ref TIMED_EVENTS: Mutex<EventRegistry> = Mutex::new(Vec::new());
+
+    /// Events scheduled while the registry was locked, e.g.
+    /// from inside of another event. These are registered
+    /// the next time the registry is locked.
+    static ref DEFERRED_EVENTS: Mutex<EventRegistry> = Mutex::new(Vec::new());
+}
+
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_LOCK: Mutex<()> = Mutex::new(());
 }
 
 pub fn update_timed_events() {
-    let mut registry = TIMED_EVENTS.lock();
+    let mut registry = lock_registry();
 
     let events: EventRegistry = registry
         .drain_filter(|e| game_time() >= e.min_exe_time())
@@ -23,10 +36,38 @@ pub fn update_timed_events() {
         event.run();
         event.handle_delete(&mut *registry);
     }
+    registry.extend(DEFERRED_EVENTS.lock().drain(..));
+    // Mobs may start new events, e.g. when a player they
+    // kill respawns, so they're updated without the lock.
+    drop(registry);
+    mobs::update_mobs();
+    merchants::update_merchants();
+}
+
+/// Advances the game clock by `ms` and immediately runs
+/// any events that are now due, allowing tests to fire
+/// scheduled events without waiting on the game loop.
+#[cfg(test)]
+pub fn test_advance_time(ms: u64) {
+    advance_game_time(ms);
+    update_timed_events();
+}
+
+/// Tests share the game clock and every registry, so any
+/// test that depends on them holds this while it runs.
+#[cfg(test)]
+pub fn test_lock() -> parking_lot::MutexGuard<'static, ()> {
+    TEST_LOCK.lock()
+}
+
+/// The number of events waiting to run. Must not be
+/// called from inside of an event.
+pub fn pending_count() -> usize {
+    lock_registry().len()
 }
 
 pub fn delete_event(id: usize) -> Option<Box<TimedEvent>> {
-    _delete_event(id, &mut *TIMED_EVENTS.lock())
+    _delete_event(id, &mut *lock_registry())
 }
 
 fn _delete_event(id: usize, registry: &mut EventRegistry) -> Option<Box<TimedEvent>> {
@@ -45,8 +86,7 @@ pub fn delete_by_flags(
     entity: Option<usize>,
     flag: Option<&str>,
 ) -> Vec<Box<TimedEvent>> {
-    TIMED_EVENTS
-        .lock()
+    lock_registry()
         .drain_filter(|e| {
             let mut condition = true;
             area.and_then(|a| Some(condition &= e.matches_area(a)));
@@ -57,8 +97,45 @@ pub fn delete_by_flags(
         .collect()
 }
 
+/// Removes the events scheduled under `flag` which are
+/// due to run no later than `time`, leaving any others.
+pub fn delete_flag_until(flag: &str, time: u64) -> Vec<Box<TimedEvent>> {
+    lock_registry()
+        .drain_filter(|e| e.matches_flag(flag) && e.min_exe_time() <= time)
+        .collect()
+}
+
+/// Determines whether any scheduled event matches all
+/// of the given flags without removing it.
+pub fn has_flags(area: Option<usize>, entity: Option<usize>, flag: Option<&str>) -> bool {
+    lock_registry()
+        .iter()
+        .any(|e| {
+            let mut condition = true;
+            area.and_then(|a| Some(condition &= e.matches_area(a)));
+            entity.and_then(|ent| Some(condition &= e.matches_entity(ent)));
+            flag.and_then(|f| Some(condition &= e.matches_flag(f)));
+            condition
+        })
+}
+
+/// Locks the registry, first moving in any events that
+/// were deferred while it was last locked.
+fn lock_registry() -> MutexGuard<'static, EventRegistry> {
+    let mut registry = TIMED_EVENTS.lock();
+    registry.extend(DEFERRED_EVENTS.lock().drain(..));
+    registry
+}
+
+/// The registry is locked while events run, so events
+/// scheduled by them are deferred instead of waiting on
+/// the lock.
 fn schedule_event(event: impl TimedEvent + 'static) {
-    TIMED_EVENTS.lock().push(Box::new(event));
+    let event: Box<TimedEvent> = Box::new(event);
+    match TIMED_EVENTS.try_lock() {
+        Some(mut registry) => registry.push(event),
+        None => DEFERRED_EVENTS.lock().push(event),
+    }
 }
 
 fn get_exe_time(from_delay: u64) -> u64 {
@@ -297,12 +374,40 @@ pub struct RepeatedEvent<F: Fn() -> bool + Send> {
     area_id: Option<usize>,
     entity_id: Option<usize>,
     flag: Option<String>,
+    stopped: Arc<AtomicBool>,
     id: usize,
 }
 
+/// A handle to a scheduled `RepeatedEvent`, used for
+/// stopping it before its duration runs out.
+#[derive(Clone)]
+pub struct RepeatHandler {
+    stopped: Arc<AtomicBool>,
+    id: usize,
+}
+
+impl RepeatHandler {
+    /// Stops the event from running again. The registry
+    /// isn't locked, so this is safe to call from inside
+    /// of any event, including the repeat itself. Does
+    /// nothing if the event has already finished.
+    pub fn cancel(&self) {
+        self.stopped.store(true, SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.stopped.load(SeqCst)
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
 impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
-    pub fn no_flags(interval: u64, duration: u64, callback: F) -> usize {
+    pub fn no_flags(interval: u64, duration: u64, callback: F) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -312,9 +417,10 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: None,
             entity_id: None,
             flag: None,
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 
     pub fn all_flags(
@@ -324,8 +430,9 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
         entity: usize,
         flag: String,
         callback: F,
-    ) -> usize {
+    ) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -335,13 +442,15 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: Some(area),
             entity_id: Some(entity),
             flag: Some(flag),
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 
-    pub fn new_for_area(interval: u64, duration: u64, area: usize, callback: F) -> usize {
+    pub fn new_for_area(interval: u64, duration: u64, area: usize, callback: F) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -351,13 +460,15 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: Some(area),
             entity_id: None,
             flag: None,
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 
-    pub fn new_for_entity(interval: u64, duration: u64, entity: usize, callback: F) -> usize {
+    pub fn new_for_entity(interval: u64, duration: u64, entity: usize, callback: F) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -367,13 +478,15 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: None,
             entity_id: Some(entity),
             flag: None,
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 
-    pub fn new_for_flag(interval: u64, duration: u64, flag: &str, callback: F) -> usize {
+    pub fn new_for_flag(interval: u64, duration: u64, flag: &str, callback: F) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -383,9 +496,10 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: None,
             entity_id: None,
             flag: Some(flag.to_string()),
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 
     pub fn new(
@@ -395,8 +509,9 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
         entity: Option<usize>,
         flag: Option<String>,
         callback: F,
-    ) -> usize {
+    ) -> RepeatHandler {
         let id = random();
+        let stopped = Arc::new(AtomicBool::new(false));
 
         schedule_event(RepeatedEvent {
             next_exe_time: Cell::new(get_exe_time(interval)),
@@ -406,9 +521,10 @@ impl<F: Fn() -> bool + 'static + Send> RepeatedEvent<F> {
             area_id: area,
             entity_id: entity,
             flag,
+            stopped: stopped.clone(),
             id,
         });
-        id
+        RepeatHandler { stopped, id }
     }
 }
 
@@ -418,15 +534,21 @@ impl<F: Fn() -> bool + 'static + Send> TimedEvent for RepeatedEvent<F> {
     }
 
     fn run(&self) {
+        // Cancelled by its handler since being scheduled.
+        if self.stopped.load(SeqCst) {
+            return;
+        }
         if (&self.run)() {
             self.next_exe_time.set(get_exe_time(self.interval));
         } else {
-            delete_event(self.id);
+            // The registry is locked while events run, so
+            // just avoid rescheduling it.
+            self.stopped.store(true, SeqCst);
         }
     }
 
     fn handle_delete(self: Box<Self>, registry: &mut EventRegistry) {
-        if game_time() <= self.max_exe_time {
+        if !self.stopped.load(SeqCst) && game_time() <= self.max_exe_time {
             registry.push(self)
         }
     }
@@ -459,3 +581,86 @@ impl<F: Fn() -> bool + 'static + Send> TimedEvent for RepeatedEvent<F> {
         self.id == id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn delayed_event_runs_once_its_time_passes() {
+        let _guard = test_lock();
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran2 = ran.clone();
+        DelayedEvent::new_for_flag(500, "test_delayed", move || ran2.store(true, SeqCst));
+
+        test_advance_time(499);
+        assert!(!ran.load(SeqCst));
+        assert!(has_flags(None, None, Some("test_delayed")));
+
+        test_advance_time(1);
+        assert!(ran.load(SeqCst));
+        assert!(!has_flags(None, None, Some("test_delayed")));
+    }
+
+    #[test]
+    fn cancel_from_inside_callback() {
+        let _guard = test_lock();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handler: Arc<Mutex<Option<RepeatHandler>>> = Arc::new(Mutex::new(None));
+
+        let runs2 = runs.clone();
+        let handler2 = handler.clone();
+        let repeat = RepeatedEvent::new_for_flag(100, 10_000, "test_cancel_inside", move || {
+            if runs2.fetch_add(1, SeqCst) == 1 {
+                handler2.lock().as_ref().unwrap().cancel();
+            }
+            true
+        });
+        *handler.lock() = Some(repeat.clone());
+
+        for _ in 0..5 {
+            test_advance_time(100);
+        }
+        assert_eq!(2, runs.load(SeqCst));
+        assert!(repeat.is_cancelled());
+        assert!(!has_flags(None, None, Some("test_cancel_inside")));
+    }
+
+    #[test]
+    fn cancel_is_idempotent() {
+        let _guard = test_lock();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs2 = runs.clone();
+        let repeat = RepeatedEvent::new_for_flag(100, 200, "test_cancel_twice", move || {
+            runs2.fetch_add(1, SeqCst);
+            true
+        });
+        for _ in 0..4 {
+            test_advance_time(100);
+        }
+        let finished_runs = runs.load(SeqCst);
+        assert!(!has_flags(None, None, Some("test_cancel_twice")));
+
+        repeat.cancel();
+        repeat.cancel();
+        test_advance_time(100);
+        assert_eq!(finished_runs, runs.load(SeqCst));
+    }
+
+    #[test]
+    fn cancel_before_first_run() {
+        let _guard = test_lock();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs2 = runs.clone();
+        let repeat = RepeatedEvent::new_for_flag(100, 1_000, "test_cancel_early", move || {
+            runs2.fetch_add(1, SeqCst);
+            true
+        });
+        repeat.cancel();
+        test_advance_time(100);
+        assert_eq!(0, runs.load(SeqCst));
+        assert!(!has_flags(None, None, Some("test_cancel_early")));
+    }
+}
@@ -2,12 +2,14 @@
 extern crate lazy_static;
 extern crate parking_lot;
 
-use std::sync::mpsc::{ self, Sender, TryRecvError::* };
+use std::sync::mpsc::{ self, Receiver, Sender, TryRecvError::* };
+use std::collections::VecDeque;
 use std::io::{ self, ErrorKind::*, Read, Write };
 use std::net::{ SocketAddr, TcpStream };
 use std::time::Duration;
 use std::str::Lines;
 use std::process;
+use std::fmt;
 use std::thread;
 use std::fs;
 
@@ -20,17 +22,94 @@ const MAX_RETRIES: u32 = 5;
 const MSG_SIZE: usize = 1024;
 const IP_STORAGE: &'static str = "last_ip.txt";
 
+/// Pass this argument to watch the game without playing.
+const OBSERVE_FLAG: &'static str = "--observe";
+
+/// Enter this to resend the previous command.
+const REPEAT_TOKEN: &'static str = "!!";
+
+/// The number of recent commands remembered.
+const MAX_HISTORY: usize = 50;
+
+/// Commands which close the client. These are never
+/// recorded, so they can't be resent by accident.
+const EXIT_COMMANDS: [&'static str; 4] = ["quit", "end", "leave", "stop"];
+
 lazy_static!
 {
     static ref CLIENT_INFO: Mutex<ClientInfo> = Mutex::new(ClientInfo::new());
 }
 
+/// The reasons a response from the server could not be
+/// understood. The client reconnects when these occur.
+#[derive(Debug)]
+enum ProtocolError
+{
+    Empty,
+    InvalidUtf8,
+    MissingField(&'static str),
+    UnknownReason(String)
+}
+
+impl fmt::Display for ProtocolError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ProtocolError::Empty => write!(f, "The server sent an empty message."),
+            ProtocolError::InvalidUtf8 => write!(f, "The server sent a message that couldn't be read."),
+            ProtocolError::MissingField(key) => write!(f, "The server's message was missing its {} line.", key),
+            ProtocolError::UnknownReason(r) => write!(f, "The server refused the login for an unknown reason: {}.", r)
+        }
+    }
+}
+
+type ProtocolResult = Result<&'static str, ProtocolError>;
+
+/// Reads the next line, which must start with `key`,
+/// e.g. `TOKEN|`. Returns whatever follows the key.
+fn parse_field<'a>(lines: &mut Lines<'a>, key: &'static str) -> Result<&'a str, ProtocolError>
+{
+    match lines.next()
+    {
+        Some(l) if l.starts_with(key) => Ok(&l[key.len()..]),
+        _ => Err(ProtocolError::MissingField(key))
+    }
+}
+
 fn main()
 {
+    CLIENT_INFO.lock().observer = std::env::args().any(| a | a == OBSERVE_FLAG);
+
     let server_ip = get_ip();
-    let client = load_client(server_ip);
+    // Inputs outlive each connection so that they can
+    // keep being read after reconnecting.
+    let (tx, rx) = mpsc::channel::<String>();
+    let mut num_reconnects = 0;
+
+    loop
+    {
+        let client = load_client(server_ip);
 
-    start_client(client);
+        match start_client(client, &tx, &rx)
+        {
+            Err(e) if num_reconnects < MAX_RETRIES =>
+            {
+                println!("\n{} Reconnecting...", e);
+                num_reconnects += 1;
+                CLIENT_INFO.lock().token = None;
+                sleep(RETRY_DELAY);
+            },
+            Err(e) =>
+            {
+                println!("\n{} Closing...", e);
+                sleep(SHUTDOWN_DELAY);
+                break;
+            },
+            Ok(_) => break
+        }
+    }
 }
 
 fn get_ip() -> SocketAddr
@@ -82,10 +161,10 @@ fn load_client(server_ip: SocketAddr) -> TcpStream
     client
 }
 
-fn start_client(mut client: TcpStream)
+/// Runs until the connection is lost, returning an error
+/// if the server sent something that couldn't be handled.
+fn start_client(mut client: TcpStream, tx: &Sender<String>, rx: &Receiver<String>) -> Result<(), ProtocolError>
 {
-    let (tx, rx) = mpsc::channel::<String>();
-
     loop
     {
         let mut buf = vec![0; MSG_SIZE];
@@ -98,22 +177,20 @@ fn start_client(mut client: TcpStream)
                     .take_while(| b | *b != 0)
                     .collect();
 
-                if let Ok(text) = String::from_utf8(msg)
+                let text = String::from_utf8(msg)
+                    .map_err(| _ | ProtocolError::InvalidUtf8)?;
+
+                if handle_response(&text, &mut client)? == "OK"
                 {
-                    match handle_response(&text, &mut client)
-                    {
-                        Ok(o) => if o == "OK" { handle_inputs(tx.clone()) },
-                        Err(_) => {/* Ignore */}
-                    };
+                    start_inputs(tx);
                 }
-                else { println!("Error parsing message from server."); }
             }
             Err(ref e) if e.kind() == WouldBlock => (),
             Err(_) =>
             {
                 println!("\nLost connection to the server. Closing...");
                 sleep(SHUTDOWN_DELAY);
-                break;
+                return Ok(());
             }
         }
 
@@ -122,20 +199,20 @@ fn start_client(mut client: TcpStream)
             Ok(msg) => {write(&msg, &mut client)
                 .expect("Error writing to socket.");},
             Err(Empty) => (),
-            Err(Disconnected) => break
+            Err(Disconnected) => return Ok(())
         };
 
         sleep(REFRESH_RATE);
     };
 }
 
-fn handle_response(msg: &str, client: &mut TcpStream) -> Result<&'static str, &'static str>
+fn handle_response(msg: &str, client: &mut TcpStream) -> ProtocolResult
 {
     let mut lines = msg.lines();
     let msg_type = match lines.next()
     {
         Some(l) => l,
-        None => return Err("Empty message.")//panic!("Somehow received an empty response")
+        None => return Err(ProtocolError::Empty)
     };
 
     match msg_type
@@ -147,7 +224,7 @@ fn handle_response(msg: &str, client: &mut TcpStream) -> Result<&'static str, &'
     }
 }
 
-fn standard_msg(msg: &str) -> Result<&'static str, &'static str>
+fn standard_msg(msg: &str) -> ProtocolResult
 {
     io::stdout().write(msg.as_bytes()).unwrap();
     io::stdout().flush().unwrap();
@@ -155,8 +232,16 @@ fn standard_msg(msg: &str) -> Result<&'static str, &'static str>
     Ok("All seems well 2.")
 }
 
-fn register_user(client: &mut TcpStream) -> Result<&'static str, &'static str>
+fn register_user(client: &mut TcpStream) -> ProtocolResult
 {
+    let mut info = CLIENT_INFO.lock();
+
+    if info.observer
+    {
+        return register_from_info(&mut info, client);
+    }
+    drop(info);
+
     let mut username = prompt("Enter a username to connect with:");
 
     // To-do: Handle this on the server side.
@@ -171,13 +256,9 @@ fn register_user(client: &mut TcpStream) -> Result<&'static str, &'static str>
     register_from_info(&mut info, client)
 }
 
-fn login_err(mut lines: Lines, client: &mut TcpStream) -> Result<&'static str, &'static str>
+fn login_err(mut lines: Lines, client: &mut TcpStream) -> ProtocolResult
 {
-    let err_message = match lines.next()
-    {
-        Some(e) if e.starts_with("REASON|") => &e[7..],
-        _ => panic!("Unable to parse error message from response.")
-    };
+    let err_message = parse_field(&mut lines, "REASON|")?;
 
     match err_message
     {
@@ -194,27 +275,36 @@ fn login_err(mut lines: Lines, client: &mut TcpStream) -> Result<&'static str, &
             process::exit(-4);
         },
         "TAKEN" => change_username(client),
-        _ => panic!("Received an unrecognized error message.")
+        "EXPIRED" =>
+        {
+            println!("Your session expired. Logging in again...");
+            let mut info = CLIENT_INFO.lock();
+            info.token = None;
+            register_from_info(&mut info, client)
+        },
+        _ => Err(ProtocolError::UnknownReason(err_message.to_string()))
     }
 }
 
-fn login_ok(mut lines: Lines) -> Result<&'static str, &'static str>
+fn login_ok(mut lines: Lines) -> ProtocolResult
 {
-    let token = match lines.next()
-    {
-        Some(t) if t.starts_with("TOKEN|") => t[6..].to_string(),
-        _ => panic!("Unable to parse token from response.")
-    };
+    let token = parse_field(&mut lines, "TOKEN|")?.to_string();
 
     let mut info = CLIENT_INFO.lock();
     info.token = Some(token);
 
+    // Observers can't send anything, so they don't need inputs.
+    if info.observer
+    {
+        println!("Observing the game. Press Ctrl+C to stop.");
+        return Ok("OBSERVING");
+    }
     println!("Logged in successfully! Press enter to begin.");
 
     Ok("OK")
 }
 
-fn change_username(client: &mut TcpStream) -> Result<&'static str, &'static str>
+fn change_username(client: &mut TcpStream) -> ProtocolResult
 {
     let username = prompt("This username is already taken. Enter a different one:");
     let mut info = CLIENT_INFO.lock();
@@ -223,8 +313,12 @@ fn change_username(client: &mut TcpStream) -> Result<&'static str, &'static str>
     register_from_info(&mut info, client)
 }
 
-fn register_from_info(info: &mut ClientInfo, client: &mut TcpStream) -> Result<&'static str, &'static str>
+fn register_from_info(info: &mut ClientInfo, client: &mut TcpStream) -> ProtocolResult
 {
+    if info.observer
+    {
+        return write("OBSERVE", client);
+    }
     let username = match info.username
     {
         Some(ref u) => u,
@@ -239,24 +333,95 @@ fn register_from_info(info: &mut ClientInfo, client: &mut TcpStream) -> Result<&
     write(&msg, client)
 }
 
-fn handle_inputs(tx: Sender<String>)
+/// Starts reading inputs, unless a previous connection
+/// already did.
+fn start_inputs(tx: &Sender<String>)
 {
-    thread::spawn(move || loop
+    let mut info = CLIENT_INFO.lock();
+    if !info.reading_inputs
     {
-        let mut msg = String::new();
-        io::stdin().read_line(&mut msg)
-            .expect("Unable to parse input.");
-        let msg = msg.trim();
+        info.reading_inputs = true;
+        handle_inputs(tx.clone());
+    }
+}
 
-        match msg
+fn handle_inputs(tx: Sender<String>)
+{
+    thread::spawn(move ||
+    {
+        let mut history = History::new();
+        loop
         {
-            "quit" | "end" | "leave" | "stop" => end(),
-            _ if try_send(msg, &tx).is_err() => end(),
-            _ => continue
+            let mut msg = String::new();
+            io::stdin().read_line(&mut msg)
+                .expect("Unable to parse input.");
+            let mut msg = msg.trim().to_string();
+
+            if msg == REPEAT_TOKEN
+            {
+                match history.last()
+                {
+                    Some(last) =>
+                    {
+                        println!("> {}", last);
+                        msg = last.to_string();
+                    }
+                    None =>
+                    {
+                        println!("There is no previous command.");
+                        continue;
+                    }
+                }
+            }
+            if EXIT_COMMANDS.contains(&msg.as_str())
+            {
+                end();
+            }
+            history.push(&msg);
+
+            if try_send(&msg, &tx).is_err()
+            {
+                end();
+            }
         }
     });
 }
 
+/// A bounded record of the commands entered this session,
+/// oldest first.
+struct History
+{
+    entries: VecDeque<String>
+}
+
+impl History
+{
+    fn new() -> History
+    {
+        History { entries: VecDeque::with_capacity(MAX_HISTORY) }
+    }
+
+    /// Records a command, forgetting the oldest one once
+    /// `MAX_HISTORY` is reached. Empty lines are skipped.
+    fn push(&mut self, msg: &str)
+    {
+        if msg.is_empty()
+        {
+            return;
+        }
+        if self.entries.len() >= MAX_HISTORY
+        {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(msg.to_string());
+    }
+
+    fn last(&self) -> Option<&String>
+    {
+        self.entries.back()
+    }
+}
+
 fn prompt(msg: &str) -> String
 {
     println!("{}", msg);
@@ -289,7 +454,7 @@ fn try_send(msg: &str, tx: &Sender<String>) -> Result<&'static str, &'static str
     Ok("Token isn't ready.") // Ignore these inputs.
 }
 
-fn write(msg: &str, stream: &mut TcpStream) -> Result<&'static str, &'static str>
+fn write(msg: &str, stream: &mut TcpStream) -> ProtocolResult
 {
     stream.write(msg.as_bytes()).expect("Error writing message.");
     stream.flush().expect("We'll see about that!");
@@ -312,13 +477,50 @@ fn end()
 struct ClientInfo
 {
     username: Option<String>,
-    token: Option<String>
+    token: Option<String>,
+    observer: bool,
+    reading_inputs: bool
 }
 
 impl ClientInfo
 {
     fn new() -> ClientInfo
     {
-        ClientInfo{ username: None, token: None }
+        ClientInfo{ username: None, token: None, observer: false, reading_inputs: false }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn malformed_login_ok_is_an_error()
+    {
+        for text in &["", "USER|someone", "token|abc"]
+        {
+            match login_ok(text.lines())
+            {
+                Err(ProtocolError::MissingField("TOKEN|")) => (),
+                _ => panic!("A malformed LOGIN_OK was accepted: {}", text)
+            }
+        }
+        assert!(CLIENT_INFO.lock().token.is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fields_must_start_with_their_key()
+    {
+        match parse_field(&mut "REASON|SOMETHING_NEW".lines(), "REASON|")
+        {
+            Ok(reason) => assert_eq!("SOMETHING_NEW", reason),
+            Err(e) => panic!("A valid field was refused: {}", e)
+        }
+        match parse_field(&mut "".lines(), "REASON|")
+        {
+            Err(ProtocolError::MissingField("REASON|")) => (),
+            _ => panic!("A missing field was accepted.")
+        }
+    }
+}
@@ -1,4 +1,4 @@
-#![recursion_limit="128"]
+#![recursion_limit="256"]
 
 #[macro_use]
 extern crate quote;
@@ -176,23 +176,59 @@ fn impl_entity_holder(ast: &DeriveInput) -> TokenStream {
                 false
             }
 
-            fn add_entity(&self, entity: Box<Entity>) {
-                entity.on_enter_area(self.coordinates);
-                self.entities.write().push(entity);
+            fn has_room(&self) -> bool {
+                self.entities.read().len() < self.get_capacity()
             }
 
-            fn remove_entity(&self, id: usize) -> Option<Box<Entity>> {
-                if let Some(num) = self.get_entity_index(id) {
-                    return Some(self.take_entity_by_index(num));
+            fn add_entity(&self, entity: Box<Entity>) -> Result<(), Box<Entity>> {
+                if !self.has_room() {
+                    return Err(entity);
                 }
-                None
+                let id = entity.get_id();
+                entity.set_coordinates(self.coordinates);
+                self.entities.write().push(entity);
+                self.on_entity_entered(id);
+                Ok(())
             }
 
-            fn transfer_entity(&self, id: usize, to: &EntityHolder) {
-                let entity = self.remove_entity(id)
-                    .expect("Error: Attempted to remove entity who no longer existed in area.");
+            fn remove_entity(&self, id: usize) -> Option<Box<Entity>> {
+                let mut entities = self.entities.write();
+                let index = entities.iter().position(|e| e.get_id() == id)?;
+                Some(entities.remove(index))
+            }
 
-                to.add_entity(entity);
+            fn transfer_entity(&self, id: usize, to: &EntityHolder) -> Result<(), &'static str> {
+                let to_coords = to.get_holder_coordinates();
+                if to_coords == self.coordinates {
+                    return Ok(());
+                }
+                // Both holders stay locked for the entire transfer,
+                // so the entity is never missing from both. Locks are
+                // always taken in coordinate order to avoid deadlocks.
+                let (mut from, mut dest) = if self.coordinates < to_coords {
+                    let from = self.entities.write();
+                    (from, to.lock_entities())
+                } else {
+                    let dest = to.lock_entities();
+                    (self.entities.write(), dest)
+                };
+                if dest.len() >= to.get_capacity() {
+                    return Err("That area is too crowded right now.");
+                }
+                let index = match from.iter().position(|e| e.get_id() == id) {
+                    Some(i) => i,
+                    None => {
+                        warn!("Entity #{} was already gone from {:?} when transferring.", id, self.coordinates);
+                        return Err("You're no longer in that area.");
+                    }
+                };
+                let entity = from.remove(index);
+                entity.set_coordinates(to_coords);
+                dest.push(entity);
+                drop(from);
+                drop(dest);
+                to.on_entity_entered(id);
+                Ok(())
             }
 
             fn contains_entity(&self, id: usize) -> bool {
@@ -212,6 +248,60 @@ fn impl_entity_holder(ast: &DeriveInput) -> TokenStream {
             fn borrow_entity_lock(&self) -> RwLockReadGuard<Vec<Box<Entity>>> {
                 self.entities.read()
             }
+
+            fn lock_entities(&self) -> parking_lot::RwLockWriteGuard<Vec<Box<Entity>>> {
+                self.entities.write()
+            }
+
+            fn get_holder_coordinates(&self) -> (usize, usize, usize) {
+                self.coordinates
+            }
+
+            fn get_capacity(&self) -> usize {
+                crate::traits::Area::max_entities(self)
+            }
+        }
+    };
+    expanded.into()
+}
+
+#[proc_macro_derive(EntityCore)]
+pub fn entity_core(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_entity_core(&ast)
+}
+
+fn impl_entity_core(ast: &DeriveInput) -> TokenStream {
+    if !has_field(ast, "id") {
+        panic!("Error: You must provide a field for id when using #[derive(EntityCore)].");
+    }
+    if !has_field(ast, "health") {
+        panic!("Error: You must provide a field for health when using #[derive(EntityCore)].");
+    }
+
+    let name = &ast.ident;
+
+    // Paths are fully qualified so that nothing is imported
+    // into, or needed from, the deriving module.
+    let expanded = quote! {
+        impl crate::traits::EntityCore for #name {
+            fn get_id(&self) -> usize { self.id }
+
+            fn set_health(&self, health: u32) {
+                self.health.store(health, std::sync::atomic::Ordering::SeqCst);
+            }
+
+            fn get_health(&self) -> u32 {
+                self.health.load(std::sync::atomic::Ordering::SeqCst)
+            }
+
+            fn get_accessor(&self) -> crate::util::access::EntityAccessor {
+                crate::util::access::EntityAccessor {
+                    coordinates: crate::traits::Entity::get_coordinates(self),
+                    entity_id: self.id,
+                    is_player: crate::traits::Entity::get_type(self) == "player",
+                }
+            }
         }
     };
     expanded.into()
@@ -0,0 +1,8 @@
+extern crate trybuild;
+
+#[test]
+fn entity_core() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/entity_core_pair.rs");
+    t.compile_fail("tests/ui/entity_core_missing_health.rs");
+}
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate test_game_derive;
+
+#[derive(EntityCore)]
+pub struct NoHealth {
+    pub id: usize,
+}
+
+fn main() {}
@@ -0,0 +1,76 @@
+// Two derives in one module which imports nothing. The
+// stubs mirror the paths used by `test_game`.
+
+#[macro_use]
+extern crate test_game_derive;
+
+mod traits {
+    pub trait EntityCore {
+        fn get_id(&self) -> usize;
+        fn set_health(&self, health: u32);
+        fn get_health(&self) -> u32;
+        fn get_accessor(&self) -> crate::util::access::EntityAccessor;
+    }
+
+    pub trait Entity {
+        fn get_coordinates(&self) -> (usize, usize, usize);
+        fn get_type(&self) -> &'static str;
+    }
+}
+
+mod util {
+    pub mod access {
+        pub struct EntityAccessor {
+            pub coordinates: (usize, usize, usize),
+            pub entity_id: usize,
+            pub is_player: bool,
+        }
+    }
+}
+
+mod entities {
+    #[derive(EntityCore)]
+    pub struct Spider {
+        pub id: usize,
+        pub health: std::sync::atomic::AtomicU32,
+    }
+
+    #[derive(EntityCore)]
+    pub struct Companion {
+        pub id: usize,
+        pub health: std::sync::atomic::AtomicU32,
+    }
+
+    impl crate::traits::Entity for Spider {
+        fn get_coordinates(&self) -> (usize, usize, usize) {
+            (1, 2, 3)
+        }
+
+        fn get_type(&self) -> &'static str {
+            "mob"
+        }
+    }
+
+    impl crate::traits::Entity for Companion {
+        fn get_coordinates(&self) -> (usize, usize, usize) {
+            (1, 2, 3)
+        }
+
+        fn get_type(&self) -> &'static str {
+            "companion"
+        }
+    }
+}
+
+fn main() {
+    use crate::traits::EntityCore;
+    use std::sync::atomic::AtomicU32;
+
+    let spider = entities::Spider { id: 1, health: AtomicU32::new(5) };
+    let companion = entities::Companion { id: 2, health: AtomicU32::new(5) };
+    spider.set_health(3);
+
+    assert_eq!(3, spider.get_health());
+    assert_eq!(2, companion.get_accessor().entity_id);
+    assert!(!spider.get_accessor().is_player);
+}